@@ -0,0 +1,87 @@
+//! Micro-benchmarks of the stanza lookups that are on the hot path of handler code
+//!
+//! Run with `cargo bench`, the numbers are per single operation averaged over the iteration count.
+
+use std::time::Instant;
+use std::{mem, ptr};
+
+use libstrophe::Stanza;
+
+const ITERATIONS: u32 = 200_000;
+
+const MUC_PRESENCE: &str = "<presence from='coven@chat.shakespeare.lit/thirdwitch' id='n13mt3l' to='hag66@shakespeare.lit/pda'>\
+	<x xmlns='http://jabber.org/protocol/muc#user'>\
+	<item affiliation='member' jid='hag66@shakespeare.lit/pda' role='participant'/>\
+	<status code='100'/><status code='110'/><status code='210'/></x></presence>";
+
+const MAM_RESULT: &str = "<message id='aeb213' to='juliet@capulet.lit/chamber'>\
+	<result xmlns='urn:xmpp:mam:2' queryid='f27' id='28482-98726-73623'>\
+	<forwarded xmlns='urn:xmpp:forward:0'>\
+	<delay xmlns='urn:xmpp:delay' stamp='2010-07-10T23:08:25Z'/>\
+	<message xmlns='jabber:client' from='witch@shakespeare.lit' to='macbeth@shakespeare.lit' type='chat'>\
+	<body>Hail to thee</body></message></forwarded></result></message>";
+
+fn black_box<T>(x: T) -> T {
+	// std::hint::black_box() requires a newer Rust than the crate MSRV
+	unsafe {
+		let out = ptr::read_volatile(&x);
+		mem::forget(x);
+		out
+	}
+}
+
+fn bench(name: &str, mut f: impl FnMut()) {
+	for _ in 0..ITERATIONS / 10 {
+		f();
+	}
+	let start = Instant::now();
+	for _ in 0..ITERATIONS {
+		f();
+	}
+	let elapsed = start.elapsed();
+	println!("{:<40} {:>10.1?}", name, elapsed / ITERATIONS);
+}
+
+fn main() {
+	let presence = Stanza::from_str(MUC_PRESENCE);
+	let message = Stanza::from_str(MAM_RESULT);
+
+	bench("get_attribute", || {
+		black_box(black_box(&presence).get_attribute("from"));
+	});
+	bench("attributes", || {
+		black_box(black_box(&presence).attributes());
+	});
+	bench("get_child_by_name", || {
+		black_box(black_box(&presence).get_child_by_name("x"));
+	});
+	bench("get_child_by_ns", || {
+		black_box(black_box(&presence).get_child_by_ns("http://jabber.org/protocol/muc#user"));
+	});
+	bench("get_child_by_name_and_ns", || {
+		black_box(black_box(&message).get_child_by_name_and_ns("result", "urn:xmpp:mam:2"));
+	});
+	bench("get_child_by_name + ns check", || {
+		black_box(
+			black_box(&message)
+				.get_child_by_name("result")
+				.filter(|result| result.ns() == Some("urn:xmpp:mam:2")),
+		);
+	});
+	bench("get_child_by_path", || {
+		black_box(black_box(&message).get_child_by_path(&["result", "forwarded", "message", "body"]));
+	});
+	bench("MUC status codes", || {
+		let codes = black_box(&presence)
+			.get_child_by_ns("http://jabber.org/protocol/muc#user")
+			.map_or(0, |x| {
+				x.children()
+					.filter_map(|status| status.attr_parse::<u16>("code").ok().flatten())
+					.count()
+			});
+		black_box(codes);
+	});
+	bench("to_text", || {
+		black_box(black_box(&message).to_text().unwrap());
+	});
+}
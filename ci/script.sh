@@ -11,12 +11,15 @@ if verlte "0.9.3" "$LIBSTROPHE_VERSION"; then
 	ARGS="--no-default-features --features=buildtime_bindgen,libstrophe-0_9_3"
 	cargo test -v $ARGS -- --test-threads=1
 	cargo test -v $ARGS --release -- --test-threads=1
+	cargo test -v $ARGS,presence,roster -- --test-threads=1
 fi
 
 if verlte "0.10.0" "$LIBSTROPHE_VERSION"; then
 	ARGS="--no-default-features --features=buildtime_bindgen,libstrophe-0_10_0"
 	cargo test -v $ARGS -- --test-threads=1
 	cargo test -v $ARGS --release -- --test-threads=1
+	# the oldest version supported by the XEP helpers
+	cargo test -v $ARGS,xep-helpers -- --test-threads=1
 fi
 
 if verlte "0.11.0" "$LIBSTROPHE_VERSION"; then
@@ -28,6 +31,7 @@ fi
 if verlte "0.12.0" "$LIBSTROPHE_VERSION"; then
 	cargo test -v -- --test-threads=1
 	cargo test -v --release -- --test-threads=1
+	cargo test -v --no-default-features --features=rust-log,libstrophe-0_12_0 -- --test-threads=1
 	cargo test -v --features=buildtime_bindgen -- --test-threads=1
 	cargo test -v --release --features=buildtime_bindgen -- --test-threads=1
 fi
@@ -0,0 +1,25 @@
+#![no_main]
+
+//! Feeds the parsed stanzas to the stanza interpreting helpers the handlers use on the incoming stanzas
+
+use libfuzzer_sys::fuzz_target;
+use libstrophe::xep::delay::Delay;
+use libstrophe::xep::jingle::Jingle;
+use libstrophe::xep::{account, disco, forwarded};
+use libstrophe::{DedupCache, MessageThreads, Stanza};
+
+fuzz_target!(|data: &[u8]| {
+	let s = match std::str::from_utf8(data) {
+		Ok(s) => s,
+		Err(_) => return,
+	};
+	if let Ok(stanza) = Stanza::parse(s) {
+		let _ = DedupCache::new(4).check(&stanza);
+		let _ = MessageThreads::new().track(&stanza);
+		let _ = Delay::from_parent(&stanza);
+		let _ = forwarded::unwrap(&stanza);
+		let _ = Jingle::from_stanza(&stanza);
+		let _ = disco::features(&stanza);
+		let _ = account::parse_response(&stanza);
+	}
+});
@@ -0,0 +1,32 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use libstrophe::Stanza;
+
+fn walk(stanza: &Stanza, depth: usize) {
+	let _ = stanza.name();
+	let _ = stanza.ns();
+	let _ = stanza.text();
+	let _ = stanza.attributes();
+	if depth < 64 {
+		for child in stanza.children() {
+			walk(&child, depth + 1);
+		}
+	}
+}
+
+fuzz_target!(|data: &[u8]| {
+	let s = match std::str::from_utf8(data) {
+		Ok(s) => s,
+		Err(_) => return,
+	};
+	if let Ok(stanza) = Stanza::parse(s) {
+		walk(&stanza, 0);
+		// serialized stanza must parse back
+		if let Ok(text) = stanza.to_text() {
+			let reparsed = Stanza::parse(&text).expect("Cannot parse serialized stanza");
+			assert_eq!(reparsed.name(), stanza.name());
+		}
+		let _ = stanza.reply();
+	}
+});
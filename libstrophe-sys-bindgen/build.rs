@@ -1,5 +1,6 @@
+/// Generates the bindings from the installed headers, returns `true` if they define `xmpp_cert_element_t` (libstrophe 0.11+)
 #[cfg(feature = "buildtime_bindgen")]
-fn build_wrapper() {
+fn build_wrapper() -> bool {
 	use bindgen::callbacks;
 	use std::{env, path::PathBuf};
 
@@ -16,7 +17,7 @@ fn build_wrapper() {
 		}
 	}
 
-	let builder = bindgen::builder()
+	let mut builder = bindgen::builder()
 		.header("wrapper.h")
 		.size_t_is_usize(true)
 		.parse_callbacks(Box::new(PCallbacks))
@@ -29,18 +30,42 @@ fn build_wrapper() {
 		.rustified_enum("xmpp_cert_element_t")
 		.rustified_enum("xmpp_queue_element_t");
 
+	let out_file = if cfg!(feature = "core_ffi") {
+		builder = builder
+			.use_core()
+			.ctypes_prefix("::core::ffi")
+			.blocklist_function("xmpp_vsnprintf")
+			.blocklist_function("xmpp_log")
+			.blocklist_type("va_list")
+			.blocklist_type("__gnuc_va_list")
+			.blocklist_type("__builtin_va_list")
+			.blocklist_type("__va_list_tag");
+		"src/ffi_core.rs"
+	} else {
+		"src/ffi.rs"
+	};
+
 	let bindings = builder.generate().expect("Unable to generate bindings");
+	let has_cert_element = bindings.to_string().contains("pub enum xmpp_cert_element_t");
 
-	// Write the bindings to the src/ffi.rs file.
+	// Write the bindings to the src/ffi.rs (or src/ffi_core.rs) file.
 	let mut out_path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
-	out_path.push("src/ffi.rs");
+	out_path.push(out_file);
 	bindings
 		.write_to_file(&out_path)
 		.unwrap_or_else(|e| panic!("Couldn't write bindings to: {}, error: {}", out_path.display(), e));
+	has_cert_element
 }
 
 fn main() {
 	println!("cargo:rustc-link-lib=strophe");
+	println!("cargo:rustc-check-cfg=cfg(strophe_cert_element)");
 	#[cfg(feature = "buildtime_bindgen")]
-	build_wrapper();
+	let has_cert_element = build_wrapper();
+	// the pre-generated bindings are from libstrophe 0.12
+	#[cfg(not(feature = "buildtime_bindgen"))]
+	let has_cert_element = true;
+	if has_cert_element {
+		println!("cargo:rustc-cfg=strophe_cert_element");
+	}
 }
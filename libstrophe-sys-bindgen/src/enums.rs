@@ -0,0 +1,128 @@
+//! Conversions of the C enums to and from the human-readable names, e.g. for the configuration files and logs
+
+use std::error::Error;
+use std::{fmt, str};
+
+#[cfg(strophe_cert_element)]
+use crate::ffi::xmpp_cert_element_t;
+use crate::ffi::{xmpp_error_type_t, xmpp_log_level_t};
+
+/// Error returned when parsing an unknown enum name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseEnumError {
+	/// Name of the enum type, e.g. `xmpp_log_level_t`
+	pub typ: &'static str,
+	/// The name that failed to parse
+	pub value: String,
+}
+
+impl fmt::Display for ParseEnumError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Unknown {} value \"{}\"", self.typ, self.value)
+	}
+}
+
+impl Error for ParseEnumError {}
+
+/// Implements `Display`, `FromStr` and optionally serde traits for the enum, the first name of each variant is the
+/// canonical one, the rest are the aliases accepted when parsing. The parsing is case-insensitive.
+macro_rules! enum_names {
+	($typ:ident { $($variant:ident => $name:literal $(| $alias:literal)*,)+ }) => {
+		impl $typ {
+			/// Canonical name of the value
+			pub fn as_str(&self) -> &'static str {
+				match self {
+					$($typ::$variant => $name,)+
+				}
+			}
+		}
+
+		impl fmt::Display for $typ {
+			fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+				f.write_str(self.as_str())
+			}
+		}
+
+		impl str::FromStr for $typ {
+			type Err = ParseEnumError;
+
+			fn from_str(s: &str) -> Result<Self, Self::Err> {
+				$(
+					if s.eq_ignore_ascii_case($name) $(|| s.eq_ignore_ascii_case($alias))* {
+						return Ok($typ::$variant);
+					}
+				)+
+				Err(ParseEnumError {
+					typ: stringify!($typ),
+					value: s.to_owned(),
+				})
+			}
+		}
+
+		#[cfg(feature = "serde")]
+		impl serde::Serialize for $typ {
+			fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+				serializer.serialize_str(self.as_str())
+			}
+		}
+
+		#[cfg(feature = "serde")]
+		impl<'de> serde::Deserialize<'de> for $typ {
+			fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+				let name = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+				name.parse().map_err(serde::de::Error::custom)
+			}
+		}
+	};
+}
+
+enum_names!(xmpp_log_level_t {
+	XMPP_LEVEL_DEBUG => "debug",
+	XMPP_LEVEL_INFO => "info",
+	XMPP_LEVEL_WARN => "warn" | "warning",
+	XMPP_LEVEL_ERROR => "error",
+});
+
+// the names of the stream error conditions from RFC 6120, `invalid-id` is from RFC 3920
+enum_names!(xmpp_error_type_t {
+	XMPP_SE_BAD_FORMAT => "bad-format",
+	XMPP_SE_BAD_NS_PREFIX => "bad-namespace-prefix",
+	XMPP_SE_CONFLICT => "conflict",
+	XMPP_SE_CONN_TIMEOUT => "connection-timeout",
+	XMPP_SE_HOST_GONE => "host-gone",
+	XMPP_SE_HOST_UNKNOWN => "host-unknown",
+	XMPP_SE_IMPROPER_ADDR => "improper-addressing",
+	XMPP_SE_INTERNAL_SERVER_ERROR => "internal-server-error",
+	XMPP_SE_INVALID_FROM => "invalid-from",
+	XMPP_SE_INVALID_ID => "invalid-id",
+	XMPP_SE_INVALID_NS => "invalid-namespace",
+	XMPP_SE_INVALID_XML => "invalid-xml",
+	XMPP_SE_NOT_AUTHORIZED => "not-authorized",
+	XMPP_SE_POLICY_VIOLATION => "policy-violation",
+	XMPP_SE_REMOTE_CONN_FAILED => "remote-connection-failed",
+	XMPP_SE_RESOURCE_CONSTRAINT => "resource-constraint",
+	XMPP_SE_RESTRICTED_XML => "restricted-xml",
+	XMPP_SE_SEE_OTHER_HOST => "see-other-host",
+	XMPP_SE_SYSTEM_SHUTDOWN => "system-shutdown",
+	XMPP_SE_UNDEFINED_CONDITION => "undefined-condition",
+	XMPP_SE_UNSUPPORTED_ENCODING => "unsupported-encoding",
+	XMPP_SE_UNSUPPORTED_STANZA_TYPE => "unsupported-stanza-type",
+	XMPP_SE_UNSUPPORTED_VERSION => "unsupported-version",
+	XMPP_SE_XML_NOT_WELL_FORMED => "not-well-formed" | "xml-not-well-formed",
+});
+
+// only defined by the headers of libstrophe 0.11+, see build.rs
+#[cfg(strophe_cert_element)]
+enum_names!(xmpp_cert_element_t {
+	XMPP_CERT_VERSION => "version",
+	XMPP_CERT_SERIALNUMBER => "serial-number" | "serialnumber",
+	XMPP_CERT_SUBJECT => "subject",
+	XMPP_CERT_ISSUER => "issuer",
+	XMPP_CERT_NOTBEFORE => "not-before" | "notbefore",
+	XMPP_CERT_NOTAFTER => "not-after" | "notafter",
+	XMPP_CERT_KEYALG => "key-algorithm" | "keyalg",
+	XMPP_CERT_SIGALG => "signature-algorithm" | "sigalg",
+	XMPP_CERT_FINGERPRINT_SHA1 => "fingerprint-sha1",
+	XMPP_CERT_FINGERPRINT_SHA256 => "fingerprint-sha256",
+	XMPP_CERT_ELEMENT_MAX => "element-max",
+});
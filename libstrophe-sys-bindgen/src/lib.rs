@@ -10,6 +10,21 @@
 //!
 //! Current bindings were generated from libstrophe version: 0.12.0
 //!
+//! The functions and types added in libstrophe 0.13 and 0.14 are not part of the pre-generated bindings yet,
+//! enable `buildtime_bindgen` to generate the bindings from the installed headers of those versions.
+//!
+//! # Crate features
+//!
+//!   * `buildtime_bindgen` - forces regeneration of the bindings instead of relying on the
+//!     pre-generated sources
+//!   * `core_ffi` - uses the bindings that refer only to `core::ffi` types and omit the functions taking
+//!     `va_list` (`xmpp_vsnprintf` and `xmpp_log`) together with the platform-specific `__va_list_tag`
+//!     struct. This allows building on targets where the `va_list` layout differs from the one of the
+//!     pre-generated bindings. Requires Rust 1.64.
+//!   * `serde` - implements `Serialize` and `Deserialize` for `xmpp_log_level_t`, `xmpp_error_type_t` and
+//!     `xmpp_cert_element_t` using the same names as their `Display` and `FromStr` implementations. The conversions of
+//!     `xmpp_cert_element_t` are only available if the bindings define it, i.e. libstrophe 0.11+ with `buildtime_bindgen`
+//!
 //! The difference from [libstrophe-sys] crate is that this one is automatically generated hence
 //! easier to maintain.
 //!
@@ -20,7 +35,15 @@
 //! [libstrophe-sys]: https://crates.io/crates/libstrophe-sys
 //! [libstrophe_crate]: https://crates.io/crates/libstrophe
 
+#[cfg(not(feature = "core_ffi"))]
 #[allow(non_upper_case_globals, non_camel_case_types, non_snake_case, dead_code, unused_imports)]
 mod ffi;
+#[cfg(feature = "core_ffi")]
+#[allow(non_upper_case_globals, non_camel_case_types, non_snake_case, dead_code, unused_imports)]
+#[path = "ffi_core.rs"]
+mod ffi;
 
+pub use crate::enums::ParseEnumError;
 pub use crate::ffi::*;
+
+mod enums;
@@ -0,0 +1,43 @@
+//! Base64 on top of the encoder and decoder of libstrophe, shared by the modules that need it outside of the stanza text
+
+#[cfg(any(feature = "xep-0388", test))]
+use std::ffi::CString;
+#[cfg(any(feature = "xep-0388", test))]
+use std::os::raw::c_uchar;
+#[cfg(any(feature = "xep-0388", test))]
+use std::{ptr, slice};
+
+use crate::{Error, Result, ALLOC_CONTEXT, FFI};
+
+/// [xmpp_base64_encode](https://github.com/strophe/libstrophe/blob/0.12.2/src/crypto.c)
+pub(crate) fn encode(data: &[u8]) -> Result<String> {
+	unsafe {
+		FFI(sys::xmpp_base64_encode(ALLOC_CONTEXT.as_ptr(), data.as_ptr(), data.len()))
+			.receive_with_free(|x| ALLOC_CONTEXT.free(x))
+			.ok_or(Error::MemoryError)
+	}
+}
+
+/// [xmpp_base64_decode_bin](https://github.com/strophe/libstrophe/blob/0.12.2/src/crypto.c), `None` if the `text` is not
+/// valid base64
+#[cfg(any(feature = "xep-0388", test))]
+pub(crate) fn decode(text: &str) -> Option<Vec<u8>> {
+	let text = CString::new(text).ok()?;
+	let mut out: *mut c_uchar = ptr::null_mut();
+	let mut out_len = 0;
+	unsafe {
+		sys::xmpp_base64_decode_bin(
+			ALLOC_CONTEXT.as_ptr(),
+			text.as_ptr(),
+			text.as_bytes().len(),
+			&mut out,
+			&mut out_len,
+		);
+		if out.is_null() {
+			return None;
+		}
+		let decoded = slice::from_raw_parts(out, out_len).to_vec();
+		ALLOC_CONTEXT.free(out);
+		Some(decoded)
+	}
+}
@@ -0,0 +1,35 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Cooperative cancellation flag for the multi-stanza operations, see [Connection::with_cancel_token]
+///
+/// The token is cheap to clone and can be cancelled from any thread, the operation is aborted by the thread running the
+/// event loop of the connection shortly after.
+///
+/// [Connection::with_cancel_token]: crate::Connection::with_cancel_token
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+	#[inline]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	#[inline]
+	/// Requests the cancellation of all operations started with this token, can't be undone
+	pub fn cancel(&self) {
+		self.0.store(true, Ordering::Release);
+	}
+
+	#[inline]
+	pub fn is_cancelled(&self) -> bool {
+		self.0.load(Ordering::Acquire)
+	}
+
+	#[inline]
+	/// Whether both tokens are the clones of the same token
+	pub(crate) fn same(&self, other: &CancelToken) -> bool {
+		Arc::ptr_eq(&self.0, &other.0)
+	}
+}
@@ -1,36 +1,60 @@
-#[cfg(feature = "libstrophe-0_11_0")]
-use std::any::TypeId;
+use std::any::{Any, TypeId};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::c_void;
 #[cfg(feature = "libstrophe-0_12_0")]
 use std::ffi::CString;
+#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+use std::net::IpAddr;
 #[cfg(feature = "libstrophe-0_12_0")]
 use std::os::raw::c_char;
-use std::os::raw::{c_int, c_ulong};
+use std::os::raw::{c_int, c_long, c_ulong};
 use std::ptr::NonNull;
 use std::rc::Rc;
-use std::time::Duration;
-use std::{fmt, mem, ptr, result, str};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, io, mem, ptr, result, str};
 
 #[cfg(feature = "libstrophe-0_11_0")]
 pub use internals::CertFailResult;
-pub use internals::HandlerResult;
-#[cfg(feature = "libstrophe-0_12_0")]
-pub use internals::SockoptResult;
+#[cfg(not(feature = "libstrophe-0_10_0"))]
+use internals::LinkState;
+use internals::{
+	ByteCounters, Cancellable, ConnectionFatHandler, EncryptionState, EventHistory, FatHandler, FatHandlers, FeatureWaiter,
+	Handlers, OutboundQueue, Redirect, ServerFeatures, StanzaFatHandler, StanzaFilter, TimedFatHandler,
+};
 #[cfg(feature = "libstrophe-0_11_0")]
-use internals::CERT_FAIL_HANDLERS;
-use internals::{ConnectionFatHandler, FatHandler, FatHandlers, Handlers, StanzaFatHandler, TimedFatHandler};
+use internals::{CertExpiryWarning, CERT_FAIL_HANDLERS};
+pub use internals::{
+	ConnectionId, HandlerChain, HandlerError, HandlerInfo, HandlerKind, HandlerResult, SendPriority, Throughput, TrafficLogPolicy,
+	Utf8Policy,
+};
+#[cfg(feature = "libstrophe-0_12_0")]
+pub use internals::{KeepaliveOpts, ResendPolicy, SockoptResult};
 #[cfg(feature = "libstrophe-0_12_0")]
 use internals::{PasswordFatHandler, SOCKOPT_HANDLERS};
 
+use crate::context::catch_panic;
+use crate::encryption::{self, EncryptionError, EncryptionPlugin};
 use crate::error::IntoResult;
 use crate::ffi_types::Nullable;
+use crate::logger::LogRoute;
+use crate::module::{self, ModuleId, XmppModule};
+use crate::proxy::{Proxy, ProxyTunnel};
+#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+use crate::socket::{
+	forget_socket, record_socket_sockopt_cb, socket_options_sockopt_cb, SocketOptions, CONNECTION_SOCKETS, SOCKET_OPTIONS,
+};
+use crate::xep::disco;
+#[cfg(feature = "presence")]
+use crate::xep::presence::{self, PresenceType};
+#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+use crate::SocketRef;
 #[cfg(feature = "libstrophe-0_11_0")]
 pub use crate::TlsCert;
 use crate::{
-	as_void_ptr, void_ptr_as, ConnectClientError, ConnectionError, ConnectionFlags, Context, Error, Result, Stanza, StreamError,
-	FFI,
+	as_void_ptr, uuid_gen, void_ptr_as_opt, CancelToken, ConnType, ConnectClientError, ConnectionError, ConnectionFlags, Context,
+	DedupCache, DispatchCtx, Error, Jid, JidMatcher, LogLevel, Logger, OwnedConnectionError, Result, SendValidationError,
+	SmMirror, Stanza, StreamError, TlsInfo, FFI,
 };
 #[cfg(feature = "libstrophe-0_12_0")]
 use crate::{QueueElement, SMState};
@@ -38,6 +62,14 @@ use crate::{QueueElement, SMState};
 #[macro_use]
 mod internals;
 
+/// Connection handler callback type the `cb_addr` of [ConnectionFatHandler] is transmuted back to
+type ConnectionHandlerFn =
+	unsafe extern "C" fn(*mut sys::xmpp_conn_t, sys::xmpp_conn_event_t, c_int, *mut sys::xmpp_stream_error_t, *mut c_void);
+/// Stanza and id handler callback type the `cb_addr` of [StanzaFatHandler] is transmuted back to
+type StanzaHandlerFn = unsafe extern "C" fn(*mut sys::xmpp_conn_t, *mut sys::xmpp_stanza_t, *mut c_void) -> c_int;
+/// Timed handler callback type the `cb_addr` of [TimedFatHandler] is transmuted back to
+type TimedHandlerFn = unsafe extern "C" fn(*mut sys::xmpp_conn_t, *mut c_void) -> c_int;
+
 /// Proxy to the underlying `xmpp_conn_t` struct.
 ///
 /// Most of the methods in this struct mimic the methods of the underlying library. So please see
@@ -68,11 +100,13 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 	#[inline]
 	/// [xmpp_conn_new](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga0bc7c0e07b52bb7470a97e8d9f9542be)
 	pub fn new(ctx: Context<'cx, 'cb>) -> Self {
-		unsafe {
+		#[allow(unused_mut)]
+		let mut out = unsafe {
 			Self::from_owned(
 				sys::xmpp_conn_new(ctx.as_ptr()),
 				ctx,
 				Rc::new(RefCell::new(FatHandlers {
+					id: ConnectionId::next(),
 					connection: None,
 					timed: Vec::with_capacity(4),
 					stanza: Vec::with_capacity(4),
@@ -82,9 +116,66 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 					sockopt_handler_id: None,
 					#[cfg(feature = "libstrophe-0_12_0")]
 					password: vec![],
+					id_generator: None,
+					stream_error: None,
+					on_connect: vec![],
+					on_disconnect: vec![],
+					handler_error_hook: None,
+					handler_budget: None,
+					slow_handler_hook: None,
+					redirect: Redirect::default(),
+					client_alt: (None, None),
+					stream_lang: None,
+					stream_version: None,
+					auto_resource: false,
+					strict_send: false,
+					conn_type: ConnType::XMPP_UNKNOWN,
+					utf8_policy: Utf8Policy::default(),
+					traffic_log_policy: TrafficLogPolicy::default(),
+					logger: None,
+					outbound_queue: None,
+					rx_tap: None,
+					sm_mirror: None,
+					byte_counters: None,
+					raw_observer: false,
+					send_interceptors: vec![],
+					cancel_scope: None,
+					cancellables: vec![],
+					iq_responses: HashMap::new(),
+					extensions: HashMap::new(),
+					modules: vec![],
+					handler_chain: None,
+					encryption: None,
+					#[cfg(feature = "libstrophe-0_11_0")]
+					cert_expiry_warning: None,
+					event_history: EventHistory::default(),
+					pending_pass: None,
+					#[cfg(not(feature = "libstrophe-0_10_0"))]
+					link_state: LinkState::Disconnected,
+					server_features: ServerFeatures::Unknown,
+					proxy: None,
+					proxy_tunnel: None,
+					tls_route: None,
+					#[cfg(feature = "libstrophe-0_12_0")]
+					resend_policy: None,
+					#[cfg(feature = "libstrophe-0_12_0")]
+					unacked: vec![],
 				})),
 			)
+		};
+		#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+		unsafe {
+			sys::xmpp_conn_set_sockopt_callback(out.inner.as_mut(), Some(record_socket_sockopt_cb))
 		}
+		out
+	}
+
+	/// Same as [`new()`](#method.new), but the messages emitted while servicing the connection go to `logger`, see
+	/// [`set_logger()`](#method.set_logger)
+	pub fn new_with_logger(ctx: Context<'cx, 'cb>, logger: Logger<'cb>) -> Self {
+		let mut out = Self::new(ctx);
+		out.set_logger(logger);
+		out
 	}
 
 	#[inline]
@@ -126,9 +217,13 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 	) where
 		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, ConnectionEvent) + Send + 'cb,
 	{
-		let connection_handler = void_ptr_as::<ConnectionFatHandler>(userdata);
+		let connection_handler = match void_ptr_as_opt::<ConnectionFatHandler>(userdata) {
+			Some(connection_handler) if !conn_ptr.is_null() => connection_handler,
+			_ => return,
+		};
 		if let Some(fat_handlers) = connection_handler.fat_handlers.upgrade() {
 			let mut conn = Self::from_ref_mut(conn_ptr, fat_handlers);
+			let _log_route = conn.log_route();
 			let event = match event {
 				sys::xmpp_conn_event_t::XMPP_CONN_RAW_CONNECT => ConnectionEvent::RawConnect,
 				sys::xmpp_conn_event_t::XMPP_CONN_CONNECT => ConnectionEvent::Connect,
@@ -136,8 +231,14 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 					let stream_error: Option<StreamError> = stream_error.as_ref().map(|e| e.into());
 					ConnectionEvent::Disconnect(ConnectionError::from((error, stream_error)))
 				}
-				sys::xmpp_conn_event_t::XMPP_CONN_FAIL => unreachable!("XMPP_CONN_FAIL is never used in the underlying library"),
+				// never used in the underlying library
+				sys::xmpp_conn_event_t::XMPP_CONN_FAIL => return,
 			};
+			#[cfg(not(feature = "libstrophe-0_10_0"))]
+			conn.set_link_state(match event {
+				ConnectionEvent::RawConnect | ConnectionEvent::Connect => LinkState::Connected,
+				ConnectionEvent::Disconnect(_) => LinkState::Disconnected,
+			});
 			ensure_unique!(
 				CB,
 				conn_ptr,
@@ -146,7 +247,46 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 				&mut conn,
 				ConnectionEvent::Connect
 			);
-			(connection_handler.handler)(conn.context_detached(), &mut conn, event);
+			{
+				let mut fat_handlers = conn.fat_handlers.borrow_mut();
+				let id = fat_handlers.id;
+				fat_handlers.event_history.push(id, &event);
+			}
+			if let ConnectionEvent::Disconnect(_) = event {
+				#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+				forget_socket(conn_ptr);
+				conn.server_features_lost();
+				conn.report_proxy_error();
+				if let Some(sm_mirror) = &mut conn.fat_handlers.borrow_mut().sm_mirror {
+					sm_mirror.enabled = false;
+				}
+				let pending_pass = conn.fat_handlers.borrow_mut().pending_pass.take();
+				if let Some(pass) = pending_pass {
+					conn.set_pending_pass(pass);
+				}
+			}
+			#[cfg(feature = "libstrophe-0_12_0")]
+			conn.handle_unacked(&event);
+			match &event {
+				ConnectionEvent::Connect => {
+					conn.fat_handlers.borrow_mut().redirect.hops = 0;
+					#[cfg(feature = "libstrophe-0_11_0")]
+					conn.check_cert_expiry();
+				}
+				ConnectionEvent::Disconnect(Some(ConnectionError::Stream(stream_error))) => {
+					conn.fire_stream_error_handler(stream_error);
+					if conn.follow_see_other_host(stream_error, Self::connection_handler_cb::<CB>, userdata) {
+						return;
+					}
+				}
+				_ => {}
+			}
+			let ctx = conn.context_detached();
+			catch_panic(ctx, (), || {
+				conn.notify_modules(ctx, &event);
+				conn.fire_event_callbacks(ctx, &event);
+				(connection_handler.handler)(ctx, &mut conn, event)
+			});
 		}
 	}
 
@@ -154,11 +294,27 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 	where
 		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>) -> HandlerResult + Send + 'cb,
 	{
-		let timed_handler = void_ptr_as::<TimedFatHandler>(userdata);
+		let timed_handler = match void_ptr_as_opt::<TimedFatHandler>(userdata) {
+			Some(timed_handler) if !conn_ptr.is_null() => timed_handler,
+			_ => return HandlerResult::RemoveHandler as c_int,
+		};
 		if let Some(fat_handlers) = timed_handler.fat_handlers.upgrade() {
 			let mut conn = Self::from_ref_mut(conn_ptr, fat_handlers);
+			let _log_route = conn.log_route();
 			ensure_unique!(CB, conn_ptr, userdata, conn.context_detached(), &mut conn);
-			let res = (timed_handler.handler)(conn.context_detached(), &mut conn);
+			let timing = conn
+				.fat_handlers
+				.borrow()
+				.handler_budget
+				.map(|budget| (budget, timed_handler.extra, Instant::now()));
+			let ctx = conn.context_detached();
+			let res = catch_panic(ctx, HandlerResult::KeepHandler, || (timed_handler.handler)(ctx, &mut conn));
+			if let Some((budget, period, start)) = timing {
+				let elapsed = start.elapsed();
+				if elapsed > budget {
+					conn.fire_slow_handler(&HandlerInfo::Timed { period }, elapsed);
+				}
+			}
 			if matches!(res, HandlerResult::RemoveHandler) {
 				Self::drop_fat_handler(&mut conn.fat_handlers.borrow_mut().timed, timed_handler);
 			}
@@ -176,12 +332,78 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 	where
 		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb,
 	{
-		let stanza_handler = void_ptr_as::<StanzaFatHandler>(userdata);
+		let stanza_handler = match void_ptr_as_opt::<StanzaFatHandler>(userdata) {
+			Some(stanza_handler) if !conn_ptr.is_null() && !stanza.is_null() => stanza_handler,
+			_ => return HandlerResult::RemoveHandler as c_int,
+		};
 		if let Some(fat_handlers) = stanza_handler.fat_handlers.upgrade() {
 			let mut conn = Self::from_ref_mut(conn_ptr, fat_handlers);
-			let stanza = Stanza::from_ref(stanza);
-			ensure_unique!(CB, conn_ptr, userdata, conn.context_detached(), &mut conn, &stanza);
-			let res = (stanza_handler.handler)(conn.context_detached(), &mut conn, &stanza);
+			let _log_route = conn.log_route();
+			let original = Stanza::from_ref(stanza);
+			let stanza = &original;
+			ensure_unique!(CB, conn_ptr, userdata, conn.context_detached(), &mut conn, stanza);
+			let utf8_policy = conn.fat_handlers.borrow().utf8_policy;
+			let lossy_stanza;
+			let stanza = match utf8_policy {
+				Utf8Policy::Strict => stanza,
+				_ if stanza.is_valid_utf8() => stanza,
+				Utf8Policy::Lossy => match stanza.to_lossy_utf8() {
+					Ok(stanza) => {
+						lossy_stanza = stanza;
+						&lossy_stanza
+					}
+					Err(_) => return HandlerResult::KeepHandler as c_int,
+				},
+				Utf8Policy::Reject => {
+					conn.context_detached().log_warn(
+						"conn",
+						&format!("{}: Dropping the stanza containing invalid UTF-8", conn.id()),
+					);
+					return HandlerResult::KeepHandler as c_int;
+				}
+			};
+			let decrypted_stanza;
+			// the stanza accessors panic on invalid UTF-8 with Utf8Policy::Strict
+			let decrypted = catch_panic(conn.context_detached(), None, || conn.decrypt_incoming(&original, stanza));
+			let stanza = match decrypted {
+				Some(decrypted) => {
+					decrypted_stanza = decrypted;
+					&decrypted_stanza
+				}
+				None => stanza,
+			};
+			let cancel_token = match &stanza_handler.extra {
+				StanzaFilter::Id(id) => conn.cancel_token_for(id),
+				StanzaFilter::Stanza { .. } => None,
+			};
+			if cancel_token.as_ref().map_or(false, CancelToken::is_cancelled) {
+				// the handler is going to be removed by the cancellation poll together with the rest of the operation
+				return HandlerResult::KeepHandler as c_int;
+			}
+			let token = conn.token();
+			// the filter is copied because the handler can remove itself
+			let timing = conn
+				.fat_handlers
+				.borrow()
+				.handler_budget
+				.map(|budget| (budget, stanza_handler.extra.clone(), Instant::now()));
+			DISPATCHED_STANZAS.with(|dispatched| dispatched.borrow_mut().push((stanza.as_ptr() as usize, token)));
+			let ctx = conn.context_detached();
+			// the requests sent from the response handler continue the operation it belongs to
+			let prev_scope = cancel_token.map(|token| conn.fat_handlers.borrow_mut().cancel_scope.replace(token));
+			let res = catch_panic(ctx, HandlerResult::KeepHandler, || {
+				(stanza_handler.handler)(ctx, &mut conn, stanza)
+			});
+			if let Some(prev_scope) = prev_scope {
+				conn.fat_handlers.borrow_mut().cancel_scope = prev_scope;
+			}
+			DISPATCHED_STANZAS.with(|dispatched| dispatched.borrow_mut().pop());
+			if let Some((budget, filter, start)) = timing {
+				let elapsed = start.elapsed();
+				if elapsed > budget {
+					conn.fire_slow_handler(&filter.info(), elapsed);
+				}
+			}
 			if matches!(res, HandlerResult::RemoveHandler) {
 				Self::drop_fat_handler(&mut conn.fat_handlers.borrow_mut().stanza, stanza_handler);
 			}
@@ -191,6 +413,48 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 		}
 	}
 
+	/// Catch-all handler feeding the traffic log, the rx tap, the SM mirror and the byte counters, see
+	/// [`sync_raw_observer()`](#method.sync_raw_observer)
+	unsafe extern "C" fn raw_observer_cb(
+		conn_ptr: *mut sys::xmpp_conn_t,
+		stanza: *mut sys::xmpp_stanza_t,
+		userdata: *mut c_void,
+	) -> c_int {
+		let fat_handlers = match void_ptr_as_opt::<RefCell<FatHandlers>>(userdata) {
+			Some(fat_handlers) if !conn_ptr.is_null() && !stanza.is_null() => fat_handlers,
+			_ => return HandlerResult::RemoveHandler as c_int,
+		};
+		let ctx = Context::from_ref_mut(sys::xmpp_conn_get_context(conn_ptr));
+		let stanza = Stanza::from_ref(stanza);
+		let log_traffic = !matches!(fat_handlers.borrow().traffic_log_policy, TrafficLogPolicy::Off);
+		let count_bytes = fat_handlers.borrow().byte_counters.is_some();
+		// the tap is taken out for the duration of the call so that it can replace itself
+		let mut tap = fat_handlers.borrow_mut().rx_tap.take();
+		if log_traffic || count_bytes || tap.is_some() {
+			let mut buf = vec![];
+			if stanza.write_to(&mut buf).is_ok() {
+				if let Some(byte_counters) = &mut fat_handlers.borrow_mut().byte_counters {
+					byte_counters.received += buf.len() as u64;
+				}
+				if log_traffic {
+					catch_panic(&ctx, (), || Self::log_traffic(conn_ptr, fat_handlers, "RECV", &buf));
+				}
+				if let Some(tap) = &mut tap {
+					catch_panic(&ctx, (), || tap(&buf));
+				}
+			}
+		}
+		if let Some(tap) = tap {
+			fat_handlers.borrow_mut().rx_tap.get_or_insert(tap);
+		}
+		let mut fat_handlers = fat_handlers.borrow_mut();
+		if let Some(sm_mirror) = &mut fat_handlers.sm_mirror {
+			// the stanza accessors panic on invalid UTF-8 with Utf8Policy::Strict
+			catch_panic(&ctx, (), || sm_mirror.observe_incoming(&stanza));
+		}
+		HandlerResult::KeepHandler as c_int
+	}
+
 	#[cfg(feature = "libstrophe-0_12_0")]
 	unsafe extern "C" fn password_handler_cb<CB>(
 		pw: *mut c_char,
@@ -201,9 +465,13 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 	where
 		CB: FnMut(&Connection<'cb, 'cx>, usize) -> Option<String> + Send + 'cb,
 	{
-		let password_handler = void_ptr_as::<PasswordFatHandler>(userdata);
+		let password_handler = match void_ptr_as_opt::<PasswordFatHandler>(userdata) {
+			Some(password_handler) if !conn_ptr.is_null() => password_handler,
+			_ => return 0,
+		};
 		if let Some(fat_handlers) = password_handler.fat_handlers.upgrade() {
 			let conn = Self::from_ref_mut(conn_ptr, fat_handlers);
+			let _log_route = conn.log_route();
 			// we need to leave place for the null byte that will be written by libstrophe
 			let max_password_len = if pw_max < 1 {
 				0
@@ -211,7 +479,9 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 				pw_max - 1
 			};
 			ensure_unique!(CB, conn_ptr, userdata, &conn, max_password_len);
-			let result = (password_handler.handler)(&conn, max_password_len);
+			let result = catch_panic(conn.context_detached(), None, || {
+				(password_handler.handler)(&conn, max_password_len)
+			});
 			if let Some(password) = result {
 				if let Ok(password) = CString::new(password) {
 					if password.as_bytes().len() <= max_password_len {
@@ -289,8 +559,19 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 
 	#[inline]
 	/// [xmpp_conn_get_flags](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga8acc2ae11389af17229b41b4c39ed16e)
+	///
+	/// Flag bits unknown to this crate (e.g. set by a newer libstrophe) are dropped, use
+	/// [`flags_raw()`](#method.flags_raw) to get them.
 	pub fn flags(&self) -> ConnectionFlags {
-		ConnectionFlags::from_bits(unsafe { sys::xmpp_conn_get_flags(self.inner.as_ptr()) }).unwrap()
+		ConnectionFlags::from_bits_truncate(self.flags_raw())
+	}
+
+	#[inline]
+	/// [xmpp_conn_get_flags](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga8acc2ae11389af17229b41b4c39ed16e)
+	///
+	/// Returns the flags as reported by the library including the bits unknown to this crate.
+	pub fn flags_raw(&self) -> c_long {
+		unsafe { sys::xmpp_conn_get_flags(self.inner.as_ptr()) }
 	}
 
 	#[inline]
@@ -299,6 +580,58 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 		unsafe { sys::xmpp_conn_set_flags(self.inner.as_mut(), flags.bits()) }.into_result()
 	}
 
+	#[inline]
+	/// Sets the `flags` in addition to the already set ones, see [`set_flags()`](#method.set_flags)
+	pub fn add_flags(&mut self, flags: ConnectionFlags) -> Result<()> {
+		let raw = self.flags_raw() | flags.bits();
+		unsafe { sys::xmpp_conn_set_flags(self.inner.as_mut(), raw) }.into_result()
+	}
+
+	#[inline]
+	/// Clears the `flags` keeping the rest of the already set ones, see [`set_flags()`](#method.set_flags)
+	pub fn remove_flags(&mut self, flags: ConnectionFlags) -> Result<()> {
+		let raw = self.flags_raw() & !flags.bits();
+		unsafe { sys::xmpp_conn_set_flags(self.inner.as_mut(), raw) }.into_result()
+	}
+
+	#[inline]
+	/// Checks whether [ConnectionFlags::DISABLE_TLS] is set
+	pub fn tls_disabled(&self) -> bool {
+		self.flags().contains(ConnectionFlags::DISABLE_TLS)
+	}
+
+	#[inline]
+	/// Checks whether [ConnectionFlags::MANDATORY_TLS] is set
+	pub fn tls_mandatory(&self) -> bool {
+		self.flags().contains(ConnectionFlags::MANDATORY_TLS)
+	}
+
+	#[inline]
+	/// Checks whether [ConnectionFlags::TRUST_TLS] is set
+	pub fn tls_trusted(&self) -> bool {
+		self.flags().contains(ConnectionFlags::TRUST_TLS)
+	}
+
+	#[inline]
+	/// Checks whether [ConnectionFlags::LEGACY_SSL] is set
+	pub fn legacy_ssl(&self) -> bool {
+		self.flags().contains(ConnectionFlags::LEGACY_SSL)
+	}
+
+	#[cfg(feature = "libstrophe-0_9_3")]
+	#[inline]
+	/// Checks whether [ConnectionFlags::LEGACY_AUTH] is set
+	pub fn legacy_auth(&self) -> bool {
+		self.flags().contains(ConnectionFlags::LEGACY_AUTH)
+	}
+
+	#[cfg(feature = "libstrophe-0_12_0")]
+	#[inline]
+	/// Checks whether [ConnectionFlags::DISABLE_SM] is set
+	pub fn sm_disabled(&self) -> bool {
+		self.flags().contains(ConnectionFlags::DISABLE_SM)
+	}
+
 	#[inline]
 	/// [xmpp_conn_get_jid](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga37a4edf0ec15c78e570165eb65a3cbad)
 	pub fn jid(&self) -> Option<&str> {
@@ -311,11 +644,147 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 		unsafe { FFI(sys::xmpp_conn_get_bound_jid(self.inner.as_ptr())).receive() }
 	}
 
+	/// Resource part of the JID of this connection
+	///
+	/// Returns the resource assigned by the server during the resource binding if the connection is established, otherwise
+	/// the resource of the configured JID.
+	pub fn resource(&self) -> Option<&str> {
+		self
+			.bound_jid()
+			.or_else(|| self.jid())
+			.and_then(|jid| jid.split_once('/'))
+			.map(|(_, resource)| resource)
+	}
+
+	#[inline]
+	/// Sets the handling of the incoming stanzas containing invalid UTF-8, [Utf8Policy::Strict] by default
+	///
+	/// The policy applies to the stanza handlers, the check walks the whole stanza so it's only done for the non-default
+	/// policies.
+	pub fn set_utf8_policy(&mut self, policy: Utf8Policy) {
+		self.fat_handlers.borrow_mut().utf8_policy = policy;
+	}
+
+	#[inline]
+	pub fn utf8_policy(&self) -> Utf8Policy {
+		self.fat_handlers.borrow().utf8_policy
+	}
+
+	/// Sets the logging of the data sent with [`send_raw()`](#method.send_raw) and of the received stanzas,
+	/// [TrafficLogPolicy::Off] by default
+	///
+	/// The raw data can contain the authentication payloads so only enable [TrafficLogPolicy::Full] for debugging. The
+	/// messages are logged with the debug level to the `conn` area. The received stanzas are taken from the same path as
+	/// the data passed to the [rx tap](#method.set_rx_tap), so they are only logged after the authentication is complete.
+	pub fn set_traffic_log_policy(&mut self, policy: TrafficLogPolicy<'cb>) {
+		self.fat_handlers.borrow_mut().traffic_log_policy = policy;
+		self.sync_raw_observer();
+	}
+
+	fn log_raw_traffic(&self, data: &[u8]) {
+		unsafe { Self::log_traffic(self.inner.as_ptr(), &self.fat_handlers, "SENT", data) }
+	}
+
+	unsafe fn log_traffic(conn_ptr: *mut sys::xmpp_conn_t, fat_handlers: &RefCell<FatHandlers>, direction: &str, data: &[u8]) {
+		let (msg, id, logger) = {
+			let fat_handlers = fat_handlers.borrow();
+			let msg = match &fat_handlers.traffic_log_policy {
+				TrafficLogPolicy::Off => return,
+				TrafficLogPolicy::Metadata => format!("{} bytes", data.len()),
+				TrafficLogPolicy::Full => match str::from_utf8(data) {
+					Ok(data) => data.to_owned(),
+					Err(_) => format!("{:?}", data),
+				},
+				TrafficLogPolicy::Redacted(cb) => cb(data),
+			};
+			(msg, fat_handlers.id, fat_handlers.logger.clone())
+		};
+		let ctx = sys::xmpp_conn_get_context(conn_ptr);
+		let _log_route = logger.map(LogRoute::new);
+		crate::context::ctx_log(
+			ctx,
+			LogLevel::XMPP_LEVEL_DEBUG,
+			"conn",
+			&format!("{}: {}: {}", id, direction, msg),
+		)
+	}
+
+	/// Sets the logger for the messages emitted while servicing this connection
+	///
+	/// The messages that would go to the logger of the [Context] are sent to `logger` instead while the handlers of this
+	/// connection run. That includes the messages logged with [Context::log] from the handlers and the ones libstrophe emits
+	/// while processing the stanzas and the events of this connection. When the context services only this connection
+	/// (e.g. after [`connect_client()`](#method.connect_client)) the whole event loop is routed to `logger`, including the
+	/// traffic log of libstrophe. With several connections in one context the messages libstrophe emits while doing the I/O
+	/// can't be attributed to a connection and go to the context logger, use a context per connection for the complete
+	/// isolation. The routing only works for the contexts created with [Context::new].
+	pub fn set_logger(&mut self, logger: Logger<'cb>) {
+		self.fat_handlers.borrow_mut().logger = Some(Rc::new(logger));
+	}
+
+	/// Removes the logger set with [`set_logger()`](#method.set_logger), the messages go to the context logger again
+	pub fn clear_logger(&mut self) {
+		self.fat_handlers.borrow_mut().logger = None;
+	}
+
+	#[inline]
+	/// Whether the connection has its own logger set with [`set_logger()`](#method.set_logger)
+	pub fn has_logger(&self) -> bool {
+		self.fat_handlers.borrow().logger.is_some()
+	}
+
+	/// Logs `msg` in the `conn` area prefixed with the [id](Connection::id) of the connection
+	fn log(&self, level: LogLevel, msg: &str) {
+		unsafe { self.context_detached() }.log(level, "conn", &format!("{}: {}", self.id(), msg));
+	}
+
+	pub(crate) fn log_route(&self) -> Option<LogRoute<'cb>> {
+		self.fat_handlers.borrow().logger.clone().map(LogRoute::new)
+	}
+
+	/// Enables generation of a random resource for the configured JID if it doesn't contain one
+	///
+	/// The resource is appended to the JID locally by [`try_connect_client()`](#method.try_connect_client) and
+	/// [`connect_client()`](#method.connect_client) instead of relying on the server to assign one. Either way the resource
+	/// that's actually used is available via [`resource()`](#method.resource) after the connection is established.
+	pub fn set_auto_resource(&mut self, enabled: bool) {
+		self.fat_handlers.borrow_mut().auto_resource = enabled;
+	}
+
+	#[inline]
+	/// Process-wide unique id of the connection for correlating the log lines, e.g. `conn#7`
+	///
+	/// The id is assigned when the connection is created and is shared by all the references to it passed to the handlers.
+	pub fn id(&self) -> ConnectionId {
+		self.fat_handlers.borrow().id
+	}
+
+	/// Recent connection events with their timestamps, oldest first
+	///
+	/// Up to 16 last events are kept by default, see [`set_event_history_capacity()`](#method.set_event_history_capacity).
+	/// The events are recorded before the connection handler is called, including the disconnects that are handled
+	/// internally by the `see-other-host` redirection.
+	pub fn event_history(&self) -> Vec<ConnectionEventRecord> {
+		self.fat_handlers.borrow().event_history.events.iter().cloned().collect()
+	}
+
+	/// Sets the maximum number of events kept in the [`event_history()`](#method.event_history), 0 disables the recording
+	pub fn set_event_history_capacity(&mut self, capacity: usize) {
+		self.fat_handlers.borrow_mut().event_history.set_capacity(capacity);
+	}
+
 	#[inline]
 	/// [xmpp_conn_set_jid](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#gab78bfef71b5c04ba1086da20f79ca61f)
-	pub fn set_jid(&mut self, jid: impl AsRef<str>) {
-		let jid = FFI(jid.as_ref()).send();
-		unsafe { sys::xmpp_conn_set_jid(self.inner.as_mut(), jid.as_ptr()) }
+	///
+	/// Validates the JID syntax, see [`Jid`]. Returns [`Error::InvalidOperation`] if the JID is malformed or the connection is
+	/// not disconnected, libstrophe would silently ignore the new value in the latter case.
+	pub fn set_jid(&mut self, jid: impl AsRef<str>) -> Result<()> {
+		let jid = jid.as_ref();
+		jid.parse::<Jid>()?;
+		self.ensure_disconnected()?;
+		let jid = FFI(jid).send();
+		unsafe { sys::xmpp_conn_set_jid(self.inner.as_mut(), jid.as_ptr()) };
+		Ok(())
 	}
 
 	#[inline]
@@ -326,9 +795,54 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 
 	#[inline]
 	/// [xmpp_conn_set_pass](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#gac5069924deadf5f2e38db01e6e960979)
-	pub fn set_pass(&mut self, pass: impl AsRef<str>) {
+	///
+	/// Returns [`Error::InvalidOperation`] if the connection is not disconnected.
+	pub fn set_pass(&mut self, pass: impl AsRef<str>) -> Result<()> {
+		self.ensure_disconnected()?;
 		let pass = FFI(pass.as_ref()).send();
-		unsafe { sys::xmpp_conn_set_pass(self.inner.as_mut(), pass.as_ptr()) }
+		unsafe { sys::xmpp_conn_set_pass(self.inner.as_mut(), pass.as_ptr()) };
+		Ok(())
+	}
+
+	/// Sets the password right away if the connection is disconnected, otherwise postpones it until the disconnect
+	pub(crate) fn set_pending_pass(&mut self, pass: String) {
+		if self.ensure_disconnected().is_ok() {
+			let pass = FFI(pass.as_str()).send();
+			unsafe { sys::xmpp_conn_set_pass(self.inner.as_mut(), pass.as_ptr()) };
+		} else {
+			self.fat_handlers.borrow_mut().pending_pass = Some(pass);
+		}
+	}
+
+	fn ensure_disconnected(&self) -> Result<()> {
+		#[cfg(feature = "libstrophe-0_10_0")]
+		let disconnected = self.is_disconnected();
+		#[cfg(not(feature = "libstrophe-0_10_0"))]
+		let disconnected = self.fat_handlers.borrow().link_state == LinkState::Disconnected;
+		if disconnected {
+			Ok(())
+		} else {
+			Err(Error::InvalidOperation)
+		}
+	}
+
+	/// Checks whether the connection is connected, falls back to the state tracked by the connection handler for the
+	/// libstrophe versions without `xmpp_conn_is_connected`
+	#[cfg(feature = "presence")]
+	fn is_established(&self) -> bool {
+		#[cfg(feature = "libstrophe-0_10_0")]
+		{
+			self.is_connected()
+		}
+		#[cfg(not(feature = "libstrophe-0_10_0"))]
+		{
+			self.fat_handlers.borrow().link_state == LinkState::Connected
+		}
+	}
+
+	#[cfg(not(feature = "libstrophe-0_10_0"))]
+	fn set_link_state(&self, state: LinkState) {
+		self.fat_handlers.borrow_mut().link_state = state;
 	}
 
 	#[inline]
@@ -344,8 +858,84 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 		unsafe { FFI(sys::xmpp_conn_is_secured(self.inner.as_ptr())).receive_bool() }
 	}
 
+	/// Makes [`try_connect_client()`](#method.try_connect_client) and [`connect_client()`](#method.connect_client) connect
+	/// through the `proxy`
+	///
+	/// A local [ProxyTunnel] is started for every connection attempt and the connection is made to it instead. Because the
+	/// tunnel needs a fixed target, the SRV lookup is not performed: the `alt_host` or, if it's not set, the JID domain is used
+	/// along with `alt_port` or the default port. If the proxy refuses the tunnel, the reason is logged as an error before the
+	/// connection handler receives the [ConnectionEvent::Disconnect]. Pass `None` to connect directly.
+	pub fn set_proxy(&mut self, proxy: Option<Proxy>) {
+		self.fat_handlers.borrow_mut().proxy = proxy;
+	}
+
+	/// Host name that's sent in SNI and that the server certificate is validated against
+	///
+	/// libstrophe always uses the domain of the connection JID, independent of the `alt_host` passed to
+	/// [`connect_client()`](#method.connect_client), see [`set_tls_hostname()`](#method.set_tls_hostname). Returns `None`
+	/// if the JID is not set.
+	pub fn tls_hostname(&self) -> Option<String> {
+		self
+			.jid()
+			.and_then(|jid| jid.parse::<Jid>().ok())
+			.map(|jid| jid.domain().to_owned())
+	}
+
+	/// Sets the host name that's sent in SNI and that the server certificate is validated against
+	///
+	/// libstrophe has no setter for it and always uses the JID domain, so it's emulated: the domain of the JID is replaced
+	/// with `host` and the previous domain becomes the `alt_host` of the following
+	/// [`connect_client()`](#method.connect_client) and [`reconnect()`](#method.reconnect) calls that don't pass one, so the
+	/// connection still goes to the same server. Because the JID is also the identity the account authenticates with, it's
+	/// only useful when `host` is the XMPP domain of the account, e.g. when the JID was configured with the IP address or a
+	/// CNAME of the server. Returns [Error::InvalidOperation] if the JID is not set or `host` is not a valid domain.
+	pub fn set_tls_hostname(&mut self, host: &str) -> Result<()> {
+		let jid = self
+			.jid()
+			.and_then(|jid| jid.parse::<Jid>().ok())
+			.ok_or(Error::InvalidOperation)?;
+		if jid.domain() == host {
+			return Ok(());
+		}
+		let new_jid = Jid::new(jid.node(), host, jid.resource())?;
+		self.set_jid(new_jid.as_str())?;
+		// the original server is kept if the host name is changed again
+		self
+			.fat_handlers
+			.borrow_mut()
+			.tls_route
+			.get_or_insert_with(|| jid.domain().to_owned());
+		Ok(())
+	}
+
+	/// Information about the TLS session of the connection, `None` if the connection is not secured
+	///
+	/// The negotiated protocol version and cipher suite are not included, the TLS backends of libstrophe keep them private
+	/// and the socket passed to the sockopt callback only carries the encrypted data.
+	pub fn tls_info(&self) -> Option<TlsInfo> {
+		if !self.is_secured() {
+			return None;
+		}
+		Some(TlsInfo {
+			direct_tls: self.legacy_ssl(),
+			trusted: self.tls_trusted(),
+			#[cfg(feature = "libstrophe-0_11_0")]
+			peer_cert: self.peer_cert(),
+		})
+	}
+
+	#[inline]
+	/// Whether the TLS session of the connection was resumed instead of doing the full handshake, always `None`
+	///
+	/// libstrophe neither exposes the session cache of its TLS backends nor reports whether the session was resumed, so
+	/// the resumption can't be detected or configured. The method is there so that the callers can handle the `Some`
+	/// answer once upstream provides that information.
+	pub fn tls_session_reused(&self) -> Option<bool> {
+		None
+	}
+
 	#[inline]
-	#[cfg_attr(feature = "libstrophe-0_12_0", deprecated(note = "replaced by set_sockopt_callback()"))]
+	#[cfg_attr(feature = "libstrophe-0_12_0", deprecated(note = "replaced by set_keepalive_opts()"))]
 	/// [xmpp_conn_set_keepalive](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga044f1e5d519bff84066317cf8b9fe607)
 	pub fn set_keepalive(&mut self, timeout: Duration, interval: Duration) {
 		unsafe { sys::xmpp_conn_set_keepalive(self.inner.as_mut(), timeout.as_secs() as _, interval.as_secs() as _) }
@@ -422,6 +1012,53 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 		}
 	}
 
+	#[cfg(feature = "libstrophe-0_11_0")]
+	/// Enables the check of the server certificate expiration
+	///
+	/// After the connection is established the `notAfter` time of the [peer certificate](#method.peer_cert) is compared with
+	/// the current time and `callback` is called with the certificate and the time left until it expires (zero if it has
+	/// already expired) when it's less than `window`. The check is skipped for the unsecured connections and when the TLS
+	/// backend doesn't report the expiration time.
+	pub fn set_cert_expiry_warning(&mut self, window: Duration, callback: impl FnMut(&TlsCert, Duration) + Send + 'cb) {
+		self.fat_handlers.borrow_mut().cert_expiry_warning = Some(CertExpiryWarning {
+			window,
+			callback: Box::new(callback),
+		});
+	}
+
+	#[cfg(feature = "libstrophe-0_11_0")]
+	/// Disables the check enabled by [`set_cert_expiry_warning()`](#method.set_cert_expiry_warning)
+	pub fn clear_cert_expiry_warning(&mut self) {
+		self.fat_handlers.borrow_mut().cert_expiry_warning = None;
+	}
+
+	#[cfg(feature = "libstrophe-0_11_0")]
+	fn check_cert_expiry(&mut self) {
+		let mut warning = match self.fat_handlers.borrow_mut().cert_expiry_warning.take() {
+			Some(warning) => warning,
+			None => return,
+		};
+		if let Some(cert) = self.peer_cert() {
+			match cert.not_after() {
+				Some(Ok(not_after)) => {
+					let left = not_after.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO);
+					if left < warning.window {
+						catch_panic(unsafe { self.context_detached() }, (), || (warning.callback)(&cert, left));
+					}
+				}
+				Some(Err(_)) => self.log(
+					LogLevel::XMPP_LEVEL_WARN,
+					&format!(
+						"Cannot parse certificate expiration time: {}",
+						cert.get_string(crate::CertElement::XMPP_CERT_NOTAFTER).unwrap_or_default()
+					),
+				),
+				None => {}
+			}
+		}
+		self.fat_handlers.borrow_mut().cert_expiry_warning.get_or_insert(warning);
+	}
+
 	#[cfg(feature = "libstrophe-0_11_0")]
 	#[inline]
 	/// [xmpp_conn_set_client_cert](https://strophe.im/libstrophe/doc/0.12.2/group___t_l_s.html#gac3d770588b083d2053a6361c9e49f235)
@@ -493,15 +1130,104 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 		unsafe { sys::xmpp_conn_set_sockopt_callback(self.inner.as_mut(), Some(callback)) }
 	}
 
+	#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+	/// Same as [`set_sockopt_callback()`](#method.set_sockopt_callback), but the callback receives a typed [SocketRef]
+	///
+	/// Returning an error from the callback fails the connection attempt.
+	pub fn set_socket_callback<CB>(&mut self, handler: CB)
+	where
+		CB: Fn(&SocketRef) -> std::io::Result<()> + Send + Sync + 'static,
+	{
+		self.set_sockopt_callback(move |sock| {
+			let sock = unsafe { SocketRef::from_raw(sock) };
+			match handler(&sock) {
+				Ok(()) => SockoptResult::Ok,
+				Err(_) => SockoptResult::Error,
+			}
+		})
+	}
+
+	#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+	/// Binds the outgoing connection socket to the local `address` before connecting
+	///
+	/// Useful for the multi-homed hosts. Installs an internal sockopt callback which also applies the keepalive settings
+	/// (see [`set_keepalive_opts()`](#method.set_keepalive_opts)), so it replaces the callback set with
+	/// [`set_sockopt_callback()`](#method.set_sockopt_callback). The address is kept when the keepalive settings are
+	/// changed later on any unix platform.
+	/// The address family must match the one of the server address, otherwise the connection attempt fails. Pass `None` to
+	/// stop binding.
+	pub fn set_local_address(&mut self, address: Option<IpAddr>) {
+		self.update_socket_options(|options| options.address = address);
+	}
+
+	#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "libstrophe-0_12_0"))]
+	/// Binds the outgoing connection socket to the network `interface` (e.g. a VPN tunnel) before connecting
+	///
+	/// Uses `SO_BINDTODEVICE` which usually requires the `CAP_NET_RAW` capability. See
+	/// [`set_local_address()`](#method.set_local_address) for the notes on the sockopt callback.
+	pub fn set_bind_interface(&mut self, interface: Option<&str>) {
+		self.update_socket_options(|options| options.interface = interface.map(str::to_owned));
+	}
+
+	#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+	fn update_socket_options(&mut self, f: impl FnOnce(&mut SocketOptions)) {
+		if let Ok(mut options) = SOCKET_OPTIONS.write() {
+			f(options.entry(self.inner.as_ptr() as usize).or_default());
+		}
+		unsafe { sys::xmpp_conn_set_sockopt_callback(self.inner.as_mut(), Some(socket_options_sockopt_cb)) }
+	}
+
 	#[cfg(feature = "libstrophe-0_12_0")]
 	#[inline]
 	/// [xmpp_sockopt_cb_keepalive](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga044f1e5d519bff84066317cf8b9fe607)
 	///
-	/// Sets default sockopt_callback function that just uses compile-time internal defaults for the socket timeout. Those
-	/// values can be changed with a deprecated [Connection::set_keepalive]. If you use that function then you don't need to call
-	/// [Connection::set_default_sockopt_callback] manually because it will be called internally.
+	/// Sets default sockopt_callback function that just uses compile-time internal defaults for the socket timeout. To
+	/// change those values use [Connection::set_keepalive_opts] instead, it installs the callback itself.
 	pub fn set_default_sockopt_callback(&mut self) {
-		unsafe { sys::xmpp_conn_set_sockopt_callback(self.inner.as_mut(), Some(sys::xmpp_sockopt_cb_keepalive)) }
+		#[cfg(unix)]
+		// wraps xmpp_sockopt_cb_keepalive to keep track of the socket, see socket_fd()
+		self.update_socket_options(|_| {});
+		#[cfg(not(unix))]
+		unsafe {
+			sys::xmpp_conn_set_sockopt_callback(self.inner.as_mut(), Some(sys::xmpp_sockopt_cb_keepalive))
+		}
+	}
+
+	#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+	/// Raw descriptor of the connection socket
+	///
+	/// Allows driving the event loop from a readiness based reactor: register the descriptor for reading and call
+	/// [Context::run_once_nonblocking] whenever it's readable. The socket is recorded by the sockopt callback when the
+	/// connection is being established, so it's `None` before connecting and after the disconnect. The callbacks set with
+	/// [`set_sockopt_callback()`](#method.set_sockopt_callback) and the other socket setters keep recording it. The
+	/// descriptor is owned by libstrophe and must not be closed.
+	pub fn socket_fd(&self) -> Option<std::os::unix::io::RawFd> {
+		CONNECTION_SOCKETS
+			.read()
+			.ok()
+			.and_then(|sockets| sockets.get(&(self.inner.as_ptr() as usize)).copied())
+	}
+
+	#[cfg(feature = "libstrophe-0_12_0")]
+	/// Configures TCP keepalive for the connection socket
+	///
+	/// This is the single entry point for the keepalive configuration, it replaces both the deprecated
+	/// [Connection::set_keepalive] and the pair of it with [Connection::set_default_sockopt_callback]. `idle` and
+	/// `interval` are applied by libstrophe in a platform-specific way, `count` is only applied on Linux and Android, other
+	/// platforms use the system default. The options are applied by the internal sockopt callback shared with
+	/// [`set_local_address()`](#method.set_local_address), so this replaces the callback set with
+	/// [`set_sockopt_callback()`](#method.set_sockopt_callback).
+	pub fn set_keepalive_opts(&mut self, opts: KeepaliveOpts) {
+		unsafe {
+			sys::xmpp_conn_set_keepalive(
+				self.inner.as_mut(),
+				c_int::try_from(opts.idle.as_secs()).unwrap_or(c_int::MAX),
+				c_int::try_from(opts.interval.as_secs()).unwrap_or(c_int::MAX),
+			)
+		}
+		self.set_default_sockopt_callback();
+		#[cfg(any(target_os = "linux", target_os = "android"))]
+		self.update_socket_options(|options| options.keepalive_count = opts.count);
 	}
 
 	#[cfg(feature = "libstrophe-0_12_0")]
@@ -535,6 +1261,74 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 		}
 	}
 
+	#[cfg(feature = "libstrophe-0_12_0")]
+	/// Sets the policy for the stanzas that were queued, but never sent before the connection was lost
+	///
+	/// When set, the unsent stanzas are taken out of the libstrophe send queue on disconnect (see
+	/// [`send_queue_drop_element()`](#method.send_queue_drop_element)) and are handled according to the `policy` after the
+	/// next successful connect. That way they are not lost when the stream management resumption fails and a new session
+	/// is started. The stanzas already acknowledged or kept by libstrophe for the resumption are not affected. Pass `None`
+	/// to disable (default).
+	pub fn set_resend_policy(&mut self, policy: Option<ResendPolicy<'cb>>) {
+		self.fat_handlers.borrow_mut().resend_policy = policy;
+	}
+
+	#[cfg(feature = "libstrophe-0_12_0")]
+	/// Stanzas collected on the last disconnect according to [`set_resend_policy()`](#method.set_resend_policy), oldest
+	/// first
+	pub fn unacked_stanzas(&self) -> Vec<Stanza> {
+		self.fat_handlers.borrow().unacked.clone()
+	}
+
+	#[cfg(feature = "libstrophe-0_12_0")]
+	fn handle_unacked(&mut self, event: &ConnectionEvent) {
+		if self.fat_handlers.borrow().resend_policy.is_none() {
+			return;
+		}
+		match event {
+			ConnectionEvent::Disconnect(_) => {
+				let mut unacked = vec![];
+				while let Some(element) = self.send_queue_drop_element(QueueElement::XMPP_QUEUE_OLDEST) {
+					// the queue also holds the data sent with send_raw() which is not necessarily a complete element
+					match Stanza::parse(&element) {
+						Ok(stanza) => unacked.push(stanza),
+						Err(_) => self.log(
+							LogLevel::XMPP_LEVEL_WARN,
+							&format!("Dropping unsent data that is not a stanza: {}", element),
+						),
+					}
+				}
+				self.fat_handlers.borrow_mut().unacked = unacked;
+			}
+			ConnectionEvent::Connect => {
+				// the policy is taken out for the duration of the call so that the callback can freely access the connection
+				let (policy, unacked) = {
+					let mut fat_handlers = self.fat_handlers.borrow_mut();
+					(fat_handlers.resend_policy.take(), fat_handlers.unacked.clone())
+				};
+				if let Some(mut policy) = policy {
+					let ctx = unsafe { self.context_detached() };
+					for stanza in &unacked {
+						let resend = match &mut policy {
+							ResendPolicy::ResendAll => true,
+							ResendPolicy::ResendNone => false,
+							ResendPolicy::Decide(cb) => catch_panic(ctx, false, || cb(stanza)),
+						};
+						if resend {
+							self.send(stanza);
+						}
+					}
+					let mut fat_handlers = self.fat_handlers.borrow_mut();
+					if !matches!(policy, ResendPolicy::ResendNone) {
+						fat_handlers.unacked.clear();
+					}
+					fat_handlers.resend_policy.get_or_insert(policy);
+				}
+			}
+			ConnectionEvent::RawConnect => {}
+		}
+	}
+
 	#[cfg(feature = "libstrophe-0_12_0")]
 	#[inline]
 	/// [xmpp_conn_get_sm_state](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#gaf990c2fd8867258545b182f52df1465e)
@@ -554,55 +1348,651 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 		unsafe { sys::xmpp_conn_set_sm_state(self.inner.as_mut(), sm_state.into_inner()).into_result() }
 	}
 
-	/// [xmpp_connect_client](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga9354fc82ccbbce2840fca7efa9603c13)
-	/// [xmpp_conn_handler](https://strophe.im/libstrophe/doc/0.12.2/strophe_8h.html#aad7c657ae239a87e2c2b746f99138e99)
-	pub fn connect_client<CB>(
-		mut self,
-		alt_host: Option<&str>,
-		alt_port: impl Into<Option<u16>>,
-		handler: CB,
-	) -> Result<Context<'cx, 'cb>, ConnectClientError<'cb, 'cx>>
+	/// Install a custom generator for stanza ids
+	///
+	/// The generator is used by [Connection::generate_id] and by all the helpers of this crate that assign ids to the outgoing
+	/// stanzas automatically, see [`clear_id_generator()`](#method.clear_id_generator) to restore the default one.
+	pub fn set_id_generator<CB>(&mut self, generator: CB)
 	where
-		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, ConnectionEvent) + Send + 'cb,
+		CB: Fn() -> String + Send + 'cb,
 	{
-		let alt_host = FFI(alt_host).send();
-		let alt_port: Nullable<_> = alt_port.into().into();
-		if self.jid().is_none() {
-			return Err(ConnectClientError {
-				conn: self,
-				error: Error::InvalidOperation,
-			});
-		}
-		let callback = Self::connection_handler_cb::<CB>;
-		let new_handler = Some(self.make_fat_handler(Box::new(handler) as _, callback as _, ()));
-		let old_handler = mem::replace(&mut self.fat_handlers.borrow_mut().connection, new_handler);
-		let out = unsafe {
-			sys::xmpp_connect_client(
-				self.inner.as_mut(),
-				alt_host.as_ptr(),
-				alt_port.val(),
-				Some(callback),
-				as_void_ptr(self.fat_handlers.borrow().connection.as_ref().unwrap()),
-			)
-		}
-		.into_result();
-		match out {
-			Ok(_) => {
-				let mut out = self.ctx.take().expect("Internal context is empty, it must never happen");
-				out.consume_connection(self);
-				Ok(out)
-			}
-			Err(e) => {
-				self.fat_handlers.borrow_mut().connection = old_handler;
-				Err(ConnectClientError { conn: self, error: e })
-			}
+		self.fat_handlers.borrow_mut().id_generator = Some(Box::new(generator));
+	}
+
+	/// Restores the default id generator which produces UUIDs using [uuid_gen]
+	pub fn clear_id_generator(&mut self) {
+		self.fat_handlers.borrow_mut().id_generator = None;
+	}
+
+	/// Generate a new stanza id using the generator set by [Connection::set_id_generator]
+	pub fn generate_id(&self) -> String {
+		if let Some(generator) = &self.fat_handlers.borrow().id_generator {
+			generator()
+		} else {
+			uuid_gen()
 		}
 	}
 
-	/// [xmpp_connect_component](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#gaa1cfa1189fdf64bb443c68f0590fd069)
-	/// [xmpp_conn_handler](https://strophe.im/libstrophe/doc/0.12.2/strophe_8h.html#aad7c657ae239a87e2c2b746f99138e99)
+	/// Sets the handler that receives `<stream:error/>` sent by the server
 	///
-	/// See also [`connect_client()`](#method.connect_client) for additional info.
+	/// The handler is called with the parsed error before the connection handler receives the corresponding
+	/// [`ConnectionEvent::Disconnect`] event, so it's the place to inspect the error stanza, e.g. to get the target of
+	/// the `see-other-host` condition. The handler is called for errors received both before and after the authentication.
+	/// Pass `None` to remove the handler.
+	pub fn set_stream_error_handler<CB>(&mut self, handler: Option<CB>)
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &StreamError) + Send + 'cb,
+	{
+		self.fat_handlers.borrow_mut().stream_error = handler.map(|h| Box::new(h) as _);
+	}
+
+	/// Adds the callback called on every [`ConnectionEvent::Connect`]
+	///
+	/// Any number of callbacks can be added, they're called in the order of registration after the
+	/// [modules](Connection::register_module) and before the handler passed to `connect_*()`, which is still required but can
+	/// be a no-op. The callbacks added while the event is being dispatched are called starting with the next event. The
+	/// callbacks survive reconnects.
+	pub fn on_connect<CB>(&mut self, callback: CB)
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>) + Send + 'cb,
+	{
+		self.fat_handlers.borrow_mut().on_connect.push(Box::new(callback));
+	}
+
+	/// Adds the callback called on every [`ConnectionEvent::Disconnect`] with its error, see
+	/// [`on_connect()`](#method.on_connect) for the calling order
+	pub fn on_disconnect<CB>(&mut self, callback: CB)
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, Option<&ConnectionError>) + Send + 'cb,
+	{
+		self.fat_handlers.borrow_mut().on_disconnect.push(Box::new(callback));
+	}
+
+	/// Removes all callbacks added with [`on_connect()`](#method.on_connect) and [`on_disconnect()`](#method.on_disconnect)
+	///
+	/// Called from within one of the callbacks it only removes the callbacks of the other event, the ones being called are kept.
+	pub fn clear_event_callbacks(&mut self) {
+		let mut fat_handlers = self.fat_handlers.borrow_mut();
+		fat_handlers.on_connect.clear();
+		fat_handlers.on_disconnect.clear();
+	}
+
+	/// Runs `f` with all the requests it sends through the id handlers (including the ones done by the XEP helpers, e.g.
+	/// the blocking list one) bound to `token`
+	///
+	/// The requests sent from the response handlers of the bound requests are bound to the same token, so the multi-stanza
+	/// operations like paging through the results are covered as a whole. Once the token is cancelled the pending response
+	/// handlers of the operation are removed shortly (within 100 ms of the event loop running), the responses that arrive
+	/// after the cancellation are not passed to them and the callbacks registered with [Connection::on_cancel] are called.
+	/// The same token can be used for any number of operations and connections.
+	pub fn with_cancel_token<R>(&mut self, token: &CancelToken, f: impl FnOnce(&mut Self) -> R) -> R {
+		let added = {
+			let mut fat_handlers = self.fat_handlers.borrow_mut();
+			let added = !fat_handlers.cancellables.iter().any(|c| c.token.same(token));
+			if added {
+				fat_handlers.cancellables.push(Cancellable {
+					token: token.clone(),
+					ids: vec![],
+					on_cancel: vec![],
+				});
+			}
+			added
+		};
+		let prev_scope = self.fat_handlers.borrow_mut().cancel_scope.replace(token.clone());
+		let out = f(self);
+		let mut fat_handlers = self.fat_handlers.borrow_mut();
+		fat_handlers.cancel_scope = prev_scope;
+		if added {
+			// the operation completed without leaving any pending requests
+			fat_handlers
+				.cancellables
+				.retain(|c| !c.token.same(token) || !c.ids.is_empty());
+		}
+		out
+	}
+
+	/// Adds the cleanup callback for the operation started with [Connection::with_cancel_token], it's called once the token
+	/// is cancelled while the operation still has the pending requests
+	///
+	/// Must be called either inside `with_cancel_token()` or from the response handler of the bound request, returns
+	/// `false` otherwise.
+	pub fn on_cancel<CB>(&mut self, callback: CB) -> bool
+	where
+		CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>) + Send + 'cb,
+	{
+		let mut fat_handlers = self.fat_handlers.borrow_mut();
+		let FatHandlers {
+			cancel_scope,
+			cancellables,
+			..
+		} = &mut *fat_handlers;
+		match cancel_scope
+			.as_ref()
+			.and_then(|scope| cancellables.iter_mut().find(|c| c.token.same(scope)))
+		{
+			Some(cancellable) => {
+				cancellable.on_cancel.push(Box::new(callback));
+				true
+			}
+			None => false,
+		}
+	}
+
+	fn cancel_token_for(&self, id: &str) -> Option<CancelToken> {
+		self
+			.fat_handlers
+			.borrow()
+			.cancellables
+			.iter()
+			.find(|c| c.ids.iter().any(|x| x == id))
+			.map(|c| c.token.clone())
+	}
+
+	/// Calls `handler` once with the response to the IQ request with `id`
+	///
+	/// The responses are dispatched from one stanza handler keyed by the request id because the handlers of the same type
+	/// are unique per connection, so the helpers can't add an id handler per request. The request becomes a part of the
+	/// current [cancel scope](#method.with_cancel_token).
+	pub(crate) fn iq_response_handler_add<CB>(&mut self, id: impl Into<String>, handler: CB)
+	where
+		CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) + Send + 'cb,
+	{
+		let id = id.into();
+		// no-op if the dispatcher is already installed because the handlers are unique, it's not generic over `CB` so that
+		// all of the requests share it
+		self.handler_add(Self::iq_response_dispatcher, None, Some("iq"), None);
+		let replaced = self
+			.fat_handlers
+			.borrow_mut()
+			.iq_responses
+			.insert(id.clone(), Box::new(handler));
+		if replaced.is_some() {
+			self.log(
+				LogLevel::XMPP_LEVEL_WARN,
+				&format!("Response handler for IQ {} replaced by a request with the same id", id),
+			);
+		}
+		self.register_cancellable_id(id);
+	}
+
+	fn iq_response_dispatcher(ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>, stanza: &Stanza) -> HandlerResult {
+		conn.dispatch_iq_response(ctx, stanza)
+	}
+
+	pub(crate) fn dispatch_iq_response(&mut self, ctx: &Context<'cx, 'cb>, stanza: &Stanza) -> HandlerResult {
+		if !matches!(stanza.stanza_type(), Some("result" | "error")) {
+			return HandlerResult::KeepHandler;
+		}
+		let id = match stanza.id() {
+			Some(id) => id,
+			None => return HandlerResult::KeepHandler,
+		};
+		let cancel_token = self.cancel_token_for(id);
+		if cancel_token.as_ref().map_or(false, CancelToken::is_cancelled) {
+			// the handler is going to be removed by the cancellation poll together with the rest of the operation
+			return HandlerResult::KeepHandler;
+		}
+		let handler = self.fat_handlers.borrow_mut().iq_responses.remove(id);
+		if let Some(handler) = handler {
+			// the requests sent from the response handler continue the operation it belongs to
+			let prev_scope = cancel_token.map(|token| self.fat_handlers.borrow_mut().cancel_scope.replace(token));
+			handler(ctx, self, stanza);
+			if let Some(prev_scope) = prev_scope {
+				self.fat_handlers.borrow_mut().cancel_scope = prev_scope;
+			}
+		}
+		HandlerResult::KeepHandler
+	}
+
+	fn register_cancellable_id(&mut self, id: String) {
+		{
+			let mut fat_handlers = self.fat_handlers.borrow_mut();
+			let scope = match fat_handlers.cancel_scope.clone() {
+				Some(scope) => scope,
+				None => return,
+			};
+			match fat_handlers.cancellables.iter_mut().find(|c| c.token.same(&scope)) {
+				Some(cancellable) => cancellable.ids.push(id),
+				None => fat_handlers.cancellables.push(Cancellable {
+					token: scope,
+					ids: vec![id],
+					on_cancel: vec![],
+				}),
+			}
+		}
+		// no-op if the poll is already installed because the handlers are unique
+		self.timed_handler_add(|ctx, conn| conn.poll_cancellations(ctx), Duration::from_millis(100));
+	}
+
+	fn poll_cancellations(&mut self, ctx: &Context<'cx, 'cb>) -> HandlerResult {
+		let cancelled = {
+			let mut fat_handlers = self.fat_handlers.borrow_mut();
+			let FatHandlers {
+				stanza,
+				iq_responses,
+				cancellables,
+				..
+			} = &mut *fat_handlers;
+			for cancellable in cancellables.iter_mut() {
+				// the ids of the requests that got their responses
+				cancellable.ids.retain(|id| {
+					iq_responses.contains_key(id) || stanza.iter().any(|x| matches!(&x.extra, StanzaFilter::Id(x) if x == id))
+				});
+			}
+			cancellables.retain(|c| !c.ids.is_empty());
+			let (cancelled, pending) = mem::take(cancellables)
+				.into_iter()
+				.partition::<Vec<_>, _>(|c| c.token.is_cancelled());
+			*cancellables = pending;
+			cancelled
+		};
+		for cancellable in cancelled {
+			self.handlers_remove_where(|info| matches!(info, HandlerInfo::Id { id } if cancellable.ids.iter().any(|x| x == id)));
+			self
+				.fat_handlers
+				.borrow_mut()
+				.iq_responses
+				.retain(|id, _| !cancellable.ids.contains(id));
+			for callback in cancellable.on_cancel {
+				callback(ctx, self);
+			}
+		}
+		if self.fat_handlers.borrow().cancellables.is_empty() {
+			HandlerResult::RemoveHandler
+		} else {
+			HandlerResult::KeepHandler
+		}
+	}
+
+	fn fire_event_callbacks(&mut self, ctx: &Context<'cx, 'cb>, event: &ConnectionEvent) {
+		match event {
+			ConnectionEvent::Connect => {
+				let mut callbacks = mem::take(&mut self.fat_handlers.borrow_mut().on_connect);
+				for callback in &mut callbacks {
+					callback(ctx, self);
+				}
+				let mut fat_handlers = self.fat_handlers.borrow_mut();
+				let added = mem::replace(&mut fat_handlers.on_connect, callbacks);
+				fat_handlers.on_connect.extend(added);
+			}
+			ConnectionEvent::Disconnect(error) => {
+				let mut callbacks = mem::take(&mut self.fat_handlers.borrow_mut().on_disconnect);
+				for callback in &mut callbacks {
+					callback(ctx, self, error.as_ref());
+				}
+				let mut fat_handlers = self.fat_handlers.borrow_mut();
+				let added = mem::replace(&mut fat_handlers.on_disconnect, callbacks);
+				fat_handlers.on_disconnect.extend(added);
+			}
+			ConnectionEvent::RawConnect => {}
+		}
+	}
+
+	/// Enables automatic redirection on the `see-other-host` stream error
+	///
+	/// When the server responds with the [`see-other-host`] stream error, the client connection is re-established to the host
+	/// and port specified in the error instead of delivering the [`ConnectionEvent::Disconnect`] event. Redirection is attempted
+	/// at most `max_hops` times in a row, the counter is reset on each successful connection. Pass `0` to disable
+	/// redirection (default). The handler set with [`set_stream_error_handler()`](#method.set_stream_error_handler) is still
+	/// called before each redirect. Only connections established with the `connect_client()` family of methods are redirected.
+	///
+	/// [`see-other-host`]: https://www.rfc-editor.org/rfc/rfc6120#section-4.9.3.19
+	pub fn set_see_other_host_redirect(&mut self, max_hops: u32) {
+		self.fat_handlers.borrow_mut().redirect.max_hops = max_hops;
+	}
+
+	unsafe fn follow_see_other_host(
+		&mut self,
+		stream_error: &StreamError,
+		callback: ConnectionHandlerFn,
+		userdata: *mut c_void,
+	) -> bool {
+		let (host, port) = match stream_error.see_other_host() {
+			Some(target) => target,
+			None => return false,
+		};
+		{
+			let redirect = &mut self.fat_handlers.borrow_mut().redirect;
+			if !redirect.client || redirect.hops >= redirect.max_hops {
+				return false;
+			}
+			redirect.hops += 1;
+		}
+		self.log(
+			LogLevel::XMPP_LEVEL_INFO,
+			&format!("Following see-other-host redirect to {}", host),
+		);
+		let alt_host = FFI(host.as_str()).send();
+		let alt_port: Nullable<_> = port.into();
+		let out = sys::xmpp_connect_client(
+			self.inner.as_ptr(),
+			alt_host.as_ptr(),
+			alt_port.val(),
+			Some(callback),
+			userdata,
+		)
+		.into_result()
+		.is_ok();
+		#[cfg(not(feature = "libstrophe-0_10_0"))]
+		if out {
+			self.set_link_state(LinkState::Connecting);
+		}
+		out
+	}
+
+	fn fire_stream_error_handler(&mut self, stream_error: &StreamError) {
+		// the handler is taken out for the duration of the call so that it can freely access the connection
+		let handler = self.fat_handlers.borrow_mut().stream_error.take();
+		if let Some(mut handler) = handler {
+			let ctx = unsafe { self.context_detached() };
+			catch_panic(ctx, (), || handler(ctx, self, stream_error));
+			self.fat_handlers.borrow_mut().stream_error.get_or_insert(handler);
+		}
+	}
+
+	/// Sets the hook receiving the errors returned from the fallible handlers
+	///
+	/// The handlers added with [`try_timed_handler_add()`](#method.try_timed_handler_add),
+	/// [`try_id_handler_add()`](#method.try_id_handler_add) and [`try_handler_add()`](#method.try_handler_add) return
+	/// `Result`, the errors are passed to this hook together with the kind of the failed handler. Without the hook the errors
+	/// are logged. Pass `None` to remove the hook.
+	pub fn set_handler_error_hook<CB>(&mut self, hook: Option<CB>)
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, HandlerKind, HandlerError) + Send + 'cb,
+	{
+		self.fat_handlers.borrow_mut().handler_error_hook = hook.map(|h| Box::new(h) as _);
+	}
+
+	fn fire_handler_error(&mut self, kind: HandlerKind, error: HandlerError) {
+		// the hook is taken out for the duration of the call so that it can freely access the connection
+		let hook = self.fat_handlers.borrow_mut().handler_error_hook.take();
+		if let Some(mut hook) = hook {
+			hook(unsafe { self.context_detached() }, self, kind, error);
+			self.fat_handlers.borrow_mut().handler_error_hook.get_or_insert(hook);
+		} else {
+			self.log(LogLevel::XMPP_LEVEL_ERROR, &format!("{:?} handler failed: {}", kind, error));
+		}
+	}
+
+	/// Sets the execution time budget of the stanza and timed handlers, `None` disables the measurement
+	///
+	/// The handlers run on the event loop thread and delay the processing of everything else while they're executing.
+	/// With the budget set each handler call is timed and the ones taking longer than `budget` are reported to the hook set
+	/// with [`set_slow_handler_hook()`](#method.set_slow_handler_hook), or logged as warnings with the filter of the handler
+	/// if there is no hook.
+	pub fn set_handler_budget(&mut self, budget: Option<Duration>) {
+		self.fat_handlers.borrow_mut().handler_budget = budget;
+	}
+
+	/// Sets the hook receiving the handlers that exceeded the budget set with
+	/// [`set_handler_budget()`](#method.set_handler_budget) together with their execution time. Pass `None` to remove the
+	/// hook.
+	pub fn set_slow_handler_hook<CB>(&mut self, hook: Option<CB>)
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &HandlerInfo, Duration) + Send + 'cb,
+	{
+		self.fat_handlers.borrow_mut().slow_handler_hook = hook.map(|h| Box::new(h) as _);
+	}
+
+	fn fire_slow_handler(&mut self, info: &HandlerInfo, elapsed: Duration) {
+		// the hook is taken out for the duration of the call so that it can freely access the connection
+		let hook = self.fat_handlers.borrow_mut().slow_handler_hook.take();
+		if let Some(mut hook) = hook {
+			let ctx = unsafe { self.context_detached() };
+			catch_panic(ctx, (), || hook(ctx, self, info, elapsed));
+			self.fat_handlers.borrow_mut().slow_handler_hook.get_or_insert(hook);
+		} else {
+			self.log(
+				LogLevel::XMPP_LEVEL_WARN,
+				&format!("Slow handler {:?} took {:?}", info, elapsed),
+			);
+		}
+	}
+
+	/// Checks whether the user's server or account supports the `feature`
+	///
+	/// On the first call the [XEP-0030](https://xmpp.org/extensions/xep-0030.html) `disco#info` queries are sent to the server
+	/// and to the bare JID of the connection, `handler` is called once both of them are answered. The combined feature list
+	/// is cached until the connection is lost, so the subsequent calls invoke `handler` immediately. If the connection is
+	/// lost before the responses arrive `handler` is called with `None`. See also
+	/// [`cached_server_supports()`](#method.cached_server_supports).
+	pub fn server_supports<CB>(&mut self, feature: impl Into<String>, handler: CB)
+	where
+		CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, Option<bool>) + Send + 'cb,
+	{
+		let feature = feature.into();
+		let waiter: Box<FeatureWaiter> = Box::new(
+			move |ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>, features: Option<&HashSet<String>>| {
+				handler(ctx, conn, features.map(|features| features.contains(&feature)))
+			},
+		);
+		let queries = {
+			let mut fat_handlers = self.fat_handlers.borrow_mut();
+			match &mut fat_handlers.server_features {
+				ServerFeatures::Known(features) => {
+					let features = features.clone();
+					drop(fat_handlers);
+					waiter(unsafe { self.context_detached() }, self, Some(&features));
+					return;
+				}
+				ServerFeatures::Pending { waiters, .. } => {
+					waiters.push(waiter);
+					return;
+				}
+				ServerFeatures::Unknown => {
+					let account = self
+						.bound_jid()
+						.or_else(|| self.jid())
+						.and_then(|jid| jid.parse::<Jid>().ok());
+					let queries = account.map_or_else(Vec::new, |account| {
+						let bare = account.bare().to_owned();
+						if account.domain() == bare {
+							vec![bare]
+						} else {
+							vec![account.domain().to_owned(), bare]
+						}
+					});
+					fat_handlers.server_features = ServerFeatures::Pending {
+						remaining: queries.len(),
+						features: HashSet::new(),
+						waiters: vec![waiter],
+					};
+					queries
+				}
+			}
+		};
+		for to in &queries {
+			let id = self.generate_id();
+			let request = match disco::info_request(to, &id) {
+				Ok(request) => request,
+				Err(_) => {
+					self.server_features_received(&[]);
+					continue;
+				}
+			};
+			self.iq_response_handler_add(id, |_ctx, conn, stanza| {
+				conn.server_features_received(&disco::features(stanza));
+			});
+			self.send(&request);
+		}
+		if queries.is_empty() {
+			self.server_features_received(&[]);
+		}
+	}
+
+	/// Returns the cached result of [`server_supports()`](#method.server_supports) or `None` if the features are not known yet
+	pub fn cached_server_supports(&self, feature: &str) -> Option<bool> {
+		match &self.fat_handlers.borrow().server_features {
+			ServerFeatures::Known(features) => Some(features.contains(feature)),
+			ServerFeatures::Unknown | ServerFeatures::Pending { .. } => None,
+		}
+	}
+
+	fn server_features_received(&mut self, received: &[String]) {
+		let ready = {
+			let mut fat_handlers = self.fat_handlers.borrow_mut();
+			match &mut fat_handlers.server_features {
+				ServerFeatures::Pending {
+					remaining,
+					features,
+					waiters,
+				} => {
+					features.extend(received.iter().cloned());
+					*remaining = remaining.saturating_sub(1);
+					if *remaining == 0 {
+						let features = mem::take(features);
+						let waiters = mem::take(waiters);
+						fat_handlers.server_features = ServerFeatures::Known(features.clone());
+						Some((features, waiters))
+					} else {
+						None
+					}
+				}
+				ServerFeatures::Unknown | ServerFeatures::Known(_) => None,
+			}
+		};
+		if let Some((features, waiters)) = ready {
+			for waiter in waiters {
+				waiter(unsafe { self.context_detached() }, self, Some(&features));
+			}
+		}
+	}
+
+	/// Forgets the features because they might change after reconnecting to a different server, the pending waiters are
+	/// called with `None`
+	fn server_features_lost(&mut self) {
+		let features = mem::replace(&mut self.fat_handlers.borrow_mut().server_features, ServerFeatures::Unknown);
+		if let ServerFeatures::Pending { waiters, .. } = features {
+			for waiter in waiters {
+				waiter(unsafe { self.context_detached() }, self, None);
+			}
+		}
+	}
+
+	/// Builds a [XEP-0308](https://xmpp.org/extensions/xep-0308.html) correction of the `previous` message
+	///
+	/// The returned message has the same type, recipient and thread as `previous`, the new `body`, a fresh id from
+	/// [Connection::generate_id] and refers to the id of `previous`. Returns [Error::InvalidOperation] if `previous` has no id.
+	pub fn new_correction(&self, previous: &Stanza, body: impl AsRef<str>) -> Result<Stanza> {
+		let previous_id = previous.id().ok_or(Error::InvalidOperation)?;
+		let mut out = Stanza::new_message(previous.stanza_type(), Some(&self.generate_id()), previous.to());
+		out.set_body(body)?;
+		if let Some(thread) = previous.thread() {
+			out.set_thread(thread, previous.thread_parent().as_deref())?;
+		}
+		out.set_replace(previous_id)?;
+		Ok(out)
+	}
+
+	/// [xmpp_connect_client](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga9354fc82ccbbce2840fca7efa9603c13)
+	/// [xmpp_conn_handler](https://strophe.im/libstrophe/doc/0.12.2/strophe_8h.html#aad7c657ae239a87e2c2b746f99138e99)
+	///
+	/// On failure the `Connection` is returned back inside the error. See [`try_connect_client()`](#method.try_connect_client)
+	/// for a variant that keeps the ownership with the caller and returns a plain [`Error`].
+	///
+	/// `alt_host` only changes the address to connect to, the server certificate is still validated against (and SNI is set
+	/// to) the domain of the connection JID, see [`tls_hostname()`](#method.tls_hostname). So it's safe to pass an IP address
+	/// or a different CNAME there.
+	pub fn connect_client<CB>(
+		mut self,
+		alt_host: Option<&str>,
+		alt_port: impl Into<Option<u16>>,
+		handler: CB,
+	) -> Result<Context<'cx, 'cb>, ConnectClientError<'cb, 'cx>>
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, ConnectionEvent) + Send + 'cb,
+	{
+		match self.try_connect_client(alt_host, alt_port, handler) {
+			Ok(_) => Ok(self.into_context()),
+			Err(error) => Err(ConnectClientError { conn: self, error }),
+		}
+	}
+
+	/// [xmpp_connect_client](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga9354fc82ccbbce2840fca7efa9603c13)
+	/// [xmpp_conn_handler](https://strophe.im/libstrophe/doc/0.12.2/strophe_8h.html#aad7c657ae239a87e2c2b746f99138e99)
+	///
+	/// Same as [`connect_client()`](#method.connect_client), but borrows the `Connection` instead of consuming it. On failure
+	/// the previous connection handler is restored and the `Connection` can be reconfigured and used for another attempt. On
+	/// success call [`into_context()`](#method.into_context) to get the `Context` to run the event loop with.
+	///
+	/// Calling any of the `connect_*()` methods while the connection is connecting or connected returns
+	/// [Error::InvalidOperation] without touching the connection. Once disconnected the same connection can be connected
+	/// again, e.g. from the connection handler on [`ConnectionEvent::Disconnect`], see also
+	/// [`reconnect()`](#method.reconnect) and [Context::reconnect].
+	pub fn try_connect_client<CB>(&mut self, alt_host: Option<&str>, alt_port: impl Into<Option<u16>>, handler: CB) -> Result<()>
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, ConnectionEvent) + Send + 'cb,
+	{
+		self.ensure_disconnected()?;
+		let alt_host = alt_host.map(str::to_owned);
+		let alt_port = alt_port.into();
+		let jid = self.jid().ok_or(Error::InvalidOperation)?;
+		if self.fat_handlers.borrow().auto_resource && !jid.contains('/') {
+			let jid = format!("{}/{}", jid, uuid_gen());
+			self.set_jid(jid)?;
+		}
+		self.fat_handlers.borrow_mut().client_alt = (alt_host.clone(), alt_port);
+		let (alt_host, alt_port) = self.client_target(alt_host, alt_port)?;
+		let alt_host = FFI(alt_host.as_deref()).send();
+		let alt_port: Nullable<_> = alt_port.into();
+		let mut fat_handlers = self.fat_handlers.borrow_mut();
+		fat_handlers.redirect.client = true;
+		fat_handlers.conn_type = ConnType::XMPP_CLIENT;
+		drop(fat_handlers);
+		self.connect_with(handler, |conn, callback, userdata| unsafe {
+			sys::xmpp_connect_client(conn, alt_host.as_ptr(), alt_port.val(), callback, userdata)
+		})
+	}
+
+	/// Connects the disconnected client connection again with the handler, the alternative host and port of the last
+	/// [`connect_client()`](#method.connect_client) call
+	///
+	/// Returns [Error::InvalidOperation] if the connection was never connected with `connect_client()` (the component and raw
+	/// connections need their original arguments, call the `connect_*()` method again for them) or if it's still connecting
+	/// or connected.
+	pub fn reconnect(&mut self) -> Result<()> {
+		self.ensure_disconnected()?;
+		if !self.fat_handlers.borrow().redirect.client {
+			return Err(Error::InvalidOperation);
+		}
+		let (callback, userdata) = self.connection_handler_raw().ok_or(Error::InvalidOperation)?;
+		let (alt_host, alt_port) = self.fat_handlers.borrow().client_alt.clone();
+		let (alt_host, alt_port) = self.client_target(alt_host, alt_port)?;
+		let alt_host = FFI(alt_host.as_deref()).send();
+		let alt_port: Nullable<_> = alt_port.into();
+		let out = unsafe { sys::xmpp_connect_client(self.inner.as_ptr(), alt_host.as_ptr(), alt_port.val(), callback, userdata) }
+			.into_result();
+		#[cfg(not(feature = "libstrophe-0_10_0"))]
+		if out.is_ok() {
+			self.set_link_state(LinkState::Connecting);
+		}
+		out
+	}
+
+	/// Returns the host and port to pass to `xmpp_connect_client`, starting the proxy tunnel if needed
+	fn client_target(&mut self, alt_host: Option<String>, mut alt_port: Option<u16>) -> Result<(Option<String>, Option<u16>)> {
+		let mut alt_host = alt_host.or_else(|| self.fat_handlers.borrow().tls_route.clone());
+		if self.fat_handlers.borrow().proxy.is_some() {
+			let target_host = match alt_host {
+				Some(alt_host) => alt_host,
+				None => self.tls_hostname().ok_or(Error::InvalidOperation)?,
+			};
+			let target_port = alt_port.unwrap_or(if self.legacy_ssl() {
+				5223
+			} else {
+				5222
+			});
+			let (host, port) = self.start_proxy_tunnel(target_host, target_port)?;
+			alt_host = Some(host);
+			alt_port = Some(port);
+		}
+		Ok((alt_host, alt_port))
+	}
+
+	/// [xmpp_connect_component](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#gaa1cfa1189fdf64bb443c68f0590fd069)
+	/// [xmpp_conn_handler](https://strophe.im/libstrophe/doc/0.12.2/strophe_8h.html#aad7c657ae239a87e2c2b746f99138e99)
+	///
+	/// See also [`connect_client()`](#method.connect_client) for additional info.
 	pub fn connect_component<CB>(
 		mut self,
 		host: impl AsRef<str>,
@@ -612,31 +2002,60 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 	where
 		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, ConnectionEvent) + Send + 'cb,
 	{
-		let host = FFI(host.as_ref()).send();
-		let port: Nullable<_> = port.into().into();
-		let callback = Self::connection_handler_cb::<CB>;
-		let new_handler = self.make_fat_handler(Box::new(handler) as _, callback as _, ());
-		let old_handler = self.fat_handlers.borrow_mut().connection.replace(new_handler);
-		let out = unsafe {
-			sys::xmpp_connect_component(
-				self.inner.as_mut(),
-				host.as_ptr(),
-				port.val(),
-				Some(callback),
-				as_void_ptr(&self.fat_handlers.borrow().connection),
-			)
+		match self.try_connect_component(host, port, handler) {
+			Ok(_) => Ok(self.into_context()),
+			Err(error) => Err(ConnectClientError { conn: self, error }),
 		}
-		.into_result();
-		match out {
-			Ok(_) => {
-				let mut out = self.ctx.take().expect("Internal context is empty, it must never happen");
-				out.consume_connection(self);
-				Ok(out)
-			}
-			Err(e) => {
-				self.fat_handlers.borrow_mut().connection = old_handler;
-				Err(ConnectClientError { conn: self, error: e })
-			}
+	}
+
+	/// [xmpp_connect_component](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#gaa1cfa1189fdf64bb443c68f0590fd069)
+	/// [xmpp_conn_handler](https://strophe.im/libstrophe/doc/0.12.2/strophe_8h.html#aad7c657ae239a87e2c2b746f99138e99)
+	///
+	/// See also [`try_connect_client()`](#method.try_connect_client) for additional info.
+	pub fn try_connect_component<CB>(&mut self, host: impl AsRef<str>, port: impl Into<Option<u16>>, handler: CB) -> Result<()>
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, ConnectionEvent) + Send + 'cb,
+	{
+		self.ensure_disconnected()?;
+		let (host, port) = self.component_target(host.as_ref().to_owned(), port.into())?;
+		let host = FFI(host.as_str()).send();
+		let port: Nullable<_> = port.into();
+		let mut fat_handlers = self.fat_handlers.borrow_mut();
+		fat_handlers.redirect.client = false;
+		fat_handlers.conn_type = ConnType::XMPP_COMPONENT;
+		drop(fat_handlers);
+		self.connect_with(handler, |conn, callback, userdata| unsafe {
+			sys::xmpp_connect_component(conn, host.as_ptr(), port.val(), callback, userdata)
+		})
+	}
+
+	/// Connects the disconnected component connection again with its current handler, the component counterpart of
+	/// [`reconnect()`](#method.reconnect) used by the [ConnectionPool](crate::ConnectionPool)
+	pub(crate) fn reconnect_component(&mut self, host: &str, port: Option<u16>) -> Result<()> {
+		self.ensure_disconnected()?;
+		if self.conn_type() != ConnType::XMPP_COMPONENT {
+			return Err(Error::InvalidOperation);
+		}
+		let (callback, userdata) = self.connection_handler_raw().ok_or(Error::InvalidOperation)?;
+		let (host, port) = self.component_target(host.to_owned(), port)?;
+		let host = FFI(host.as_str()).send();
+		let port: Nullable<_> = port.into();
+		let out =
+			unsafe { sys::xmpp_connect_component(self.inner.as_ptr(), host.as_ptr(), port.val(), callback, userdata) }.into_result();
+		#[cfg(not(feature = "libstrophe-0_10_0"))]
+		if out.is_ok() {
+			self.set_link_state(LinkState::Connecting);
+		}
+		out
+	}
+
+	/// Returns the host and port to pass to `xmpp_connect_component`, starting the proxy tunnel if needed
+	fn component_target(&mut self, host: String, port: Option<u16>) -> Result<(String, Option<u16>)> {
+		if self.fat_handlers.borrow().proxy.is_some() {
+			let (tunnel_host, tunnel_port) = self.start_proxy_tunnel(host, port.unwrap_or(5347))?;
+			Ok((tunnel_host, Some(tunnel_port)))
+		} else {
+			Ok((host, port))
 		}
 	}
 
@@ -653,115 +2072,804 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 	where
 		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, ConnectionEvent) + Send + 'cb,
 	{
+		match self.try_connect_raw(alt_host, alt_port, handler) {
+			Ok(_) => Ok(self.into_context()),
+			Err(error) => Err(ConnectClientError { conn: self, error }),
+		}
+	}
+
+	/// [xmpp_connect_raw](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga3873544638e8123c667f074d86dbad5a)
+	/// [xmpp_conn_handler](https://strophe.im/libstrophe/doc/0.12.2/strophe_8h.html#aad7c657ae239a87e2c2b746f99138e99)
+	///
+	/// See also [`try_connect_client()`](#method.try_connect_client) for additional info.
+	pub fn try_connect_raw<CB>(&mut self, alt_host: Option<&str>, alt_port: impl Into<Option<u16>>, handler: CB) -> Result<()>
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, ConnectionEvent) + Send + 'cb,
+	{
+		self.ensure_disconnected()?;
 		let alt_host = FFI(alt_host).send();
 		let alt_port: Nullable<_> = alt_port.into().into();
 		if self.jid().is_none() {
-			return Err(ConnectClientError {
-				conn: self,
-				error: Error::InvalidOperation,
-			});
+			return Err(Error::InvalidOperation);
+		}
+		let mut fat_handlers = self.fat_handlers.borrow_mut();
+		fat_handlers.redirect.client = false;
+		fat_handlers.conn_type = ConnType::XMPP_CLIENT;
+		drop(fat_handlers);
+		self.connect_with(handler, |conn, callback, userdata| unsafe {
+			sys::xmpp_connect_raw(conn, alt_host.as_ptr(), alt_port.val(), callback, userdata)
+		})
+	}
+
+	/// Starts the tunnel through the configured proxy and returns the local address to connect to instead of the target
+	fn start_proxy_tunnel(&mut self, target_host: String, target_port: u16) -> Result<(String, u16)> {
+		let proxy = self.fat_handlers.borrow().proxy.clone().ok_or(Error::InvalidOperation)?;
+		let tunnel = ProxyTunnel::start(proxy, target_host, target_port).map_err(|e| {
+			self.log(LogLevel::XMPP_LEVEL_ERROR, &format!("Cannot start proxy tunnel: {}", e));
+			Error::InternalError
+		})?;
+		let local_addr = tunnel.local_addr();
+		self.fat_handlers.borrow_mut().proxy_tunnel = Some(tunnel);
+		Ok((local_addr.ip().to_string(), local_addr.port()))
+	}
+
+	fn report_proxy_error(&self) {
+		let error = self
+			.fat_handlers
+			.borrow()
+			.proxy_tunnel
+			.as_ref()
+			.and_then(ProxyTunnel::take_error);
+		if let Some(e) = error {
+			self.log(LogLevel::XMPP_LEVEL_ERROR, &format!("Proxy tunnel failed: {}", e));
+		}
+	}
+
+	#[inline]
+	/// Type of the connection, [ConnType::XMPP_UNKNOWN] until the first `connect_*()` call
+	///
+	/// [xmpp_connect_raw](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga3873544638e8123c667f074d86dbad5a)
+	/// connections are client connections.
+	pub fn conn_type(&self) -> ConnType {
+		self.fat_handlers.borrow().conn_type
+	}
+
+	/// Consumes the `Connection` and returns the `Context` that owns it from now on
+	///
+	/// Use it after a successful `try_connect_*()` call to run the event loop.
+	pub fn into_context(mut self) -> Context<'cx, 'cb> {
+		let mut out = self.ctx.take().expect("Internal context is empty, it must never happen");
+		out.consume_connection(self);
+		out
+	}
+
+	fn connect_with<CB>(
+		&mut self,
+		handler: CB,
+		connect: impl FnOnce(*mut sys::xmpp_conn_t, sys::xmpp_conn_handler, *mut c_void) -> c_int,
+	) -> Result<()>
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, ConnectionEvent) + Send + 'cb,
+	{
+		let callback = Self::connection_handler_cb::<CB>;
+		let new_handler = self.make_fat_handler(Box::new(handler) as _, callback as _, ());
+		let old_handler = self.fat_handlers.borrow_mut().connection.replace(new_handler);
+		let userdata = as_void_ptr(self.fat_handlers.borrow().connection.as_ref().unwrap());
+		let out = connect(self.inner.as_ptr(), Some(callback), userdata).into_result();
+		if out.is_err() {
+			self.fat_handlers.borrow_mut().connection = old_handler;
+		}
+		#[cfg(not(feature = "libstrophe-0_10_0"))]
+		if out.is_ok() {
+			self.set_link_state(LinkState::Connecting);
+		}
+		out
+	}
+
+	#[inline]
+	/// Opaque identity of this connection, equal for all `Connection` instances referring to the same underlying
+	/// connection (e.g. the ones passed to the handlers), see also [Stanza::connection_ptr_token]
+	pub fn token(&self) -> ConnectionToken {
+		ConnectionToken(self.inner.as_ptr() as usize)
+	}
+
+	/// Non-owning `Connection` pointing to the same `xmpp_conn_t`, like the one passed to the handlers
+	pub(crate) fn proxy(&self) -> Self {
+		unsafe { Self::from_ref_mut(self.inner.as_ptr(), Rc::clone(&self.fat_handlers)) }
+	}
+
+	pub(crate) fn as_ptr(&self) -> *mut sys::xmpp_conn_t {
+		self.inner.as_ptr()
+	}
+
+	/// Callback and userdata of the current connection handler, they allow restarting the connection with the same handler
+	/// without access to the `Connection` (see [ConnectionPool](crate::ConnectionPool))
+	pub(crate) fn connection_handler_raw(&self) -> Option<(sys::xmpp_conn_handler, *mut c_void)> {
+		self.fat_handlers.borrow().connection.as_ref().map(|handler| {
+			let callback = unsafe { mem::transmute::<*const (), ConnectionHandlerFn>(handler.cb_addr) };
+			(Some(callback), as_void_ptr(handler))
+		})
+	}
+
+	/// Non-owning `Connection` for `conn_ptr` and the userdata of its connection handler (see
+	/// [`connection_handler_raw()`](#method.connection_handler_raw)), `None` if the handlers are already dropped
+	///
+	/// The caller must make sure that the connection handler wasn't replaced or freed since the userdata was obtained.
+	pub(crate) unsafe fn from_handler_userdata(conn_ptr: *mut sys::xmpp_conn_t, userdata: *mut c_void) -> Option<Self> {
+		let connection_handler = void_ptr_as_opt::<ConnectionFatHandler>(userdata)?;
+		let fat_handlers = connection_handler.fat_handlers.upgrade()?;
+		Some(Self::from_ref_mut(conn_ptr, fat_handlers))
+	}
+
+	/// [xmpp_conn_open_stream_default](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga73e477d4abfd439bcd27ddf78d601c0f)
+	///
+	/// Related to [`connect_raw()`](#method.connect_raw).
+	///
+	/// The header carries the language and the version set with [`set_xml_lang()`](#method.set_xml_lang) and
+	/// [`set_stream_version()`](#method.set_stream_version).
+	pub fn open_stream_default(&self) -> Result<()> {
+		let (lang, version) = {
+			let fat_handlers = self.fat_handlers.borrow();
+			(fat_handlers.stream_lang.clone(), fat_handlers.stream_version.clone())
+		};
+		if lang.is_none() && version.is_none() {
+			return unsafe { sys::xmpp_conn_open_stream_default(self.inner.as_ptr()) }.into_result();
+		}
+		let jid = self
+			.jid()
+			.and_then(|jid| jid.parse::<Jid>().ok())
+			.ok_or(Error::InvalidOperation)?;
+		let mut attributes = HashMap::with_capacity(5);
+		attributes.insert("to", jid.domain());
+		attributes.insert("xml:lang", lang.as_deref().unwrap_or("en"));
+		attributes.insert("version", version.as_deref().unwrap_or("1.0"));
+		attributes.insert("xmlns", "jabber:client");
+		attributes.insert("xmlns:stream", "http://etherx.jabber.org/streams");
+		self.open_stream(&attributes)
+	}
+
+	/// [xmpp_conn_open_stream](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga747589e1fdf44891c601958742d115b7)
+	///
+	/// Related to [`connect_raw()`](#method.connect_raw).
+	pub fn open_stream(&self, attributes: &HashMap<&str, &str>) -> Result<()> {
+		let mut storage = Vec::with_capacity(attributes.len() * 2);
+		for attr in attributes {
+			storage.push(FFI(*attr.0).send());
+			storage.push(FFI(*attr.1).send());
+		}
+		let mut attrs = storage.iter().map(|s| s.as_ptr() as *mut _).collect::<Vec<_>>();
+		unsafe { sys::xmpp_conn_open_stream(self.inner.as_ptr(), attrs.as_mut_ptr(), attrs.len()) }.into_result()
+	}
+
+	#[inline]
+	/// [xmpp_conn_tls_start](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga65a92215a59a365f89e908e90178f7b8)
+	///
+	/// Related to [`connect_raw()`](#method.connect_raw).
+	pub fn tls_start(&self) -> Result<()> {
+		unsafe { sys::xmpp_conn_tls_start(self.inner.as_ptr()) }.into_result()
+	}
+
+	#[inline]
+	/// [xmpp_disconnect](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#gaa635ceddb5941d011e290073f7552355)
+	pub fn disconnect(&mut self) {
+		unsafe { sys::xmpp_disconnect(self.inner.as_mut()) }
+	}
+
+	#[cfg(feature = "presence")]
+	/// Broadcasts the unavailable presence with an optional `status` and disconnects
+	///
+	/// The presence is only sent if the connection is established. The server then notifies the contacts and the entities
+	/// that received the directed presence, see [RFC 6121](https://www.rfc-editor.org/rfc/rfc6121#section-4.5.2).
+	pub fn disconnect_graceful(&mut self, status: Option<&str>) -> Result<()> {
+		if self.is_established() {
+			self.send(&presence::unavailable(status)?);
+		}
+		self.disconnect();
+		Ok(())
+	}
+
+	#[cfg(feature = "presence")]
+	/// Sends the presence of `typ` to `to` following the addressing rules, see [presence::directed]
+	pub fn send_presence_to(&mut self, to: &str, typ: PresenceType) -> Result<()> {
+		self.send(&presence::directed(to, typ)?);
+		Ok(())
+	}
+
+	#[inline]
+	#[cfg(feature = "presence")]
+	/// Sends the presence probe to the bare JID of `to`, see [presence::probe]
+	pub fn send_presence_probe(&mut self, to: &str) -> Result<()> {
+		self.send_presence_to(to, PresenceType::Probe)
+	}
+
+	#[inline]
+	/// [xmpp_send_raw_string](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#gaf67110aced5d20909069d33d17bec025)
+	///
+	/// Be aware that this method performs a lot of allocations internally so you might want to use
+	/// [`send_raw()`](#method.send_raw) instead.
+	pub fn send_raw_string(&mut self, data: impl AsRef<str>) {
+		self.count_sent(data.as_ref().len());
+		let data = FFI(data.as_ref()).send();
+		unsafe {
+			sys::xmpp_send_raw_string(self.inner.as_mut(), data.as_ptr());
+		}
+	}
+
+	/// [xmpp_send_raw](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#gaa1be7bdb58f3610b7997f1186d87c896)
+	///
+	/// The data is logged according to the policy set with [`set_traffic_log_policy()`](#method.set_traffic_log_policy).
+	pub fn send_raw(&mut self, data: impl AsRef<[u8]>) {
+		let data = data.as_ref();
+		self.log_raw_traffic(data);
+		self.count_sent(data.len());
+		unsafe {
+			sys::xmpp_send_raw(self.inner.as_mut(), data.as_ptr() as _, data.len());
+		}
+	}
+
+	#[inline]
+	/// [xmpp_send](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga0e879d34b2ea28c08cacbb012eadfbc1)
+	///
+	/// When an [EncryptionPlugin] is installed the messages are encrypted before sending, see
+	/// [`send_encrypted()`](#method.send_encrypted). The messages that fail to encrypt and the stanzas rejected in the strict
+	/// send mode are logged and dropped, use [`try_send()`](#method.try_send) to get the error instead.
+	///
+	/// There is no variant taking the stanza by value: `xmpp_send` serializes the stanza into the send queue right away
+	/// and doesn't keep a reference to it, so passing the ownership wouldn't save a copy. Sending the pre-serialized text
+	/// with [`send_raw()`](#method.send_raw) doesn't help either, it's the same single serialization, only done outside
+	/// of libstrophe.
+	pub fn send(&mut self, stanza: &Stanza) {
+		if let Err(e) = self.try_send(stanza) {
+			self.log(LogLevel::XMPP_LEVEL_ERROR, &format!("Stanza not sent: {}", e));
+		}
+	}
+
+	/// Same as [`send()`](#method.send), but returns the reason for rejecting the stanza in the strict send mode
+	///
+	/// Nothing is sent if the stanza is rejected, see [`set_strict_send()`](#method.set_strict_send). The failure of the
+	/// installed [EncryptionPlugin] is returned as [SendValidationError::Encryption], the message is not sent then.
+	pub fn try_send(&mut self, stanza: &Stanza) -> Result<(), SendValidationError> {
+		if self.fat_handlers.borrow().strict_send {
+			self.validate_outgoing(stanza)?;
+		}
+		let intercepted = self.intercept_outgoing(stanza);
+		let stanza = intercepted.as_ref().unwrap_or(stanza);
+		if stanza.name() == Some("message") && self.fat_handlers.borrow().encryption.is_some() {
+			return self.send_encrypted(stanza).map_err(SendValidationError::from);
+		}
+		self.observe_sent(stanza);
+		unsafe { sys::xmpp_send(self.inner.as_mut(), stanza.as_ptr()) }
+		Ok(())
+	}
+
+	/// Adds the `interceptor` that can replace the stanzas sent with [`send()`](#method.send) and
+	/// [`try_send()`](#method.try_send)
+	///
+	/// The interceptors are called in the order of registration after the strict send validation and before the
+	/// encryption, each one receives the output of the previous one. Returning `None` sends the stanza unchanged. The
+	/// data sent with [`send_raw()`](#method.send_raw) and the internal traffic of libstrophe (e.g. the stream
+	/// management acks) are not intercepted. See
+	/// [`xep::delay::offline_delay_interceptor()`](crate::xep::delay::offline_delay_interceptor) for an example.
+	pub fn send_interceptor_add<CB>(&mut self, interceptor: CB)
+	where
+		CB: FnMut(&Stanza) -> Option<Stanza> + Send + 'cb,
+	{
+		self.fat_handlers.borrow_mut().send_interceptors.push(Box::new(interceptor));
+	}
+
+	/// Removes all interceptors added with [`send_interceptor_add()`](#method.send_interceptor_add)
+	pub fn send_interceptors_clear(&mut self) {
+		self.fat_handlers.borrow_mut().send_interceptors.clear();
+	}
+
+	/// Runs the `stanza` through the send interceptors, returns `None` if none of them replaced it
+	pub(crate) fn intercept_outgoing(&self, stanza: &Stanza) -> Option<Stanza> {
+		let mut out: Option<Stanza> = None;
+		for interceptor in &mut self.fat_handlers.borrow_mut().send_interceptors {
+			if let Some(replaced) = interceptor(out.as_ref().unwrap_or(stanza)) {
+				out = Some(replaced);
+			}
+		}
+		out
+	}
+
+	/// Sets the `xml:lang` of the stream header sent by [`open_stream_default()`](#method.open_stream_default), `None`
+	/// restores the default `en`
+	///
+	/// Only the raw connections can use it. libstrophe writes the opening stream header of the client and component
+	/// connects itself and always declares `xml:lang="en"` there. It has no API to change that, so the language of a
+	/// client connection can't be configured. Use [`connect_raw()`](#method.connect_raw) with
+	/// [`open_stream_default()`](#method.open_stream_default) if the server needs a different language in the header.
+	pub fn set_xml_lang(&mut self, lang: Option<&str>) {
+		self.fat_handlers.borrow_mut().stream_lang = lang.map(str::to_owned);
+	}
+
+	/// Language set with [`set_xml_lang()`](#method.set_xml_lang)
+	pub fn xml_lang(&self) -> Option<String> {
+		self.fat_handlers.borrow().stream_lang.clone()
+	}
+
+	/// Overrides the `version` of the stream header sent by [`open_stream_default()`](#method.open_stream_default), `None`
+	/// restores the default `1.0`
+	///
+	/// Only the raw connections can use it, libstrophe always declares version `1.0` on the client and component connects.
+	pub fn set_stream_version(&mut self, version: Option<&str>) {
+		self.fat_handlers.borrow_mut().stream_version = version.map(str::to_owned);
+	}
+
+	#[inline]
+	/// Enables the validation of the outgoing stanzas against the session type, disabled by default
+	///
+	/// In the strict mode [`send()`](#method.send) and [`try_send()`](#method.try_send) reject the stanzas that the server
+	/// would answer with a stream error closing the connection, see [`validate_outgoing()`](#method.validate_outgoing).
+	pub fn set_strict_send(&mut self, enabled: bool) {
+		self.fat_handlers.borrow_mut().strict_send = enabled;
+	}
+
+	/// Checks that the top-level `stanza` (`<message/>`, `<presence/>` or `<iq/>`) is valid for the session type
+	///
+	/// The stanzas of the component sessions ([XEP-0114](https://xmpp.org/extensions/xep-0114.html)) must have both the
+	/// `to` and the `from` attributes and the `from` must be within the component domain. The client sessions can omit the
+	/// `from` attribute, but when it's present it must match the JID of the session, see
+	/// [RFC 6120](https://www.rfc-editor.org/rfc/rfc6120#section-8.1.2.1). Other elements and the stanzas of the
+	/// connections that weren't started yet are not checked.
+	pub fn validate_outgoing(&self, stanza: &Stanza) -> Result<(), SendValidationError> {
+		if !matches!(stanza.name(), Some("message" | "presence" | "iq")) {
+			return Ok(());
+		}
+		let own = match self
+			.bound_jid()
+			.or_else(|| self.jid())
+			.and_then(|jid| jid.parse::<Jid>().ok())
+		{
+			Some(own) => own,
+			None => return Ok(()),
+		};
+		let parse_from = |from: &str| {
+			from
+				.parse::<Jid>()
+				.map_err(|_| SendValidationError::InvalidFrom(from.to_owned()))
+		};
+		match self.conn_type() {
+			ConnType::XMPP_COMPONENT => {
+				stanza.to().ok_or(SendValidationError::MissingTo)?;
+				let from = stanza.from().ok_or(SendValidationError::MissingFrom)?;
+				if !parse_from(from)?.domain().eq_ignore_ascii_case(own.domain()) {
+					return Err(SendValidationError::ForeignFrom {
+						from: from.to_owned(),
+						domain: own.domain().to_owned(),
+					});
+				}
+			}
+			ConnType::XMPP_CLIENT => {
+				if let Some(from) = stanza.from() {
+					let from_jid = parse_from(from)?;
+					let resource_matches = match (from_jid.resource(), own.resource()) {
+						(Some(from_resource), Some(own_resource)) => from_resource == own_resource,
+						_ => true,
+					};
+					if !from_jid.bare().eq_ignore_ascii_case(own.bare()) || !resource_matches {
+						return Err(SendValidationError::SpoofedFrom {
+							from: from.to_owned(),
+							jid: own.as_str().to_owned(),
+						});
+					}
+				}
+			}
+			_ => {}
+		}
+		Ok(())
+	}
+
+	/// Sends the message encrypted with the [EncryptionPlugin] installed with
+	/// [`set_encryption_plugin()`](#method.set_encryption_plugin)
+	///
+	/// The plugin decides whether the message is actually encrypted, see [EncryptionPlugin::encrypt]. Nothing is sent if
+	/// the encryption fails, [Error::InvalidOperation] is returned (wrapped in [EncryptionError::Stanza]) if no plugin is
+	/// installed or if `stanza` is not a message.
+	pub fn send_encrypted(&mut self, stanza: &Stanza) -> Result<(), EncryptionError> {
+		let encrypted = match &mut self.fat_handlers.borrow_mut().encryption {
+			Some(encryption) if stanza.name() == Some("message") => encryption.plugin.encrypt(stanza)?,
+			_ => return Err(Error::InvalidOperation.into()),
+		};
+		let stanza = encrypted.as_ref().unwrap_or(stanza);
+		self.observe_sent(stanza);
+		unsafe { sys::xmpp_send(self.inner.as_mut(), stanza.as_ptr()) }
+		Ok(())
+	}
+
+	/// Installs the end-to-end encryption `plugin` replacing the previous one, see the [encryption](crate::encryption)
+	/// module
+	///
+	/// The outgoing messages are then encrypted by [`send()`](#method.send) and the incoming messages carrying the payload
+	/// of [EncryptionPlugin::namespace] are decrypted before they're passed to the stanza handlers. If the decryption fails
+	/// the error is logged and the handlers receive the message as is.
+	pub fn set_encryption_plugin(&mut self, plugin: impl EncryptionPlugin + 'cb) {
+		self.fat_handlers.borrow_mut().encryption = Some(EncryptionState {
+			plugin: Box::new(plugin),
+			decrypted: None,
+		});
+	}
+
+	/// Removes the plugin installed with [`set_encryption_plugin()`](#method.set_encryption_plugin)
+	pub fn clear_encryption_plugin(&mut self) {
+		self.fat_handlers.borrow_mut().encryption = None;
+	}
+
+	pub(crate) fn encryption_plugin_device_list(&self) -> Option<(String, Stanza)> {
+		self
+			.fat_handlers
+			.borrow()
+			.encryption
+			.as_ref()
+			.and_then(|encryption| encryption.plugin.device_list())
+	}
+
+	/// Returns the decrypted version of the incoming `stanza` if it's encrypted for the installed plugin
+	///
+	/// `original` is the stanza received from libstrophe, it's used to decrypt the stanza only once for all the handlers
+	/// it's dispatched to.
+	fn decrypt_incoming(&self, original: &Stanza, stanza: &Stanza) -> Option<Stanza> {
+		let mut fat_handlers = self.fat_handlers.borrow_mut();
+		let encryption = fat_handlers.encryption.as_mut()?;
+		if stanza.name() != Some("message") {
+			return None;
 		}
-		let callback = Self::connection_handler_cb::<CB>;
-		let new_handler = Some(self.make_fat_handler(Box::new(handler) as _, callback as _, ()));
-		let old_handler = mem::replace(&mut self.fat_handlers.borrow_mut().connection, new_handler);
-		let out = unsafe {
-			sys::xmpp_connect_raw(
-				self.inner.as_mut(),
-				alt_host.as_ptr(),
-				alt_port.val(),
-				Some(callback),
-				as_void_ptr(self.fat_handlers.borrow().connection.as_ref().unwrap()),
-			)
+		if let Some((cached_original, decrypted)) = &encryption.decrypted {
+			if cached_original.as_ptr() == original.as_ptr() {
+				return Some(decrypted.share());
+			}
 		}
-		.into_result();
-		match out {
-			Ok(_) => {
-				let mut out = self.ctx.take().expect("Internal context is empty, it must never happen");
-				out.consume_connection(self);
-				Ok(out)
+		let namespace = encryption.plugin.namespace().to_owned();
+		let payload = encryption::payload(stanza, &namespace)?;
+		let plugin = &mut encryption.plugin;
+		let res = catch_panic(
+			unsafe { self.context_detached() },
+			Err(EncryptionError::Failed("the plugin panicked".to_owned())),
+			|| plugin.decrypt(stanza, &payload),
+		)
+		.and_then(|content| Ok(encryption::unframe(stanza, &namespace, content)?));
+		match res {
+			Ok(decrypted) => {
+				encryption.decrypted = Some((original.share(), decrypted.share()));
+				Some(decrypted)
 			}
 			Err(e) => {
-				self.fat_handlers.borrow_mut().connection = old_handler;
-				Err(ConnectClientError { conn: self, error: e })
+				drop(fat_handlers);
+				self.log(LogLevel::XMPP_LEVEL_WARN, &format!("Cannot decrypt incoming message: {}", e));
+				None
 			}
 		}
 	}
 
-	#[inline]
-	/// [xmpp_conn_open_stream_default](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga73e477d4abfd439bcd27ddf78d601c0f)
+	/// Sets the callback receiving the inbound data of this connection, replacing the previous one
 	///
-	/// Related to [`connect_raw()`](#method.connect_raw).
-	pub fn open_stream_default(&self) -> Result<()> {
-		unsafe { sys::xmpp_conn_open_stream_default(self.inner.as_ptr()) }.into_result()
+	/// libstrophe doesn't expose its read buffer, so the tap is fed with every incoming stanza as serialized by
+	/// [xmpp_stanza_to_text](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga2918484877ac34d483cc14cf5e957fad)
+	/// before it's passed to the handlers added after the tap. This is the parsed data, so the formatting (e.g. the
+	/// attribute quotes or whitespace) can differ from the bytes on the wire. Like the other handlers the tap only
+	/// receives the stanzas after the authentication is complete, see also
+	/// [`set_traffic_log_policy()`](#method.set_traffic_log_policy) which logs the same data
+	pub fn set_rx_tap(&mut self, tap: impl FnMut(&[u8]) + Send + 'cb) {
+		self.fat_handlers.borrow_mut().rx_tap = Some(Box::new(tap));
+		self.sync_raw_observer();
 	}
 
-	/// [xmpp_conn_open_stream](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga747589e1fdf44891c601958742d115b7)
+	/// Removes the callback set with [`set_rx_tap()`](#method.set_rx_tap)
+	pub fn clear_rx_tap(&mut self) {
+		self.fat_handlers.borrow_mut().rx_tap = None;
+		self.sync_raw_observer();
+	}
+
+	/// Starts mirroring the stream management state from the SM elements, see [SmMirror]
 	///
-	/// Related to [`connect_raw()`](#method.connect_raw).
-	pub fn open_stream(&self, attributes: &HashMap<&str, &str>) -> Result<()> {
-		let mut storage = Vec::with_capacity(attributes.len() * 2);
-		for attr in attributes {
-			storage.push(FFI(*attr.0).send());
-			storage.push(FFI(*attr.1).send());
+	/// Pass [SmMirror::default()] to start from scratch or the state saved from the previous session to continue its
+	/// counters, the mirror is updated when the server enables or resumes the stream management. The current state is
+	/// available with [`sm_mirror()`](#method.sm_mirror).
+	pub fn set_sm_mirror(&mut self, sm_mirror: SmMirror) {
+		self.fat_handlers.borrow_mut().sm_mirror = Some(sm_mirror);
+		self.sync_raw_observer();
+	}
+
+	/// Returns the state mirrored since [`set_sm_mirror()`](#method.set_sm_mirror)
+	pub fn sm_mirror(&self) -> Option<SmMirror> {
+		self.fat_handlers.borrow().sm_mirror.clone()
+	}
+
+	/// Stops mirroring the stream management state, returns the last state
+	pub fn clear_sm_mirror(&mut self) -> Option<SmMirror> {
+		let sm_mirror = self.fat_handlers.borrow_mut().sm_mirror.take();
+		self.sync_raw_observer();
+		sm_mirror
+	}
+
+	/// Installs the catch-all handler feeding the incoming stanzas to the traffic log, the rx tap, the SM mirror and the
+	/// byte counters when any of them is enabled and removes it when none of them is
+	fn sync_raw_observer(&self) {
+		let mut fat_handlers = self.fat_handlers.borrow_mut();
+		let active = !matches!(fat_handlers.traffic_log_policy, TrafficLogPolicy::Off)
+			|| fat_handlers.rx_tap.is_some()
+			|| fat_handlers.sm_mirror.is_some()
+			|| fat_handlers.byte_counters.is_some();
+		if active == fat_handlers.raw_observer {
+			return;
+		}
+		fat_handlers.raw_observer = active;
+		drop(fat_handlers);
+		if active {
+			unsafe {
+				sys::xmpp_handler_add(
+					self.inner.as_ptr(),
+					Some(Self::raw_observer_cb),
+					ptr::null(),
+					ptr::null(),
+					ptr::null(),
+					Rc::as_ptr(&self.fat_handlers) as _,
+				)
+			}
+		} else {
+			unsafe { sys::xmpp_handler_delete(self.inner.as_ptr(), Some(Self::raw_observer_cb)) }
 		}
-		let mut attrs = storage.iter().map(|s| s.as_ptr() as *mut _).collect::<Vec<_>>();
-		unsafe { sys::xmpp_conn_open_stream(self.inner.as_ptr(), attrs.as_mut_ptr(), attrs.len()) }.into_result()
 	}
 
-	#[inline]
-	/// [xmpp_conn_tls_start](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga65a92215a59a365f89e908e90178f7b8)
+	fn observe_sent(&self, stanza: &Stanza) {
+		let mut fat_handlers = self.fat_handlers.borrow_mut();
+		if let Some(sm_mirror) = &mut fat_handlers.sm_mirror {
+			sm_mirror.observe_outgoing(stanza);
+		}
+		if let Some(byte_counters) = &mut fat_handlers.byte_counters {
+			// the estimate is only used if the stanza can't be serialized, libstrophe then fails to send it too
+			let len = stanza
+				.write_to(&mut io::sink())
+				.unwrap_or_else(|_| stanza.byte_size_estimate());
+			byte_counters.sent += len as u64;
+		}
+	}
+
+	fn count_sent(&self, len: usize) {
+		if let Some(byte_counters) = &mut self.fat_handlers.borrow_mut().byte_counters {
+			byte_counters.sent += len as u64;
+		}
+	}
+
+	/// Starts counting the bytes sent and received by this connection, see [`bytes_sent()`](#method.bytes_sent) and
+	/// [`bytes_received()`](#method.bytes_received)
 	///
-	/// Related to [`connect_raw()`](#method.connect_raw).
-	pub fn tls_start(&self) -> Result<()> {
-		unsafe { sys::xmpp_conn_tls_start(self.inner.as_ptr()) }.into_result()
+	/// The stanzas are counted by the length of their serialized form and the data passed to
+	/// [`send_raw()`](#method.send_raw) by its length, so the enabled counters serialize every stanza once more.
+	/// libstrophe doesn't expose its socket I/O, so the incoming side is observed through a catch-all stanza handler: only
+	/// the stanzas received after the authentication are counted. The stream headers, the stream negotiation and the TLS
+	/// overhead are not counted in either direction. The counts are kept if the counters are already enabled.
+	pub fn enable_byte_counters(&mut self) {
+		self
+			.fat_handlers
+			.borrow_mut()
+			.byte_counters
+			.get_or_insert_with(ByteCounters::default);
+		self.sync_raw_observer();
+	}
+
+	/// Stops counting the bytes and resets the counters to zero
+	pub fn disable_byte_counters(&mut self) {
+		self.fat_handlers.borrow_mut().byte_counters = None;
+		self.sync_raw_observer();
 	}
 
 	#[inline]
-	/// [xmpp_disconnect](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#gaa635ceddb5941d011e290073f7552355)
-	pub fn disconnect(&mut self) {
-		unsafe { sys::xmpp_disconnect(self.inner.as_mut()) }
+	/// Number of bytes sent since [`enable_byte_counters()`](#method.enable_byte_counters), 0 if it wasn't called
+	pub fn bytes_sent(&self) -> u64 {
+		self
+			.fat_handlers
+			.borrow()
+			.byte_counters
+			.map_or(0, |byte_counters| byte_counters.sent)
 	}
 
 	#[inline]
-	/// [xmpp_send_raw_string](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#gaf67110aced5d20909069d33d17bec025)
+	/// Number of bytes received since [`enable_byte_counters()`](#method.enable_byte_counters), 0 if it wasn't called
+	pub fn bytes_received(&self) -> u64 {
+		self
+			.fat_handlers
+			.borrow()
+			.byte_counters
+			.map_or(0, |byte_counters| byte_counters.received)
+	}
+
+	/// Calls `handler` every `period` with the traffic of the connection during the last period
 	///
-	/// Be aware that this method performs a lot of allocations internally so you might want to use
-	/// [`send_raw()`](#method.send_raw) instead.
-	pub fn send_raw_string(&mut self, data: impl AsRef<str>) {
-		let data = FFI(data.as_ref()).send();
-		unsafe {
-			sys::xmpp_send_raw_string(self.inner.as_mut(), data.as_ptr());
+	/// The byte counters are enabled if they aren't yet, see [`enable_byte_counters()`](#method.enable_byte_counters). Like
+	/// the other timed handlers it only fires while the connection is established, the first event after the reconnect
+	/// includes the traffic since the last event before the disconnect. The returned id can be passed to
+	/// [`timed_handler_delete()`](#method.timed_handler_delete).
+	pub fn throughput_handler_add<CB>(
+		&mut self,
+		mut handler: CB,
+		period: Duration,
+	) -> Option<TimedHandlerId<'cb, 'cx, impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>) -> HandlerResult + Send + 'cb>>
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, Throughput) + Send + 'cb,
+	{
+		self.enable_byte_counters();
+		let mut last = (self.bytes_sent(), self.bytes_received(), Instant::now());
+		self.timed_handler_add(
+			move |ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>| {
+				let now = (conn.bytes_sent(), conn.bytes_received(), Instant::now());
+				let throughput = Throughput {
+					// the counters could have been reset in the meantime
+					sent: now.0.saturating_sub(last.0),
+					received: now.1.saturating_sub(last.1),
+					period: now.2 - last.2,
+				};
+				last = now;
+				handler(ctx, conn, throughput);
+				HandlerResult::KeepHandler
+			},
+			period,
+		)
+	}
+
+	/// Registers the `module` receiving the stanzas of its namespaces and the connection events, see [XmppModule]
+	///
+	/// All of the modules share a single stanza handler that's installed together with the first module, the modules are
+	/// called in the order of registration. Note that [`handlers_clear()`](#method.handlers_clear) removes that handler too.
+	pub fn register_module(&mut self, module: Box<dyn XmppModule + 'cb>) -> ModuleId {
+		let id = ModuleId::next();
+		let install = {
+			let mut fat_handlers = self.fat_handlers.borrow_mut();
+			fat_handlers.modules.push((id, Some(module)));
+			fat_handlers.modules.len() == 1
+		};
+		if install {
+			// it's a no-op if the handler is still installed
+			self.handler_add(|ctx, conn, stanza| conn.dispatch_to_modules(ctx, stanza), None, None, None);
 		}
+		id
 	}
 
-	/// [xmpp_send_raw](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#gaa1be7bdb58f3610b7997f1186d87c896)
-	pub fn send_raw(&mut self, data: impl AsRef<[u8]>) {
-		let data = data.as_ref();
-		#[cfg(feature = "log")]
-		if log::log_enabled!(log::Level::Debug) {
-			use crate::LogLevel;
-			use std::fmt::Write;
-
-			let ctx = unsafe { sys::xmpp_conn_get_context(self.inner.as_ptr()) };
-			let mut data_str = "SENT: ".to_owned();
-			if let Ok(data) = str::from_utf8(data) {
-				write!(&mut data_str, "{}", data).expect("Can't write to string");
-			} else {
-				write!(&mut data_str, "{:?}", data).expect("Can't write to string");
+	/// Removes the module added with [`register_module()`](#method.register_module), returns `false` if it's not registered
+	///
+	/// It can be called from the module callbacks, the module is then dropped once its callback returns.
+	pub fn unregister_module(&mut self, id: ModuleId) -> bool {
+		let mut fat_handlers = self.fat_handlers.borrow_mut();
+		let len = fat_handlers.modules.len();
+		fat_handlers.modules.retain(|(module_id, _)| *module_id != id);
+		fat_handlers.modules.len() != len
+	}
+
+	fn dispatch_to_modules(&mut self, ctx: &Context, stanza: &Stanza) -> HandlerResult {
+		let ids = self.module_ids();
+		if ids.is_empty() {
+			return HandlerResult::RemoveHandler;
+		}
+		for id in ids {
+			self.with_module(id, |module, conn| {
+				if module::matches_ns(stanza, module.namespaces()) {
+					module.on_stanza(ctx, conn, stanza);
+				}
+			});
+		}
+		HandlerResult::KeepHandler
+	}
+
+	fn notify_modules(&mut self, ctx: &Context, event: &ConnectionEvent) {
+		for id in self.module_ids() {
+			self.with_module(id, |module, conn| match event {
+				ConnectionEvent::Connect => module.on_connect(ctx, conn),
+				ConnectionEvent::Disconnect(error) => module.on_disconnect(ctx, conn, error.as_ref()),
+				ConnectionEvent::RawConnect => {}
+			});
+		}
+	}
+
+	fn module_ids(&self) -> Vec<ModuleId> {
+		self.fat_handlers.borrow().modules.iter().map(|(id, _)| *id).collect()
+	}
+
+	/// Calls `f` with the module taken out of its slot so that it can access the connection
+	fn with_module(&mut self, id: ModuleId, f: impl FnOnce(&mut (dyn XmppModule + 'cb), &mut Self)) {
+		let module = self
+			.fat_handlers
+			.borrow_mut()
+			.modules
+			.iter_mut()
+			.find(|(module_id, _)| *module_id == id)
+			.and_then(|(_, module)| module.take());
+		if let Some(mut module) = module {
+			f(module.as_mut(), self);
+			// the module might have been unregistered while running
+			if let Some((_, slot)) = self
+				.fat_handlers
+				.borrow_mut()
+				.modules
+				.iter_mut()
+				.find(|(module_id, _)| *module_id == id)
+			{
+				slot.get_or_insert(module);
 			}
-			unsafe {
-				crate::context::ctx_log(ctx, LogLevel::XMPP_LEVEL_DEBUG, "conn", &data_str);
+		}
+	}
+
+	/// Enables the wrapper-level outbound queue for the stanzas sent with [Connection::send_with_priority]
+	///
+	/// Every `interval` the queued stanzas are passed to libstrophe in the [SendPriority] order until `budget` bytes of
+	/// serialized stanzas is reached, so that the bulk transfers don't delay the pings and presences. The queue is only
+	/// drained while the connection is established. Calling it again on the enabled queue changes the `budget` and the
+	/// `interval` of the same queue, the queue is always run by a single timed handler.
+	pub fn enable_outbound_queue(&mut self, budget: usize, interval: Duration) {
+		let reschedule = {
+			let mut fat_handlers = self.fat_handlers.borrow_mut();
+			match &mut fat_handlers.outbound_queue {
+				Some(queue) => {
+					queue.budget = budget;
+					mem::replace(&mut queue.interval, interval) != interval
+				}
+				None => {
+					fat_handlers.outbound_queue = Some(OutboundQueue::new(budget, interval));
+					true
+				}
 			}
+		};
+		if reschedule {
+			// the timer of the previous queue might still be installed if it didn't fire since the queue was disabled
+			self.timed_handler_remove_by_type(Self::outbound_queue_timer);
+			self.timed_handler_add(Self::outbound_queue_timer, interval);
 		}
-		unsafe {
-			sys::xmpp_send_raw(self.inner.as_mut(), data.as_ptr() as _, data.len());
+	}
+
+	/// Disables the outbound queue enabled with [Connection::enable_outbound_queue], the queued stanzas are passed to
+	/// libstrophe immediately
+	pub fn disable_outbound_queue(&mut self) {
+		self.run_outbound_queue(true);
+		self.fat_handlers.borrow_mut().outbound_queue = None;
+		self.timed_handler_remove_by_type(Self::outbound_queue_timer);
+	}
+
+	fn outbound_queue_timer(_ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>) -> HandlerResult {
+		if conn.fat_handlers.borrow().outbound_queue.is_none() {
+			return HandlerResult::RemoveHandler;
 		}
+		conn.run_outbound_queue(false);
+		HandlerResult::KeepHandler
 	}
 
-	#[inline]
-	/// [xmpp_send](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#ga0e879d34b2ea28c08cacbb012eadfbc1)
-	pub fn send(&mut self, stanza: &Stanza) {
-		unsafe { sys::xmpp_send(self.inner.as_mut(), stanza.as_ptr()) }
+	/// Sends the stanza through the outbound queue with the specified `priority`, see [Connection::enable_outbound_queue]
+	///
+	/// Use [SendPriority::of] for the default classification. The stanza is sent immediately with [Connection::send] if
+	/// the queue is not enabled.
+	pub fn send_with_priority(&mut self, stanza: &Stanza, priority: SendPriority) {
+		if let Some(queue) = &mut self.fat_handlers.borrow_mut().outbound_queue {
+			queue.push(stanza.clone(), priority);
+			return;
+		}
+		self.send(stanza)
+	}
+
+	/// Number of the stanzas with `priority` waiting in the outbound queue
+	pub fn outbound_queue_len(&self, priority: SendPriority) -> usize {
+		self
+			.fat_handlers
+			.borrow()
+			.outbound_queue
+			.as_ref()
+			.map_or(0, |queue| queue.len(priority))
+	}
+
+	fn run_outbound_queue(&mut self, all: bool) {
+		let batch = self
+			.fat_handlers
+			.borrow_mut()
+			.outbound_queue
+			.as_mut()
+			.map_or_else(Vec::new, |queue| {
+				if all {
+					queue.take_all()
+				} else {
+					queue.take_batch()
+				}
+			});
+		for stanza in batch {
+			self.send(&stanza);
+		}
 	}
 
 	/// [xmpp_timed_handler_add](https://strophe.im/libstrophe/doc/0.12.2/group___handlers.html#ga5835cd8c81174d06d35953e8b13edccb)
@@ -773,7 +2881,7 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>) -> HandlerResult + Send + 'cb,
 	{
 		let callback = Self::timed_handler_cb::<CB>;
-		let handler = self.make_fat_handler(Box::new(handler) as _, callback as _, ());
+		let handler = self.make_fat_handler(Box::new(handler) as _, callback as _, period);
 		let fat_handlers = Rc::clone(&self.fat_handlers);
 		let timed_handlers = &mut fat_handlers.borrow_mut().timed;
 		Self::store_fat_handler(timed_handlers, handler).map(|fat_handler_ptr| {
@@ -801,6 +2909,19 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 		Self::drop_fat_handler(&mut self.fat_handlers.borrow_mut().timed, handler_id.0 as _);
 	}
 
+	/// Removes the timed handler of type `CB`, for the internal handlers that don't keep their [TimedHandlerId]
+	fn timed_handler_remove_by_type<CB>(&mut self, _handler: CB)
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>) -> HandlerResult + Send + 'cb,
+	{
+		let callback = Self::timed_handler_cb::<CB>;
+		let mut fat_handlers = self.fat_handlers.borrow_mut();
+		if let Some(pos) = Self::get_fat_handler_pos_by_callback(&fat_handlers.timed, callback as _) {
+			unsafe { sys::xmpp_timed_handler_delete(self.inner.as_mut(), Some(callback)) }
+			fat_handlers.timed.remove(pos);
+		}
+	}
+
 	/// See [Connection::handlers_clear] for additional information.
 	pub fn timed_handlers_clear(&mut self) {
 		for handler in self.fat_handlers.borrow_mut().timed.drain(..) {
@@ -820,15 +2941,18 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 		let id = id.into();
 		let ffi_id = FFI(id.as_str()).send();
 		let callback = Self::handler_cb::<CB>;
-		let handler = self.make_fat_handler(Box::new(handler) as _, callback as _, Some(id));
+		let handler = self.make_fat_handler(Box::new(handler) as _, callback as _, StanzaFilter::Id(id.clone()));
 		let fat_handlers = Rc::clone(&self.fat_handlers);
-		let stanza_handlers = &mut fat_handlers.borrow_mut().stanza;
-		Self::store_fat_handler(stanza_handlers, handler).map(|fat_handler_ptr| {
+		let out = Self::store_fat_handler(&mut fat_handlers.borrow_mut().stanza, handler).map(|fat_handler_ptr| {
 			unsafe {
 				sys::xmpp_id_handler_add(self.inner.as_mut(), Some(callback), ffi_id.as_ptr(), fat_handler_ptr as _);
 			}
 			IdHandlerId(fat_handler_ptr as _)
-		})
+		});
+		if out.is_some() {
+			self.register_cancellable_id(id);
+		}
+		out
 	}
 
 	/// [xmpp_id_handler_delete](https://strophe.im/libstrophe/doc/0.12.2/group___handlers.html#gaee081149b7c6889b6b692a44b407d42d)
@@ -840,8 +2964,10 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 	{
 		#![allow(clippy::needless_pass_by_value)]
 		if let Some(fat_handler) = Self::validate_fat_handler(&self.fat_handlers.borrow().stanza, handler_id.0 as _) {
-			let id = FFI(fat_handler.extra.as_ref().unwrap().as_str()).send();
-			unsafe { sys::xmpp_id_handler_delete(self.inner.as_mut(), Some(Self::handler_cb::<CB>), id.as_ptr()) }
+			if let StanzaFilter::Id(id) = &fat_handler.extra {
+				let id = FFI(id.as_str()).send();
+				unsafe { sys::xmpp_id_handler_delete(self.inner.as_mut(), Some(Self::handler_cb::<CB>), id.as_ptr()) }
+			}
 		}
 		Self::drop_fat_handler(&mut self.fat_handlers.borrow_mut().stanza, handler_id.0 as _);
 	}
@@ -849,7 +2975,7 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 	/// See [Connection::handlers_clear] for additional information.
 	pub fn id_handlers_clear(&mut self) {
 		self.fat_handlers.borrow_mut().stanza.retain(|x| {
-			if let Some(ref id) = x.extra {
+			if let StanzaFilter::Id(id) = &x.extra {
 				unsafe {
 					sys::xmpp_id_handler_delete(
 						self.inner.as_ptr(),
@@ -869,6 +2995,13 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 	/// [xmpp_handler](https://strophe.im/libstrophe/doc/0.12.2/strophe_8h.html#a079ae14399be93d363164ad35d434496)
 	///
 	/// This function returns [HandlerId] which is later can be used to remove the handler using [Connection::handler_delete].
+	///
+	/// For each incoming stanza libstrophe first calls the id handlers (see [Connection::id_handler_add]) registered for the
+	/// `id` of the stanza and then the handlers added with this function whose filter matches, both in the order of
+	/// registration. The handlers are only called after the connection is authenticated, so [ConnectionEvent::Connect] is
+	/// always delivered to the connection handler before any stanza. The handlers added while the stanza is being
+	/// dispatched are not called for that stanza. Use [Connection::handler_chain_add] when the relative order of the id and
+	/// the ordinary handlers matters.
 	pub fn handler_add<CB>(
 		&mut self,
 		handler: CB,
@@ -879,11 +3012,16 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 	where
 		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb,
 	{
+		let filter = StanzaFilter::Stanza {
+			ns: ns.map(str::to_owned),
+			name: name.map(str::to_owned),
+			typ: typ.map(str::to_owned),
+		};
 		let ns = FFI(ns).send();
 		let name = FFI(name).send();
 		let typ = FFI(typ).send();
 		let callback = Self::handler_cb::<CB>;
-		let handler = self.make_fat_handler(Box::new(handler) as _, callback as _, None);
+		let handler = self.make_fat_handler(Box::new(handler) as _, callback as _, filter);
 		let fat_handlers = Rc::clone(&self.fat_handlers);
 		let stanza_handlers = &mut fat_handlers.borrow_mut().stanza;
 		Self::store_fat_handler(stanza_handlers, handler).map(|fat_handler_ptr| {
@@ -901,6 +3039,331 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 		})
 	}
 
+	/// Registers the handlers of the `chain` as a single catch-all handler
+	///
+	/// The wrapper calls the matching handlers of the chain strictly in the order they were added to it, regardless of
+	/// whether they're id or ordinary handlers, see [HandlerChain]. The chain is removed once all of its handlers return
+	/// [HandlerResult::RemoveHandler]. Only one chain can be registered for the connection at a time, `false` is returned
+	/// if there is one already.
+	pub fn handler_chain_add(&mut self, chain: HandlerChain<'cb, 'cx>) -> bool {
+		{
+			let mut fat_handlers = self.fat_handlers.borrow_mut();
+			if fat_handlers.handler_chain.is_some() {
+				return false;
+			}
+			fat_handlers.handler_chain = Some(chain);
+		}
+		// it's a no-op if the handler is still installed
+		self.handler_add(|ctx, conn, stanza| conn.dispatch_handler_chain(ctx, stanza), None, None, None);
+		true
+	}
+
+	fn dispatch_handler_chain(&mut self, ctx: &Context<'cx, 'cb>, stanza: &Stanza) -> HandlerResult {
+		let chain = self.fat_handlers.borrow_mut().handler_chain.take();
+		let mut chain = match chain {
+			Some(chain) => chain,
+			None => return HandlerResult::RemoveHandler,
+		};
+		chain.dispatch(ctx, self, stanza);
+		let mut fat_handlers = self.fat_handlers.borrow_mut();
+		// the chain could have been replaced from one of its handlers
+		if fat_handlers.handler_chain.is_none() && !chain.is_empty() {
+			fat_handlers.handler_chain = Some(chain);
+		}
+		if fat_handlers.handler_chain.is_some() {
+			HandlerResult::KeepHandler
+		} else {
+			HandlerResult::RemoveHandler
+		}
+	}
+
+	/// Same as [`timed_handler_add()`](#method.timed_handler_add), but the handler returns `Result`
+	///
+	/// The error is passed to the hook set with [`set_handler_error_hook()`](#method.set_handler_error_hook) and the handler
+	/// is kept registered.
+	pub fn try_timed_handler_add<CB, E>(
+		&mut self,
+		mut handler: CB,
+		period: Duration,
+	) -> Option<TimedHandlerId<'cb, 'cx, impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>) -> HandlerResult + Send + 'cb>>
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>) -> result::Result<HandlerResult, E> + Send + 'cb,
+		E: Into<HandlerError>,
+	{
+		self.timed_handler_add(
+			move |ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>| {
+				handler(ctx, conn).unwrap_or_else(|e| {
+					conn.fire_handler_error(HandlerKind::Timed, e.into());
+					HandlerResult::KeepHandler
+				})
+			},
+			period,
+		)
+	}
+
+	/// Same as [`id_handler_add()`](#method.id_handler_add), but the handler returns `Result`
+	///
+	/// The error is passed to the hook set with [`set_handler_error_hook()`](#method.set_handler_error_hook). The response
+	/// with the matching id has already arrived at this point so the handler is removed.
+	pub fn try_id_handler_add<CB, E>(
+		&mut self,
+		mut handler: CB,
+		id: impl Into<String>,
+	) -> Option<
+		IdHandlerId<'cb, 'cx, impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb>,
+	>
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> result::Result<HandlerResult, E> + Send + 'cb,
+		E: Into<HandlerError>,
+	{
+		self.id_handler_add(
+			move |ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>, stanza: &Stanza| {
+				handler(ctx, conn, stanza).unwrap_or_else(|e| {
+					conn.fire_handler_error(HandlerKind::Id, e.into());
+					HandlerResult::RemoveHandler
+				})
+			},
+			id,
+		)
+	}
+
+	/// Same as [`handler_add()`](#method.handler_add), but the handler returns `Result`
+	///
+	/// The error is passed to the hook set with [`set_handler_error_hook()`](#method.set_handler_error_hook) and the handler
+	/// is kept registered.
+	pub fn try_handler_add<CB, E>(
+		&mut self,
+		mut handler: CB,
+		ns: Option<&str>,
+		name: Option<&str>,
+		typ: Option<&str>,
+	) -> Option<
+		HandlerId<'cb, 'cx, impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb>,
+	>
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> result::Result<HandlerResult, E> + Send + 'cb,
+		E: Into<HandlerError>,
+	{
+		self.handler_add(
+			move |ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>, stanza: &Stanza| {
+				handler(ctx, conn, stanza).unwrap_or_else(|e| {
+					conn.fire_handler_error(HandlerKind::Stanza, e.into());
+					HandlerResult::KeepHandler
+				})
+			},
+			ns,
+			name,
+			typ,
+		)
+	}
+
+	/// Same as [`handler_add()`](#method.handler_add), but the handler receives [DispatchCtx] with the time budget information
+	/// instead of the plain [Context]
+	///
+	/// The returned [HandlerId] can be passed to [`handler_delete()`](#method.handler_delete) as usual.
+	pub fn dispatch_handler_add<CB>(
+		&mut self,
+		mut handler: CB,
+		ns: Option<&str>,
+		name: Option<&str>,
+		typ: Option<&str>,
+	) -> Option<
+		HandlerId<'cb, 'cx, impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb>,
+	>
+	where
+		CB: FnMut(&DispatchCtx<'_, 'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb,
+	{
+		self.handler_add(
+			move |ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>, stanza: &Stanza| {
+				handler(&DispatchCtx::new(ctx), conn, stanza)
+			},
+			ns,
+			name,
+			typ,
+		)
+	}
+
+	/// Stores `value` in the extensions of the connection, returns the previous value of the same type
+	///
+	/// The extensions are the per-connection state, one value per type, that lives as long as the connection. They're
+	/// passed to the handlers added with [`state_handler_add()`](#method.state_handler_add) and friends, much like the
+	/// `userdata` of the C API.
+	pub fn extension_insert<T: Any + Send>(&mut self, value: T) -> Option<T> {
+		self
+			.fat_handlers
+			.borrow_mut()
+			.extensions
+			.insert(TypeId::of::<T>(), Box::new(value))
+			.and_then(|old| old.downcast().ok())
+			.map(|old| *old)
+	}
+
+	/// Removes the extension of type `T` and returns it
+	pub fn extension_remove<T: Any + Send>(&mut self) -> Option<T> {
+		self
+			.fat_handlers
+			.borrow_mut()
+			.extensions
+			.remove(&TypeId::of::<T>())
+			.and_then(|value| value.downcast().ok())
+			.map(|value| *value)
+	}
+
+	#[inline]
+	pub fn has_extension<T: Any + Send>(&self) -> bool {
+		self.fat_handlers.borrow().extensions.contains_key(&TypeId::of::<T>())
+	}
+
+	/// Calls `f` with the extension of type `T`, returns `None` without calling it if there is no such extension
+	///
+	/// The extension is taken out for the duration of the call, so `f` can use the connection freely. If `f` inserts another
+	/// extension of the same type, that one is kept.
+	pub fn with_extension<T: Any + Send, R>(&mut self, f: impl FnOnce(&mut T, &mut Self) -> R) -> Option<R> {
+		let type_id = TypeId::of::<T>();
+		let mut value = self.fat_handlers.borrow_mut().extensions.remove(&type_id)?;
+		let out = value.downcast_mut().map(|value| f(value, self));
+		self.fat_handlers.borrow_mut().extensions.entry(type_id).or_insert(value);
+		out
+	}
+
+	/// Same as [`handler_add()`](#method.handler_add), but the handler also receives the extension of type `S` as its first
+	/// argument, see [`extension_insert()`](#method.extension_insert)
+	///
+	/// This eases porting the C code that keeps its state in the `userdata`: the handler can be a plain function that doesn't
+	/// capture anything. It's not called, but kept registered, while there is no extension of type `S`. The handlers are
+	/// unique per type, so pass the function itself and not a `fn` pointer it was coerced to.
+	pub fn state_handler_add<S, F>(
+		&mut self,
+		mut handler: F,
+		ns: Option<&str>,
+		name: Option<&str>,
+		typ: Option<&str>,
+	) -> Option<
+		HandlerId<'cb, 'cx, impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb>,
+	>
+	where
+		S: Any + Send,
+		F: FnMut(&mut S, &Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb,
+	{
+		self.handler_add(
+			move |ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>, stanza: &Stanza| {
+				conn
+					.with_extension(|state, conn| handler(state, ctx, conn, stanza))
+					.unwrap_or(HandlerResult::KeepHandler)
+			},
+			ns,
+			name,
+			typ,
+		)
+	}
+
+	/// Same as [`id_handler_add()`](#method.id_handler_add), but the handler also receives the extension of type `S`, see
+	/// [`state_handler_add()`](#method.state_handler_add)
+	pub fn state_id_handler_add<S, F>(
+		&mut self,
+		mut handler: F,
+		id: impl Into<String>,
+	) -> Option<
+		IdHandlerId<'cb, 'cx, impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb>,
+	>
+	where
+		S: Any + Send,
+		F: FnMut(&mut S, &Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb,
+	{
+		self.id_handler_add(
+			move |ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>, stanza: &Stanza| {
+				conn
+					.with_extension(|state, conn| handler(state, ctx, conn, stanza))
+					.unwrap_or(HandlerResult::KeepHandler)
+			},
+			id,
+		)
+	}
+
+	/// Same as [`timed_handler_add()`](#method.timed_handler_add), but the handler also receives the extension of type `S`,
+	/// see [`state_handler_add()`](#method.state_handler_add)
+	pub fn state_timed_handler_add<S, F>(
+		&mut self,
+		mut handler: F,
+		period: Duration,
+	) -> Option<TimedHandlerId<'cb, 'cx, impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>) -> HandlerResult + Send + 'cb>>
+	where
+		S: Any + Send,
+		F: FnMut(&mut S, &Context<'cx, 'cb>, &mut Connection<'cb, 'cx>) -> HandlerResult + Send + 'cb,
+	{
+		self.timed_handler_add(
+			move |ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>| {
+				conn
+					.with_extension(|state, conn| handler(state, ctx, conn))
+					.unwrap_or(HandlerResult::KeepHandler)
+			},
+			period,
+		)
+	}
+
+	/// Same as [`handler_add()`](#method.handler_add), but the handler is not called for the duplicate stanzas
+	///
+	/// Every handler gets its own [DedupCache] with the specified `capacity`, the duplicates are silently skipped keeping the
+	/// handler registered.
+	pub fn dedup_handler_add<CB>(
+		&mut self,
+		mut handler: CB,
+		ns: Option<&str>,
+		name: Option<&str>,
+		typ: Option<&str>,
+		capacity: usize,
+	) -> Option<
+		HandlerId<'cb, 'cx, impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb>,
+	>
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb,
+	{
+		let mut cache = DedupCache::new(capacity);
+		self.handler_add(
+			move |ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>, stanza: &Stanza| {
+				if cache.check(stanza) {
+					HandlerResult::KeepHandler
+				} else {
+					handler(ctx, conn, stanza)
+				}
+			},
+			ns,
+			name,
+			typ,
+		)
+	}
+
+	/// Same as [`handler_add()`](#method.handler_add), but the handler is only called for the stanzas with the `from`
+	/// attribute matching the `from` pattern
+	///
+	/// The stanzas without the `from` attribute or with a malformed one are skipped keeping the handler registered.
+	pub fn handler_add_from<CB>(
+		&mut self,
+		mut handler: CB,
+		from: JidMatcher,
+		ns: Option<&str>,
+		name: Option<&str>,
+		typ: Option<&str>,
+	) -> Option<
+		HandlerId<'cb, 'cx, impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb>,
+	>
+	where
+		CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb,
+	{
+		self.handler_add(
+			move |ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>, stanza: &Stanza| {
+				if stanza.from().map_or(false, |jid| from.matches(jid)) {
+					handler(ctx, conn, stanza)
+				} else {
+					HandlerResult::KeepHandler
+				}
+			},
+			ns,
+			name,
+			typ,
+		)
+	}
+
 	/// [xmpp_handler_delete](https://strophe.im/libstrophe/doc/0.12.2/group___handlers.html#gaf4fa6f67b11dee0158739c907ba71adb)
 	///
 	/// This version of this function accepts `HandlerId` returned from `add_handler()` function instead of function reference as the underlying
@@ -918,7 +3381,7 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 	/// this function if you can't keep track of specific closure handles returned from `handler_add()`, but want to remove handlers anyway.
 	pub fn handlers_clear(&mut self) {
 		self.fat_handlers.borrow_mut().stanza.retain(|x| {
-			if x.extra.is_none() {
+			if let StanzaFilter::Stanza { .. } = x.extra {
 				unsafe { sys::xmpp_handler_delete(self.inner.as_ptr(), Some(mem::transmute(x.cb_addr))) };
 				false
 			} else {
@@ -928,6 +3391,45 @@ impl<'cb, 'cx> Connection<'cb, 'cx> {
 		self.fat_handlers.borrow_mut().stanza.shrink_to_fit();
 	}
 
+	/// Removes all stanza, id and timed handlers for which `predicate` returns `true`, returns the number of removed handlers
+	///
+	/// The predicate receives the filters the handler was registered with, e.g. to remove all handlers for a namespace
+	/// without keeping track of the individual handler ids. The handlers are removed from libstrophe and from the
+	/// wrapper in one pass.
+	pub fn handlers_remove_where(&mut self, mut predicate: impl FnMut(&HandlerInfo) -> bool) -> usize {
+		let mut removed = 0;
+		let inner = self.inner.as_ptr();
+		let mut fat_handlers = self.fat_handlers.borrow_mut();
+		fat_handlers.stanza.retain(|x| {
+			if !predicate(&x.extra.info()) {
+				return true;
+			}
+			match &x.extra {
+				StanzaFilter::Id(id) => unsafe {
+					sys::xmpp_id_handler_delete(
+						inner,
+						Some(mem::transmute::<*const (), StanzaHandlerFn>(x.cb_addr)),
+						FFI(id.as_str()).send().as_ptr(),
+					)
+				},
+				StanzaFilter::Stanza { .. } => unsafe {
+					sys::xmpp_handler_delete(inner, Some(mem::transmute::<*const (), StanzaHandlerFn>(x.cb_addr)))
+				},
+			}
+			removed += 1;
+			false
+		});
+		fat_handlers.timed.retain(|x| {
+			if !predicate(&HandlerInfo::Timed { period: x.extra }) {
+				return true;
+			}
+			unsafe { sys::xmpp_timed_handler_delete(inner, Some(mem::transmute::<*const (), TimedHandlerFn>(x.cb_addr))) };
+			removed += 1;
+			false
+		});
+		removed
+	}
+
 	#[allow(dead_code)]
 	pub(crate) fn timed_handlers_same<L, R>(_left: L, _right: R) -> bool
 	where
@@ -986,6 +3488,12 @@ impl Drop for Connection<'_, '_> {
 					handlers.remove(&handler_id);
 				}
 			}
+			#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+			if let Ok(mut options) = SOCKET_OPTIONS.write() {
+				options.remove(&(self.inner.as_ptr() as usize));
+			}
+			#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+			forget_socket(self.inner.as_ptr());
 			unsafe {
 				sys::xmpp_conn_release(self.inner.as_mut());
 			}
@@ -995,6 +3503,31 @@ impl Drop for Connection<'_, '_> {
 
 unsafe impl Send for Connection<'_, '_> {}
 
+thread_local! {
+	/// Stanzas currently passed to the handlers together with the connection they were received on, innermost last
+	static DISPATCHED_STANZAS: RefCell<Vec<(usize, ConnectionToken)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Token of the connection that received the `stanza` if it's currently being passed to a handler
+pub(crate) fn dispatched_stanza_connection(stanza: *mut sys::xmpp_stanza_t) -> Option<ConnectionToken> {
+	DISPATCHED_STANZAS.with(|dispatched| {
+		dispatched
+			.borrow()
+			.iter()
+			.rev()
+			.find(|(ptr, _)| *ptr == stanza as usize)
+			.map(|(_, token)| *token)
+	})
+}
+
+/// Opaque identity of the connection, see [Connection::token]
+///
+/// Tokens of the connections are equal if and only if they refer to the same underlying `xmpp_conn_t`. Use them as keys of
+/// the per-connection state shared between the handlers of several connections. A token of the released connection can be
+/// reused by a new connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnectionToken(usize);
+
 pub struct HandlerId<'cb, 'cx, CB>(*const FatHandler<'cb, 'cx, CB, ()>);
 
 impl<CB> fmt::Debug for HandlerId<'_, '_, CB> {
@@ -1038,6 +3571,44 @@ impl fmt::Display for ConnectionEvent<'_, '_> {
 	}
 }
 
+/// Owned version of [ConnectionEvent]
+#[derive(Debug, Clone)]
+pub enum OwnedConnectionEvent {
+	RawConnect,
+	Connect,
+	Disconnect(Option<OwnedConnectionError>),
+}
+
+impl From<&ConnectionEvent<'_, '_>> for OwnedConnectionEvent {
+	fn from(s: &ConnectionEvent) -> Self {
+		match s {
+			ConnectionEvent::RawConnect => OwnedConnectionEvent::RawConnect,
+			ConnectionEvent::Connect => OwnedConnectionEvent::Connect,
+			ConnectionEvent::Disconnect(e) => OwnedConnectionEvent::Disconnect(e.as_ref().map(OwnedConnectionError::from)),
+		}
+	}
+}
+
+impl fmt::Display for OwnedConnectionEvent {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			OwnedConnectionEvent::RawConnect => write!(f, "Raw connect"),
+			OwnedConnectionEvent::Connect => write!(f, "Connect"),
+			OwnedConnectionEvent::Disconnect(None) => write!(f, "Disconnect"),
+			OwnedConnectionEvent::Disconnect(Some(e)) => write!(f, "Disconnect, error: {}", e),
+		}
+	}
+}
+
+/// Entry of the [Connection::event_history]
+#[derive(Debug, Clone)]
+pub struct ConnectionEventRecord {
+	pub time: SystemTime,
+	/// Connection that emitted the event
+	pub connection: ConnectionId,
+	pub event: OwnedConnectionEvent,
+}
+
 #[test]
 fn callbacks() {
 	{
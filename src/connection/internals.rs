@@ -1,25 +1,35 @@
-#[cfg(any(feature = "libstrophe-0_11_0", feature = "libstrophe-0_12_0"))]
-use std::any::TypeId;
+use std::any::{Any, TypeId};
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 #[cfg(feature = "libstrophe-0_12_0")]
 use std::ffi::c_void;
 use std::fmt;
 #[cfg(any(feature = "libstrophe-0_11_0", feature = "libstrophe-0_12_0"))]
 use std::os::raw::{c_char, c_int};
-use std::rc::Weak;
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
 
 #[cfg(feature = "libstrophe-0_11_0")]
 pub use libstrophe_0_11::*;
 #[cfg(feature = "libstrophe-0_12_0")]
 pub use libstrophe_0_12::*;
 
-use crate::{Connection, ConnectionEvent, Context, Stanza};
+#[cfg(feature = "libstrophe-0_11_0")]
+use crate::context::catch_panic;
+use crate::encryption::EncryptionPlugin;
+use crate::proxy::{Proxy, ProxyTunnel};
+use crate::{
+	CancelToken, ConnType, Connection, ConnectionError, ConnectionEvent, ConnectionEventRecord, Context, Logger, ModuleId,
+	SmMirror, Stanza, StreamError, XmppModule,
+};
 
 #[cfg(feature = "libstrophe-0_11_0")]
 mod libstrophe_0_11 {
 	use std::any::TypeId;
 	use std::collections::HashMap;
 	use std::sync::RwLock;
+	use std::time::Duration;
 
 	use once_cell::sync::Lazy;
 
@@ -28,6 +38,14 @@ mod libstrophe_0_11 {
 	pub type CertFailCallback = dyn Fn(&TlsCert, &str) -> CertFailResult + Send + Sync;
 	pub static CERT_FAIL_HANDLERS: Lazy<RwLock<HashMap<TypeId, Box<CertFailCallback>>>> = Lazy::new(Default::default);
 
+	pub type CertExpiryCallback<'cb> = dyn FnMut(&TlsCert, Duration) + Send + 'cb;
+
+	/// Opt-in check of the peer certificate expiration done after the connection is established
+	pub struct CertExpiryWarning<'cb> {
+		pub window: Duration,
+		pub callback: Box<CertExpiryCallback<'cb>>,
+	}
+
 	#[derive(Debug)]
 	#[repr(i32)]
 	pub enum CertFailResult {
@@ -41,12 +59,14 @@ mod libstrophe_0_12 {
 	use std::any::TypeId;
 	use std::collections::HashMap;
 	use std::ffi::c_void;
+	use std::fmt;
 	use std::sync::RwLock;
+	use std::time::Duration;
 
 	use once_cell::sync::Lazy;
 
 	use crate::connection::internals::FatHandler;
-	use crate::Connection;
+	use crate::{Connection, Stanza};
 
 	pub type SockoptCallback = dyn Fn(*mut c_void) -> SockoptResult + Send + Sync;
 	pub static SOCKOPT_HANDLERS: Lazy<RwLock<HashMap<TypeId, Box<SockoptCallback>>>> = Lazy::new(Default::default);
@@ -58,6 +78,39 @@ mod libstrophe_0_12 {
 		Error = -1,
 	}
 
+	/// TCP keepalive settings, see [Connection::set_keepalive_opts]
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+	pub struct KeepaliveOpts {
+		/// Time the connection must stay idle before the first keepalive probe is sent, rounded down to seconds
+		pub idle: Duration,
+		/// Time between the keepalive probes, rounded down to seconds
+		pub interval: Duration,
+		/// Number of unanswered probes before the connection is dropped, `None` uses the system default
+		pub count: Option<u32>,
+	}
+
+	pub type ResendCallback<'cb> = dyn FnMut(&Stanza) -> bool + Send + 'cb;
+
+	/// What to do with the stanzas that were never sent in the previous session, see [Connection::set_resend_policy]
+	pub enum ResendPolicy<'cb> {
+		/// Send all of the stanzas again after reconnecting
+		ResendAll,
+		/// Discard the stanzas, they are still available through [Connection::unacked_stanzas] until the next disconnect
+		ResendNone,
+		/// Resend only the stanzas for which the callback returns `true`
+		Decide(Box<ResendCallback<'cb>>),
+	}
+
+	impl fmt::Debug for ResendPolicy<'_> {
+		fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			match self {
+				ResendPolicy::ResendAll => write!(f, "ResendAll"),
+				ResendPolicy::ResendNone => write!(f, "ResendNone"),
+				ResendPolicy::Decide(_) => write!(f, "Decide"),
+			}
+		}
+	}
+
 	pub type PasswordCallback<'cb, 'cx> = dyn Fn(&Connection<'cb, 'cx>, usize) -> Option<String> + Send + 'cb;
 	pub type PasswordFatHandler<'cb, 'cx> = FatHandler<'cb, 'cx, PasswordCallback<'cb, 'cx>, ()>;
 }
@@ -75,13 +128,511 @@ pub type ConnectionFatHandler<'cb, 'cx> = FatHandler<'cb, 'cx, ConnectionCallbac
 pub type Handlers<H> = Vec<Box<H>>;
 
 pub type TimedCallback<'cb, 'cx> = dyn FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>) -> HandlerResult + Send + 'cb;
-pub type TimedFatHandler<'cb, 'cx> = FatHandler<'cb, 'cx, TimedCallback<'cb, 'cx>, ()>;
+pub type TimedFatHandler<'cb, 'cx> = FatHandler<'cb, 'cx, TimedCallback<'cb, 'cx>, Duration>;
 
 pub type StanzaCallback<'cb, 'cx> =
 	dyn FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb;
-pub type StanzaFatHandler<'cb, 'cx> = FatHandler<'cb, 'cx, StanzaCallback<'cb, 'cx>, Option<String>>;
+pub type StanzaFatHandler<'cb, 'cx> = FatHandler<'cb, 'cx, StanzaCallback<'cb, 'cx>, StanzaFilter>;
+
+/// Filter the stanza handler was registered with
+#[derive(Debug, Clone)]
+pub enum StanzaFilter {
+	Id(String),
+	Stanza {
+		ns: Option<String>,
+		name: Option<String>,
+		typ: Option<String>,
+	},
+}
+
+impl StanzaFilter {
+	/// Whether the handler with this filter is called for `stanza`, the rules are the same as in libstrophe
+	pub fn matches(&self, stanza: &Stanza) -> bool {
+		match self {
+			StanzaFilter::Id(id) => stanza.id() == Some(id.as_str()),
+			StanzaFilter::Stanza { ns, name, typ } => {
+				ns.as_deref()
+					.map_or(true, |ns| stanza.ns() == Some(ns) || stanza.get_child_by_ns(ns).is_some())
+					&& name.as_deref().map_or(true, |name| stanza.name() == Some(name))
+					&& typ.as_deref().map_or(true, |typ| stanza.stanza_type() == Some(typ))
+			}
+		}
+	}
+
+	pub fn info(&self) -> HandlerInfo<'_> {
+		match self {
+			StanzaFilter::Id(id) => HandlerInfo::Id { id },
+			StanzaFilter::Stanza { ns, name, typ } => HandlerInfo::Stanza {
+				ns: ns.as_deref(),
+				name: name.as_deref(),
+				typ: typ.as_deref(),
+			},
+		}
+	}
+}
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Process-wide unique id of the [Connection] assigned at its creation, see [Connection::id]
+///
+/// Both `Display` and `Debug` format it as `conn#7`, the crate prefixes its own log lines about the connection with it.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConnectionId(u64);
+
+impl ConnectionId {
+	pub(crate) fn next() -> Self {
+		Self(NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed))
+	}
+
+	#[inline]
+	pub fn as_u64(self) -> u64 {
+		self.0
+	}
+}
+
+impl fmt::Display for ConnectionId {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "conn#{}", self.0)
+	}
+}
+
+impl fmt::Debug for ConnectionId {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Display::fmt(self, f)
+	}
+}
+
+/// Stanza handlers called in a fixed order by the wrapper, see [Connection::handler_chain_add]
+///
+/// The handlers are checked in the order they were added to the chain regardless of their kind, unlike libstrophe which
+/// calls the id handlers before the rest. The handler returning [HandlerResult::RemoveHandler] is removed from the chain.
+pub struct HandlerChain<'cb, 'cx> {
+	handlers: Vec<(StanzaFilter, Box<StanzaCallback<'cb, 'cx>>)>,
+}
+
+impl<'cb, 'cx> HandlerChain<'cb, 'cx> {
+	pub fn new() -> Self {
+		Self { handlers: vec![] }
+	}
+
+	/// Appends the handler with the same filter as [Connection::handler_add]
+	pub fn handler(
+		mut self,
+		ns: Option<&str>,
+		name: Option<&str>,
+		typ: Option<&str>,
+		handler: impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb,
+	) -> Self {
+		let filter = StanzaFilter::Stanza {
+			ns: ns.map(str::to_owned),
+			name: name.map(str::to_owned),
+			typ: typ.map(str::to_owned),
+		};
+		self.handlers.push((filter, Box::new(handler)));
+		self
+	}
+
+	/// Appends the handler with the same filter as [Connection::id_handler_add]
+	pub fn id_handler(
+		mut self,
+		id: impl Into<String>,
+		handler: impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb,
+	) -> Self {
+		self.handlers.push((StanzaFilter::Id(id.into()), Box::new(handler)));
+		self
+	}
+
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.handlers.len()
+	}
+
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.handlers.is_empty()
+	}
+
+	pub fn dispatch(&mut self, ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>, stanza: &Stanza) -> HandlerResult {
+		let mut i = 0;
+		while i < self.handlers.len() {
+			let (filter, handler) = &mut self.handlers[i];
+			if filter.matches(stanza) && matches!(handler(ctx, conn, stanza), HandlerResult::RemoveHandler) {
+				drop(self.handlers.remove(i));
+			} else {
+				i += 1;
+			}
+		}
+		if self.handlers.is_empty() {
+			HandlerResult::RemoveHandler
+		} else {
+			HandlerResult::KeepHandler
+		}
+	}
+}
+
+impl Default for HandlerChain<'_, '_> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl fmt::Debug for HandlerChain<'_, '_> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_list()
+			.entries(self.handlers.iter().map(|(filter, _)| filter))
+			.finish()
+	}
+}
+
+/// Registration data of the handler passed to the predicate of
+/// [Connection::handlers_remove_where](crate::Connection::handlers_remove_where) and to the hook set with
+/// [Connection::set_slow_handler_hook](crate::Connection::set_slow_handler_hook)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandlerInfo<'i> {
+	/// Handler added with [Connection::timed_handler_add](crate::Connection::timed_handler_add)
+	Timed { period: Duration },
+	/// Handler added with [Connection::id_handler_add](crate::Connection::id_handler_add)
+	Id { id: &'i str },
+	/// Handler added with [Connection::handler_add](crate::Connection::handler_add)
+	Stanza {
+		ns: Option<&'i str>,
+		name: Option<&'i str>,
+		typ: Option<&'i str>,
+	},
+}
+
+pub type SlowHandlerHook<'cb, 'cx> =
+	dyn FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &HandlerInfo, Duration) + Send + 'cb;
+
+pub type IdGenerator<'cb> = dyn Fn() -> String + Send + 'cb;
+
+/// Handling of the incoming stanzas containing invalid UTF-8, see
+/// [Connection::set_utf8_policy](crate::Connection::set_utf8_policy)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Utf8Policy {
+	/// The stanzas are passed to the handlers as is, the string accessors panic on invalid UTF-8
+	///
+	/// The panic doesn't unwind into libstrophe, it stops the event loop and is resumed by
+	/// [Context::run](crate::Context::run) like any other panic of a handler.
+	Strict,
+	/// The handlers receive a copy of the stanza with the invalid sequences replaced by `U+FFFD REPLACEMENT CHARACTER`
+	Lossy,
+	/// The stanzas are not passed to the handlers and a warning is logged instead
+	Reject,
+}
+
+impl Default for Utf8Policy {
+	#[inline]
+	fn default() -> Self {
+		Utf8Policy::Strict
+	}
+}
+
+pub type RedactCallback<'cb> = dyn Fn(&[u8]) -> String + Send + 'cb;
+
+pub type RxTap<'cb> = dyn FnMut(&[u8]) + Send + 'cb;
+
+/// Installed [EncryptionPlugin] with the cache of the last decrypted incoming stanza
+pub struct EncryptionState<'cb> {
+	pub plugin: Box<dyn EncryptionPlugin + 'cb>,
+	/// The original incoming stanza and its decrypted version, the reference to the original keeps it alive so that its
+	/// address is not reused while it's being compared against
+	pub decrypted: Option<(Stanza, Stanza)>,
+}
+
+/// Logging of the data sent with [Connection::send_raw](crate::Connection::send_raw) and of the received stanzas, see
+/// [Connection::set_traffic_log_policy](crate::Connection::set_traffic_log_policy)
+pub enum TrafficLogPolicy<'cb> {
+	/// Nothing is logged
+	Off,
+	/// Only the size of the payload is logged
+	Metadata,
+	/// The whole payload is logged as is, it can include the credentials
+	Full,
+	/// The result of the callback receiving the payload is logged
+	Redacted(Box<RedactCallback<'cb>>),
+}
+
+impl Default for TrafficLogPolicy<'_> {
+	#[inline]
+	fn default() -> Self {
+		TrafficLogPolicy::Off
+	}
+}
+
+impl fmt::Debug for TrafficLogPolicy<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			TrafficLogPolicy::Off => write!(f, "Off"),
+			TrafficLogPolicy::Metadata => write!(f, "Metadata"),
+			TrafficLogPolicy::Full => write!(f, "Full"),
+			TrafficLogPolicy::Redacted(_) => write!(f, "Redacted"),
+		}
+	}
+}
+
+/// Traffic of the connection during the period of the throughput handler, see
+/// [Connection::throughput_handler_add](crate::Connection::throughput_handler_add)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throughput {
+	/// Bytes sent during the period
+	pub sent: u64,
+	/// Bytes received during the period
+	pub received: u64,
+	/// Actual length of the period
+	pub period: Duration,
+}
+
+impl Throughput {
+	/// Average sending rate in bytes per second
+	pub fn sent_per_sec(&self) -> f64 {
+		per_sec(self.sent, self.period)
+	}
+
+	/// Average receiving rate in bytes per second
+	pub fn received_per_sec(&self) -> f64 {
+		per_sec(self.received, self.period)
+	}
+}
+
+fn per_sec(bytes: u64, period: Duration) -> f64 {
+	if period.is_zero() {
+		0.
+	} else {
+		bytes as f64 / period.as_secs_f64()
+	}
+}
+
+/// Totals of the byte counters of the connection
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByteCounters {
+	pub sent: u64,
+	pub received: u64,
+}
+
+pub type SendInterceptor<'cb> = dyn FnMut(&Stanza) -> Option<Stanza> + Send + 'cb;
+
+pub type CancelCallback<'cb, 'cx> = dyn FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>) + Send + 'cb;
+
+pub type IqResponseCallback<'cb, 'cx> = dyn FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) + Send + 'cb;
+
+/// Operation started with [Connection::with_cancel_token](crate::Connection::with_cancel_token)
+pub struct Cancellable<'cb, 'cx> {
+	pub token: CancelToken,
+	/// Ids of the pending id handlers of the operation
+	pub ids: Vec<String>,
+	pub on_cancel: Vec<Box<CancelCallback<'cb, 'cx>>>,
+}
+
+impl fmt::Debug for Cancellable<'_, '_> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Cancellable")
+			.field("token", &self.token)
+			.field("ids", &self.ids)
+			.field("on_cancel", &format!("{} callbacks", self.on_cancel.len()))
+			.finish()
+	}
+}
+
+/// Priority class of the stanza sent through the outbound queue, see
+/// [Connection::send_with_priority](crate::Connection::send_with_priority)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SendPriority {
+	/// Stream control traffic like IQs (including pings) and presences, sent first
+	Control,
+	/// Chat messages
+	Chat,
+	/// Bulk transfers like MAM paging or IBB data, sent when nothing else is waiting
+	Bulk,
+}
+
+impl SendPriority {
+	const ALL: [SendPriority; 3] = [SendPriority::Control, SendPriority::Chat, SendPriority::Bulk];
+
+	/// Default class of the stanza: IQs and presences are [SendPriority::Control], everything else is [SendPriority::Chat]
+	pub fn of(stanza: &Stanza) -> Self {
+		match stanza.name() {
+			Some("iq" | "presence") => SendPriority::Control,
+			_ => SendPriority::Chat,
+		}
+	}
+}
+
+/// Wrapper-level outbound queue drained into libstrophe in priority order, see
+/// [Connection::enable_outbound_queue](crate::Connection::enable_outbound_queue)
+#[derive(Debug)]
+pub struct OutboundQueue {
+	/// Maximum number of bytes passed to libstrophe per run of the queue
+	pub budget: usize,
+	/// Period of the timed handler running the queue
+	pub interval: Duration,
+	/// Stanzas with their estimated serialized size, one queue per [SendPriority]
+	queues: [VecDeque<(Stanza, usize)>; 3],
+}
+
+impl OutboundQueue {
+	pub fn new(budget: usize, interval: Duration) -> Self {
+		Self {
+			budget,
+			interval,
+			queues: Default::default(),
+		}
+	}
+
+	pub fn push(&mut self, stanza: Stanza, priority: SendPriority) {
+		let size = stanza.byte_size_estimate();
+		self.queues[priority as usize].push_back((stanza, size));
+	}
+
+	pub fn len(&self, priority: SendPriority) -> usize {
+		self.queues[priority as usize].len()
+	}
+
+	/// Takes the stanzas that fit into the budget, higher priority first
+	///
+	/// Lower priority stanzas never overtake the higher priority ones. At least one stanza is taken on each run so that a
+	/// stanza larger than the budget is not stuck forever.
+	pub fn take_batch(&mut self) -> Vec<Stanza> {
+		let mut out = vec![];
+		let mut spent = 0;
+		for priority in SendPriority::ALL {
+			let queue = &mut self.queues[priority as usize];
+			while let Some((_, size)) = queue.front() {
+				if !out.is_empty() && spent + size > self.budget {
+					return out;
+				}
+				spent += size;
+				if let Some((stanza, _)) = queue.pop_front() {
+					out.push(stanza);
+				}
+			}
+		}
+		out
+	}
+
+	/// Takes all queued stanzas in the priority order
+	pub fn take_all(&mut self) -> Vec<Stanza> {
+		self
+			.queues
+			.iter_mut()
+			.flat_map(|queue| queue.drain(..).map(|(stanza, _)| stanza))
+			.collect()
+	}
+}
+
+/// Kind of the failed handler passed to the hook set with
+/// [Connection::set_handler_error_hook](crate::Connection::set_handler_error_hook)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HandlerKind {
+	/// Handler added with [Connection::try_timed_handler_add](crate::Connection::try_timed_handler_add)
+	Timed,
+	/// Handler added with [Connection::try_id_handler_add](crate::Connection::try_id_handler_add)
+	Id,
+	/// Handler added with [Connection::try_handler_add](crate::Connection::try_handler_add)
+	Stanza,
+}
+
+/// Error returned from the fallible handlers
+pub type HandlerError = Box<dyn std::error::Error + Send + Sync>;
+
+pub type HandlerErrorHook<'cb, 'cx> =
+	dyn FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, HandlerKind, HandlerError) + Send + 'cb;
+
+pub type StreamErrorCallback<'cb, 'cx> = dyn FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &StreamError) + Send + 'cb;
+
+pub type ConnectCallback<'cb, 'cx> = dyn FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>) + Send + 'cb;
+
+pub type DisconnectCallback<'cb, 'cx> =
+	dyn FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, Option<&ConnectionError>) + Send + 'cb;
+
+/// State of the automatic `see-other-host` redirection
+#[derive(Debug, Default)]
+pub struct Redirect {
+	pub max_hops: u32,
+	pub hops: u32,
+	pub client: bool,
+}
+
+/// Connection state for the libstrophe versions that don't expose it, updated by the `connect_*()` methods and the
+/// connection handler
+#[cfg(not(feature = "libstrophe-0_10_0"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+	Disconnected,
+	Connecting,
+	Connected,
+}
+
+/// Bounded history of the connection events, see [Connection::event_history]
+#[derive(Debug)]
+pub struct EventHistory {
+	pub capacity: usize,
+	pub events: VecDeque<ConnectionEventRecord>,
+}
+
+impl EventHistory {
+	pub const DEFAULT_CAPACITY: usize = 16;
+
+	pub fn push(&mut self, connection: ConnectionId, event: &ConnectionEvent) {
+		if self.capacity == 0 {
+			return;
+		}
+		while self.events.len() >= self.capacity {
+			self.events.pop_front();
+		}
+		self.events.push_back(ConnectionEventRecord {
+			time: SystemTime::now(),
+			connection,
+			event: event.into(),
+		});
+	}
+
+	pub fn set_capacity(&mut self, capacity: usize) {
+		self.capacity = capacity;
+		while self.events.len() > capacity {
+			self.events.pop_front();
+		}
+	}
+}
+
+impl Default for EventHistory {
+	fn default() -> Self {
+		Self {
+			capacity: Self::DEFAULT_CAPACITY,
+			events: VecDeque::with_capacity(Self::DEFAULT_CAPACITY),
+		}
+	}
+}
+
+/// Receives `None` if the connection is lost before the features are known
+pub type FeatureWaiter<'cb, 'cx> =
+	dyn FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, Option<&HashSet<String>>) + Send + 'cb;
+
+/// Features of the server and the account, see [Connection::server_supports]
+pub enum ServerFeatures<'cb, 'cx> {
+	Unknown,
+	Pending {
+		remaining: usize,
+		features: HashSet<String>,
+		waiters: Vec<Box<FeatureWaiter<'cb, 'cx>>>,
+	},
+	Known(HashSet<String>),
+}
+
+impl fmt::Debug for ServerFeatures<'_, '_> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ServerFeatures::Unknown => write!(f, "Unknown"),
+			ServerFeatures::Pending { remaining, waiters, .. } => {
+				write!(f, "Pending ({} responses, {} waiters)", remaining, waiters.len())
+			}
+			ServerFeatures::Known(features) => write!(f, "Known ({} features)", features.len()),
+		}
+	}
+}
 
 pub struct FatHandlers<'cb, 'cx> {
+	pub id: ConnectionId,
 	pub connection: Option<ConnectionFatHandler<'cb, 'cx>>,
 	pub timed: Handlers<TimedFatHandler<'cb, 'cx>>,
 	pub stanza: Handlers<StanzaFatHandler<'cb, 'cx>>,
@@ -91,11 +642,67 @@ pub struct FatHandlers<'cb, 'cx> {
 	pub sockopt_handler_id: Option<TypeId>,
 	#[cfg(feature = "libstrophe-0_12_0")]
 	pub password: Handlers<PasswordFatHandler<'cb, 'cx>>,
+	pub id_generator: Option<Box<IdGenerator<'cb>>>,
+	pub stream_error: Option<Box<StreamErrorCallback<'cb, 'cx>>>,
+	/// Taken out while they're being called
+	pub on_connect: Handlers<ConnectCallback<'cb, 'cx>>,
+	/// Taken out while they're being called
+	pub on_disconnect: Handlers<DisconnectCallback<'cb, 'cx>>,
+	pub handler_error_hook: Option<Box<HandlerErrorHook<'cb, 'cx>>>,
+	pub handler_budget: Option<Duration>,
+	pub slow_handler_hook: Option<Box<SlowHandlerHook<'cb, 'cx>>>,
+	pub redirect: Redirect,
+	/// Alternative host and port of the last `connect_client()`, reused by [Connection::reconnect]
+	pub client_alt: (Option<String>, Option<u16>),
+	pub stream_lang: Option<String>,
+	pub stream_version: Option<String>,
+	pub auto_resource: bool,
+	pub strict_send: bool,
+	pub conn_type: ConnType,
+	pub utf8_policy: Utf8Policy,
+	pub traffic_log_policy: TrafficLogPolicy<'cb>,
+	/// Shared with the active log routes so that it can be replaced while the messages are routed to it
+	pub logger: Option<Rc<Logger<'cb>>>,
+	pub outbound_queue: Option<OutboundQueue>,
+	pub rx_tap: Option<Box<RxTap<'cb>>>,
+	pub sm_mirror: Option<SmMirror>,
+	pub byte_counters: Option<ByteCounters>,
+	/// Whether the catch-all handler feeding `traffic_log_policy`, `rx_tap`, `sm_mirror` and `byte_counters` is installed
+	pub raw_observer: bool,
+	pub send_interceptors: Vec<Box<SendInterceptor<'cb>>>,
+	/// Token of the operation that's currently being started or continued, see [Connection::with_cancel_token]
+	pub cancel_scope: Option<CancelToken>,
+	pub cancellables: Vec<Cancellable<'cb, 'cx>>,
+	/// Pending IQ requests by id, see [Connection::iq_response_handler_add]
+	pub iq_responses: HashMap<String, Box<IqResponseCallback<'cb, 'cx>>>,
+	/// Per-connection state keyed by its type, see [Connection::extension_insert]
+	pub extensions: HashMap<TypeId, Box<dyn Any + Send>>,
+	/// Modules in the order of registration, the module is taken out of its slot while it's being called
+	pub modules: Vec<(ModuleId, Option<Box<dyn XmppModule + 'cb>>)>,
+	/// Taken out while it's being dispatched
+	pub handler_chain: Option<HandlerChain<'cb, 'cx>>,
+	pub encryption: Option<EncryptionState<'cb>>,
+	#[cfg(feature = "libstrophe-0_11_0")]
+	pub cert_expiry_warning: Option<CertExpiryWarning<'cb>>,
+	pub event_history: EventHistory,
+	pub pending_pass: Option<String>,
+	#[cfg(not(feature = "libstrophe-0_10_0"))]
+	pub link_state: LinkState,
+	pub server_features: ServerFeatures<'cb, 'cx>,
+	pub proxy: Option<Proxy>,
+	pub proxy_tunnel: Option<ProxyTunnel>,
+	/// Server to connect to when the JID domain was replaced by [Connection::set_tls_hostname]
+	pub tls_route: Option<String>,
+	#[cfg(feature = "libstrophe-0_12_0")]
+	pub resend_policy: Option<ResendPolicy<'cb>>,
+	#[cfg(feature = "libstrophe-0_12_0")]
+	pub unacked: Vec<Stanza>,
 }
 
 impl fmt::Debug for FatHandlers<'_, '_> {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		let mut s = f.debug_struct("FatHandlers");
+		s.field("id", &self.id);
 		s.field(
 			"connection",
 			&if self.connection.is_some() {
@@ -126,6 +733,91 @@ impl fmt::Debug for FatHandlers<'_, '_> {
 		);
 		#[cfg(feature = "libstrophe-0_12_0")]
 		s.field("password", &format!("{} handlers", self.password.len()));
+		s.field(
+			"id_generator",
+			&if self.id_generator.is_some() {
+				"set"
+			} else {
+				"unset"
+			},
+		);
+		s.field(
+			"stream_error",
+			&if self.stream_error.is_some() {
+				"set"
+			} else {
+				"unset"
+			},
+		);
+		s.field("on_connect", &format!("{} callbacks", self.on_connect.len()));
+		s.field("on_disconnect", &format!("{} callbacks", self.on_disconnect.len()));
+		s.field(
+			"handler_error_hook",
+			&if self.handler_error_hook.is_some() {
+				"set"
+			} else {
+				"unset"
+			},
+		);
+		s.field("handler_budget", &self.handler_budget);
+		s.field(
+			"slow_handler_hook",
+			&if self.slow_handler_hook.is_some() {
+				"set"
+			} else {
+				"unset"
+			},
+		);
+		s.field("redirect", &self.redirect);
+		s.field("client_alt", &self.client_alt);
+		s.field("stream_lang", &self.stream_lang);
+		s.field("stream_version", &self.stream_version);
+		s.field("auto_resource", &self.auto_resource);
+		s.field("strict_send", &self.strict_send);
+		s.field("conn_type", &self.conn_type);
+		s.field("utf8_policy", &self.utf8_policy);
+		s.field("traffic_log_policy", &self.traffic_log_policy);
+		s.field("logger", &self.logger);
+		s.field("outbound_queue", &self.outbound_queue);
+		s.field(
+			"rx_tap",
+			&if self.rx_tap.is_some() {
+				"set"
+			} else {
+				"unset"
+			},
+		);
+		s.field("sm_mirror", &self.sm_mirror);
+		s.field("byte_counters", &self.byte_counters);
+		s.field("raw_observer", &self.raw_observer);
+		s.field("send_interceptors", &format!("{} interceptors", self.send_interceptors.len()));
+		s.field("cancel_scope", &self.cancel_scope);
+		s.field("cancellables", &self.cancellables);
+		s.field("iq_responses", &format!("{} pending", self.iq_responses.len()));
+		s.field("extensions", &format!("{} extensions", self.extensions.len()));
+		s.field("modules", &format!("{} modules", self.modules.len()));
+		s.field("handler_chain", &self.handler_chain);
+		s.field(
+			"encryption",
+			&self.encryption.as_ref().map(|encryption| encryption.plugin.namespace()),
+		);
+		#[cfg(feature = "libstrophe-0_11_0")]
+		s.field(
+			"cert_expiry_warning",
+			&self.cert_expiry_warning.as_ref().map(|warning| warning.window),
+		);
+		s.field("event_history", &format!("{} events", self.event_history.events.len()));
+		s.field("pending_pass", &self.pending_pass.as_ref().map(|_| "set"));
+		#[cfg(not(feature = "libstrophe-0_10_0"))]
+		s.field("link_state", &self.link_state);
+		s.field("server_features", &self.server_features);
+		s.field("proxy", &self.proxy.as_ref().map(|_| "set"));
+		s.field("proxy_tunnel", &self.proxy_tunnel);
+		s.field("tls_route", &self.tls_route);
+		#[cfg(feature = "libstrophe-0_12_0")]
+		s.field("resend_policy", &self.resend_policy);
+		#[cfg(feature = "libstrophe-0_12_0")]
+		s.field("unacked", &format!("{} stanzas", self.unacked.len()));
 		s.finish()
 	}
 }
@@ -156,19 +848,23 @@ macro_rules! ensure_unique {
 pub unsafe extern "C" fn certfail_handler_cb<CB: 'static>(cert: *const sys::xmpp_tlscert_t, errormsg: *const c_char) -> c_int {
 	if let Ok(handlers) = CERT_FAIL_HANDLERS.read() {
 		if let Some(handler) = handlers.get(&TypeId::of::<CB>()) {
+			let ctx = Context::from_ref_mut(sys::xmpp_tlscert_get_ctx(cert));
 			let cert = crate::TlsCert::from_ref(cert);
-			let error_msg = crate::FFI(errormsg).receive().unwrap_or("Can't process libstrophe error");
-			return handler(&cert, error_msg) as c_int;
+			let error_msg = crate::FFI(errormsg).try_receive().unwrap_or("Can't process libstrophe error");
+			return catch_panic(&ctx, CertFailResult::TerminateConnection, || handler(&cert, error_msg)) as c_int;
 		}
 	}
 	CertFailResult::TerminateConnection as c_int
 }
 
 #[cfg(feature = "libstrophe-0_12_0")]
-pub unsafe extern "C" fn sockopt_callback<CB: 'static>(_conn: *mut sys::xmpp_conn_t, sock: *mut c_void) -> c_int {
+pub unsafe extern "C" fn sockopt_callback<CB: 'static>(conn: *mut sys::xmpp_conn_t, sock: *mut c_void) -> c_int {
+	#[cfg(unix)]
+	crate::socket::record_socket(conn, sock);
 	if let Ok(handlers) = SOCKOPT_HANDLERS.read() {
 		if let Some(handler) = handlers.get(&TypeId::of::<CB>()) {
-			return handler(sock) as c_int;
+			let ctx = Context::from_ref_mut(sys::xmpp_conn_get_context(conn));
+			return catch_panic(&ctx, SockoptResult::Error, || handler(sock)) as c_int;
 		}
 	}
 	SockoptResult::Error as c_int
@@ -1,8 +1,165 @@
-use std::os::raw::c_ulong;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::c_void;
+use std::os::raw::{c_char, c_int, c_ulong};
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr::NonNull;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{fmt, mem, ops, ptr};
 
-use crate::{AllocContext, Connection, LogLevel, Logger, FFI};
+use once_cell::sync::Lazy;
+
+pub use async_loop::RunAsync;
+
+use crate::logger::{self, LogRoute};
+use crate::scheduler::{self, Scheduler};
+use crate::{AllocContext, Connection, Error, Jid, LogLevel, Logger, Result, FFI};
+
+mod async_loop;
+
+/// Default event loop timeout of libstrophe
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Default [RunAsync::poll_interval]
+const ASYNC_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often the event loop started with [Context::spawn_run] checks whether [RunHandle::stop] was called
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Name of the scheduled job polling the stop flag of [RunHandle]
+const STOP_POLL_JOB: &str = "libstrophe::run_handle_stop";
+
+/// Name of the scheduled job running the callback set with [Context::set_idle_callback]
+const IDLE_JOB: &str = "libstrophe::idle";
+
+/// Event loop timeouts set with [Context::set_timeout] keyed by the `xmpp_ctx_t` address, libstrophe has no getter for it
+static LOOP_TIMEOUTS: Lazy<RwLock<HashMap<usize, Duration>>> = Lazy::new(Default::default);
+
+static NEXT_CONTEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Ids of the contexts keyed by the `xmpp_ctx_t` address, so that all the references to the context share the id
+static CONTEXT_IDS: Lazy<RwLock<HashMap<usize, ContextId>>> = Lazy::new(Default::default);
+
+/// Address of the [ContextState] of the owned context keyed by the `xmpp_ctx_t` address, so that the references to the
+/// context passed to the callbacks share its state
+static CONTEXT_STATES: Lazy<RwLock<HashMap<usize, usize>>> = Lazy::new(Default::default);
+
+type DeferredJob = Box<dyn FnOnce(&Context<'_, '_>) + Send>;
+type ShutdownHook = Box<dyn FnOnce(&Context<'_, '_>, &mut [Connection<'_, '_>]) + Send>;
+
+/// State of the wrapper kept for the lifetime of the `xmpp_ctx_t`, it's owned by the [Context] and moves together with it
+#[derive(Default)]
+struct ContextState {
+	/// Jobs queued with [Context::spawn_deferred]
+	deferred: RefCell<VecDeque<DeferredJob>>,
+	scheduler: RefCell<Scheduler>,
+	/// Hooks registered with [Context::on_shutdown]
+	shutdown_hooks: RefCell<Vec<ShutdownHook>>,
+	/// Stop request of the loop driven by [RunAsync], `None` if there is no such loop, `xmpp_stop()` only affects the loop
+	/// started with `xmpp_run()`
+	async_stop: Cell<Option<bool>>,
+}
+
+impl fmt::Debug for ContextState {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("ContextState")
+			.field("deferred", &format!("{} jobs", self.deferred.borrow().len()))
+			.field("scheduler", &format!("{} jobs", self.scheduler.borrow().len()))
+			.field("shutdown_hooks", &format!("{} hooks", self.shutdown_hooks.borrow().len()))
+			.field("async_stop", &self.async_stop.get())
+			.finish()
+	}
+}
+
+thread_local! {
+	/// First panic caught in the callbacks of the context keyed by the `xmpp_ctx_t` address, it's taken when the event loop
+	/// exits
+	static CAUGHT_PANICS: RefCell<HashMap<usize, Box<dyn Any + Send>>> = RefCell::new(HashMap::new());
+}
+
+/// Process-wide unique id of the [Context] assigned at its creation, see [Context::id]
+///
+/// Both `Display` and `Debug` format it as `ctx#3`, the crate prefixes its own log lines about the event loop with it.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ContextId(u64);
+
+impl ContextId {
+	#[inline]
+	pub fn as_u64(self) -> u64 {
+		self.0
+	}
+}
+
+impl fmt::Display for ContextId {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "ctx#{}", self.0)
+	}
+}
+
+impl fmt::Debug for ContextId {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Display::fmt(self, f)
+	}
+}
+
+/// The way [Context::run_supervised] has finished
+pub enum RunOutcome {
+	/// The event loop was stopped with [Context::stop]
+	Stopped,
+	/// One of the callbacks has panicked, contains the panic payload as passed to [std::panic::resume_unwind]
+	Panicked(Box<dyn Any + Send>),
+}
+
+impl RunOutcome {
+	/// Message of the panic if the payload is a string, which is the case for the `panic!()` invocations
+	pub fn panic_message(&self) -> Option<&str> {
+		match self {
+			RunOutcome::Stopped => None,
+			RunOutcome::Panicked(payload) => panic_message(payload.as_ref()),
+		}
+	}
+}
+
+impl fmt::Debug for RunOutcome {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RunOutcome::Stopped => f.write_str("Stopped"),
+			RunOutcome::Panicked(payload) => f
+				.debug_tuple("Panicked")
+				.field(&panic_message(payload.as_ref()).unwrap_or("Box<dyn Any>"))
+				.finish(),
+		}
+	}
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+	payload
+		.downcast_ref::<&str>()
+		.copied()
+		.or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+}
+
+/// Calls the user callback `f` invoked from libstrophe and keeps a panic from unwinding into the C code
+///
+/// The panic payload is stored and the event loop of `ctx` is stopped, [Context::run] then resumes the panic and
+/// [Context::run_supervised] returns it. `fallback` is returned to libstrophe in place of the callback result.
+pub(crate) fn catch_panic<R>(ctx: &Context, fallback: R, f: impl FnOnce() -> R) -> R {
+	match panic::catch_unwind(AssertUnwindSafe(f)) {
+		Ok(res) => res,
+		Err(payload) => {
+			// only the first panic is kept, the later ones are usually its consequences
+			let _ = CAUGHT_PANICS.try_with(|panics| {
+				panics.borrow_mut().entry(ctx.inner.as_ptr() as usize).or_insert(payload);
+			});
+			ctx.stop();
+			fallback
+		}
+	}
+}
 
 /// Proxy to the underlying `xmpp_ctx_t` struct.
 ///
@@ -22,28 +179,57 @@ use crate::{AllocContext, Connection, LogLevel, Logger, FFI};
 /// [ctx.c]: https://github.com/strophe/libstrophe/blob/0.12.2/src/ctx.c
 /// [event.c]: https://github.com/strophe/libstrophe/blob/0.12.2/src/event.c
 /// [xmpp_ctx_free]: https://strophe.im/libstrophe/doc/0.12.2/group___context.html#ga39010d64cdf77f7a4d0f1457c952baca
-#[derive(Debug)]
 pub struct Context<'cb, 'cn> {
 	inner: NonNull<sys::xmpp_ctx_t>,
 	owned: bool,
 	connections: Vec<Connection<'cn, 'cb>>,
 	_logger: Option<Logger<'cb>>,
-	_memory: Option<Box<sys::xmpp_mem_t>>,
+	_memory: Option<Box<ContextMemory>>,
+}
+
+/// Structs passed to `xmpp_ctx_new` that must outlive the context together with the state of the wrapper
+#[derive(Debug)]
+struct ContextMemory {
+	mem: sys::xmpp_mem_t,
+	log_router: sys::xmpp_log_t,
+	state: ContextState,
 }
 
 impl<'cb, 'cn> Context<'cb, 'cn> {
 	/// [xmpp_ctx_new](https://strophe.im/libstrophe/doc/0.12.2/group___context.html#ga6a671ae0afe7eb14f685d512701ed989  )
+	///
+	/// The verbose debug output is enabled if the `logger` was created with
+	/// [`Logger::with_max_level(LevelFilter::Trace)`](Logger::with_max_level). The messages emitted while servicing a
+	/// connection with its own logger are sent there instead, see [Connection::set_logger].
 	pub fn new(logger: Logger<'cb>) -> Self {
 		crate::init();
-		let memory = Box::new(AllocContext::get_xmpp_mem_t());
-		unsafe {
+		let memory = Box::new(ContextMemory {
+			mem: AllocContext::get_xmpp_mem_t(),
+			log_router: logger::router(&logger),
+			state: ContextState::default(),
+		});
+		let state = &memory.state as *const ContextState as usize;
+		#[cfg(all(feature = "log", feature = "libstrophe-0_10_0"))]
+		let verbose = logger.max_level() == Some(log::LevelFilter::Trace);
+		let out = unsafe {
 			Self::with_inner(
-				sys::xmpp_ctx_new(memory.as_ref(), logger.as_ptr()),
+				sys::xmpp_ctx_new(&memory.mem, &memory.log_router),
 				true,
 				Some(memory),
 				Some(logger),
 			)
+		};
+		#[cfg(all(feature = "log", feature = "libstrophe-0_10_0"))]
+		if verbose {
+			unsafe { sys::xmpp_ctx_set_verbosity(out.inner.as_ptr(), 1) }
 		}
+		CONTEXT_STATES
+			.write()
+			.unwrap_or_else(|e| e.into_inner())
+			.insert(out.inner.as_ptr() as usize, state);
+		// assign the id right away so that the ids follow the order of creation
+		out.id();
+		out
 	}
 
 	/// Shortcut to return a new context with default logger.
@@ -64,7 +250,7 @@ impl<'cb, 'cn> Context<'cb, 'cn> {
 	unsafe fn with_inner(
 		inner: *mut sys::xmpp_ctx_t,
 		owned: bool,
-		memory: Option<Box<sys::xmpp_mem_t>>,
+		memory: Option<Box<ContextMemory>>,
 		logger: Option<Logger<'cb>>,
 	) -> Self {
 		if owned && (memory.is_none() || logger.is_none()) {
@@ -79,6 +265,24 @@ impl<'cb, 'cn> Context<'cb, 'cn> {
 		}
 	}
 
+	/// State shared by all the references to the context, `None` if it was created outside of this crate
+	fn state(&self) -> Option<&ContextState> {
+		if let Some(memory) = &self._memory {
+			return Some(&memory.state);
+		}
+		let state = CONTEXT_STATES
+			.read()
+			.ok()
+			.and_then(|states| states.get(&(self.inner.as_ptr() as usize)).copied())?;
+		// the references to the context must not outlive it, see [Context::from_ref]
+		Some(unsafe { &*(state as *const ContextState) })
+	}
+
+	/// Jobs added with [Context::schedule] and [Context::schedule_at]
+	pub(crate) fn scheduler(&self) -> Option<&RefCell<Scheduler>> {
+		self.state().map(|state| &state.scheduler)
+	}
+
 	pub(crate) fn consume_connection(&mut self, conn: Connection<'cn, 'cb>) {
 		self.connections.push(conn);
 	}
@@ -101,33 +305,407 @@ impl<'cb, 'cn> Context<'cb, 'cn> {
 		self.inner.as_ptr()
 	}
 
+	/// Process-wide unique id of the context for correlating the log lines, e.g. `ctx#3`
+	///
+	/// The id is assigned when the context is created and is shared by all the references to it passed to the callbacks.
+	pub fn id(&self) -> ContextId {
+		let key = self.inner.as_ptr() as usize;
+		if let Some(id) = CONTEXT_IDS.read().ok().and_then(|ids| ids.get(&key).copied()) {
+			return id;
+		}
+		// the context created outside of this crate gets its id on the first use
+		let mut ids = CONTEXT_IDS.write().unwrap_or_else(|e| e.into_inner());
+		*ids
+			.entry(key)
+			.or_insert_with(|| ContextId(NEXT_CONTEXT_ID.fetch_add(1, Ordering::Relaxed)))
+	}
+
 	/// [xmpp_set_timeout](https://strophe.im/libstrophe/doc/0.12.2/group___event_loop.html#ga7c4c01959561fbf6df5d236078e54a3b)
 	///
 	/// Default timeout is 1000ms
 	pub fn set_timeout(&mut self, timeout: Duration) {
 		unsafe { sys::xmpp_ctx_set_timeout(self.inner.as_mut(), timeout.as_millis() as c_ulong) }
+		if let Ok(mut timeouts) = LOOP_TIMEOUTS.write() {
+			timeouts.insert(self.inner.as_ptr() as usize, timeout);
+		}
+	}
+
+	/// Event loop timeout as set by [Context::set_timeout]
+	pub fn timeout(&self) -> Duration {
+		LOOP_TIMEOUTS
+			.read()
+			.ok()
+			.and_then(|timeouts| timeouts.get(&(self.inner.as_ptr() as usize)).copied())
+			.unwrap_or(DEFAULT_TIMEOUT)
 	}
 
 	// todo: add global_timed_handler support
 
+	/// Queues `job` to be run by the event loop after the current dispatch completes
+	///
+	/// Use it to quickly hand off expensive processing from the stanza handlers. The jobs are run in the order they were
+	/// queued by a zero-period
+	/// [xmpp_global_timed_handler_add](https://github.com/strophe/libstrophe/blob/0.12.2/src/handler.c) handler. Jobs
+	/// queued from within a deferred job are run on the next loop iteration. The queue belongs to the context, so the job
+	/// is run by whichever thread runs its event loop. Nothing is queued for the contexts created outside of this crate.
+	pub fn spawn_deferred(&self, job: impl FnOnce(&Context<'_, '_>) + Send + 'static) {
+		let state = match self.state() {
+			Some(state) => state,
+			None => {
+				self.log_error(
+					"xmpp",
+					&format!("{}: Deferred job dropped, the context has no state", self.id()),
+				);
+				return;
+			}
+		};
+		let schedule = {
+			let mut queue = state.deferred.borrow_mut();
+			queue.push_back(Box::new(job));
+			queue.len() == 1
+		};
+		if schedule {
+			// libstrophe ignores the handler if it's already registered
+			unsafe { sys::xmpp_global_timed_handler_add(self.inner.as_ptr(), Some(deferred_cb), 0, ptr::null_mut()) }
+		}
+	}
+
+	/// Runs `job` every `every` interval until it's cancelled with [Context::unschedule]
+	///
+	/// All of the jobs scheduled with this method and [Context::schedule_at] are multiplexed onto a single
+	/// [xmpp_global_timed_handler_add](https://github.com/strophe/libstrophe/blob/0.12.2/src/handler.c) handler. The runs
+	/// are aligned to the time of scheduling so the period doesn't drift because of the job execution time, the runs missed
+	/// due to the busy event loop are skipped. Scheduling a job with the `name` that's already in use replaces that job. The jobs
+	/// are stored in the context so they move with it to the thread that runs the event loop.
+	pub fn schedule(&self, every: Duration, name: impl Into<String>, job: impl FnMut(&Context<'_, '_>) + Send + 'static) {
+		scheduler::schedule_periodic(self, name.into(), every, Box::new(job));
+	}
+
+	/// Runs `job` once at the time `at` (or as soon as possible if it's in the past), see [Context::schedule]
+	pub fn schedule_at(&self, at: Instant, job: impl FnOnce(&Context<'_, '_>) + Send + 'static) {
+		scheduler::schedule_once(self, at, Box::new(job));
+	}
+
+	/// Cancels the job added with [Context::schedule], returns `false` if there is no job with such `name`
+	pub fn unschedule(&self, name: &str) -> bool {
+		scheduler::unschedule(self, name)
+	}
+
+	/// Sets the `callback` that the event loop calls at least every `max_interval` regardless of the network activity,
+	/// replacing the previous one
+	///
+	/// It's meant for polling the non-XMPP sources (e.g. a channel fed by another part of the application or a database)
+	/// from the thread running the loop, so that the handlers and the polling code can share the connections without a
+	/// second thread. The callback is run as a [scheduled job](Context::schedule) which libstrophe takes into account when
+	/// waiting for the network, so it fires on time even with the long [loop timeout](Context::set_timeout). Keep it short,
+	/// it delays the processing of the incoming data.
+	pub fn set_idle_callback(&self, callback: impl FnMut(&Context<'_, '_>) + Send + 'static, max_interval: Duration) {
+		self.schedule(max_interval, IDLE_JOB, callback);
+	}
+
+	/// Removes the callback set with [Context::set_idle_callback], returns `false` if there was none
+	pub fn clear_idle_callback(&self) -> bool {
+		self.unschedule(IDLE_JOB)
+	}
+
+	/// Registers `hook` to be run when the event loop started with [Context::run] exits or when the context is dropped,
+	/// whichever happens first
+	///
+	/// The hooks are run once in the reverse order of registration, so the helpers registered later (and usually depending
+	/// on the earlier ones) are shut down first. They receive the connections owned by the context which are still alive at
+	/// that point, so it's the place to send the unavailable presence or to persist the stream management state. The event
+	/// loop is run once more after the hooks to write out the data they have sent.
+	pub fn on_shutdown(&self, hook: impl FnOnce(&Context<'_, '_>, &mut [Connection<'_, '_>]) + Send + 'static) {
+		match self.state() {
+			Some(state) => state.shutdown_hooks.borrow_mut().push(Box::new(hook)),
+			None => self.log_error(
+				"xmpp",
+				&format!("{}: Shutdown hook not registered, the context has no state", self.id()),
+			),
+		}
+	}
+
+	fn take_caught_panic(&self) -> Option<Box<dyn Any + Send>> {
+		CAUGHT_PANICS
+			.try_with(|panics| panics.borrow_mut().remove(&(self.inner.as_ptr() as usize)))
+			.ok()
+			.flatten()
+	}
+
+	fn resume_caught_panic(&self) {
+		if let Some(payload) = self.take_caught_panic() {
+			panic::resume_unwind(payload);
+		}
+	}
+
+	fn run_shutdown_hooks(&self) {
+		let hooks = self
+			.state()
+			.map(|state| mem::take(&mut *state.shutdown_hooks.borrow_mut()))
+			.unwrap_or_default();
+		if hooks.is_empty() {
+			return;
+		}
+		let mut conns = self.connections.iter().map(Connection::proxy).collect::<Vec<_>>();
+		for hook in hooks.into_iter().rev() {
+			catch_panic(self, (), || hook(self, &mut conns));
+		}
+		unsafe { sys::xmpp_run_once(self.inner.as_ptr(), 0) }
+	}
+
 	/// [xmpp_run_once](https://strophe.im/libstrophe/doc/0.12.2/group___event_loop.html#ga9e6bcc704aca8209bccdeb42a79bd328)
+	///
+	/// A panic in one of the callbacks is resumed after the iteration, see [Context::run].
 	pub fn run_once(&self, timeout: Duration) {
+		let _log_route = self.log_route();
 		unsafe { sys::xmpp_run_once(self.inner.as_ptr(), timeout.as_millis() as c_ulong) }
+		self.resume_caught_panic();
+	}
+
+	/// Runs one iteration of the event loop without waiting for the network, the building block for driving the loop from
+	/// an external reactor
+	///
+	/// Same as [`run_once(Duration::ZERO)`](Context::run_once).
+	pub fn run_once_nonblocking(&self) {
+		self.run_once(Duration::ZERO)
+	}
+
+	/// Returns the future running the event loop until [Context::stop] is called, for driving the connections from an async
+	/// runtime (e.g. tokio or async-std) without blocking it
+	///
+	/// The future runs the loop with [Context::run_once_nonblocking] every time it's polled and a helper thread wakes it
+	/// every [poll interval](RunAsync::poll_interval). While the connections have the data queued for sending (only
+	/// detected with the `libstrophe-0_12_0` feature) it's polled again right away and then with the growing delay up to
+	/// the poll interval. To avoid the polling, register the sockets returned by
+	/// `Connection::socket_fd()` (unix with the `libstrophe-0_12_0` feature) with the reactor of the runtime and call
+	/// [Context::run_once_nonblocking] when they're readable instead. The shutdown hooks and the panics of the callbacks are
+	/// handled the same way as in [Context::run].
+	pub fn run_async(&self) -> RunAsync<'_, 'cb, 'cn> {
+		RunAsync::new(self, ASYNC_POLL_INTERVAL)
 	}
 
 	/// [xmpp_run](https://strophe.im/libstrophe/doc/0.12.2/group___event_loop.html#ga14ca97546803cf27c772fa8d2eabfffd)
+	///
+	/// The hooks registered with [Context::on_shutdown] are run when the loop exits after [Context::stop].
+	///
+	/// A panic in one of the callbacks can't unwind through libstrophe, so it's caught, the loop is stopped and the panic is
+	/// resumed here once `xmpp_run` returns. Use [Context::run_supervised] to handle it without unwinding.
+	///
+	/// The loop only returns after [Context::stop], a warning is logged if it's started without any connections (see
+	/// [Context::has_connections]) because then nothing is going to stop it unless a job or a handler of another context does.
 	pub fn run(&self) {
+		self.warn_no_connections();
+		let _log_route = self.log_route();
+		unsafe { sys::xmpp_run(self.inner.as_ptr()) }
+		self.resume_caught_panic();
+		self.run_shutdown_hooks();
+		self.resume_caught_panic();
+	}
+
+	/// Like [Context::run], but returns the panic of a callback as [RunOutcome::Panicked] instead of resuming it
+	///
+	/// The panic is logged, then the shutdown hooks are run and the connections owned by the context are disconnected so that
+	/// the context can be dropped or reused without taking the whole process down. The panics caught during this teardown
+	/// are logged and dropped.
+	pub fn run_supervised(&self) -> RunOutcome {
+		self.warn_no_connections();
+		let _log_route = self.log_route();
 		unsafe { sys::xmpp_run(self.inner.as_ptr()) }
+		let payload = match self.take_caught_panic() {
+			Some(payload) => payload,
+			None => {
+				self.run_shutdown_hooks();
+				self.log_caught_panic("Panic in the shutdown hook");
+				return RunOutcome::Stopped;
+			}
+		};
+		self.log_error(
+			"xmpp",
+			&format!(
+				"{}: Event loop stopped after a panic in the callback: {}",
+				self.id(),
+				panic_message(payload.as_ref()).unwrap_or("Box<dyn Any>")
+			),
+		);
+		self.run_shutdown_hooks();
+		self.log_caught_panic("Panic in the shutdown hook");
+		for conn in &self.connections {
+			catch_panic(self, (), || conn.proxy().disconnect());
+		}
+		unsafe { sys::xmpp_run_once(self.inner.as_ptr(), 0) }
+		self.log_caught_panic("Panic while disconnecting");
+		RunOutcome::Panicked(payload)
+	}
+
+	/// Checks whether any connection was attached to the context
+	///
+	/// The connection is attached to the context returned by [Connection::connect_client] and the other `connect_*()` methods
+	/// and stays there until the context is dropped. Running the event loop of the context without connections blocks
+	/// forever unless something calls [Context::stop], usually it means that the [Connection] was dropped before connecting
+	/// or that the loop is run on the wrong context. The contexts passed to the callbacks don't own the connections and
+	/// always return `true`.
+	pub fn has_connections(&self) -> bool {
+		!self.owned || !self.connections.is_empty()
+	}
+
+	fn warn_no_connections(&self) {
+		if !self.has_connections() {
+			self.log_warn(
+				"xmpp",
+				&format!(
+					"{}: Event loop started without any connections, it will block until Context::stop() is called. Use the \
+					 context returned by Connection::connect_*() to run the connection.",
+					self.id()
+				),
+			);
+		}
+	}
+
+	/// Libstrophe doesn't tell which connection emits the message, so the whole loop is only routed to the connection logger
+	/// when there is a single connection
+	fn log_route(&self) -> Option<LogRoute<'cn>> {
+		match self.connections.as_slice() {
+			[conn] => conn.log_route(),
+			_ => None,
+		}
+	}
+
+	fn log_caught_panic(&self, what: &str) {
+		if let Some(payload) = self.take_caught_panic() {
+			self.log_error(
+				"xmpp",
+				&format!(
+					"{}: {}: {}",
+					self.id(),
+					what,
+					panic_message(payload.as_ref()).unwrap_or("Box<dyn Any>")
+				),
+			);
+		}
+	}
+
+	#[cfg(feature = "libstrophe-0_10_0")]
+	/// [xmpp_ctx_set_verbosity](https://strophe.im/libstrophe/doc/0.12.2/group___context.html)
+	///
+	/// With `level` above 0 libstrophe emits additional debug messages, e.g. the full contents of the sent data. The
+	/// messages are still subject to the level filter of the logger.
+	pub fn set_verbosity(&mut self, level: i32) {
+		unsafe { sys::xmpp_ctx_set_verbosity(self.inner.as_mut(), level as c_int) }
 	}
 
 	/// [xmpp_stop](https://strophe.im/libstrophe/doc/0.12.2/group___event_loop.html#ga44689e9b7782cec520ed60196e8c15c2)
+	///
+	/// Also stops the loop run with [Context::run_async].
 	pub fn stop(&self) {
+		async_loop::request_stop(self);
 		unsafe { sys::xmpp_stop(self.inner.as_ptr()) }
 	}
 
+	/// Connects the disconnected client connection owned by this context again, see [Connection::reconnect]
+	///
+	/// It's the way to reuse the connection consumed by [Connection::connect_client] once the event loop returns, e.g. after
+	/// the connection handler called [Context::stop] on disconnect. The connection is picked by its `jid`, the bare JID
+	/// matches the connection regardless of its resource. Returns [Error::InvalidOperation] if there is no such connection or
+	/// it can't be reconnected, run the event loop again afterwards.
+	pub fn reconnect(&self, jid: &str) -> Result<()> {
+		let target = jid.parse::<Jid>()?;
+		let conn = self
+			.connections
+			.iter()
+			.find(|conn| {
+				conn.jid().and_then(|jid| jid.parse::<Jid>().ok()).map_or(false, |jid| {
+					if target.is_bare() {
+						jid.bare() == target.bare()
+					} else {
+						jid == target
+					}
+				})
+			})
+			.ok_or(Error::InvalidOperation)?;
+		conn.proxy().reconnect()
+	}
+
+	/// Emit a log message through the logger of this context
+	///
+	/// Dispatches to one of [Context::log_error], [Context::log_warn], [Context::log_info] or [Context::log_debug] depending
+	/// on the `level`.
 	pub fn log(&self, level: LogLevel, area: &str, msg: &str) {
 		unsafe { ctx_log(self.inner.as_ptr(), level, area, msg) }
 	}
+
+	#[inline]
+	/// [xmpp_error](https://github.com/strophe/libstrophe/blob/0.12.2/src/ctx.c)
+	///
+	/// The message is passed as is, formatting should be done on the Rust side.
+	pub fn log_error(&self, area: &str, msg: &str) {
+		unsafe { ctx_log_with(sys::xmpp_error, self.inner.as_ptr(), area, msg) }
+	}
+
+	#[inline]
+	/// [xmpp_warn](https://github.com/strophe/libstrophe/blob/0.12.2/src/ctx.c)
+	///
+	/// The message is passed as is, formatting should be done on the Rust side.
+	pub fn log_warn(&self, area: &str, msg: &str) {
+		unsafe { ctx_log_with(sys::xmpp_warn, self.inner.as_ptr(), area, msg) }
+	}
+
+	#[inline]
+	/// [xmpp_info](https://github.com/strophe/libstrophe/blob/0.12.2/src/ctx.c)
+	///
+	/// The message is passed as is, formatting should be done on the Rust side.
+	pub fn log_info(&self, area: &str, msg: &str) {
+		unsafe { ctx_log_with(sys::xmpp_info, self.inner.as_ptr(), area, msg) }
+	}
+
+	#[inline]
+	/// [xmpp_debug](https://github.com/strophe/libstrophe/blob/0.12.2/src/ctx.c)
+	///
+	/// The message is passed as is, formatting should be done on the Rust side.
+	pub fn log_debug(&self, area: &str, msg: &str) {
+		unsafe { ctx_log_with(sys::xmpp_debug, self.inner.as_ptr(), area, msg) }
+	}
+}
+
+impl Context<'static, 'static> {
+	/// Moves the context to a new thread and runs the event loop there with [Context::run_supervised]
+	///
+	/// The returned [RunHandle] can stop the loop from any thread and wait for it to finish. The context is dropped on the
+	/// background thread after the loop exits. The jobs queued with [Context::spawn_deferred] or [Context::schedule] and the
+	/// hooks added with [Context::on_shutdown] are moved together with the context.
+	pub fn spawn_run(self) -> RunHandle {
+		let stop = Arc::new(AtomicBool::new(false));
+		let running = Arc::new(AtomicBool::new(true));
+		let thread = thread::spawn({
+			let stop = Arc::clone(&stop);
+			let running = Arc::clone(&running);
+			move || {
+				let _running = RunningGuard(running);
+				let ctx = self;
+				ctx.schedule(STOP_POLL_INTERVAL, STOP_POLL_JOB, move |ctx| {
+					if stop.load(Ordering::Acquire) {
+						ctx.stop();
+					}
+				});
+				let outcome = ctx.run_supervised();
+				drop(ctx);
+				outcome
+			}
+		});
+		RunHandle { thread, stop, running }
+	}
+}
+
+impl fmt::Debug for Context<'_, '_> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Context")
+			.field("id", &self.id())
+			.field("inner", &self.inner)
+			.field("owned", &self.owned)
+			.field("connections", &self.connections)
+			.field("_logger", &self._logger)
+			.field("_memory", &self._memory)
+			.finish()
+	}
 }
 
 impl PartialEq for Context<'_, '_> {
@@ -142,31 +720,198 @@ impl Drop for Context<'_, '_> {
 	/// [xmpp_ctx_free](https://strophe.im/libstrophe/doc/0.12.2/group___context.html#ga39010d64cdf77f7a4d0f1457c952baca)
 	fn drop(&mut self) {
 		if self.owned {
+			self.run_shutdown_hooks();
 			self.connections.clear();
+			let key = self.inner.as_ptr() as usize;
+			if let Ok(mut timeouts) = LOOP_TIMEOUTS.write() {
+				timeouts.remove(&key);
+			}
+			if let Ok(mut ids) = CONTEXT_IDS.write() {
+				ids.remove(&key);
+			}
+			if let Some(memory) = &self._memory {
+				// the jobs can hold the connections of the context, so they must be dropped before it's freed
+				mem::take(&mut *memory.state.deferred.borrow_mut());
+				memory.state.scheduler.borrow_mut().clear();
+			}
+			let _ = CAUGHT_PANICS.try_with(|panics| panics.borrow_mut().remove(&key));
+			#[cfg(feature = "stanza-tracking")]
+			{
+				let report = crate::stanza_tracking::report();
+				for stanza in &report.live {
+					self.log_warn(
+						"stanza-tracking",
+						&format!("Stanza {:#x} is still alive, created at:\n{}", stanza.ptr, stanza.backtrace),
+					);
+				}
+				for stanza in &report.dangling {
+					self.log_error(
+						"stanza-tracking",
+						&format!(
+							"StanzaRef {:#x} has outlived its stanza, created at:\n{}",
+							stanza.ptr, stanza.backtrace
+						),
+					);
+				}
+				crate::stanza_tracking::clear_dangling();
+			}
 			unsafe {
 				sys::xmpp_ctx_free(self.inner.as_mut());
 			}
+			if let Ok(mut states) = CONTEXT_STATES.write() {
+				states.remove(&key);
+			}
 		}
 	}
 }
 
+unsafe extern "C" fn deferred_cb(ctx_ptr: *mut sys::xmpp_ctx_t, _userdata: *mut c_void) -> c_int {
+	let ctx = Context::from_ref_mut(ctx_ptr);
+	let state = match ctx.state() {
+		Some(state) => state,
+		None => return 0,
+	};
+	let jobs = mem::take(&mut *state.deferred.borrow_mut());
+	for job in jobs {
+		catch_panic(&ctx, (), || job(&ctx));
+	}
+	// keep the handler registered if the jobs have queued more work
+	let more = !state.deferred.borrow().is_empty();
+	more as c_int
+}
+
 #[allow(clippy::non_send_fields_in_send_ty)]
 unsafe impl Send for Context<'_, '_> {}
 
-pub(crate) unsafe fn ctx_log(ctx: *const sys::xmpp_ctx_t, level: sys::xmpp_log_level_t, area: &str, msg: &str) {
-	#[allow(non_camel_case_types)]
-	#[repr(C)]
-	struct _xmpp_ctx_t {
-		mem: *const sys::xmpp_mem_t,
-		log: *const sys::xmpp_log_t,
-		// ...
+/// Handle to the event loop started with [Context::spawn_run]
+///
+/// Like [thread::JoinHandle] dropping the handle detaches the background thread, the loop keeps running until it's stopped
+/// from one of the handlers.
+#[derive(Debug)]
+pub struct RunHandle {
+	thread: thread::JoinHandle<RunOutcome>,
+	stop: Arc<AtomicBool>,
+	running: Arc<AtomicBool>,
+}
+
+impl RunHandle {
+	/// Asks the event loop to stop, it exits within 100 ms or after the handler that's currently
+	/// running returns
+	pub fn stop(&self) {
+		self.stop.store(true, Ordering::Release);
+	}
+
+	/// Returns `false` once the event loop has exited, either after [RunHandle::stop], [Context::stop] or a panic
+	pub fn is_running(&self) -> bool {
+		self.running.load(Ordering::Acquire)
+	}
+
+	/// Waits for the event loop to exit and for the context to be dropped
+	///
+	/// The panic in the context teardown is reported as [RunOutcome::Panicked] too.
+	pub fn join(self) -> RunOutcome {
+		self.thread.join().unwrap_or_else(RunOutcome::Panicked)
 	}
-	let inner = (ctx as *mut _xmpp_ctx_t).as_ref().expect("Null pointer for Context");
-	if let Some(log) = inner.log.as_ref() {
-		if let Some(log_handler) = log.handler {
-			let area = FFI(area).send();
-			let msg = FFI(msg).send();
-			log_handler(log.userdata, level, area.as_ptr(), msg.as_ptr());
+
+	/// Shortcut for [RunHandle::stop] followed by [RunHandle::join]
+	pub fn stop_and_join(self) -> RunOutcome {
+		self.stop();
+		self.join()
+	}
+}
+
+/// Clears the running flag of [RunHandle] when the background thread finishes, even by unwinding
+struct RunningGuard(Arc<AtomicBool>);
+
+impl Drop for RunningGuard {
+	fn drop(&mut self) {
+		self.0.store(false, Ordering::Release);
+	}
+}
+
+/// [Context] enriched with the time budget of the current handler dispatch
+///
+/// Passed to the handlers added with [Connection::dispatch_handler_add]. The budget is equal to the event loop timeout
+/// (see [Context::set_timeout]) and starts running when the handler is called. Long-running handlers can check
+/// [`yield_hint()`](#method.yield_hint) and defer the rest of their work (e.g. to a timed handler) instead of starving the
+/// event loop. Derefs to [Context], so it can be used in its place.
+#[derive(Debug)]
+pub struct DispatchCtx<'d, 'cb, 'cn> {
+	ctx: &'d Context<'cb, 'cn>,
+	started: Instant,
+	budget: Duration,
+}
+
+impl<'d, 'cb, 'cn> DispatchCtx<'d, 'cb, 'cn> {
+	pub(crate) fn new(ctx: &'d Context<'cb, 'cn>) -> Self {
+		Self {
+			ctx,
+			started: Instant::now(),
+			budget: ctx.timeout(),
 		}
 	}
+
+	#[inline]
+	pub fn context(&self) -> &'d Context<'cb, 'cn> {
+		self.ctx
+	}
+
+	#[inline]
+	/// Time when the dispatch has started
+	pub fn started(&self) -> Instant {
+		self.started
+	}
+
+	#[inline]
+	/// Total time budget of the dispatch
+	pub fn budget(&self) -> Duration {
+		self.budget
+	}
+
+	#[inline]
+	pub fn elapsed(&self) -> Duration {
+		self.started.elapsed()
+	}
+
+	#[inline]
+	/// Time left until the budget is exhausted, zero if it's already exceeded
+	pub fn remaining_time(&self) -> Duration {
+		self.budget.saturating_sub(self.elapsed())
+	}
+
+	#[inline]
+	/// Returns `true` when more than half of the budget is used and the handler should defer the remaining work to leave
+	/// time for the rest of the event loop
+	pub fn yield_hint(&self) -> bool {
+		self.elapsed() > self.budget / 2
+	}
+}
+
+impl<'cb, 'cn> ops::Deref for DispatchCtx<'_, 'cb, 'cn> {
+	type Target = Context<'cb, 'cn>;
+
+	#[inline]
+	fn deref(&self) -> &Self::Target {
+		self.ctx
+	}
+}
+
+type CtxLogFn = unsafe extern "C" fn(ctx: *const sys::xmpp_ctx_t, area: *const c_char, fmt: *const c_char, ...);
+
+/// Calls one of the variadic `xmpp_error/warn/info/debug` functions passing the `msg` through the "%s" format so that it's
+/// not interpreted as a format string
+unsafe fn ctx_log_with(log_fn: CtxLogFn, ctx: *const sys::xmpp_ctx_t, area: &str, msg: &str) {
+	let area = FFI(area).send();
+	let msg = FFI(msg).send();
+	log_fn(ctx, area.as_ptr(), b"%s\0".as_ptr() as _, msg.as_ptr());
+}
+
+pub(crate) unsafe fn ctx_log(ctx: *const sys::xmpp_ctx_t, level: sys::xmpp_log_level_t, area: &str, msg: &str) {
+	let log_fn = match level {
+		LogLevel::XMPP_LEVEL_ERROR => sys::xmpp_error,
+		LogLevel::XMPP_LEVEL_WARN => sys::xmpp_warn,
+		LogLevel::XMPP_LEVEL_INFO => sys::xmpp_info,
+		LogLevel::XMPP_LEVEL_DEBUG => sys::xmpp_debug,
+	};
+	ctx_log_with(log_fn, ctx, area, msg)
 }
@@ -0,0 +1,188 @@
+//! Driving the event loop from the async runtimes, see [Context::run_async]
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::task::{Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+use crate::Context;
+
+/// First retry delay of the poll while the connections have the data queued for sending, it's doubled on every poll with
+/// the data still queued up to the poll interval
+const WRITE_BACKOFF_MIN: Duration = Duration::from_millis(1);
+
+/// Flags the stop of the [RunAsync] loop of the context if there is one
+pub(super) fn request_stop(ctx: &Context) {
+	if let Some(state) = ctx.state() {
+		if state.async_stop.get().is_some() {
+			state.async_stop.set(Some(true));
+		}
+	}
+}
+
+/// Future returned by [Context::run_async], completes once [Context::stop] is called
+///
+/// The future is not `Send` because the context is bound to the thread it's run on, so it should be awaited directly or
+/// spawned as a local task (e.g. with `tokio::task::spawn_local()`).
+#[must_use = "futures do nothing unless polled"]
+pub struct RunAsync<'a, 'cb, 'cn> {
+	ctx: &'a Context<'cb, 'cn>,
+	poll_interval: Duration,
+	/// Waker of the last poll, taken and woken by the ticker thread, the thread exits once this is dropped
+	ticker: Option<Arc<Ticker>>,
+	/// Delay of the next poll while the data is queued for sending, zero when nothing was queued on the last poll
+	write_backoff: Duration,
+	started: bool,
+	done: bool,
+}
+
+impl<'a, 'cb, 'cn> RunAsync<'a, 'cb, 'cn> {
+	pub(super) fn new(ctx: &'a Context<'cb, 'cn>, poll_interval: Duration) -> Self {
+		Self {
+			ctx,
+			poll_interval,
+			ticker: None,
+			write_backoff: Duration::ZERO,
+			started: false,
+			done: false,
+		}
+	}
+
+	/// Sets how often the loop is polled when there is nothing to write, the default is 10 ms
+	///
+	/// It's the upper bound of the latency of receiving the data and of firing the timed handlers.
+	pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+		self.poll_interval = poll_interval;
+		self
+	}
+
+	fn set_stop_state(&self, stop: Option<bool>) {
+		if let Some(state) = self.ctx.state() {
+			state.async_stop.set(stop);
+		}
+	}
+
+	fn take_stop_request(&self) -> bool {
+		let requested = self.ctx.state().map_or(false, |state| state.async_stop.get() == Some(true));
+		if requested {
+			self.set_stop_state(None);
+		}
+		requested
+	}
+
+	fn schedule_wake(&mut self, waker: &Waker, delay: Duration) {
+		let ticker = self.ticker.get_or_insert_with(|| {
+			let ticker = Arc::new(Ticker {
+				slot: Mutex::new(TickerSlot { waker: None, delay }),
+				changed: Condvar::new(),
+			});
+			let weak = Arc::downgrade(&ticker);
+			thread::spawn(move || tick(&weak));
+			ticker
+		});
+		if let Ok(mut slot) = ticker.slot.lock() {
+			slot.waker = Some(waker.clone());
+			if slot.delay != delay {
+				slot.delay = delay;
+				ticker.changed.notify_one();
+			}
+		}
+	}
+}
+
+struct Ticker {
+	slot: Mutex<TickerSlot>,
+	/// Signalled when the delay changes so that the ticker doesn't sleep for the old one
+	changed: Condvar,
+}
+
+struct TickerSlot {
+	waker: Option<Waker>,
+	delay: Duration,
+}
+
+/// Wakes the last poll of [RunAsync] after the requested delay until the future is dropped
+fn tick(ticker: &Weak<Ticker>) {
+	while let Some(ticker) = ticker.upgrade() {
+		let slot = match ticker.slot.lock() {
+			Ok(slot) => slot,
+			Err(_) => break,
+		};
+		let delay = slot.delay;
+		let (mut slot, res) = match ticker.changed.wait_timeout(slot, delay) {
+			Ok(res) => res,
+			Err(_) => break,
+		};
+		if !res.timed_out() {
+			// the delay has changed, wait for the new one
+			continue;
+		}
+		let waker = slot.waker.take();
+		drop(slot);
+		if let Some(waker) = waker {
+			waker.wake();
+		}
+	}
+}
+
+impl Future for RunAsync<'_, '_, '_> {
+	type Output = ();
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context) -> Poll<()> {
+		let this = &mut *self;
+		if this.done {
+			return Poll::Ready(());
+		}
+		if !this.started {
+			this.started = true;
+			this.ctx.warn_no_connections();
+			this.set_stop_state(Some(false));
+		}
+		this.ctx.run_once_nonblocking();
+		if this.take_stop_request() {
+			this.done = true;
+			this.ticker = None;
+			this.ctx.run_shutdown_hooks();
+			this.ctx.resume_caught_panic();
+			return Poll::Ready(());
+		}
+		if has_pending_writes(this.ctx) {
+			// the data queued by the handlers is only written on the next iteration, retry right away first and then back off
+			// in case the socket doesn't accept more data
+			let delay = this.write_backoff;
+			this.write_backoff = (delay * 2).max(WRITE_BACKOFF_MIN).min(this.poll_interval);
+			if delay.is_zero() {
+				cx.waker().wake_by_ref();
+			} else {
+				this.schedule_wake(cx.waker(), delay);
+			}
+		} else {
+			this.write_backoff = Duration::ZERO;
+			let poll_interval = this.poll_interval;
+			this.schedule_wake(cx.waker(), poll_interval);
+		}
+		Poll::Pending
+	}
+}
+
+impl Drop for RunAsync<'_, '_, '_> {
+	fn drop(&mut self) {
+		if self.started && !self.done {
+			self.set_stop_state(None);
+		}
+	}
+}
+
+#[cfg(feature = "libstrophe-0_12_0")]
+fn has_pending_writes(ctx: &Context) -> bool {
+	ctx.connections
+		.iter()
+		.any(|conn| unsafe { sys::xmpp_conn_send_queue_len(conn.as_ptr()) } > 0)
+}
+
+#[cfg(not(feature = "libstrophe-0_12_0"))]
+fn has_pending_writes(_ctx: &Context) -> bool {
+	false
+}
@@ -0,0 +1,168 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::ParseDateTimeError;
+
+/// Parses [XEP-0082](https://xmpp.org/extensions/xep-0082.html) `DateTime` profile: `CCYY-MM-DDThh:mm:ss[.sss]TZD`
+pub fn parse(s: &str) -> Result<SystemTime, ParseDateTimeError> {
+	let b = s.as_bytes();
+	if b.len() < 20 || b[4] != b'-' || b[7] != b'-' || b[10] != b'T' || b[13] != b':' || b[16] != b':' {
+		return Err(ParseDateTimeError);
+	}
+	let year = digits(&b[0..4])?;
+	let month = digits(&b[5..7])?;
+	let day = digits(&b[8..10])?;
+	let hour = digits(&b[11..13])?;
+	let minute = digits(&b[14..16])?;
+	let second = digits(&b[17..19])?;
+	if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) || hour > 23 || minute > 59 || second > 59 {
+		return Err(ParseDateTimeError);
+	}
+
+	let mut rest = &b[19..];
+	let mut nanos = 0;
+	if let Some((b'.', frac)) = rest.split_first() {
+		let len = frac.iter().take_while(|c| c.is_ascii_digit()).count();
+		if len == 0 {
+			return Err(ParseDateTimeError);
+		}
+		// digits beyond nanosecond precision are ignored
+		nanos = frac[..len]
+			.iter()
+			.take(9)
+			.chain([b'0'; 9].iter())
+			.take(9)
+			.fold(0, |acc, c| acc * 10 + u32::from(c - b'0'));
+		rest = &frac[len..];
+	}
+
+	let offset = match rest {
+		[b'Z'] => 0,
+		[sign @ (b'+' | b'-'), h1, h2, b':', m1, m2] => {
+			let hours = digits(&[*h1, *h2])?;
+			let minutes = digits(&[*m1, *m2])?;
+			if hours > 23 || minutes > 59 {
+				return Err(ParseDateTimeError);
+			}
+			let offset = i64::from(hours * 3600 + minutes * 60);
+			if *sign == b'-' {
+				-offset
+			} else {
+				offset
+			}
+		}
+		_ => return Err(ParseDateTimeError),
+	};
+
+	let secs = days_from_civil(i64::from(year), month, day) * 86400 + i64::from(hour * 3600 + minute * 60 + second) - offset;
+	let out = if secs >= 0 {
+		UNIX_EPOCH.checked_add(Duration::new(secs.unsigned_abs(), nanos))
+	} else {
+		UNIX_EPOCH
+			.checked_sub(Duration::from_secs(secs.unsigned_abs()))
+			.and_then(|t| t.checked_add(Duration::from_nanos(u64::from(nanos))))
+	};
+	out.ok_or(ParseDateTimeError)
+}
+
+/// Parses the certificate validity time as printed by the TLS backends of libstrophe
+///
+/// OpenSSL prints the time as `Mon DD HH:MM:SS[.fff] YYYY GMT` (the day is padded with a space), the ISO 8601 form used by
+/// the other backends is handled by [parse].
+#[cfg(feature = "libstrophe-0_11_0")]
+pub fn parse_cert_time(s: &str) -> Result<SystemTime, ParseDateTimeError> {
+	const MONTHS: [&str; 12] = [
+		"Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+	];
+	let mut parts = s.split_whitespace();
+	let (month, day, time, year) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+		(Some(month), Some(day), Some(time), Some(year)) => (month, day, time, year),
+		_ => return parse(s),
+	};
+	if !matches!(parts.next(), None | Some("GMT" | "UTC")) || parts.next().is_some() {
+		return Err(ParseDateTimeError);
+	}
+	let month = MONTHS.iter().position(|&m| m == month).ok_or(ParseDateTimeError)? + 1;
+	let day = digits(day.as_bytes())?;
+	let year = digits(year.as_bytes())?;
+	// reuse the XEP-0082 parser for the validation and the conversion
+	parse(&format!("{:04}-{:02}-{:02}T{}Z", year, month, day, time))
+}
+
+fn digits(b: &[u8]) -> Result<u32, ParseDateTimeError> {
+	b.iter().try_fold(0, |acc, c| {
+		if c.is_ascii_digit() {
+			Ok(acc * 10 + u32::from(c - b'0'))
+		} else {
+			Err(ParseDateTimeError)
+		}
+	})
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+	match month {
+		2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+		2 => 28,
+		4 | 6 | 9 | 11 => 30,
+		_ => 31,
+	}
+}
+
+/// Number of days since 1970-01-01, see <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+	let (year, month) = match month {
+		1 | 2 => (year - 1, i64::from(month) + 9),
+		_ => (year, i64::from(month) - 3),
+	};
+	let era = year.div_euclid(400);
+	let yoe = year - era * 400;
+	let doy = (153 * month + 2) / 5 + i64::from(day) - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	era * 146097 + doe - 719468
+}
+
+/// Formats the time as [XEP-0082](https://xmpp.org/extensions/xep-0082.html) `DateTime` in UTC, fractional seconds are
+/// only included if non-zero and are truncated to milliseconds
+#[cfg_attr(not(feature = "xep-0203"), allow(dead_code))]
+pub fn format(time: SystemTime) -> String {
+	let (secs, millis) = match time.duration_since(UNIX_EPOCH) {
+		Ok(since) => (since.as_secs() as i64, since.subsec_millis()),
+		Err(e) => {
+			let before = e.duration();
+			let millis = (1000 - before.subsec_millis()) % 1000;
+			(-(before.as_secs() as i64) - i64::from(millis != 0), millis)
+		}
+	};
+	let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+	let secs_of_day = secs.rem_euclid(86400);
+	let mut out = format!(
+		"{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+		year,
+		month,
+		day,
+		secs_of_day / 3600,
+		secs_of_day / 60 % 60,
+		secs_of_day % 60
+	);
+	if millis != 0 {
+		out.push_str(&format!(".{:03}", millis));
+	}
+	out.push('Z');
+	out
+}
+
+/// Inverse of [days_from_civil], see <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+#[cfg_attr(not(feature = "xep-0203"), allow(dead_code))]
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+	let days = days + 719468;
+	let era = days.div_euclid(146097);
+	let doe = days - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+	let (year_offset, month) = match mp {
+		10 | 11 => (1, mp - 9),
+		_ => (0, mp + 3),
+	};
+	(yoe + era * 400 + year_offset, month as u32, day)
+}
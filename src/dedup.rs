@@ -0,0 +1,87 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::Stanza;
+
+/// [XEP-0359](https://xmpp.org/extensions/xep-0359.html) namespace
+pub const NS_SID: &str = "urn:xmpp:sid:0";
+
+/// Bounded LRU cache of the recently seen stanzas used to drop the exact duplicates
+///
+/// Duplicates are commonly delivered after a stream resumption or a MUC rejoin. A stanza is identified by its `from`
+/// attribute and its [XEP-0359](https://xmpp.org/extensions/xep-0359.html) `<origin-id/>`, falling back to the `id`
+/// attribute. Stanzas without any id are never considered duplicates. See also
+/// [Connection::dedup_handler_add](crate::Connection::dedup_handler_add).
+#[derive(Debug, Clone)]
+pub struct DedupCache {
+	capacity: usize,
+	order: VecDeque<(Option<String>, String)>,
+	seen: HashSet<(Option<String>, String)>,
+}
+
+impl DedupCache {
+	/// Creates a cache remembering at most `capacity` stanzas, the least recently seen stanza is evicted when it's full
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			capacity,
+			order: VecDeque::with_capacity(capacity),
+			seen: HashSet::with_capacity(capacity),
+		}
+	}
+
+	/// Key identifying the stanza for the de-duplication purposes
+	pub fn key(stanza: &Stanza) -> Option<(Option<String>, String)> {
+		let id = stanza
+			.find_child_by_name_and_ns("origin-id", NS_SID)
+			.and_then(|origin_id| origin_id.get_attribute("id").map(str::to_owned))
+			.or_else(|| stanza.id().map(str::to_owned))?;
+		Some((stanza.from().map(str::to_owned), id))
+	}
+
+	/// Records the `stanza` and returns `true` if it has already been seen
+	pub fn check(&mut self, stanza: &Stanza) -> bool {
+		if self.capacity == 0 {
+			return false;
+		}
+		let key = match Self::key(stanza) {
+			Some(key) => key,
+			None => return false,
+		};
+		if self.seen.contains(&key) {
+			if let Some(pos) = self.order.iter().position(|x| *x == key) {
+				if let Some(key) = self.order.remove(pos) {
+					self.order.push_back(key);
+				}
+			}
+			return true;
+		}
+		if self.order.len() >= self.capacity {
+			if let Some(evicted) = self.order.pop_front() {
+				self.seen.remove(&evicted);
+			}
+		}
+		self.seen.insert(key.clone());
+		self.order.push_back(key);
+		false
+	}
+
+	#[inline]
+	pub fn capacity(&self) -> usize {
+		self.capacity
+	}
+
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.order.len()
+	}
+
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.order.is_empty()
+	}
+
+	#[inline]
+	pub fn clear(&mut self) {
+		self.order.clear();
+		self.seen.clear();
+	}
+}
@@ -0,0 +1,195 @@
+//! Integration surface for the end-to-end message encryption implementations
+//!
+//! The crate doesn't implement any cryptography itself. External implementations of e.g.
+//! [OMEMO](https://xmpp.org/extensions/xep-0384.html) or [OpenPGP for XMPP](https://xmpp.org/extensions/xep-0373.html)
+//! implement [EncryptionPlugin] and install it with
+//! [`Connection::set_encryption_plugin()`](crate::Connection::set_encryption_plugin). The connection then passes the
+//! outgoing messages through [EncryptionPlugin::encrypt] in [`send()`](crate::Connection::send) and the incoming messages
+//! carrying the payload of the plugin namespace through [EncryptionPlugin::decrypt] before they reach the stanza handlers.
+//!
+//! The [frame] and [unframe] helpers take care of the message structure around the encrypted payload and
+//! [publish_request] builds the [XEP-0060](https://xmpp.org/extensions/xep-0060.html) publish request used to advertise
+//! the device lists and keys.
+
+use std::error::Error as StdError;
+use std::fmt;
+
+use crate::xep::NS_XHTML_IM;
+use crate::{Connection, Error, HandlerResult, Result, Stanza, StanzaRef};
+
+pub const NS_EME: &str = "urn:xmpp:eme:0";
+pub const NS_HINTS: &str = "urn:xmpp:hints";
+pub const NS_PUBSUB: &str = "http://jabber.org/protocol/pubsub";
+
+/// Failure of the encryption plugin
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EncryptionError {
+	/// The plugin couldn't encrypt or decrypt the message, e.g. because there is no session with the recipient
+	Failed(String),
+	/// The stanza couldn't be built
+	Stanza(Error),
+}
+
+impl fmt::Display for EncryptionError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			EncryptionError::Failed(reason) => write!(f, "Encryption failed: {}", reason),
+			EncryptionError::Stanza(e) => write!(f, "Cannot build stanza: {}", e),
+		}
+	}
+}
+
+impl StdError for EncryptionError {
+	fn source(&self) -> Option<&(dyn StdError + 'static)> {
+		match self {
+			EncryptionError::Failed(_) => None,
+			EncryptionError::Stanza(e) => Some(e),
+		}
+	}
+}
+
+impl From<Error> for EncryptionError {
+	fn from(e: Error) -> Self {
+		EncryptionError::Stanza(e)
+	}
+}
+
+/// Hooks of the end-to-end encryption implementation, see the [module docs](self)
+pub trait EncryptionPlugin: Send {
+	/// Namespace of the encrypted payload element, e.g. `eu.siacs.conversations.axolotl` or `urn:xmpp:openpgp:0`
+	fn namespace(&self) -> &str;
+
+	/// Human-readable name of the encryption method for the [XEP-0380](https://xmpp.org/extensions/xep-0380.html) hint
+	fn name(&self) -> Option<&str> {
+		None
+	}
+
+	/// Encrypts the outgoing `message`
+	///
+	/// Returns the stanza to send instead of `message`, usually built with [frame], or `None` to send `message` as is
+	/// (e.g. for the messages without a body). When an error is returned the message is not sent at all.
+	fn encrypt(&mut self, message: &Stanza) -> Result<Option<Stanza>, EncryptionError>;
+
+	/// Decrypts the `payload` element of the incoming `message`
+	///
+	/// Returns the decrypted content elements (e.g. `<body/>`) that replace the payload in the stanza passed to the
+	/// handlers, see [unframe]. Each incoming stanza is decrypted only once even if it's dispatched to several handlers.
+	fn decrypt(&mut self, message: &Stanza, payload: &Stanza) -> Result<Vec<Stanza>, EncryptionError>;
+
+	/// Returns the PEP node and the item payload advertising the devices or the keys of the account, see
+	/// [publish_device_list]
+	fn device_list(&self) -> Option<(String, Stanza)> {
+		None
+	}
+}
+
+/// Builds the encrypted version of `message`
+///
+/// The resulting stanza has the attributes of `message` and all of its children except the plaintext ones (`<body/>`,
+/// `<subject/>` and the XHTML-IM `<html/>`), followed by the `payload`, the XEP-0380 `<encryption/>` hint for the
+/// `namespace`, the `<store/>` processing hint and an optional `fallback` body for the clients not supporting the
+/// encryption.
+pub fn frame(message: &Stanza, payload: Stanza, namespace: &str, name: Option<&str>, fallback: Option<&str>) -> Result<Stanza> {
+	let mut out = copy_without(message, is_plaintext)?;
+	out.add_child(payload)?;
+	let mut hint = Stanza::new();
+	hint.set_name("encryption")?;
+	hint.set_ns(NS_EME)?;
+	hint.set_attribute("namespace", namespace)?;
+	if let Some(name) = name {
+		hint.set_attribute("name", name)?;
+	}
+	out.add_child(hint)?;
+	let mut store = Stanza::new();
+	store.set_name("store")?;
+	store.set_ns(NS_HINTS)?;
+	out.add_child(store)?;
+	if let Some(fallback) = fallback {
+		out.set_body(fallback)?;
+	}
+	Ok(out)
+}
+
+/// Returns the encrypted payload element of the `namespace` from `message`
+pub fn payload<'st>(message: &'st Stanza, namespace: &str) -> Option<StanzaRef<'st>> {
+	message.get_child_by_ns(namespace)
+}
+
+/// Builds the decrypted version of `message`
+///
+/// The payload element of the `namespace`, the `<encryption/>` hint and the fallback body are replaced with the
+/// decrypted `content`.
+pub fn unframe(message: &Stanza, namespace: &str, content: Vec<Stanza>) -> Result<Stanza> {
+	let mut out = copy_without(message, |child| {
+		child.name() == Some("body") || child.ns() == Some(namespace) || child.ns() == Some(NS_EME)
+	})?;
+	for child in content {
+		out.add_child(child)?;
+	}
+	Ok(out)
+}
+
+/// Builds the [XEP-0060](https://xmpp.org/extensions/xep-0060.html) request publishing the `item` payload to the `node`
+/// of the own account
+pub fn publish_request(node: &str, item_id: Option<&str>, item: Stanza, id: &str) -> Result<Stanza> {
+	let mut out = Stanza::new_iq(Some("set"), Some(id));
+	let mut pubsub = Stanza::new();
+	pubsub.set_name("pubsub")?;
+	pubsub.set_ns(NS_PUBSUB)?;
+	let mut publish = Stanza::new();
+	publish.set_name("publish")?;
+	publish.set_attribute("node", node)?;
+	let mut item_el = Stanza::new();
+	item_el.set_name("item")?;
+	if let Some(item_id) = item_id {
+		item_el.set_attribute("id", item_id)?;
+	}
+	item_el.add_child(item)?;
+	publish.add_child(item_el)?;
+	pubsub.add_child(publish)?;
+	out.add_child(pubsub)?;
+	Ok(out)
+}
+
+/// Publishes the [EncryptionPlugin::device_list] of the plugin installed in `conn`
+///
+/// The item is published with the `current` id. Returns `false` if there is no plugin or it has nothing to publish, the
+/// publish errors are logged.
+pub fn publish_device_list(conn: &mut Connection) -> Result<bool> {
+	let (node, item) = match conn.encryption_plugin_device_list() {
+		Some(device_list) => device_list,
+		None => return Ok(false),
+	};
+	let id = conn.generate_id();
+	let request = publish_request(&node, Some("current"), item, &id)?;
+	conn.id_handler_add(
+		move |ctx, _conn, stanza| {
+			if stanza.stanza_type() != Some("result") {
+				ctx.log_warn("encryption", &format!("Cannot publish device list to {}", node));
+			}
+			HandlerResult::RemoveHandler
+		},
+		id,
+	);
+	conn.send(&request);
+	Ok(true)
+}
+
+fn is_plaintext(child: &Stanza) -> bool {
+	matches!(child.name(), Some("body") | Some("subject")) || (child.name() == Some("html") && child.ns() == Some(NS_XHTML_IM))
+}
+
+fn copy_without(message: &Stanza, mut skip: impl FnMut(&Stanza) -> bool) -> Result<Stanza> {
+	let mut out = Stanza::new();
+	out.set_name(message.name().ok_or(Error::InvalidOperation)?)?;
+	for (name, value) in message.attributes() {
+		out.set_attribute(name, value)?;
+	}
+	for child in message.children() {
+		if !skip(&child) {
+			out.add_child(child.try_clone()?)?;
+		}
+	}
+	Ok(out)
+}
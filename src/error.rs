@@ -1,13 +1,14 @@
 use std::error::Error as StdError;
-use std::fmt;
 use std::os::raw::{c_char, c_int};
 use std::result::Result as StdResult;
 use std::str::Utf8Error;
 use std::sync::Mutex;
+use std::{fmt, io};
 
-use crate::{Connection, Stanza, StanzaMutRef, FFI};
+use crate::{Connection, EncryptionError, Stanza, StanzaMutRef, FFI};
 
 #[derive(Copy, Eq, PartialEq, Clone, Debug)]
+#[non_exhaustive]
 pub enum Error {
 	MemoryError,
 	InvalidOperation,
@@ -26,6 +27,28 @@ impl fmt::Display for Error {
 
 impl StdError for Error {}
 
+impl Error {
+	/// Returns the raw libstrophe error code (`XMPP_E*`) corresponding to this error
+	pub fn code(&self) -> c_int {
+		match self {
+			Error::MemoryError => sys::XMPP_EMEM,
+			Error::InvalidOperation => sys::XMPP_EINVOP,
+			Error::InternalError => sys::XMPP_EINT,
+		}
+	}
+}
+
+impl From<Error> for io::Error {
+	fn from(s: Error) -> Self {
+		let kind = match s {
+			Error::MemoryError => io::ErrorKind::OutOfMemory,
+			Error::InvalidOperation => io::ErrorKind::InvalidInput,
+			Error::InternalError => io::ErrorKind::Other,
+		};
+		io::Error::new(kind, s)
+	}
+}
+
 impl From<c_int> for Error {
 	fn from(code: c_int) -> Self {
 		match code {
@@ -47,6 +70,7 @@ impl From<Error> for fmt::Error {
 pub type Result<T, E = Error> = StdResult<T, E>;
 
 #[derive(Copy, Eq, PartialEq, Clone, Debug)]
+#[non_exhaustive]
 pub enum ToTextError {
 	StropheError(Error),
 	Utf8Error(Utf8Error),
@@ -82,12 +106,207 @@ impl From<Error> for ToTextError {
 	}
 }
 
+impl From<ToTextError> for io::Error {
+	fn from(s: ToTextError) -> Self {
+		match s {
+			ToTextError::StropheError(e) => e.into(),
+			ToTextError::Utf8Error(e) => io::Error::new(io::ErrorKind::InvalidData, e),
+		}
+	}
+}
+
+/// Error returned from the `Connection::connect_*()` methods, allows to take back the ownership of the `Connection`
 #[derive(Debug)]
 pub struct ConnectClientError<'cb, 'cx> {
 	pub conn: Connection<'cb, 'cx>,
 	pub error: Error,
 }
 
+impl fmt::Display for ConnectClientError<'_, '_> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Cannot connect: {}", self.error)
+	}
+}
+
+impl StdError for ConnectClientError<'_, '_> {
+	fn source(&self) -> Option<&(dyn StdError + 'static)> {
+		Some(&self.error)
+	}
+}
+
+impl From<ConnectClientError<'_, '_>> for Error {
+	#[inline]
+	fn from(s: ConnectClientError) -> Self {
+		s.error
+	}
+}
+
+/// Reason for rejecting the outgoing stanza in the strict send mode, see
+/// [`Connection::set_strict_send()`](crate::Connection::set_strict_send), or for failing to encrypt it
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SendValidationError {
+	/// The stanza of the component session doesn't have the `to` attribute
+	MissingTo,
+	/// The stanza of the component session doesn't have the `from` attribute
+	MissingFrom,
+	/// The `from` attribute is not a valid JID
+	InvalidFrom(String),
+	/// The `from` attribute of the component stanza is outside of the component `domain`
+	ForeignFrom { from: String, domain: String },
+	/// The `from` attribute of the client stanza doesn't match the `jid` of the session
+	SpoofedFrom { from: String, jid: String },
+	/// The installed [EncryptionPlugin](crate::EncryptionPlugin) couldn't encrypt the message
+	Encryption(EncryptionError),
+}
+
+impl fmt::Display for SendValidationError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SendValidationError::MissingTo => write!(f, "Component stanza without \"to\" attribute"),
+			SendValidationError::MissingFrom => write!(f, "Component stanza without \"from\" attribute"),
+			SendValidationError::InvalidFrom(from) => write!(f, "Invalid \"from\" attribute: {}", from),
+			SendValidationError::ForeignFrom { from, domain } => {
+				write!(f, "Sender {} is outside of the component domain {}", from, domain)
+			}
+			SendValidationError::SpoofedFrom { from, jid } => write!(f, "Sender {} doesn't match session JID {}", from, jid),
+			SendValidationError::Encryption(e) => write!(f, "Message not encrypted: {}", e),
+		}
+	}
+}
+
+impl StdError for SendValidationError {
+	fn source(&self) -> Option<&(dyn StdError + 'static)> {
+		match self {
+			SendValidationError::Encryption(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<EncryptionError> for SendValidationError {
+	fn from(e: EncryptionError) -> Self {
+		SendValidationError::Encryption(e)
+	}
+}
+
+impl From<SendValidationError> for io::Error {
+	fn from(s: SendValidationError) -> Self {
+		io::Error::new(io::ErrorKind::InvalidInput, s)
+	}
+}
+
+/// Reason for rejecting the stanza in [`StanzaBuilder::build()`](crate::StanzaBuilder::build)
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BuildStanzaError {
+	/// One of the building steps has failed
+	Strophe(Error),
+	/// The required attribute is missing, e.g. the `id` of the `<iq/>`
+	MissingAttribute(&'static str),
+	/// The `type` is not defined for the element `name`
+	InvalidType { name: String, typ: String },
+	/// The `get` or `set` `<iq/>` doesn't have exactly one payload element, contains their number
+	IqPayloads(usize),
+	/// The stanza of type `error` doesn't contain the `<error/>` element
+	MissingError,
+}
+
+impl fmt::Display for BuildStanzaError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			BuildStanzaError::Strophe(e) => write!(f, "Cannot build stanza: {}", e),
+			BuildStanzaError::MissingAttribute(name) => write!(f, "Stanza without \"{}\" attribute", name),
+			BuildStanzaError::InvalidType { name, typ } => write!(f, "Invalid type \"{}\" of <{}/>", typ, name),
+			BuildStanzaError::IqPayloads(count) => write!(f, "Request iq must have exactly one payload, found {}", count),
+			BuildStanzaError::MissingError => write!(f, "Error stanza without <error/> element"),
+		}
+	}
+}
+
+impl StdError for BuildStanzaError {
+	fn source(&self) -> Option<&(dyn StdError + 'static)> {
+		match self {
+			BuildStanzaError::Strophe(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<Error> for BuildStanzaError {
+	#[inline]
+	fn from(s: Error) -> Self {
+		BuildStanzaError::Strophe(s)
+	}
+}
+
+impl From<BuildStanzaError> for io::Error {
+	fn from(s: BuildStanzaError) -> Self {
+		io::Error::new(io::ErrorKind::InvalidInput, s)
+	}
+}
+
+/// Error returned from [`Stanza::attr_parse()`] and the typed getters built on top of it
+///
+/// [`Stanza::attr_parse()`]: crate::Stanza::attr_parse
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseAttrError<E> {
+	/// Name of the attribute or element that failed to parse
+	pub name: String,
+	/// Raw value that failed to parse
+	pub value: String,
+	pub error: E,
+}
+
+impl<E: fmt::Display> fmt::Display for ParseAttrError<E> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Cannot parse {} value \"{}\": {}", self.name, self.value, self.error)
+	}
+}
+
+impl<E: StdError + 'static> StdError for ParseAttrError<E> {
+	fn source(&self) -> Option<&(dyn StdError + 'static)> {
+		Some(&self.error)
+	}
+}
+
+impl<E: StdError + Send + Sync + 'static> From<ParseAttrError<E>> for io::Error {
+	fn from(s: ParseAttrError<E>) -> Self {
+		io::Error::new(io::ErrorKind::InvalidData, s)
+	}
+}
+
+/// Error returned when parsing an [XEP-0082](https://xmpp.org/extensions/xep-0082.html) date-time string
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ParseDateTimeError;
+
+impl fmt::Display for ParseDateTimeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("Invalid XEP-0082 date-time")
+	}
+}
+
+impl StdError for ParseDateTimeError {}
+
+fn parse_host_port(s: &str) -> Option<(String, Option<u16>)> {
+	if let Some(rest) = s.strip_prefix('[') {
+		let (host, port) = rest.split_once(']')?;
+		let port = match port {
+			"" => None,
+			port => Some(port.strip_prefix(':')?.parse().ok()?),
+		};
+		Some((host.to_owned(), port))
+	} else {
+		match s.split_once(':') {
+			// bare IPv6 address
+			Some((_, port)) if port.contains(':') => Some((s.to_owned(), None)),
+			Some((host, port)) if !host.is_empty() => Some((host.to_owned(), Some(port.parse().ok()?))),
+			None if !s.is_empty() => Some((s.to_owned(), None)),
+			_ => None,
+		}
+	}
+}
+
 fn error_type_to_str(typ: sys::xmpp_error_type_t) -> &'static str {
 	match typ {
 		sys::xmpp_error_type_t::XMPP_SE_BAD_FORMAT => "Bad format",
@@ -131,7 +350,7 @@ impl<'t> From<&'t sys::xmpp_stream_error_t> for StreamError<'t, 't> {
 	fn from(inner: &'t sys::xmpp_stream_error_t) -> Self {
 		StreamError {
 			typ: inner.type_,
-			text: unsafe { FFI(inner.text as *const c_char).receive() },
+			text: unsafe { FFI(inner.text as *const c_char).try_receive() },
 			stanza: unsafe { Stanza::from_ref_mut(inner.stanza) },
 		}
 	}
@@ -149,6 +368,21 @@ impl fmt::Display for StreamError<'_, '_> {
 }
 
 impl StreamError<'_, '_> {
+	/// Target host and optional port of the `see-other-host` stream error condition
+	///
+	/// See [RFC 6120 §4.9.3.19](https://www.rfc-editor.org/rfc/rfc6120#section-4.9.3.19). IPv6 addresses are returned without
+	/// the enclosing square brackets.
+	pub fn see_other_host(&self) -> Option<(String, Option<u16>)> {
+		if self.typ != sys::xmpp_error_type_t::XMPP_SE_SEE_OTHER_HOST {
+			return None;
+		}
+		self
+			.stanza
+			.find_child_by_name_and_ns("see-other-host", "urn:ietf:params:xml:ns:xmpp-streams")
+			.and_then(|target| target.with_text(|text| parse_host_port(text.trim())))
+			.flatten()
+	}
+
 	pub fn to_owned(&self) -> OwnedStreamError {
 		OwnedStreamError {
 			typ: self.typ,
@@ -220,6 +454,7 @@ impl IntoResult for c_int {
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ConnectionError<'t, 's> {
 	Aborted,
 	TimedOut,
@@ -255,6 +490,7 @@ impl fmt::Display for ConnectionError<'_, '_> {
 impl StdError for ConnectionError<'_, '_> {}
 
 #[derive(Clone, Debug)]
+#[non_exhaustive]
 pub enum OwnedConnectionError {
 	Aborted,
 	TimedOut,
@@ -275,6 +511,18 @@ impl From<ConnectionError<'_, '_>> for OwnedConnectionError {
 	}
 }
 
+impl From<&ConnectionError<'_, '_>> for OwnedConnectionError {
+	fn from(s: &ConnectionError<'_, '_>) -> Self {
+		match s {
+			ConnectionError::Aborted => OwnedConnectionError::Aborted,
+			ConnectionError::TimedOut => OwnedConnectionError::TimedOut,
+			ConnectionError::ConnectionReset => OwnedConnectionError::ConnectionReset,
+			ConnectionError::TLS(e) => OwnedConnectionError::TLS(*e),
+			ConnectionError::Stream(e) => OwnedConnectionError::Stream(e.into()),
+		}
+	}
+}
+
 impl fmt::Display for OwnedConnectionError {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match self {
@@ -296,3 +544,15 @@ impl StdError for OwnedConnectionError {
 		}
 	}
 }
+
+impl From<OwnedConnectionError> for io::Error {
+	fn from(s: OwnedConnectionError) -> Self {
+		let kind = match s {
+			OwnedConnectionError::Aborted => io::ErrorKind::ConnectionAborted,
+			OwnedConnectionError::TimedOut => io::ErrorKind::TimedOut,
+			OwnedConnectionError::ConnectionReset => io::ErrorKind::ConnectionReset,
+			OwnedConnectionError::TLS(_) | OwnedConnectionError::Stream(_) => io::ErrorKind::Other,
+		};
+		io::Error::new(kind, s)
+	}
+}
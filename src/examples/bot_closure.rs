@@ -105,8 +105,8 @@ pub fn main() {
 	};
 
 	let mut conn = libstrophe::Connection::new(libstrophe::Context::new_with_default_logger());
-	conn.set_jid(jid);
-	conn.set_pass(pass);
+	conn.set_jid(jid).expect("Invalid JID");
+	conn.set_pass(pass).expect("Cannot set password");
 	let ctx = conn
 		.connect_client(None, None, conn_handler)
 		.expect("Cannot connect to XMPP server");
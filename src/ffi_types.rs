@@ -1,7 +1,11 @@
+use std::borrow::Cow;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
 use std::ptr;
 
+/// Strings shorter than this are passed to [`FFI::<&str>::with_ptr()`] using a buffer on stack
+const STACK_BUF_LEN: usize = 64;
+
 #[allow(clippy::upper_case_acronyms)]
 pub struct FFI<T>(pub T);
 
@@ -10,6 +14,23 @@ impl FFI<&str> {
 	pub fn send(self) -> CString {
 		CString::new(self.0).expect("Cannot convert to CString")
 	}
+
+	/// Calls `f` with a pointer to NUL-terminated copy of the string, the pointer is only valid during the call
+	///
+	/// Unlike [`send()`](#method.send) it doesn't allocate for short strings like element names and namespaces which makes
+	/// it preferable for lookups.
+	#[inline]
+	pub fn with_ptr<R>(self, f: impl FnOnce(*const c_char) -> R) -> R {
+		let bytes = self.0.as_bytes();
+		if bytes.len() < STACK_BUF_LEN {
+			let mut buf = [0; STACK_BUF_LEN];
+			buf[..bytes.len()].copy_from_slice(bytes);
+			let s = CStr::from_bytes_with_nul(&buf[..=bytes.len()]).expect("Cannot convert to CString");
+			f(s.as_ptr())
+		} else {
+			f(self.send().as_ptr())
+		}
+	}
 }
 
 impl<T: num_traits::Zero + PartialEq> FFI<T> {
@@ -28,6 +49,20 @@ impl FFI<*const c_char> {
 			.as_ref()
 			.map(|x| CStr::from_ptr(x).to_str().expect("Cannot convert non-null pointer into &str"))
 	}
+
+	/// Same as [`receive()`](#method.receive), but returns `None` instead of panicking on invalid UTF-8
+	///
+	/// Use it for the strings received inside the C callbacks.
+	#[inline]
+	pub unsafe fn try_receive<'s>(self) -> Option<&'s str> {
+		self.0.as_ref().and_then(|x| CStr::from_ptr(x).to_str().ok())
+	}
+
+	/// Same as [`receive()`](#method.receive), but replaces the invalid UTF-8 sequences instead of panicking
+	#[inline]
+	pub unsafe fn receive_lossy<'s>(self) -> Option<Cow<'s, str>> {
+		self.0.as_ref().map(|x| CStr::from_ptr(x).to_string_lossy())
+	}
 }
 
 impl FFI<*mut c_char> {
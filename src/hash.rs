@@ -0,0 +1,96 @@
+//! SHA-1 implementation of libstrophe, e.g. for the [XEP-0115](https://xmpp.org/extensions/xep-0115.html) verification
+//! strings
+//!
+//! See [crypto.c](https://github.com/strophe/libstrophe/blob/0.12.2/src/crypto.c) for the underlying functions.
+
+use std::fmt;
+use std::ptr::NonNull;
+
+use crate::{ALLOC_CONTEXT, FFI};
+
+/// Length of the SHA-1 digest in bytes
+pub const SHA1_DIGEST_SIZE: usize = 20;
+
+/// Incremental SHA-1 hash
+///
+/// [xmpp_sha1_new](https://github.com/strophe/libstrophe/blob/0.12.2/src/crypto.c)
+pub struct Sha1 {
+	inner: NonNull<sys::xmpp_sha1_t>,
+}
+
+impl Sha1 {
+	pub fn new() -> Self {
+		Self {
+			inner: NonNull::new(unsafe { sys::xmpp_sha1_new(ALLOC_CONTEXT.as_ptr()) }).expect("Cannot allocate memory for SHA-1"),
+		}
+	}
+
+	/// [xmpp_sha1_update](https://github.com/strophe/libstrophe/blob/0.12.2/src/crypto.c)
+	pub fn update(&mut self, data: impl AsRef<[u8]>) {
+		let data = data.as_ref();
+		unsafe { sys::xmpp_sha1_update(self.inner.as_ptr(), data.as_ptr(), data.len()) }
+	}
+
+	/// [xmpp_sha1_final](https://github.com/strophe/libstrophe/blob/0.12.2/src/crypto.c)
+	/// [xmpp_sha1_to_digest](https://github.com/strophe/libstrophe/blob/0.12.2/src/crypto.c)
+	pub fn finalize(self) -> [u8; SHA1_DIGEST_SIZE] {
+		let mut out = [0; SHA1_DIGEST_SIZE];
+		unsafe {
+			sys::xmpp_sha1_final(self.inner.as_ptr());
+			sys::xmpp_sha1_to_digest(self.inner.as_ptr(), out.as_mut_ptr());
+		}
+		out
+	}
+
+	/// Same as [Sha1::finalize], but returns the digest as a lowercase hex string
+	///
+	/// [xmpp_sha1_to_string_alloc](https://github.com/strophe/libstrophe/blob/0.12.2/src/crypto.c)
+	pub fn finalize_hex(self) -> String {
+		unsafe {
+			sys::xmpp_sha1_final(self.inner.as_ptr());
+			FFI(sys::xmpp_sha1_to_string_alloc(self.inner.as_ptr())).receive_with_free(|x| ALLOC_CONTEXT.free(x))
+		}
+		.expect("Cannot allocate memory for SHA-1 string")
+	}
+}
+
+impl Default for Sha1 {
+	#[inline]
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl fmt::Debug for Sha1 {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Sha1").finish_non_exhaustive()
+	}
+}
+
+impl Drop for Sha1 {
+	/// [xmpp_sha1_free](https://github.com/strophe/libstrophe/blob/0.12.2/src/crypto.c)
+	fn drop(&mut self) {
+		unsafe { sys::xmpp_sha1_free(self.inner.as_ptr()) }
+	}
+}
+
+unsafe impl Send for Sha1 {}
+
+/// Calculates SHA-1 of `data` and returns it as a lowercase hex string
+///
+/// [xmpp_sha1](https://github.com/strophe/libstrophe/blob/0.12.2/src/crypto.c)
+pub fn sha1(data: impl AsRef<[u8]>) -> String {
+	let data = data.as_ref();
+	unsafe { FFI(sys::xmpp_sha1(ALLOC_CONTEXT.as_ptr(), data.as_ptr(), data.len())).receive_with_free(|x| ALLOC_CONTEXT.free(x)) }
+		.expect("Cannot allocate memory for SHA-1 string")
+}
+
+/// Calculates SHA-1 of `data` and returns the raw digest
+///
+/// [xmpp_sha1_digest](https://github.com/strophe/libstrophe/blob/0.12.2/src/crypto.c)
+pub fn sha1_digest(data: impl AsRef<[u8]>) -> [u8; SHA1_DIGEST_SIZE] {
+	let data = data.as_ref();
+	let mut out = [0; SHA1_DIGEST_SIZE];
+	unsafe { sys::xmpp_sha1_digest(data.as_ptr(), data.len(), out.as_mut_ptr()) };
+	out
+}
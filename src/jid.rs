@@ -1,4 +1,272 @@
-use crate::{ALLOC_CONTEXT, FFI};
+use std::{fmt, str};
+
+use crate::{Error, Result, ALLOC_CONTEXT, FFI};
+
+/// Maximum length of each JID part in bytes, see [RFC 7622](https://www.rfc-editor.org/rfc/rfc7622#section-3.1)
+const MAX_PART_LEN: usize = 1023;
+
+/// Validated JID in the form of `[node@]domain[/resource]`
+///
+/// The parsing follows the [RFC 7622](https://www.rfc-editor.org/rfc/rfc7622#section-3.1) structure, but doesn't perform
+/// the stringprep/PRECIS normalization. The [`FromStr`](str::FromStr) implementation returns [Error::InvalidOperation] for
+/// malformed input.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Jid {
+	jid: String,
+	at: Option<usize>,
+	slash: Option<usize>,
+}
+
+impl Jid {
+	/// Builds the JID from parts, see [jid_new()]
+	pub fn new(node: Option<&str>, domain: impl AsRef<str>, resource: Option<&str>) -> Result<Self> {
+		let domain = domain.as_ref();
+		let mut jid = String::with_capacity(
+			node.map_or(0, |node| node.len() + 1) + domain.len() + resource.map_or(0, |resource| resource.len() + 1),
+		);
+		if let Some(node) = node {
+			jid.push_str(node);
+			jid.push('@');
+		}
+		jid.push_str(domain);
+		if let Some(resource) = resource {
+			jid.push('/');
+			jid.push_str(resource);
+		}
+		let out = jid.parse::<Self>()?;
+		// make sure that the parts didn't contain separators themselves
+		if out.node() == node && out.domain() == domain && out.resource() == resource {
+			Ok(out)
+		} else {
+			Err(Error::InvalidOperation)
+		}
+	}
+
+	#[inline]
+	pub fn as_str(&self) -> &str {
+		&self.jid
+	}
+
+	#[inline]
+	pub fn node(&self) -> Option<&str> {
+		self.at.map(|at| &self.jid[..at])
+	}
+
+	#[inline]
+	pub fn domain(&self) -> &str {
+		let start = self.at.map_or(0, |at| at + 1);
+		let end = self.slash.unwrap_or(self.jid.len());
+		&self.jid[start..end]
+	}
+
+	#[inline]
+	pub fn resource(&self) -> Option<&str> {
+		self.slash.map(|slash| &self.jid[slash + 1..])
+	}
+
+	#[inline]
+	/// String representation of the JID without the resource
+	pub fn bare(&self) -> &str {
+		&self.jid[..self.slash.unwrap_or(self.jid.len())]
+	}
+
+	#[inline]
+	pub fn to_bare(&self) -> Self {
+		Jid {
+			jid: self.bare().to_owned(),
+			at: self.at,
+			slash: None,
+		}
+	}
+
+	#[inline]
+	pub fn is_bare(&self) -> bool {
+		self.slash.is_none()
+	}
+}
+
+impl str::FromStr for Jid {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		let slash = s.find('/');
+		let at = s[..slash.unwrap_or(s.len())].find('@');
+		let out = Jid {
+			jid: s.to_owned(),
+			at,
+			slash,
+		};
+		let node_valid = out.node().map_or(true, |node| {
+			valid_part(node) && !node.contains(['"', '&', '\'', '/', ':', '<', '>', '@'])
+		});
+		let domain_valid = valid_part(out.domain()) && !out.domain().contains('@');
+		let resource_valid = out.resource().map_or(true, |resource| {
+			!resource.is_empty() && resource.len() <= MAX_PART_LEN && !resource.contains(char::is_control)
+		});
+		if node_valid && domain_valid && resource_valid {
+			Ok(out)
+		} else {
+			Err(Error::InvalidOperation)
+		}
+	}
+}
+
+impl fmt::Display for Jid {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&self.jid)
+	}
+}
+
+impl AsRef<str> for Jid {
+	#[inline]
+	fn as_ref(&self) -> &str {
+		&self.jid
+	}
+}
+
+impl From<Jid> for String {
+	#[inline]
+	fn from(s: Jid) -> Self {
+		s.jid
+	}
+}
+
+/// Pattern for matching the JIDs, e.g. the sender of a stanza, see [Connection::handler_add_from]
+///
+/// Node and domain are compared case-insensitively and the trailing dot of the domain is ignored, the resource is compared
+/// as is. This approximates the RFC 7622 normalization without full PRECIS support.
+///
+/// [Connection::handler_add_from]: crate::Connection::handler_add_from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JidMatcher {
+	/// Matches exactly this JID, a bare JID only matches the bare JIDs
+	Full(Jid),
+	/// Matches the bare part of this JID with any resource or without one
+	Bare(Jid),
+	/// Matches any JID of this domain
+	Domain(String),
+	/// Matches any JID of this domain or any of its subdomains
+	DomainWildcard(String),
+}
+
+impl JidMatcher {
+	/// Checks whether the `jid` matches this pattern, a malformed `jid` never matches
+	pub fn matches(&self, jid: &str) -> bool {
+		jid.parse::<Jid>().map_or(false, |jid| self.matches_jid(&jid))
+	}
+
+	pub fn matches_jid(&self, jid: &Jid) -> bool {
+		match self {
+			JidMatcher::Full(pattern) => bare_matches(pattern, jid) && pattern.resource() == jid.resource(),
+			JidMatcher::Bare(pattern) => bare_matches(pattern, jid),
+			JidMatcher::Domain(domain) => domain_eq(domain, jid.domain()),
+			JidMatcher::DomainWildcard(domain) => {
+				let domain = domain.strip_suffix('.').unwrap_or(domain);
+				let jid_domain = jid.domain().strip_suffix('.').unwrap_or(jid.domain());
+				domain_eq(domain, jid_domain)
+					|| jid_domain
+						.len()
+						.checked_sub(domain.len() + 1)
+						.and_then(|dot| jid_domain.get(dot..))
+						.and_then(|suffix| suffix.strip_prefix('.'))
+						.map_or(false, |suffix| fold_eq(suffix, domain))
+			}
+		}
+	}
+}
+
+fn bare_matches(pattern: &Jid, jid: &Jid) -> bool {
+	let node_eq = match (pattern.node(), jid.node()) {
+		(Some(pattern), Some(node)) => fold_eq(pattern, node),
+		(None, None) => true,
+		_ => false,
+	};
+	node_eq && domain_eq(pattern.domain(), jid.domain())
+}
+
+fn domain_eq(a: &str, b: &str) -> bool {
+	fold_eq(a.strip_suffix('.').unwrap_or(a), b.strip_suffix('.').unwrap_or(b))
+}
+
+/// Case-insensitive comparison, allocates only for the non-ASCII strings
+fn fold_eq(a: &str, b: &str) -> bool {
+	if a.is_ascii() && b.is_ascii() {
+		a.eq_ignore_ascii_case(b)
+	} else {
+		a.to_lowercase() == b.to_lowercase()
+	}
+}
+
+/// Characters escaped by [escape_node] with their escape sequences
+const NODE_ESCAPES: [(char, &str); 10] = [
+	(' ', "20"),
+	('"', "22"),
+	('&', "26"),
+	('\'', "27"),
+	('/', "2f"),
+	(':', "3a"),
+	('<', "3c"),
+	('>', "3e"),
+	('@', "40"),
+	('\\', "5c"),
+];
+
+/// Escapes the node (localpart) of the JID according to [XEP-0106](https://xmpp.org/extensions/xep-0106.html)
+///
+/// Lets the gateways and transports map the foreign addresses containing the characters not allowed in the node, e.g.
+/// `user@host` becomes `user\40host` in `user\40host@transport.example`. The leading and trailing spaces can't be
+/// represented and are removed. The backslash is only escaped when it's followed by something that looks like an escape
+/// sequence, so the escaping of an already escaped string is not idempotent, but it always round-trips with
+/// [unescape_node].
+pub fn escape_node(node: &str) -> String {
+	let node = node.trim_matches(' ');
+	let mut out = String::with_capacity(node.len());
+	for (i, c) in node.char_indices() {
+		let escape = match c {
+			'\\' if escape_sequence_at(node, i + 1).is_none() => None,
+			c => NODE_ESCAPES.iter().find(|(escaped, _)| *escaped == c).map(|(_, code)| code),
+		};
+		match escape {
+			Some(code) => {
+				out.push('\\');
+				out.push_str(code);
+			}
+			None => out.push(c),
+		}
+	}
+	out
+}
+
+/// Reverses [escape_node], the backslashes that don't start one of the XEP-0106 escape sequences are kept as is
+pub fn unescape_node(node: &str) -> String {
+	let mut out = String::with_capacity(node.len());
+	let mut rest = node;
+	while let Some(pos) = rest.find('\\') {
+		out.push_str(&rest[..pos]);
+		match escape_sequence_at(rest, pos + 1) {
+			Some(c) => {
+				out.push(c);
+				rest = &rest[pos + 3..];
+			}
+			None => {
+				out.push('\\');
+				rest = &rest[pos + 1..];
+			}
+		}
+	}
+	out.push_str(rest);
+	out
+}
+
+/// Returns the character escaped by the 2 hex digits at `pos` of `s`
+fn escape_sequence_at(s: &str, pos: usize) -> Option<char> {
+	let code = s.get(pos..pos + 2)?;
+	NODE_ESCAPES.iter().find(|(_, escape)| *escape == code).map(|(c, _)| *c)
+}
+
+fn valid_part(part: &str) -> bool {
+	!part.is_empty() && part.len() <= MAX_PART_LEN && !part.contains(|c: char| c.is_whitespace() || c.is_control())
+}
 
 #[inline]
 /// [xmpp_jid_new](https://strophe.im/libstrophe/doc/0.12.2/jid_8c.html#af7473f6e7a3338ec42d1c874ae563a47)
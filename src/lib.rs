@@ -79,8 +79,8 @@
 //!
 //! let ctx = libstrophe::Context::new_with_default_logger();
 //! let mut conn = libstrophe::Connection::new(ctx);
-//! conn.set_jid("example@127.0.0.1");
-//! conn.set_pass("password");
+//! conn.set_jid("example@127.0.0.1").expect("Invalid JID");
+//! conn.set_pass("password").expect("Cannot set password");
 //! let ctx = conn.connect_client(None, None, connection_handler).unwrap();
 //! ctx.run();
 //! libstrophe::shutdown();
@@ -100,6 +100,23 @@
 //!   * `libstrophe-0_12_0` - enabled by default, enables functionality specific to libstrophe-0.12.0
 //!   * `buildtime_bindgen` - forces regeneration of the bindings instead of relying on the
 //!     pre-generated sources
+//!   * `unsafe-internals` - enabled by default, allows the crate to rely on the memory layout of the private
+//!     libstrophe structs (known for versions 0.9.3 to 0.12.2) to avoid copying stanzas when cloning them, without
+//!     it the stanzas are deep-copied using only the public API of the library
+//!   * `stanza-tracking` - records the creation backtraces of the stanza wrappers to help finding leaks and
+//!     use-after-free bugs, see [`stanza_tracking`] module, slow and for debugging only, requires Rust 1.65
+//!   * `core_ffi` - uses the `va_list`-free bindings referring only to `core::ffi` types, see the
+//!     `libstrophe-sys-bindgen` crate docs for details
+//!   * `serde` - implements `Serialize` and `Deserialize` for [`LogLevel`], [`ErrorType`] and [`CertElement`] using the
+//!     names of their `Display` and `FromStr` implementations (e.g. `"debug"` or `"not-authorized"`)
+//!   * `xep-helpers` - enabled by default, enables all of the protocol helpers in the [`xep`] module, each of them
+//!     can also be enabled separately with the corresponding feature: `xep-0004` (Data Forms), `xep-0016` (Privacy
+//!     Lists), `xep-0045` (Multi-User Chat), `xep-0055` (Jabber Search), `xep-0071` (XHTML-IM), `xep-0077` (In-Band
+//!     Registration), `xep-0166` (Jingle), `xep-0191` (Blocking Command), `xep-0203` (Delayed Delivery), `xep-0224`
+//!     (Attention), `xep-0249` (Direct MUC Invitations), `xep-0297` (Stanza Forwarding) and `xep-0388` (Extensible SASL
+//!     Profile). The XEP helpers need at least libstrophe 0.10, so each of them enables `libstrophe-0_10_0`. The
+//!     `presence` and `roster` features enable the RFC 6121 presence and roster helpers, they work with all of the
+//!     supported libstrophe versions.
 //!
 //! [libstrophe]: https://strophe.im/libstrophe/
 //! [`log`]: https://crates.io/crates/log
@@ -117,42 +134,82 @@ use bitflags::bitflags;
 use once_cell::sync::Lazy;
 
 pub use alloc_context::AllocContext;
+pub use cancel::CancelToken;
 #[cfg(feature = "libstrophe-0_11_0")]
 pub use connection::CertFailResult;
+pub use connection::{
+	Connection, ConnectionEvent, ConnectionEventRecord, ConnectionId, ConnectionToken, HandlerChain, HandlerError, HandlerId,
+	HandlerInfo, HandlerKind, HandlerResult, IdHandlerId, OwnedConnectionEvent, SendPriority, Throughput, TimedHandlerId,
+	TrafficLogPolicy, Utf8Policy,
+};
 #[cfg(feature = "libstrophe-0_12_0")]
-pub use connection::SockoptResult;
-pub use connection::{Connection, ConnectionEvent, HandlerId, HandlerResult, IdHandlerId, TimedHandlerId};
-pub use context::Context;
+pub use connection::{KeepaliveOpts, ResendPolicy, SockoptResult};
+pub use context::{Context, ContextId, DispatchCtx, RunAsync, RunHandle, RunOutcome};
+pub use dedup::DedupCache;
+pub use encryption::{EncryptionError, EncryptionPlugin};
 pub use error::{
-	ConnectClientError, ConnectionError, Error, OwnedConnectionError, OwnedStreamError, Result, StreamError, ToTextError,
+	BuildStanzaError, ConnectClientError, ConnectionError, Error, OwnedConnectionError, OwnedStreamError, ParseAttrError,
+	ParseDateTimeError, Result, SendValidationError, StreamError, ToTextError,
 };
 use ffi_types::FFI;
+pub use jid::{Jid, JidMatcher};
 pub use logger::Logger;
+pub use message_threads::{MessageThread, MessageThreads};
+pub use module::{ModuleId, XmppModule};
+pub use pool::{ConnectionPool, PoolHealth};
+pub use redaction::RedactionPolicy;
+pub use sm_mirror::SmMirror;
 #[cfg(feature = "libstrophe-0_12_0")]
 pub use sm_state::SMState;
-pub use stanza::{Stanza, StanzaMutRef, StanzaRef, XMPP_STANZA_NAME_IN_NS};
+#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+pub use socket::SocketRef;
+pub use stanza::{Node, Stanza, StanzaBuilder, StanzaMutRef, StanzaRef, NS_XML, XMPP_STANZA_NAME_IN_NS};
 #[cfg(feature = "libstrophe-0_11_0")]
 pub use sys::xmpp_cert_element_t as CertElement;
+pub use sys::xmpp_conn_type_t as ConnType;
 #[cfg(feature = "libstrophe-0_9_3")]
 pub use sys::xmpp_error_type_t as ErrorType;
 pub use sys::xmpp_log_level_t as LogLevel;
 #[cfg(feature = "libstrophe-0_12_0")]
 pub use sys::xmpp_queue_element_t as QueueElement;
+pub use sys::ParseEnumError;
 #[cfg(feature = "libstrophe-0_11_0")]
 pub use tls_cert::TlsCert;
+pub use tls_info::TlsInfo;
+pub use version::RuntimeVersion;
 
 mod alloc_context;
+mod base64;
+mod cancel;
 mod connection;
 mod context;
+mod datetime;
+mod dedup;
+pub mod encryption;
 mod error;
 mod ffi_types;
+pub mod hash;
 pub mod jid;
 mod logger;
+mod message_threads;
+pub mod module;
+mod pool;
+pub mod proxy;
+mod redaction;
+mod scheduler;
+mod sm_mirror;
 #[cfg(feature = "libstrophe-0_12_0")]
 mod sm_state;
+#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+mod socket;
 mod stanza;
+#[cfg(feature = "stanza-tracking")]
+pub mod stanza_tracking;
 #[cfg(feature = "libstrophe-0_11_0")]
 mod tls_cert;
+mod tls_info;
+mod version;
+pub mod xep;
 
 #[cfg(test)]
 mod examples;
@@ -181,7 +238,22 @@ fn as_void_ptr<T>(cb: &T) -> *mut c_void {
 
 /// Convert *void from `userdata` to appropriate type
 unsafe fn void_ptr_as<'cb, T>(ptr: *const c_void) -> &'cb mut T {
-	(ptr as *mut T).as_mut().expect("userdata must be non-null")
+	void_ptr_as_opt(ptr).expect("userdata must be non-null")
+}
+
+/// Same as [void_ptr_as], but returns `None` for the null `userdata`, use it inside the C callbacks where panics are not
+/// allowed
+unsafe fn void_ptr_as_opt<'cb, T>(ptr: *const c_void) -> Option<&'cb mut T> {
+	(ptr as *mut T).as_mut()
+}
+
+/// Generates a new random UUID using [xmpp_uuid_gen](https://github.com/strophe/libstrophe/blob/0.12.2/src/uuid.c)
+///
+/// The result is a version 4 UUID in the canonical hyphenated form, suitable for the stanza ids. To generate the ids for a
+/// particular connection prefer [Connection::generate_id] which respects the custom generator.
+pub fn uuid_gen() -> String {
+	unsafe { FFI(sys::xmpp_uuid_gen(ALLOC_CONTEXT.as_ptr())).receive_with_free(|x| ALLOC_CONTEXT.free(x)) }
+		.expect("Cannot generate UUID")
 }
 
 /// Ensure that underlying C library is initialized
@@ -204,6 +276,14 @@ pub fn version_check(major: i32, minor: i32) -> bool {
 	unsafe { FFI(sys::xmpp_version_check(major, minor)).receive_bool() }
 }
 
+/// Returns the versions of this crate and of the linked libstrophe together with the enabled crate features
+///
+/// libstrophe only exposes [version_check], so the linked major and minor versions are found by probing it, the patch
+/// version is not available.
+pub fn runtime_version() -> RuntimeVersion {
+	RuntimeVersion::detect()
+}
+
 /// [xmpp_shutdown](https://strophe.im/libstrophe/doc/0.12.2/group___init.html#ga06e07524aee531de1ceb825541307963)
 ///
 /// Call this function when your application terminates, but be aware that you can't use the library
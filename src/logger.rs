@@ -1,15 +1,59 @@
+use std::cell::Cell;
 use std::ffi::c_void;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::os::raw::c_char;
-use std::ptr::NonNull;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr::{self, NonNull};
+use std::rc::Rc;
 
 #[cfg(feature = "log")]
-use log::{debug, error, info, warn};
+use log::{log, Level, LevelFilter};
 
-use crate::{as_void_ptr, void_ptr_as, LogLevel, FFI};
+use crate::{as_void_ptr, void_ptr_as_opt, LogLevel, FFI};
 
-type LogHandler<'cb> = dyn Fn(LogLevel, &str, &str) + Send + 'cb;
+/// Destination of the log messages, checked with [LogSink::enabled] before the message is converted from the C strings
+trait LogSink: Send {
+	fn enabled(&self, level: LogLevel) -> bool;
+
+	fn log(&self, level: LogLevel, area: &str, msg: &str);
+}
+
+struct Unfiltered<CB>(CB);
+
+impl<CB: Fn(LogLevel, &str, &str) + Send> LogSink for Unfiltered<CB> {
+	#[inline]
+	fn enabled(&self, _level: LogLevel) -> bool {
+		true
+	}
+
+	#[inline]
+	fn log(&self, level: LogLevel, area: &str, msg: &str) {
+		(self.0)(level, area, msg)
+	}
+}
+
+struct Filtered<E, CB> {
+	enabled: E,
+	handler: CB,
+}
+
+impl<E: Fn(LogLevel) -> bool + Send, CB: Fn(LogLevel, &str, &str) + Send> LogSink for Filtered<E, CB> {
+	#[inline]
+	fn enabled(&self, level: LogLevel) -> bool {
+		(self.enabled)(level)
+	}
+
+	#[inline]
+	fn log(&self, level: LogLevel, area: &str, msg: &str) {
+		(self.handler)(level, area, msg)
+	}
+}
+
+thread_local! {
+	/// Logger of the connection that's currently being serviced, see [LogRoute]
+	static ROUTE: Cell<*const sys::xmpp_log_t> = const { Cell::new(ptr::null()) };
+}
 
 /// Wrapper around the underlying `xmpp_log_t` struct.
 ///
@@ -28,7 +72,9 @@ type LogHandler<'cb> = dyn Fn(LogLevel, &str, &str) + Send + 'cb;
 pub struct Logger<'cb> {
 	inner: NonNull<sys::xmpp_log_t>,
 	owned: bool,
-	handler: Box<LogHandler<'cb>>,
+	handler: Box<dyn LogSink + 'cb>,
+	#[cfg(feature = "log")]
+	max_level: Option<LevelFilter>,
 }
 
 impl<'cb> Logger<'cb> {
@@ -39,10 +85,26 @@ impl<'cb> Logger<'cb> {
 	where
 		CB: Fn(LogLevel, &str, &str) + Send + 'cb,
 	{
-		let handler = Box::new(handler);
+		Self::with_sink(Unfiltered(handler))
+	}
+
+	/// Create a new custom logger that only receives the messages passing the `enabled` check
+	///
+	/// The check is done before the message is converted from the C strings, so the messages that are filtered out (e.g.
+	/// the high-frequency debug ones when the debug logging is disabled) cost no allocations.
+	pub fn new_with_filter<E, CB>(enabled: E, handler: CB) -> Self
+	where
+		E: Fn(LogLevel) -> bool + Send + 'cb,
+		CB: Fn(LogLevel, &str, &str) + Send + 'cb,
+	{
+		Self::with_sink(Filtered { enabled, handler })
+	}
+
+	fn with_sink<S: LogSink + 'cb>(sink: S) -> Self {
+		let handler = Box::new(sink);
 		Logger::with_inner(
 			Box::into_raw(Box::new(sys::xmpp_log_t {
-				handler: Some(Self::log_handler_cb::<CB>),
+				handler: Some(Self::log_handler_cb::<S>),
 				userdata: as_void_ptr(&*handler),
 			})),
 			handler,
@@ -51,14 +113,45 @@ impl<'cb> Logger<'cb> {
 	}
 
 	#[inline]
-	fn with_inner(inner: *mut sys::xmpp_log_t, handler: Box<LogHandler<'cb>>, owned: bool) -> Self {
+	fn with_inner(inner: *mut sys::xmpp_log_t, handler: Box<dyn LogSink + 'cb>, owned: bool) -> Self {
 		Logger {
 			inner: NonNull::new(inner).expect("Cannot allocate memory for Logger"),
 			owned,
 			handler,
+			#[cfg(feature = "log")]
+			max_level: None,
 		}
 	}
 
+	/// Return a new logger like [`Logger::default()`] that drops the libstrophe messages above `max_level` before they
+	/// reach the [`log`] crate
+	///
+	/// Useful to quiet the XMPP traffic without lowering the level of the rest of the application. With
+	/// [`LevelFilter::Trace`] the [Context](crate::Context) created with this logger also enables the verbose debug output
+	/// of libstrophe, see [`Context::set_verbosity()`](crate::Context::set_verbosity).
+	///
+	/// [`Logger::default()`]: struct.Logger.html#method.default
+	/// [`log`]: https://crates.io/crates/log
+	#[cfg(feature = "log")]
+	pub fn with_max_level(max_level: LevelFilter) -> Logger<'static> {
+		let mut out = Logger::new_with_filter(
+			move |log_level| {
+				let level = to_log_level(log_level);
+				level <= max_level && level <= log::max_level()
+			},
+			|log_level, area, message| log!(to_log_level(log_level), "{}: {}", area, message),
+		);
+		out.max_level = Some(max_level);
+		out
+	}
+
+	#[cfg(feature = "log")]
+	#[inline]
+	/// The level set with [`Logger::with_max_level()`](#method.with_max_level)
+	pub fn max_level(&self) -> Option<LevelFilter> {
+		self.max_level
+	}
+
 	/// [xmpp_get_default_logger](https://strophe.im/libstrophe/doc/0.12.2/group___context.html#ga40caddfbd7d786f8ef1390866880edb9)
 	///
 	/// This method returns default `libstrophe` logger that just outputs log lines to stderr. Use it
@@ -66,27 +159,33 @@ impl<'cb> Logger<'cb> {
 	pub fn new_internal(log_level: LogLevel) -> Logger<'static> {
 		Logger::with_inner(
 			unsafe { sys::xmpp_get_default_logger(log_level) },
-			Box::new(|_, _, _| {}),
+			Box::new(Unfiltered(|_: LogLevel, _: &str, _: &str| {})),
 			false,
 		)
 	}
 
 	/// This method returns null logger that doesn't output any information.
 	pub fn new_null() -> Logger<'static> {
-		Logger::new(|_, _, _| {})
+		Logger::new_with_filter(|_| false, |_, _, _| {})
 	}
 
-	unsafe extern "C" fn log_handler_cb<CB>(
+	unsafe extern "C" fn log_handler_cb<S>(
 		userdata: *mut c_void,
 		level: sys::xmpp_log_level_t,
 		area: *const c_char,
 		msg: *const c_char,
 	) where
-		CB: FnMut(LogLevel, &str, &str) + Send + 'cb,
+		S: LogSink + 'cb,
 	{
-		let area = FFI(area).receive().unwrap();
-		let msg = FFI(msg).receive().unwrap();
-		void_ptr_as::<CB>(userdata)(level, area, msg);
+		if let Some(sink) = void_ptr_as_opt::<S>(userdata) {
+			if !sink.enabled(level) {
+				return;
+			}
+			let area = FFI(area).receive_lossy().unwrap_or_default();
+			let msg = FFI(msg).receive_lossy().unwrap_or_default();
+			// there is no context to report the panic to, the message is dropped instead of unwinding into the C code
+			let _ = panic::catch_unwind(AssertUnwindSafe(|| sink.log(level, &area, &msg)));
+		}
 	}
 
 	pub(crate) fn as_ptr(&self) -> *const sys::xmpp_log_t {
@@ -94,7 +193,16 @@ impl<'cb> Logger<'cb> {
 	}
 
 	pub fn log(&self, level: LogLevel, area: &str, msg: &str) {
-		(self.handler)(level, area, msg);
+		if self.handler.enabled(level) {
+			self.handler.log(level, area, msg);
+		}
+	}
+
+	#[inline]
+	/// Checks whether the message of the `level` would be passed to the handler, useful to skip formatting the messages that
+	/// are going to be filtered out anyway
+	pub fn enabled(&self, level: LogLevel) -> bool {
+		self.handler.enabled(level)
 	}
 }
 
@@ -107,12 +215,10 @@ impl Default for Logger<'static> {
 	/// [`log`]: https://crates.io/crates/log
 	#[cfg(feature = "log")]
 	fn default() -> Self {
-		Logger::new(|log_level, area, message| match log_level {
-			LogLevel::XMPP_LEVEL_DEBUG => debug!("{}: {}", area, message),
-			LogLevel::XMPP_LEVEL_INFO => info!("{}: {}", area, message),
-			LogLevel::XMPP_LEVEL_WARN => warn!("{}: {}", area, message),
-			LogLevel::XMPP_LEVEL_ERROR => error!("{}: {}", area, message),
-		})
+		Logger::new_with_filter(
+			|log_level| to_log_level(log_level) <= log::max_level(),
+			|log_level, area, message| log!(to_log_level(log_level), "{}: {}", area, message),
+		)
 	}
 
 	/// Create a new default logger by calling [`new_internal()`] with debug log level.
@@ -161,15 +267,67 @@ impl Drop for Logger<'_> {
 
 unsafe impl Send for Logger<'_> {}
 
+/// Redirects the messages of the context loggers on the current thread to the connection logger until dropped
+pub(crate) struct LogRoute<'cb> {
+	prev: *const sys::xmpp_log_t,
+	_logger: Rc<Logger<'cb>>,
+}
+
+impl<'cb> LogRoute<'cb> {
+	pub fn new(logger: Rc<Logger<'cb>>) -> Self {
+		let prev = ROUTE.with(|route| route.replace(logger.as_ptr()));
+		Self { prev, _logger: logger }
+	}
+}
+
+impl Drop for LogRoute<'_> {
+	fn drop(&mut self) {
+		let _ = ROUTE.try_with(|route| route.set(self.prev));
+	}
+}
+
+/// Returns the `xmpp_log_t` for the context that forwards the messages to `default` unless they are redirected with
+/// [LogRoute]
+pub(crate) fn router(default: &Logger) -> sys::xmpp_log_t {
+	sys::xmpp_log_t {
+		handler: Some(route_cb),
+		userdata: default.as_ptr() as _,
+	}
+}
+
+unsafe extern "C" fn route_cb(userdata: *mut c_void, level: sys::xmpp_log_level_t, area: *const c_char, msg: *const c_char) {
+	let routed = ROUTE.try_with(Cell::get).unwrap_or(ptr::null());
+	let target = if routed.is_null() {
+		userdata as *const sys::xmpp_log_t
+	} else {
+		routed
+	};
+	if let Some(target) = target.as_ref() {
+		if let Some(handler) = target.handler {
+			let _ = panic::catch_unwind(|| handler(target.userdata, level, area, msg));
+		}
+	}
+}
+
+#[cfg(feature = "log")]
+fn to_log_level(log_level: LogLevel) -> Level {
+	match log_level {
+		LogLevel::XMPP_LEVEL_DEBUG => Level::Debug,
+		LogLevel::XMPP_LEVEL_INFO => Level::Info,
+		LogLevel::XMPP_LEVEL_WARN => Level::Warn,
+		LogLevel::XMPP_LEVEL_ERROR => Level::Error,
+	}
+}
+
 #[test]
 fn callbacks() {
 	fn logger_eq<L, R>(_left: L, _right: R) -> bool
 	where
-		L: FnMut(LogLevel, &str, &str) + Send,
-		R: FnMut(LogLevel, &str, &str) + Send,
+		L: Fn(LogLevel, &str, &str) + Send,
+		R: Fn(LogLevel, &str, &str) + Send,
 	{
-		let ptr_left = Logger::log_handler_cb::<L> as *const ();
-		let ptr_right = Logger::log_handler_cb::<R> as *const ();
+		let ptr_left = Logger::log_handler_cb::<Unfiltered<L>> as *const ();
+		let ptr_right = Logger::log_handler_cb::<Unfiltered<R>> as *const ();
 		ptr_left == ptr_right
 	}
 
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use crate::Stanza;
+
+/// Messages of a single conversation thread, see [MessageThreads]
+#[derive(Debug, Clone, Default)]
+pub struct MessageThread {
+	parent: Option<String>,
+	messages: Vec<Stanza>,
+}
+
+impl MessageThread {
+	#[inline]
+	/// Id of the thread this one was forked from
+	pub fn parent(&self) -> Option<&str> {
+		self.parent.as_deref()
+	}
+
+	#[inline]
+	/// Tracked messages of the thread in the order of arrival
+	pub fn messages(&self) -> &[Stanza] {
+		&self.messages
+	}
+}
+
+/// Groups messages into conversations by their [XEP-0201](https://xmpp.org/extensions/xep-0201.html) `<thread/>` element
+///
+/// Feed the incoming (and, optionally, outgoing) message stanzas to [`track()`](#method.track) from the message handler.
+/// The tracker stores copies of the messages, so remove the threads that are no longer needed to limit the memory usage.
+#[derive(Debug, Default)]
+pub struct MessageThreads {
+	threads: HashMap<String, MessageThread>,
+}
+
+impl MessageThreads {
+	#[inline]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds a copy of the `message` to its thread
+	///
+	/// Returns the id of the thread or `None` if the message doesn't belong to any thread, such messages are not tracked.
+	pub fn track(&mut self, message: &Stanza) -> Option<String> {
+		let id = message.thread()?;
+		let thread = self.threads.entry(id.clone()).or_default();
+		if let Some(parent) = message.thread_parent() {
+			thread.parent = Some(parent);
+		}
+		thread.messages.push(message.clone());
+		Some(id)
+	}
+
+	#[inline]
+	pub fn get(&self, thread: &str) -> Option<&MessageThread> {
+		self.threads.get(thread)
+	}
+
+	/// Ids of the threads that were forked from the `thread`
+	pub fn children<'s>(&'s self, thread: &'s str) -> impl Iterator<Item = &'s str> {
+		self
+			.threads
+			.iter()
+			.filter(move |(_, child)| child.parent() == Some(thread))
+			.map(|(id, _)| id.as_str())
+	}
+
+	#[inline]
+	pub fn remove(&mut self, thread: &str) -> Option<MessageThread> {
+		self.threads.remove(thread)
+	}
+
+	#[inline]
+	pub fn iter(&self) -> impl Iterator<Item = (&str, &MessageThread)> {
+		self.threads.iter().map(|(id, thread)| (id.as_str(), thread))
+	}
+
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.threads.len()
+	}
+
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.threads.is_empty()
+	}
+}
@@ -0,0 +1,47 @@
+//! Extension point for the protocol implementations shipped as self-contained modules
+//!
+//! A module declares the namespaces it's interested in and receives the matching incoming stanzas together with the
+//! connection lifecycle events. [`Connection::register_module()`](crate::Connection::register_module) wires all of that
+//! at once, so the module doesn't need to track the handlers it has installed.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{Connection, ConnectionError, Context, Stanza};
+
+static NEXT_MODULE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Handle of the module registered with [`Connection::register_module()`](crate::Connection::register_module)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModuleId(u64);
+
+impl ModuleId {
+	pub(crate) fn next() -> Self {
+		Self(NEXT_MODULE_ID.fetch_add(1, Ordering::Relaxed))
+	}
+}
+
+/// Protocol implementation handling the stanzas of its namespaces, see the [module docs](self)
+pub trait XmppModule: Send {
+	/// Namespaces of the stanzas passed to [XmppModule::on_stanza]
+	///
+	/// A stanza matches if the namespace is either the namespace of the stanza itself or of one of its direct children,
+	/// the same way as the `ns` filter of [`Connection::handler_add()`](crate::Connection::handler_add) works. The list is
+	/// checked for every incoming stanza, so it can change over time.
+	fn namespaces(&self) -> &[&str];
+
+	/// Called for every incoming stanza matching one of the [XmppModule::namespaces]
+	fn on_stanza(&mut self, ctx: &Context, conn: &mut Connection, stanza: &Stanza);
+
+	/// Called when the connection is established, before the connection handler
+	fn on_connect(&mut self, _ctx: &Context, _conn: &mut Connection) {}
+
+	/// Called when the connection is closed, before the connection handler
+	fn on_disconnect(&mut self, _ctx: &Context, _conn: &mut Connection, _error: Option<&ConnectionError>) {}
+}
+
+/// Whether `stanza` matches the namespace filter the same way libstrophe does it for the handlers
+pub(crate) fn matches_ns(stanza: &Stanza, namespaces: &[&str]) -> bool {
+	namespaces
+		.iter()
+		.any(|ns| stanza.ns() == Some(*ns) || stanza.get_child_by_ns(ns).is_some())
+}
@@ -0,0 +1,364 @@
+use std::ffi::c_void;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::{ConnectClientError, Connection, ConnectionEvent, Context, Error, Result, Stanza};
+
+/// Pool of the component connections to the same server sharing the load of the outgoing stanzas
+///
+/// All pool members are owned by a single [Context] returned from [ConnectionPool::start]. Outgoing stanzas sent with
+/// [ConnectionPool::send] are distributed between the connected members in a round-robin fashion. When a member gets
+/// disconnected it's transparently reconnected with an exponential backoff starting at
+/// [`retry_delay`](ConnectionPool::set_retry_delay) and capped at [`max_retry_delay`](ConnectionPool::set_max_retry_delay).
+/// The aggregate state of the pool is available through [ConnectionPool::health].
+///
+/// `ConnectionPool` is a cheap handle that can be cloned into the handlers of the members. The methods that touch the
+/// connections require the [Context] of the pool to make sure that they're called from the thread running its event loop.
+#[derive(Debug, Clone)]
+pub struct ConnectionPool {
+	shared: Arc<Mutex<PoolShared>>,
+}
+
+/// Aggregate health metrics of the [ConnectionPool]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PoolHealth {
+	/// Number of the pool members
+	pub size: usize,
+	/// Number of the members that are currently connected
+	pub connected: usize,
+	/// Total number of the reconnection attempts made for the failed members
+	pub reconnects: u64,
+	/// Total number of the stanzas sent through the pool
+	pub sent: u64,
+	/// Number of the [ConnectionPool::send] calls that failed because no member was connected
+	pub failed_sends: u64,
+}
+
+impl PoolHealth {
+	#[inline]
+	/// Returns `true` if all members of the pool are connected
+	pub fn is_healthy(&self) -> bool {
+		self.size > 0 && self.connected == self.size
+	}
+}
+
+#[derive(Debug)]
+struct PoolShared {
+	jid: String,
+	pass: String,
+	host: String,
+	port: Option<u16>,
+	retry_delay: Duration,
+	max_retry_delay: Duration,
+	/// Address of the `xmpp_ctx_t` of the started pool
+	ctx: Option<usize>,
+	members: Vec<Member>,
+	next: usize,
+	stopping: bool,
+	reconnects: u64,
+	failed_sends: u64,
+}
+
+#[derive(Debug, Default)]
+struct Member {
+	/// Address of the `xmpp_conn_t`, `None` when the member is gone
+	conn: Option<usize>,
+	/// Address of the connection handler userdata, it's used to get the [Connection] back from `conn`
+	userdata: usize,
+	connected: bool,
+	sent: u64,
+	/// Delay before the next reconnection attempt, `None` until the first failure after a successful connection
+	backoff: Option<Duration>,
+}
+
+/// Lives in the connection handler of the pool member, marks the member as gone when the connection is freed
+struct MemberGuard {
+	shared: Arc<Mutex<PoolShared>>,
+	index: usize,
+}
+
+impl PoolShared {
+	/// Returns the delay before the next reconnection attempt of the member and doubles it for the following one
+	fn next_delay(&mut self, index: usize) -> Duration {
+		let (retry_delay, max_retry_delay) = (self.retry_delay, self.max_retry_delay);
+		let member = &mut self.members[index];
+		let delay = member.backoff.unwrap_or(retry_delay);
+		member.backoff = Some((delay * 2).min(max_retry_delay));
+		delay
+	}
+}
+
+impl MemberGuard {
+	fn member<'s>(&self, shared: &'s mut PoolShared) -> &'s mut Member {
+		&mut shared.members[self.index]
+	}
+}
+
+impl Drop for MemberGuard {
+	fn drop(&mut self) {
+		let mut shared = lock_shared(&self.shared);
+		let member = self.member(&mut shared);
+		member.conn = None;
+		member.connected = false;
+	}
+}
+
+impl ConnectionPool {
+	/// Creates a pool of `size` component connections authenticating with `jid` and `pass` to the server at `host` and
+	/// `port`, see [Connection::connect_component]
+	pub fn new(
+		jid: impl Into<String>,
+		pass: impl Into<String>,
+		host: impl Into<String>,
+		port: impl Into<Option<u16>>,
+		size: usize,
+	) -> Self {
+		Self {
+			shared: Arc::new(Mutex::new(PoolShared {
+				jid: jid.into(),
+				pass: pass.into(),
+				host: host.into(),
+				port: port.into(),
+				retry_delay: Duration::from_secs(1),
+				max_retry_delay: Duration::from_secs(60),
+				ctx: None,
+				members: (0..size).map(|_| Member::default()).collect(),
+				next: 0,
+				stopping: false,
+				reconnects: 0,
+				failed_sends: 0,
+			})),
+		}
+	}
+
+	/// Sets the delay before the first reconnection attempt of the failed member, 1 second by default
+	pub fn set_retry_delay(&self, delay: Duration) {
+		self.lock().retry_delay = delay;
+	}
+
+	/// Sets the upper limit for the reconnection delay that doubles after each failed attempt, 60 seconds by default
+	pub fn set_max_retry_delay(&self, delay: Duration) {
+		self.lock().max_retry_delay = delay;
+	}
+
+	/// Creates the pool members in `ctx` and starts connecting them, returns the context that owns them
+	///
+	/// `setup` is called for each member before connecting, use it to add the stanza handlers or to configure the
+	/// connection. The connection handler is managed by the pool and must not be replaced. The pool can only be started
+	/// once.
+	pub fn start<'cb, 'cx>(
+		&self,
+		mut ctx: Context<'cx, 'cb>,
+		mut setup: impl FnMut(&mut Connection<'cb, 'cx>),
+	) -> Result<Context<'cx, 'cb>, ConnectClientError<'cb, 'cx>> {
+		let (jid, pass, host, port, size) = {
+			let mut shared = self.lock();
+			let started = shared.ctx.is_some();
+			shared.ctx = Some(ctx.as_ptr() as usize);
+			shared.stopping = false;
+			(
+				shared.jid.clone(),
+				shared.pass.clone(),
+				shared.host.clone(),
+				shared.port,
+				if started {
+					0
+				} else {
+					shared.members.len()
+				},
+			)
+		};
+		for index in 0..size {
+			let mut conn = Connection::new(ctx);
+			let init = conn.set_jid(&jid).and_then(|_| conn.set_pass(&pass));
+			if let Err(error) = init {
+				return Err(ConnectClientError { conn, error });
+			}
+			setup(&mut conn);
+			self.lock().members[index].conn = Some(conn.as_ptr() as usize);
+			let guard = MemberGuard {
+				shared: Arc::clone(&self.shared),
+				index,
+			};
+			let connected = conn.try_connect_component(&host, port, move |ctx, conn, event| {
+				Self::member_event(&guard, ctx, conn, event)
+			});
+			if let Err(error) = connected {
+				return Err(ConnectClientError { conn, error });
+			}
+			// recorded right away so that the member can be disconnected while it's still connecting
+			if let Some((_, userdata)) = conn.connection_handler_raw() {
+				self.lock().members[index].userdata = userdata as usize;
+			}
+			ctx = conn.into_context();
+		}
+		Ok(ctx)
+	}
+
+	/// Sends `stanza` through the next connected pool member, see [Connection::try_send]
+	///
+	/// Returns [Error::InvalidOperation] if no member is currently connected, if the member rejected the stanza in the
+	/// strict send mode or failed to encrypt it, or if `ctx` is not the context of the pool.
+	pub fn send(&self, ctx: &Context, stanza: &Stanza) -> Result<()> {
+		let (index, conn, userdata) = {
+			let mut shared = self.lock();
+			if shared.ctx != Some(ctx.as_ptr() as usize) {
+				return Err(Error::InvalidOperation);
+			}
+			let len = shared.members.len();
+			let start = shared.next;
+			let found = (0..len).map(|offset| (start + offset) % len).find_map(|i| {
+				let member = &shared.members[i];
+				match member.conn {
+					Some(conn) if member.connected => Some((i, conn, member.userdata)),
+					_ => None,
+				}
+			});
+			match found {
+				Some(found) => {
+					shared.next = (found.0 + 1) % len;
+					found
+				}
+				None => {
+					shared.failed_sends += 1;
+					return Err(Error::InvalidOperation);
+				}
+			}
+		};
+		// the lock is released for the send so that the interceptors and the taps of the member can use the pool
+		let mut conn =
+			unsafe { Connection::from_handler_userdata(conn as *mut _, userdata as *mut c_void) }.ok_or(Error::InvalidOperation)?;
+		match conn.try_send(stanza) {
+			Ok(()) => {
+				self.lock().members[index].sent += 1;
+				Ok(())
+			}
+			Err(e) => {
+				ctx.log_warn("pool", &format!("Pool member {} rejected the stanza: {}", index, e));
+				Err(Error::InvalidOperation)
+			}
+		}
+	}
+
+	/// Disconnects all pool members, they're not reconnected afterwards
+	pub fn disconnect(&self, ctx: &Context) {
+		let mut shared = self.lock();
+		if shared.ctx != Some(ctx.as_ptr() as usize) {
+			return;
+		}
+		shared.stopping = true;
+		let conns = shared
+			.members
+			.iter()
+			.filter_map(|member| member.conn.map(|conn| (conn, member.userdata)))
+			.collect::<Vec<_>>();
+		// libstrophe calls the connection handler synchronously from xmpp_disconnect() in some states
+		drop(shared);
+		for (conn, userdata) in conns {
+			if let Some(mut conn) = unsafe { Connection::from_handler_userdata(conn as *mut _, userdata as *mut c_void) } {
+				conn.disconnect();
+			}
+		}
+	}
+
+	/// Returns the current aggregate metrics of the pool
+	pub fn health(&self) -> PoolHealth {
+		let shared = self.lock();
+		PoolHealth {
+			size: shared.members.len(),
+			connected: shared.members.iter().filter(|member| member.connected).count(),
+			reconnects: shared.reconnects,
+			sent: shared.members.iter().map(|member| member.sent).sum(),
+			failed_sends: shared.failed_sends,
+		}
+	}
+
+	fn lock(&self) -> MutexGuard<'_, PoolShared> {
+		lock_shared(&self.shared)
+	}
+
+	fn member_event(guard: &MemberGuard, ctx: &Context, conn: &mut Connection, event: ConnectionEvent) {
+		match event {
+			ConnectionEvent::RawConnect => {}
+			ConnectionEvent::Connect => {
+				let stopping = {
+					let mut shared = lock_shared(&guard.shared);
+					let stopping = shared.stopping;
+					let member = guard.member(&mut shared);
+					member.connected = !stopping;
+					member.backoff = None;
+					stopping
+				};
+				// the pool was stopped while the member was connecting
+				if stopping {
+					conn.disconnect();
+				}
+			}
+			ConnectionEvent::Disconnect(error) => {
+				let delay = {
+					let mut shared = lock_shared(&guard.shared);
+					guard.member(&mut shared).connected = false;
+					if shared.stopping {
+						return;
+					}
+					shared.next_delay(guard.index)
+				};
+				if let Some(error) = error {
+					ctx.log_warn("pool", &format!("Pool member {} disconnected: {}", guard.index, error));
+				}
+				if let Some((_, userdata)) = conn.connection_handler_raw() {
+					let reconnect = Reconnect {
+						shared: Arc::clone(&guard.shared),
+						index: guard.index,
+						conn: conn.as_ptr() as usize,
+						userdata: userdata as usize,
+					};
+					reconnect.schedule(ctx, delay);
+				}
+			}
+		}
+	}
+}
+
+fn lock_shared(shared: &Mutex<PoolShared>) -> MutexGuard<'_, PoolShared> {
+	shared.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Pending reconnection of the failed pool member
+struct Reconnect {
+	shared: Arc<Mutex<PoolShared>>,
+	index: usize,
+	conn: usize,
+	userdata: usize,
+}
+
+impl Reconnect {
+	fn schedule(self, ctx: &Context, delay: Duration) {
+		ctx.schedule_at(Instant::now() + delay, move |ctx| self.run(ctx));
+	}
+
+	fn run(self, ctx: &Context) {
+		let (host, port) = {
+			let mut shared = lock_shared(&self.shared);
+			let member = &shared.members[self.index];
+			// the member might have been freed or stopped while waiting
+			if shared.stopping || member.connected || member.conn != Some(self.conn) {
+				return;
+			}
+			shared.reconnects += 1;
+			(shared.host.clone(), shared.port)
+		};
+		let mut conn = match unsafe { Connection::from_handler_userdata(self.conn as *mut _, self.userdata as *mut c_void) } {
+			Some(conn) => conn,
+			None => return,
+		};
+		if let Err(e) = conn.reconnect_component(&host, port) {
+			let delay = lock_shared(&self.shared).next_delay(self.index);
+			ctx.log_warn(
+				"pool",
+				&format!("Cannot reconnect pool member {}: {}, retrying in {:?}", self.index, e, delay),
+			);
+			self.schedule(ctx, delay);
+		}
+	}
+}
@@ -0,0 +1,242 @@
+//! Outbound connections through SOCKS5 and HTTP `CONNECT` proxies
+//!
+//! libstrophe has no proxy support of its own, so [ProxyTunnel] listens on a local loopback port and forwards the
+//! accepted connection to the target through the proxy. The connection is then pointed to that local port, see
+//! [Connection::set_proxy](crate::Connection::set_proxy). The server certificate is still validated against the JID domain.
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::base64;
+
+/// Proxy server to connect through
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Proxy {
+	/// [RFC 1928](https://www.rfc-editor.org/rfc/rfc1928) SOCKS5 proxy with optional
+	/// [RFC 1929](https://www.rfc-editor.org/rfc/rfc1929) username/password authentication
+	Socks5 {
+		host: String,
+		port: u16,
+		auth: Option<(String, String)>,
+	},
+	/// HTTP proxy supporting the `CONNECT` method with optional basic authentication
+	Http {
+		host: String,
+		port: u16,
+		auth: Option<(String, String)>,
+	},
+}
+
+impl Proxy {
+	/// Connects to the proxy and asks it to open a tunnel to `target_host:target_port`
+	pub fn connect(&self, target_host: &str, target_port: u16) -> io::Result<TcpStream> {
+		match self {
+			Proxy::Socks5 { host, port, auth } => {
+				let mut stream = TcpStream::connect((host.as_str(), *port))?;
+				socks5_handshake(&mut stream, auth.as_ref(), target_host, target_port)?;
+				Ok(stream)
+			}
+			Proxy::Http { host, port, auth } => {
+				let mut stream = TcpStream::connect((host.as_str(), *port))?;
+				http_connect_handshake(&mut stream, auth.as_ref(), target_host, target_port)?;
+				Ok(stream)
+			}
+		}
+	}
+}
+
+/// Local loopback listener relaying a single accepted connection to the target through the [Proxy]
+///
+/// The relaying is done by background threads. The listener is closed after the first accepted connection or when the
+/// tunnel is dropped, whichever comes first.
+#[derive(Debug)]
+pub struct ProxyTunnel {
+	local_addr: SocketAddr,
+	stop: Arc<AtomicBool>,
+	error: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl ProxyTunnel {
+	pub fn start(proxy: Proxy, target_host: impl Into<String>, target_port: u16) -> io::Result<Self> {
+		let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+		let local_addr = listener.local_addr()?;
+		let stop = Arc::new(AtomicBool::new(false));
+		let error = Arc::new(Mutex::new(None));
+		let target_host = target_host.into();
+		thread::Builder::new().name("libstrophe-proxy".to_owned()).spawn({
+			let stop = Arc::clone(&stop);
+			let error = Arc::clone(&error);
+			move || {
+				let accepted = listener.accept();
+				drop(listener);
+				if stop.load(Ordering::Acquire) {
+					return;
+				}
+				let set_error = |e| {
+					if let Ok(mut error) = error.lock() {
+						*error = Some(e);
+					}
+				};
+				let client = match accepted {
+					Ok((client, _)) => client,
+					Err(e) => return set_error(e),
+				};
+				match proxy.connect(&target_host, target_port) {
+					Ok(upstream) => {
+						let _ = relay(client, upstream);
+					}
+					// the error is stored before the client connection is closed, so it's there when the client notices that
+					Err(e) => set_error(e),
+				}
+			}
+		})?;
+		Ok(Self { local_addr, stop, error })
+	}
+
+	/// Takes the error of the accept or of the proxy handshake, `None` if there was none or it was already taken
+	pub fn take_error(&self) -> Option<io::Error> {
+		self.error.lock().ok().and_then(|mut error| error.take())
+	}
+
+	#[inline]
+	/// Loopback address to connect to instead of the target
+	pub fn local_addr(&self) -> SocketAddr {
+		self.local_addr
+	}
+}
+
+impl Drop for ProxyTunnel {
+	fn drop(&mut self) {
+		self.stop.store(true, Ordering::Release);
+		// wake up the blocking accept
+		let _ = TcpStream::connect(self.local_addr);
+	}
+}
+
+fn relay(client: TcpStream, upstream: TcpStream) -> io::Result<()> {
+	let mut client_read = client.try_clone()?;
+	let mut upstream_write = upstream.try_clone()?;
+	let outbound = thread::spawn(move || {
+		let _ = io::copy(&mut client_read, &mut upstream_write);
+		let _ = upstream_write.shutdown(Shutdown::Write);
+	});
+	let (mut upstream_read, mut client_write) = (upstream, client);
+	let _ = io::copy(&mut upstream_read, &mut client_write);
+	let _ = client_write.shutdown(Shutdown::Write);
+	let _ = outbound.join();
+	Ok(())
+}
+
+fn proxy_error(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::Other, msg)
+}
+
+fn socks5_handshake(
+	stream: &mut (impl Read + Write),
+	auth: Option<&(String, String)>,
+	target_host: &str,
+	target_port: u16,
+) -> io::Result<()> {
+	if auth.is_some() {
+		stream.write_all(&[5, 2, 0, 2])?;
+	} else {
+		stream.write_all(&[5, 1, 0])?;
+	}
+	let mut reply = [0; 2];
+	stream.read_exact(&mut reply)?;
+	if reply[0] != 5 {
+		return Err(proxy_error("Invalid SOCKS5 reply"));
+	}
+	match (reply, auth) {
+		([5, 0], _) => {}
+		([5, 2], Some((user, pass))) => {
+			if user.len() > 255 || pass.len() > 255 {
+				return Err(proxy_error("SOCKS5 credentials are too long"));
+			}
+			let mut request = vec![1, user.len() as u8];
+			request.extend_from_slice(user.as_bytes());
+			request.push(pass.len() as u8);
+			request.extend_from_slice(pass.as_bytes());
+			stream.write_all(&request)?;
+			stream.read_exact(&mut reply)?;
+			if reply[1] != 0 {
+				return Err(proxy_error("SOCKS5 authentication failed"));
+			}
+		}
+		_ => {
+			return Err(proxy_error(
+				"SOCKS5 proxy doesn't support any of the offered authentication methods",
+			))
+		}
+	}
+
+	if target_host.len() > 255 {
+		return Err(proxy_error("Target host name is too long"));
+	}
+	let mut request = vec![5, 1, 0, 3, target_host.len() as u8];
+	request.extend_from_slice(target_host.as_bytes());
+	request.extend_from_slice(&target_port.to_be_bytes());
+	stream.write_all(&request)?;
+	let mut head = [0; 4];
+	stream.read_exact(&mut head)?;
+	if head[0] != 5 || head[1] != 0 {
+		return Err(proxy_error("SOCKS5 proxy refused to connect to the target"));
+	}
+	let addr_len = match head[3] {
+		1 => 4,
+		4 => 16,
+		3 => {
+			let mut len = [0; 1];
+			stream.read_exact(&mut len)?;
+			usize::from(len[0])
+		}
+		_ => return Err(proxy_error("Invalid SOCKS5 reply")),
+	};
+	// bound address and port are not used
+	let mut bound = vec![0; addr_len + 2];
+	stream.read_exact(&mut bound)
+}
+
+fn http_connect_handshake(
+	stream: &mut (impl Read + Write),
+	auth: Option<&(String, String)>,
+	target_host: &str,
+	target_port: u16,
+) -> io::Result<()> {
+	let authority = if target_host.contains(':') {
+		format!("[{}]:{}", target_host, target_port)
+	} else {
+		format!("{}:{}", target_host, target_port)
+	};
+	let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+	if let Some((user, pass)) = auth {
+		let credentials = base64::encode(format!("{}:{}", user, pass).as_bytes())
+			.map_err(|_| proxy_error("Cannot encode the proxy credentials"))?;
+		request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+	}
+	request.push_str("\r\n");
+	stream.write_all(request.as_bytes())?;
+
+	// read byte by byte to avoid consuming the tunneled data
+	let mut response = Vec::with_capacity(128);
+	let mut byte = [0; 1];
+	while !response.ends_with(b"\r\n\r\n") {
+		if response.len() > 8192 {
+			return Err(proxy_error("HTTP proxy response is too long"));
+		}
+		stream.read_exact(&mut byte)?;
+		response.push(byte[0]);
+	}
+	let status = response
+		.split(|&c| c == b' ')
+		.nth(1)
+		.and_then(|status| std::str::from_utf8(status).ok());
+	if status.map_or(false, |status| status.starts_with('2')) {
+		Ok(())
+	} else {
+		Err(proxy_error("HTTP proxy refused to connect to the target"))
+	}
+}
@@ -0,0 +1,112 @@
+use crate::xep::NS_XHTML_IM;
+use crate::{Error, Result, Stanza};
+
+/// Namespace of the [RFC 6120](https://www.rfc-editor.org/rfc/rfc6120#section-6) SASL negotiation elements
+const NS_SASL: &str = "urn:ietf:params:xml:ns:xmpp-sasl";
+/// Namespace of the [XEP-0388](https://xmpp.org/extensions/xep-0388.html) SASL2 negotiation elements
+const NS_SASL2: &str = "urn:xmpp:sasl:2";
+
+/// Rules for hiding the sensitive content of the stanzas before logging them, see [Stanza::redacted]
+///
+/// The content of the matching elements (both the text and the child elements) is replaced with the placeholder text and
+/// so are the values of the matching attributes anywhere in the stanza. The elements are matched by their name and the
+/// namespace, including the one inherited from the parent element. [RedactionPolicy::default()] hides the message
+/// bodies, the XHTML-IM payloads, the passwords and the SASL and SASL2 exchange, start with [RedactionPolicy::new()] to
+/// only hide the elements added explicitly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedactionPolicy {
+	/// `(name, ns)` pairs, `None` matches anything
+	elements: Vec<(Option<String>, Option<String>)>,
+	attributes: Vec<String>,
+	placeholder: String,
+}
+
+impl RedactionPolicy {
+	/// Creates the policy that doesn't hide anything with the `[redacted]` placeholder
+	pub fn new() -> Self {
+		Self {
+			elements: vec![],
+			attributes: vec![],
+			placeholder: "[redacted]".to_owned(),
+		}
+	}
+
+	/// Hides the content of the elements with the `name` in the `ns` namespace, `None` matches any name or namespace
+	pub fn element(mut self, name: Option<&str>, ns: Option<&str>) -> Self {
+		self.elements.push((name.map(str::to_owned), ns.map(str::to_owned)));
+		self
+	}
+
+	/// Hides the values of the attributes with the `name`, e.g. the authentication tokens of the custom elements
+	pub fn attribute(mut self, name: impl Into<String>) -> Self {
+		self.attributes.push(name.into());
+		self
+	}
+
+	/// Replaces the text used in place of the hidden content
+	pub fn placeholder(mut self, placeholder: impl Into<String>) -> Self {
+		self.placeholder = placeholder.into();
+		self
+	}
+
+	/// Text used in place of the hidden content
+	pub fn placeholder_text(&self) -> &str {
+		&self.placeholder
+	}
+
+	/// Returns the copy of `stanza` with the content hidden according to the policy
+	pub fn redact(&self, stanza: &Stanza) -> Result<Stanza> {
+		self.redact_with_ns(stanza, None)
+	}
+
+	fn redact_with_ns(&self, stanza: &Stanza, parent_ns: Option<&str>) -> Result<Stanza> {
+		let mut out = Stanza::new();
+		if stanza.is_text() {
+			out.set_text(stanza.text_ptr().unwrap_or_default())?;
+			return Ok(out);
+		}
+		let name = stanza.name().ok_or(Error::InvalidOperation)?;
+		let ns = stanza.ns().or(parent_ns);
+		out.set_name(name)?;
+		for (key, value) in stanza.attributes() {
+			if self.attributes.iter().any(|attribute| attribute == key) {
+				out.set_attribute(key, &self.placeholder)?;
+			} else {
+				out.set_attribute(key, value)?;
+			}
+		}
+		let hidden = self.elements.iter().any(|(rule_name, rule_ns)| {
+			rule_name.as_deref().map_or(true, |rule_name| rule_name == name)
+				&& rule_ns.as_deref().map_or(true, |rule_ns| Some(rule_ns) == ns)
+		});
+		if hidden {
+			// the empty elements stay empty, they carry nothing to hide
+			if stanza.get_first_child().is_some() {
+				let mut text = Stanza::new();
+				text.set_text(&self.placeholder)?;
+				out.add_child(text)?;
+			}
+		} else {
+			for child in stanza.children() {
+				out.add_child(self.redact_with_ns(&child, ns)?)?;
+			}
+		}
+		Ok(out)
+	}
+}
+
+impl Default for RedactionPolicy {
+	fn default() -> Self {
+		let mut out = Self::new()
+			.element(Some("body"), None)
+			.element(Some("html"), Some(NS_XHTML_IM))
+			.element(Some("password"), None);
+		for name in ["auth", "challenge", "response", "success"] {
+			out = out.element(Some(name), Some(NS_SASL));
+		}
+		for name in ["initial-response", "challenge", "response", "additional-data"] {
+			out = out.element(Some(name), Some(NS_SASL2));
+		}
+		out
+	}
+}
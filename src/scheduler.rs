@@ -0,0 +1,195 @@
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::os::raw::{c_int, c_ulong};
+use std::ptr;
+use std::time::{Duration, Instant};
+
+use crate::context::catch_panic;
+use crate::Context;
+
+type PeriodicJob = Box<dyn FnMut(&Context<'_, '_>) + Send>;
+type OneShotJob = Box<dyn FnOnce(&Context<'_, '_>) + Send>;
+
+enum JobKind {
+	Periodic(Duration, PeriodicJob),
+	OneShot(OneShotJob),
+}
+
+struct ScheduledJob {
+	id: u64,
+	name: Option<String>,
+	due: Instant,
+	/// `None` while the job is running
+	kind: Option<JobKind>,
+}
+
+/// Jobs of a single context added with [Context::schedule] and [Context::schedule_at], multiplexed onto one libstrophe
+/// global timed handler
+#[derive(Default)]
+pub(crate) struct Scheduler {
+	jobs: Vec<ScheduledJob>,
+	next_id: u64,
+	/// Which of the [tick_a]/[tick_b] handlers is currently registered and when it's due
+	registered: Option<(bool, Instant)>,
+	in_tick: bool,
+}
+
+impl Scheduler {
+	pub(crate) fn len(&self) -> usize {
+		self.jobs.len()
+	}
+
+	/// Drops all the jobs, the handler is removed together with the context
+	pub(crate) fn clear(&mut self) {
+		self.jobs.clear();
+		self.registered = None;
+	}
+}
+
+pub(crate) fn schedule_periodic(ctx: &Context, name: String, every: Duration, job: PeriodicJob) {
+	add_job(ctx, Some(name), Instant::now() + every, JobKind::Periodic(every, job));
+}
+
+pub(crate) fn schedule_once(ctx: &Context, at: Instant, job: OneShotJob) {
+	add_job(ctx, None, at, JobKind::OneShot(job));
+}
+
+pub(crate) fn unschedule(ctx: &Context, name: &str) -> bool {
+	ctx.scheduler().map_or(false, |scheduler| {
+		let mut scheduler = scheduler.borrow_mut();
+		let len = scheduler.jobs.len();
+		scheduler.jobs.retain(|job| job.name.as_deref() != Some(name));
+		scheduler.jobs.len() != len
+	})
+}
+
+fn add_job(ctx: &Context, name: Option<String>, due: Instant, kind: JobKind) {
+	let scheduler = match ctx.scheduler() {
+		Some(scheduler) => scheduler,
+		None => {
+			ctx.log_error("xmpp", &format!("{}: Job not scheduled, the context has no state", ctx.id()));
+			return;
+		}
+	};
+	let reregister = {
+		let mut scheduler = scheduler.borrow_mut();
+		if let Some(name) = &name {
+			scheduler.jobs.retain(|job| job.name.as_ref() != Some(name));
+		}
+		let id = scheduler.next_id;
+		scheduler.next_id += 1;
+		scheduler.jobs.push(ScheduledJob {
+			id,
+			name,
+			due,
+			kind: Some(kind),
+		});
+		// the tick re-registers itself when it's finished
+		!scheduler.in_tick && scheduler.registered.map_or(true, |(_, registered_due)| due < registered_due)
+	};
+	if reregister {
+		unsafe {
+			sys::xmpp_global_timed_handler_delete(ctx.as_ptr(), Some(tick_a));
+			sys::xmpp_global_timed_handler_delete(ctx.as_ptr(), Some(tick_b));
+		}
+		register_next(ctx, scheduler, false);
+	}
+}
+
+/// Registers one of the tick handlers to fire when the earliest job is due
+///
+/// A handler can't be re-added with a different period from its own callback (libstrophe ignores it while it's still
+/// registered), so the two identical handlers take turns, `after_a` tells which one has just fired.
+fn register_next(ctx: &Context, scheduler: &RefCell<Scheduler>, after_a: bool) {
+	let next_due = {
+		let mut scheduler = scheduler.borrow_mut();
+		let next_due = scheduler.jobs.iter().map(|job| job.due).min();
+		scheduler.registered = next_due.map(|due| (!after_a, due));
+		next_due
+	};
+	if let Some(next_due) = next_due {
+		let wait = next_due.saturating_duration_since(Instant::now());
+		// round up so that the handler doesn't fire before the job is due
+		let period = wait.as_millis() + u128::from(wait.subsec_nanos() % 1_000_000 != 0);
+		let handler = if after_a {
+			tick_b
+		} else {
+			tick_a
+		};
+		unsafe {
+			sys::xmpp_global_timed_handler_add(
+				ctx.as_ptr(),
+				Some(handler),
+				c_ulong::try_from(period).unwrap_or(c_ulong::MAX),
+				ptr::null_mut(),
+			)
+		}
+	}
+}
+
+unsafe extern "C" fn tick_a(ctx: *mut sys::xmpp_ctx_t, _userdata: *mut c_void) -> c_int {
+	tick(ctx, true)
+}
+
+unsafe extern "C" fn tick_b(ctx: *mut sys::xmpp_ctx_t, _userdata: *mut c_void) -> c_int {
+	tick(ctx, false)
+}
+
+unsafe fn tick(ctx_ptr: *mut sys::xmpp_ctx_t, is_a: bool) -> c_int {
+	let ctx = Context::from_ref_mut(ctx_ptr);
+	let scheduler = match ctx.scheduler() {
+		Some(scheduler) => scheduler,
+		None => return 0,
+	};
+	let now = Instant::now();
+	let due_ids = {
+		let mut scheduler = scheduler.borrow_mut();
+		scheduler.in_tick = true;
+		scheduler.registered = None;
+		scheduler
+			.jobs
+			.iter()
+			.filter(|job| job.due <= now)
+			.map(|job| job.id)
+			.collect::<Vec<_>>()
+	};
+	for id in due_ids {
+		// the job is taken out for the duration of the call so that it can (un)schedule jobs itself
+		let kind = scheduler
+			.borrow_mut()
+			.jobs
+			.iter_mut()
+			.find(|job| job.id == id)
+			.and_then(|job| job.kind.take());
+		match kind {
+			Some(JobKind::Periodic(every, mut job)) => {
+				catch_panic(&ctx, (), || job(&ctx));
+				// the job might have been cancelled while running
+				if let Some(scheduled) = scheduler.borrow_mut().jobs.iter_mut().find(|job| job.id == id) {
+					scheduled.due = next_due(scheduled.due, every, Instant::now());
+					scheduled.kind = Some(JobKind::Periodic(every, job));
+				}
+			}
+			Some(JobKind::OneShot(job)) => {
+				scheduler.borrow_mut().jobs.retain(|job| job.id != id);
+				catch_panic(&ctx, (), || job(&ctx));
+			}
+			None => {}
+		}
+	}
+	scheduler.borrow_mut().in_tick = false;
+	register_next(&ctx, scheduler, is_a);
+	0
+}
+
+/// Next due time of the periodic job keeping it aligned to the original schedule, the runs missed due to the event loop
+/// being busy are skipped
+fn next_due(due: Instant, every: Duration, now: Instant) -> Instant {
+	let next = due + every;
+	if next > now || every.is_zero() {
+		return next.max(now);
+	}
+	let behind = now.duration_since(next).as_nanos();
+	let missed = behind / every.as_nanos() + 1;
+	next + every * u32::try_from(missed).unwrap_or(u32::MAX)
+}
@@ -0,0 +1,134 @@
+use std::{fmt, str};
+
+use crate::{Error, Result, Stanza};
+
+/// Namespace of [XEP-0198](https://xmpp.org/extensions/xep-0198.html) Stream Management
+pub const NS_SM: &str = "urn:xmpp:sm:3";
+
+/// Stream management bookkeeping mirrored by the wrapper from the SM elements, see
+/// [`Connection::set_sm_mirror()`](crate::Connection::set_sm_mirror)
+///
+/// It's a fallback for the builds where `SMState` is not available. The counters are taken from the stanzas themselves:
+/// the incoming ones are counted by a handler and the outgoing ones when they're sent through the [Connection](crate::Connection)
+/// methods, so the stanzas sent by libstrophe on its own (e.g. the resource binding) are not included. Unlike `SMState` it
+/// can't resume the stream by itself, but it has enough data for the `<resume/>` request and for the diagnostics.
+///
+/// The [Display](fmt::Display) and [FromStr](str::FromStr) implementations serialize the resumption data (the counters,
+/// the resume id and the location) into a single line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SmMirror {
+	/// `true` between `<enabled/>` or `<resumed/>` and the disconnect or `<failed/>`
+	pub enabled: bool,
+	/// Id of the stream to resume, `None` if the server doesn't allow the resumption
+	pub resume_id: Option<String>,
+	/// Preferred address to reconnect to for the resumption
+	pub location: Option<String>,
+	/// Number of the stanzas received since the stream management was enabled
+	pub h_in: u32,
+	/// Number of the stanzas sent since the stream management was enabled
+	pub h_out: u32,
+	/// Last number of the handled stanzas acknowledged by the server
+	pub acked: u32,
+	/// Condition of the last `<failed/>` element
+	pub failed: Option<String>,
+}
+
+impl SmMirror {
+	/// Number of the sent stanzas not yet acknowledged by the server
+	pub fn unacked(&self) -> u32 {
+		self.h_out.wrapping_sub(self.acked)
+	}
+
+	pub(crate) fn observe_incoming(&mut self, stanza: &Stanza) {
+		if stanza.ns() != Some(NS_SM) {
+			if self.enabled && is_counted(stanza) {
+				self.h_in = self.h_in.wrapping_add(1);
+			}
+			return;
+		}
+		match stanza.name() {
+			Some("enabled") => {
+				let resume = matches!(stanza.get_attribute("resume"), Some("true") | Some("1"));
+				*self = Self {
+					enabled: true,
+					resume_id: stanza.get_attribute("id").filter(|_| resume).map(str::to_owned),
+					location: stanza.get_attribute("location").map(str::to_owned),
+					..Self::default()
+				};
+			}
+			Some("resumed") => {
+				self.enabled = true;
+				self.failed = None;
+				if let Some(h) = stanza.get_attribute("h").and_then(|h| h.parse().ok()) {
+					self.acked = h;
+				}
+			}
+			Some("failed") => {
+				self.enabled = false;
+				self.resume_id = None;
+				self.failed = Some(
+					stanza
+						.children()
+						.find_map(|child| child.name().map(str::to_owned))
+						.unwrap_or_else(|| "undefined-condition".to_owned()),
+				);
+			}
+			Some("a") => {
+				if let Some(h) = stanza.get_attribute("h").and_then(|h| h.parse().ok()) {
+					self.acked = h;
+				}
+			}
+			_ => {}
+		}
+	}
+
+	pub(crate) fn observe_outgoing(&mut self, stanza: &Stanza) {
+		if self.enabled && is_counted(stanza) {
+			self.h_out = self.h_out.wrapping_add(1);
+		}
+	}
+}
+
+impl fmt::Display for SmMirror {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{} {} {} {} {}",
+			self.h_in,
+			self.h_out,
+			self.acked,
+			self.location.as_deref().unwrap_or("-"),
+			self.resume_id.as_deref().unwrap_or("-")
+		)
+	}
+}
+
+impl str::FromStr for SmMirror {
+	type Err = Error;
+
+	/// Parses the output of the [Display](fmt::Display) implementation, the result is not [enabled](Self::enabled)
+	fn from_str(s: &str) -> Result<Self> {
+		let mut parts = s.splitn(5, ' ');
+		let mut counter = || -> Result<u32> { parts.next().and_then(|part| part.parse().ok()).ok_or(Error::InvalidOperation) };
+		let (h_in, h_out, acked) = (counter()?, counter()?, counter()?);
+		let mut optional = || match parts.next() {
+			Some("-") => Ok(None),
+			Some(part) if !part.is_empty() => Ok(Some(part.to_owned())),
+			_ => Err(Error::InvalidOperation),
+		};
+		let location = optional()?;
+		let resume_id = optional()?;
+		Ok(Self {
+			resume_id,
+			location,
+			h_in,
+			h_out,
+			acked,
+			..Self::default()
+		})
+	}
+}
+
+fn is_counted(stanza: &Stanza) -> bool {
+	matches!(stanza.name(), Some("message") | Some("presence") | Some("iq"))
+}
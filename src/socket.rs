@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::net::IpAddr;
+use std::os::raw::c_int;
+use std::os::unix::io::RawFd;
+use std::sync::RwLock;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::time::Duration;
+use std::{io, mem};
+
+use once_cell::sync::Lazy;
+
+/// Typed view of the socket passed to the sockopt callback, see [Connection::set_socket_callback]
+///
+/// Wraps the most common socket tuning options so that the callback doesn't need to deal with the raw pointer and
+/// `setsockopt()` directly. Use [`fd()`](#method.fd) for anything that's not covered.
+///
+/// [Connection::set_socket_callback]: crate::Connection::set_socket_callback
+#[derive(Debug)]
+pub struct SocketRef {
+	fd: RawFd,
+}
+
+impl SocketRef {
+	/// # Safety
+	/// `sock` must be the pointer to the socket handle passed by libstrophe to the
+	/// [xmpp_sockopt_callback](https://strophe.im/libstrophe/doc/0.12.2/group___connections.html#gab69556790910b0875d9aa8564c415384)
+	pub unsafe fn from_raw(sock: *mut c_void) -> Self {
+		Self {
+			fd: *(sock as *const c_int),
+		}
+	}
+
+	#[inline]
+	pub fn fd(&self) -> RawFd {
+		self.fd
+	}
+
+	/// Sets `TCP_NODELAY`, i.e. disables the Nagle's algorithm
+	pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+		self.set_opt(libc::IPPROTO_TCP, libc::TCP_NODELAY, c_int::from(nodelay))
+	}
+
+	/// Sets `SO_KEEPALIVE`
+	pub fn set_keepalive(&self, keepalive: bool) -> io::Result<()> {
+		self.set_opt(libc::SOL_SOCKET, libc::SO_KEEPALIVE, c_int::from(keepalive))
+	}
+
+	#[cfg(any(target_os = "linux", target_os = "android"))]
+	/// Enables `SO_KEEPALIVE` and sets `TCP_KEEPIDLE`, `TCP_KEEPINTVL` and `TCP_KEEPCNT`
+	///
+	/// `idle` and `interval` are rounded down to seconds.
+	pub fn set_keepalive_params(&self, idle: Duration, interval: Duration, count: u32) -> io::Result<()> {
+		self.set_keepalive(true)?;
+		self.set_opt(libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, secs_to_c_int(idle))?;
+		self.set_opt(libc::IPPROTO_TCP, libc::TCP_KEEPINTVL, secs_to_c_int(interval))?;
+		self.set_keepalive_count(count)
+	}
+
+	#[cfg(any(target_os = "linux", target_os = "android"))]
+	/// Sets `TCP_KEEPCNT`, the number of unanswered keepalive probes before the connection is dropped
+	pub fn set_keepalive_count(&self, count: u32) -> io::Result<()> {
+		self.set_opt(
+			libc::IPPROTO_TCP,
+			libc::TCP_KEEPCNT,
+			c_int::try_from(count).unwrap_or(c_int::MAX),
+		)
+	}
+
+	#[cfg(any(target_os = "linux", target_os = "android"))]
+	/// Sets `SO_BINDTODEVICE` to bind the socket to a specific network interface
+	pub fn bind_to_device(&self, interface: &str) -> io::Result<()> {
+		let res = unsafe {
+			libc::setsockopt(
+				self.fd,
+				libc::SOL_SOCKET,
+				libc::SO_BINDTODEVICE,
+				interface.as_ptr() as *const c_void,
+				interface.len() as libc::socklen_t,
+			)
+		};
+		check(res)
+	}
+
+	/// Binds the socket to the local `addr` with an ephemeral port
+	///
+	/// The address family must match the one of the socket, i.e. the one of the server address being connected to.
+	pub fn bind(&self, addr: IpAddr) -> io::Result<()> {
+		let res = match addr {
+			IpAddr::V4(addr) => {
+				let mut sockaddr: libc::sockaddr_in = unsafe { mem::zeroed() };
+				sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+				sockaddr.sin_addr = libc::in_addr {
+					s_addr: u32::from_ne_bytes(addr.octets()),
+				};
+				unsafe {
+					libc::bind(
+						self.fd,
+						&sockaddr as *const libc::sockaddr_in as *const libc::sockaddr,
+						mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+					)
+				}
+			}
+			IpAddr::V6(addr) => {
+				let mut sockaddr: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+				sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+				sockaddr.sin6_addr = libc::in6_addr { s6_addr: addr.octets() };
+				unsafe {
+					libc::bind(
+						self.fd,
+						&sockaddr as *const libc::sockaddr_in6 as *const libc::sockaddr,
+						mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+					)
+				}
+			}
+		};
+		check(res)
+	}
+
+	/// Sets the IP type-of-service (DSCP and ECN) byte: `IP_TOS` for IPv4 sockets and `IPV6_TCLASS` for IPv6 ones
+	pub fn set_tos(&self, tos: u8) -> io::Result<()> {
+		self
+			.set_opt(libc::IPPROTO_IP, libc::IP_TOS, c_int::from(tos))
+			.or_else(|_| self.set_opt(libc::IPPROTO_IPV6, libc::IPV6_TCLASS, c_int::from(tos)))
+	}
+
+	fn set_opt(&self, level: c_int, name: c_int, value: c_int) -> io::Result<()> {
+		let res = unsafe {
+			libc::setsockopt(
+				self.fd,
+				level,
+				name,
+				&value as *const c_int as *const c_void,
+				mem::size_of::<c_int>() as libc::socklen_t,
+			)
+		};
+		check(res)
+	}
+}
+
+fn check(res: c_int) -> io::Result<()> {
+	if res == 0 {
+		Ok(())
+	} else {
+		Err(io::Error::last_os_error())
+	}
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn secs_to_c_int(duration: Duration) -> c_int {
+	c_int::try_from(duration.as_secs()).unwrap_or(c_int::MAX)
+}
+
+/// Socket options configured with [Connection::set_local_address](crate::Connection::set_local_address),
+/// [Connection::set_bind_interface](crate::Connection::set_bind_interface) and
+/// [Connection::set_keepalive_opts](crate::Connection::set_keepalive_opts)
+#[derive(Debug, Default, Clone)]
+pub(crate) struct SocketOptions {
+	pub address: Option<IpAddr>,
+	#[cfg(any(target_os = "linux", target_os = "android"))]
+	pub interface: Option<String>,
+	#[cfg(any(target_os = "linux", target_os = "android"))]
+	pub keepalive_count: Option<u32>,
+}
+
+/// Socket options keyed by the `xmpp_conn_t` address, libstrophe doesn't pass any userdata to the sockopt callback
+pub(crate) static SOCKET_OPTIONS: Lazy<RwLock<HashMap<usize, SocketOptions>>> = Lazy::new(Default::default);
+
+/// Sockets of the connections keyed by the `xmpp_conn_t` address, see [Connection::socket_fd](crate::Connection::socket_fd)
+pub(crate) static CONNECTION_SOCKETS: Lazy<RwLock<HashMap<usize, RawFd>>> = Lazy::new(Default::default);
+
+/// Remembers the socket of the connection, called from every sockopt callback installed by the crate
+pub(crate) unsafe fn record_socket(conn: *mut sys::xmpp_conn_t, sock: *mut c_void) {
+	if let Ok(mut sockets) = CONNECTION_SOCKETS.write() {
+		sockets.insert(conn as usize, SocketRef::from_raw(sock).fd());
+	}
+}
+
+pub(crate) fn forget_socket(conn: *mut sys::xmpp_conn_t) {
+	if let Ok(mut sockets) = CONNECTION_SOCKETS.write() {
+		sockets.remove(&(conn as usize));
+	}
+}
+
+/// Sockopt callback that only records the socket, installed by default so that the socket is known without any options
+pub(crate) unsafe extern "C" fn record_socket_sockopt_cb(conn: *mut sys::xmpp_conn_t, sock: *mut c_void) -> c_int {
+	record_socket(conn, sock);
+	0
+}
+
+/// Sockopt callback applying the libstrophe keepalive settings and then the [SocketOptions] of the connection
+pub(crate) unsafe extern "C" fn socket_options_sockopt_cb(conn: *mut sys::xmpp_conn_t, sock: *mut c_void) -> c_int {
+	record_socket(conn, sock);
+	let res = sys::xmpp_sockopt_cb_keepalive(conn, sock);
+	if res != 0 {
+		return res;
+	}
+	let options = SOCKET_OPTIONS
+		.read()
+		.ok()
+		.and_then(|options| options.get(&(conn as usize)).cloned());
+	if let Some(options) = options {
+		let sock = SocketRef::from_raw(sock);
+		#[cfg(any(target_os = "linux", target_os = "android"))]
+		if let Some(count) = options.keepalive_count {
+			if sock.set_keepalive_count(count).is_err() {
+				return -1;
+			}
+		}
+		#[cfg(any(target_os = "linux", target_os = "android"))]
+		if let Some(interface) = &options.interface {
+			if sock.bind_to_device(interface).is_err() {
+				return -1;
+			}
+		}
+		if let Some(address) = options.address {
+			if sock.bind(address).is_err() {
+				return -1;
+			}
+		}
+	}
+	0
+}
@@ -3,12 +3,18 @@ use std::ffi::CStr;
 use std::fmt::Display;
 use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
-use std::os::raw::{c_char, c_int, c_uint};
+use std::os::raw::c_char;
+#[cfg(feature = "unsafe-internals")]
+use std::os::raw::{c_int, c_uint};
 use std::ptr::NonNull;
-use std::{fmt, ops, ptr, slice};
+use std::time::SystemTime;
+use std::{fmt, io, num, ops, ptr, slice, str};
 
 use crate::error::IntoResult;
-use crate::{Error, ErrorType, Result, ToTextError, ALLOC_CONTEXT, FFI};
+use crate::{
+	datetime, uuid_gen, BuildStanzaError, ConnectionToken, Error, ErrorType, ParseAttrError, ParseDateTimeError, RedactionPolicy,
+	Result, ToTextError, ALLOC_CONTEXT, FFI,
+};
 
 mod internals;
 
@@ -36,6 +42,8 @@ mod internals;
 pub struct Stanza {
 	inner: NonNull<sys::xmpp_stanza_t>,
 	owned: bool,
+	#[cfg(feature = "stanza-tracking")]
+	track_id: u64,
 }
 
 impl Stanza {
@@ -49,13 +57,13 @@ impl Stanza {
 	/// [`set_text()`]: struct.Stanza.html#method.set_text
 	/// [`set_name()`]: struct.Stanza.html#method.set_name
 	pub fn new() -> Self {
-		unsafe { Stanza::from_owned(sys::xmpp_stanza_new(ALLOC_CONTEXT.as_ptr())) }
+		unsafe { Stanza::from_owned_alloc(sys::xmpp_stanza_new(ALLOC_CONTEXT.as_ptr())) }
 	}
 
 	#[inline]
 	/// [xmpp_presence_new](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#gac47cc19072d39056d24d579b3a96b5db)
 	pub fn new_presence() -> Self {
-		unsafe { Stanza::from_owned(sys::xmpp_presence_new(ALLOC_CONTEXT.as_ptr())) }
+		unsafe { Stanza::from_owned_alloc(sys::xmpp_presence_new(ALLOC_CONTEXT.as_ptr())) }
 	}
 
 	#[inline]
@@ -63,7 +71,7 @@ impl Stanza {
 	pub fn new_iq(typ: Option<&str>, id: Option<&str>) -> Self {
 		let typ = FFI(typ).send();
 		let id = FFI(id).send();
-		unsafe { Stanza::from_owned(sys::xmpp_iq_new(ALLOC_CONTEXT.as_ptr(), typ.as_ptr(), id.as_ptr())) }
+		unsafe { Stanza::from_owned_alloc(sys::xmpp_iq_new(ALLOC_CONTEXT.as_ptr(), typ.as_ptr(), id.as_ptr())) }
 	}
 
 	#[inline]
@@ -73,7 +81,7 @@ impl Stanza {
 		let to = FFI(to).send();
 		let id = FFI(id).send();
 		unsafe {
-			Stanza::from_owned(sys::xmpp_message_new(
+			Stanza::from_owned_alloc(sys::xmpp_message_new(
 				ALLOC_CONTEXT.as_ptr(),
 				typ.as_ptr(),
 				to.as_ptr(),
@@ -87,7 +95,7 @@ impl Stanza {
 	/// [xmpp_error_new](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga867086f16735eff5220a116d9d5e353b)
 	pub fn new_error(typ: ErrorType, text: Option<&str>) -> Self {
 		let text = FFI(text).send();
-		unsafe { Stanza::from_owned(sys::xmpp_error_new(ALLOC_CONTEXT.as_ptr(), typ, text.as_ptr())) }
+		unsafe { Stanza::from_owned_alloc(sys::xmpp_error_new(ALLOC_CONTEXT.as_ptr(), typ, text.as_ptr())) }
 	}
 
 	#[inline]
@@ -96,19 +104,32 @@ impl Stanza {
 	pub fn from_str(s: impl AsRef<str>) -> Self {
 		#![allow(clippy::should_implement_trait)]
 		let s = FFI(s.as_ref()).send();
-		unsafe { Stanza::from_owned(sys::xmpp_stanza_new_from_string(ALLOC_CONTEXT.as_ptr(), s.as_ptr())) }
+		unsafe { Stanza::from_owned_alloc(sys::xmpp_stanza_new_from_string(ALLOC_CONTEXT.as_ptr(), s.as_ptr())) }
+	}
+
+	#[cfg(feature = "libstrophe-0_10_0")]
+	/// Fallible version of [Stanza::from_str]
+	///
+	/// Returns [Error::InvalidOperation] if `s` contains a NUL byte or is not a well-formed XML element instead of panicking.
+	/// Use it when parsing untrusted input.
+	pub fn parse(s: impl AsRef<str>) -> Result<Self> {
+		let s = std::ffi::CString::new(s.as_ref()).map_err(|_| Error::InvalidOperation)?;
+		let inner = unsafe { sys::xmpp_stanza_new_from_string(ALLOC_CONTEXT.as_ptr(), s.as_ptr()) };
+		if inner.is_null() {
+			Err(Error::InvalidOperation)
+		} else {
+			Ok(unsafe { Stanza::from_owned_alloc(inner) })
+		}
 	}
 
 	#[inline]
 	unsafe fn with_inner(inner: *mut sys::xmpp_stanza_t, owned: bool) -> Self {
-		let mut out = Stanza {
+		Stanza {
 			inner: NonNull::new(inner).expect("Cannot allocate memory for Stanza"),
 			owned,
-		};
-		if owned {
-			out.set_alloc_context();
+			#[cfg(feature = "stanza-tracking")]
+			track_id: crate::stanza_tracking::track(inner, owned),
 		}
-		out
 	}
 
 	#[inline]
@@ -117,6 +138,47 @@ impl Stanza {
 	/// inner must be a valid pointer to a previously allocated xmpp_stanza_t and you must make sure
 	/// that there are no other usages of that pointer after calling this function.
 	pub unsafe fn from_owned(inner: *mut sys::xmpp_stanza_t) -> Self {
+		Stanza::with_inner(inner, true).into_alloc_context()
+	}
+
+	#[inline]
+	/// Same as [Stanza::from_owned], but returns [Error::MemoryError] instead of panicking if `inner` is null, for internal
+	/// use
+	unsafe fn try_from_owned(inner: *mut sys::xmpp_stanza_t) -> Result<Self> {
+		if inner.is_null() {
+			Err(Error::MemoryError)
+		} else {
+			Ok(Stanza::from_owned(inner))
+		}
+	}
+
+	#[inline]
+	/// [xmpp_stanza_copy](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga1ab1122fdf477d9d755fc094b4fbf6b6)
+	///
+	/// Fallible version of `clone()`, returns [Error::MemoryError] instead of panicking when the copy can't be allocated.
+	pub fn try_clone(&self) -> Result<Self> {
+		#[cfg(feature = "unsafe-internals")]
+		{
+			unsafe { Stanza::try_from_owned(sys::xmpp_stanza_copy(self.inner.as_ptr())) }
+		}
+		#[cfg(not(feature = "unsafe-internals"))]
+		{
+			self.copy_to_alloc_context()
+		}
+	}
+
+	#[inline]
+	/// [xmpp_stanza_clone](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga9fe1d2ac8ba6ac7a5b5d3d00bb0e0d40)
+	///
+	/// Owning handle to the same stanza that only increments its reference count, the stanza stays in its original
+	/// context, for internal use
+	pub(crate) fn share(&self) -> Self {
+		unsafe { Stanza::with_inner(sys::xmpp_stanza_clone(self.inner.as_ptr()), true) }
+	}
+
+	#[inline]
+	/// Create an owning stanza from the raw pointer that was allocated using `ALLOC_CONTEXT`, for internal use
+	unsafe fn from_owned_alloc(inner: *mut sys::xmpp_stanza_t) -> Self {
 		Stanza::with_inner(inner, true)
 	}
 
@@ -138,11 +200,60 @@ impl Stanza {
 		Stanza::with_inner(inner, false).into()
 	}
 
+	/// Token of the connection that received this stanza, only available while the stanza is passed to a handler
+	///
+	/// libstrophe stanzas only reference their context, so this is tracked by the wrapper for the top-level stanzas passed to
+	/// the handlers, `None` is returned otherwise (including for their children). Compare it with [Connection::token] to key
+	/// the per-connection state without holding the connection itself.
+	///
+	/// [Connection::token]: crate::Connection::token
+	pub fn connection_ptr_token(&self) -> Option<ConnectionToken> {
+		crate::connection::dispatched_stanza_connection(self.inner.as_ptr())
+	}
+
 	/// Return internal raw pointer to stanza, for internal use
 	pub(crate) fn as_ptr(&self) -> *mut sys::xmpp_stanza_t {
 		self.inner.as_ptr()
 	}
 
+	/// Make the owned stanza independent of whatever context it was created with, see [Stanza::set_alloc_context]
+	#[cfg(feature = "unsafe-internals")]
+	fn into_alloc_context(mut self) -> Self {
+		self.set_alloc_context();
+		self
+	}
+
+	/// Make the owned stanza independent of whatever context it was created with
+	///
+	/// Without the `unsafe-internals` feature the context can't be changed in place so the stanza is copied to the global
+	/// `ALLOC_CONTEXT` using only the public libstrophe API and the original is released.
+	#[cfg(not(feature = "unsafe-internals"))]
+	fn into_alloc_context(self) -> Self {
+		self.copy_to_alloc_context().expect("Cannot allocate memory for Stanza")
+	}
+
+	/// Deep copy of the stanza allocated with the global `ALLOC_CONTEXT`, uses only the public libstrophe API
+	#[cfg(not(feature = "unsafe-internals"))]
+	fn copy_to_alloc_context(&self) -> Result<Self> {
+		let inner = unsafe { sys::xmpp_stanza_new(ALLOC_CONTEXT.as_ptr()) };
+		if inner.is_null() {
+			return Err(Error::MemoryError);
+		}
+		let mut out = unsafe { Stanza::from_owned_alloc(inner) };
+		if let Some(text) = self.text_ptr() {
+			out.set_text(text)?;
+		} else if let Some(name) = self.name() {
+			out.set_name(name)?;
+			for (name, value) in self.attributes() {
+				out.set_attribute(name, value)?;
+			}
+			for child in self.children() {
+				out.add_child(child.copy_to_alloc_context()?)?;
+			}
+		}
+		Ok(out)
+	}
+
 	/// Reset Stanza context to the 'static global ALLOC_CONTEXT to make it independent of whatever context it was created with
 	///
 	/// Generally libstrophe's `xmpp_stanza_t` needs `xmpp_ctx_t` only for allocation so it's possible to make `Stanza` 'static
@@ -152,6 +263,10 @@ impl Stanza {
 	/// receives the context that is tied to the one running the connection and it is not 'static. This function fixes that
 	/// situation by overwriting the `ctx` reference for current stanza (including one in attributes hash table) and all of
 	/// its children.
+	///
+	/// The internal structs of libstrophe are not a part of its public API so this function is only available with the
+	/// `unsafe-internals` feature.
+	#[cfg(feature = "unsafe-internals")]
 	fn set_alloc_context(&mut self) {
 		#[allow(non_camel_case_types)]
 		#[repr(C)]
@@ -204,6 +319,48 @@ impl Stanza {
 		stanza_to_text(self.inner.as_ptr(), |buf| Ok(buf.to_str()?.to_owned()))
 	}
 
+	/// Serializes the stanza directly into `w`, returns the number of bytes written
+	///
+	/// Same output as [`to_text()`](#method.to_text), but the buffer produced by
+	/// [xmpp_stanza_to_text](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga2918484877ac34d483cc14cf5e957fad)
+	/// is written out as is without validating it and copying into an intermediate `String`. Serialization failures are
+	/// converted from [Error].
+	pub fn write_to(&self, w: &mut impl io::Write) -> io::Result<usize> {
+		stanza_to_text(self.inner.as_ptr(), |buf| {
+			let buf = buf.to_bytes();
+			w.write_all(buf).map(|_| buf.len())
+		})
+	}
+
+	/// Serialize stanza into text with deterministic formatting
+	///
+	/// Unlike [`to_text()`] the output of this method doesn't depend on the internal order of the attributes (which changes e.g.
+	/// during `clone()`) so it's suitable for hashing, signing and comparison. The output follows the rules of [XML-C14N] where
+	/// they are applicable to stanzas: namespace declarations are placed first followed by other attributes sorted by name,
+	/// empty elements are written as a pair of start and end tags and special characters are escaped using the canonical
+	/// set of entities.
+	///
+	/// [`to_text()`]: struct.Stanza.html#method.to_text
+	/// [XML-C14N]: https://www.w3.org/TR/xml-c14n/
+	pub fn to_text_canonical(&self) -> Result<String, ToTextError> {
+		let mut out = String::new();
+		stanza_to_text_canonical(self, &mut out)?;
+		Ok(out)
+	}
+
+	/// Serializes the copy of the stanza with the sensitive content hidden according to the `policy`
+	///
+	/// Use it to log the protocol flows without the message bodies, the authentication payloads and the like, see
+	/// [RedactionPolicy]. If the copy can't be made or serialized the placeholder of the policy is returned instead, so the
+	/// original content never leaks into the log.
+	pub fn redacted(&self, policy: &RedactionPolicy) -> String {
+		policy
+			.redact(self)
+			.ok()
+			.and_then(|redacted| redacted.to_text().ok())
+			.unwrap_or_else(|| policy.placeholder_text().to_owned())
+	}
+
 	#[inline]
 	/// [xmpp_stanza_set_name](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga8331fbddc0f2fc7286a267ef60c69df2)
 	///
@@ -236,8 +393,7 @@ impl Stanza {
 	#[inline]
 	/// [xmpp_stanza_get_attribute](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#gad836688d17b7c5af148f32823e72b81b)
 	pub fn get_attribute(&self, name: impl AsRef<str>) -> Option<&str> {
-		let name = FFI(name.as_ref()).send();
-		unsafe { FFI(sys::xmpp_stanza_get_attribute(self.inner.as_ptr(), name.as_ptr())).receive() }
+		FFI(name.as_ref()).with_ptr(|name| unsafe { FFI(sys::xmpp_stanza_get_attribute(self.inner.as_ptr(), name)).receive() })
 	}
 
 	/// [xmpp_stanza_get_attributes](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga2eea8820dcf9b3e2440a06de55a35850)
@@ -267,6 +423,82 @@ impl Stanza {
 		unsafe { sys::xmpp_stanza_del_attribute(self.inner.as_mut(), name.as_ptr()) }.into_result()
 	}
 
+	/// Gets the value of the attribute `name` in the namespace `ns`
+	///
+	/// The attribute is looked up as `prefix:name` where `prefix` is bound to `ns` by an `xmlns:prefix` declaration on
+	/// this element. The `xml` prefix is always bound to [NS_XML]. Be aware that the XML parser of libstrophe drops the
+	/// namespace information of the attributes so for the parsed stanzas such attributes are available only via
+	/// [`get_attribute()`](#method.get_attribute) with the local name.
+	pub fn get_attribute_ns(&self, name: impl AsRef<str>, ns: impl AsRef<str>) -> Option<&str> {
+		let prefix = self.ns_prefix(ns.as_ref())?;
+		self.get_attribute(format!("{prefix}:{}", name.as_ref()))
+	}
+
+	/// Sets the value of the attribute `name` in the namespace `ns`
+	///
+	/// If there is no prefix bound to `ns` on this element yet then a new `xmlns:nsN` declaration is added. See
+	/// [`get_attribute_ns()`](#method.get_attribute_ns) for details.
+	pub fn set_attribute_ns(&mut self, name: impl AsRef<str>, ns: impl AsRef<str>, value: impl AsRef<str>) -> Result<()> {
+		let ns = ns.as_ref();
+		let prefix = match self.ns_prefix(ns) {
+			Some(prefix) => prefix.to_owned(),
+			None => {
+				let prefix = (0..)
+					.map(|i| format!("ns{i}"))
+					.find(|prefix| self.get_attribute(format!("xmlns:{prefix}")).is_none())
+					.expect("Exhausted namespace prefixes");
+				self.declare_ns(&prefix, ns)?;
+				prefix
+			}
+		};
+		self.set_attribute(format!("{prefix}:{}", name.as_ref()), value)
+	}
+
+	/// Deletes the attribute `name` in the namespace `ns`, the prefix declaration is kept
+	///
+	/// See [`get_attribute_ns()`](#method.get_attribute_ns) for details.
+	pub fn del_attribute_ns(&mut self, name: impl AsRef<str>, ns: impl AsRef<str>) -> Result<()> {
+		if let Some(prefix) = self.ns_prefix(ns.as_ref()) {
+			let name = format!("{prefix}:{}", name.as_ref());
+			self.del_attribute(name)
+		} else {
+			Ok(())
+		}
+	}
+
+	#[inline]
+	/// Binds `prefix` to the namespace `ns` by adding an `xmlns:prefix` declaration to this element
+	///
+	/// The prefix is then in scope for this element and all of its descendants, use
+	/// [`add_prefixed_child()`](#method.add_prefixed_child) to create the elements that use it.
+	pub fn declare_ns(&mut self, prefix: impl AsRef<str>, ns: impl AsRef<str>) -> Result<()> {
+		self.set_attribute(format!("xmlns:{}", prefix.as_ref()), ns)
+	}
+
+	/// Creates a new child element serialized as `<prefix:name/>` and returns a reference to it
+	///
+	/// The `prefix` must be declared with [`declare_ns()`](#method.declare_ns) on this element or one of its ancestors,
+	/// this is not checked.
+	pub fn add_prefixed_child(&mut self, prefix: impl AsRef<str>, name: impl AsRef<str>) -> Result<StanzaMutRef<'_>> {
+		let mut child = Stanza::new();
+		child.set_name(format!("{}:{}", prefix.as_ref(), name.as_ref()))?;
+		let child_ptr = child.inner.as_ptr();
+		self.add_child(child)?;
+		// the child is now owned by self and lives as long as it
+		Ok(unsafe { Self::from_ref_mut(child_ptr) })
+	}
+
+	/// Returns the prefix bound to the namespace `ns` by an `xmlns:prefix` declaration on this element
+	fn ns_prefix(&self, ns: &str) -> Option<&str> {
+		if ns == NS_XML {
+			return Some("xml");
+		}
+		self
+			.attributes()
+			.into_iter()
+			.find_map(|(key, val)| key.strip_prefix("xmlns:").filter(|_| val == ns))
+	}
+
 	#[inline]
 	/// [xmpp_stanza_set_text_with_size](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga779812852611947d0181cd4c58644ef1)
 	///
@@ -282,6 +514,95 @@ impl Stanza {
 		unsafe { FFI(sys::xmpp_stanza_get_text(self.inner.as_ptr())).receive_with_free(|x| ALLOC_CONTEXT.free(x)) }
 	}
 
+	#[inline]
+	/// [xmpp_stanza_get_text_ptr](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html)
+	///
+	/// Returns text of the `XMPP_STANZA_TEXT` stanza without copying it, `None` for `XMPP_STANZA_TAG` stanzas.
+	pub fn text_ptr(&self) -> Option<&str> {
+		unsafe { FFI(sys::xmpp_stanza_get_text_ptr(self.inner.as_ptr())).receive() }
+	}
+
+	/// Non-allocating version of [`text()`]
+	///
+	/// Calls `cb` with the text of the stanza and returns its result. If the stanza is an `XMPP_STANZA_TEXT` stanza or a tag
+	/// with a single text child then the text is passed directly from the underlying storage. Only when the text is split
+	/// between several child nodes and needs to be concatenated does this method fall back to allocation via [`text()`].
+	///
+	/// [`text()`]: struct.Stanza.html#method.text
+	pub fn with_text<T>(&self, cb: impl FnOnce(&str) -> T) -> Option<T> {
+		if let Some(text) = self.text_ptr() {
+			return Some(cb(text));
+		}
+		let mut text_children = self.children().filter(|child| child.is_text());
+		match (text_children.next(), text_children.next()) {
+			(None, _) => None,
+			(Some(child), None) => child.text_ptr().map(cb),
+			(Some(_), Some(_)) => self.text().map(|text| cb(&text)),
+		}
+	}
+
+	/// Fills the element with the text read from `r` in chunks, returns the number of bytes read
+	///
+	/// Every chunk is added as a separate text child so neither the whole payload nor the whole resulting text is held in
+	/// memory at once, [`text()`] returns the chunks concatenated and they are serialized back to back. With `base64` set
+	/// the bytes are encoded using [xmpp_base64_encode], otherwise they must be valid UTF-8. The stanza must be an element
+	/// without children.
+	///
+	/// [`text()`]: struct.Stanza.html#method.text
+	/// [xmpp_base64_encode]: https://github.com/strophe/libstrophe/blob/0.12.2/src/crypto.c
+	pub fn set_text_from_reader(&mut self, mut r: impl io::Read, base64: bool) -> io::Result<u64> {
+		if !self.is_tag() || self.get_first_child().is_some() {
+			return Err(Error::InvalidOperation.into());
+		}
+		let mut buf = vec![0; TEXT_CHUNK_SIZE];
+		let mut total = 0;
+		// number of bytes at the start of `buf` left over from the previous chunk (incomplete UTF-8 sequence)
+		let mut pending = 0;
+		loop {
+			let len = pending + read_full(&mut r, &mut buf[pending..])?;
+			total += (len - pending) as u64;
+			if len == 0 {
+				break;
+			}
+			let mut child = Stanza::new();
+			if base64 {
+				let encoded = unsafe { sys::xmpp_base64_encode(ALLOC_CONTEXT.as_ptr(), buf.as_ptr(), len) };
+				if encoded.is_null() {
+					return Err(Error::MemoryError.into());
+				}
+				let res = unsafe { sys::xmpp_stanza_set_text(child.inner.as_mut(), encoded) }.into_result();
+				unsafe { ALLOC_CONTEXT.free(encoded) };
+				res?;
+			} else {
+				let valid_len = match str::from_utf8(&buf[..len]) {
+					Ok(_) => len,
+					Err(e) if e.error_len().is_none() && len == buf.len() => e.valid_up_to(),
+					Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+				};
+				child.set_text(unsafe { str::from_utf8_unchecked(&buf[..valid_len]) })?;
+				buf.copy_within(valid_len..len, 0);
+				pending = len - valid_len;
+			}
+			self.add_child(child)?;
+			if len < buf.len() {
+				break;
+			}
+		}
+		Ok(total)
+	}
+
+	/// Checks that the strings of the stanza and of all its descendants are valid UTF-8 so that the accessors can't panic
+	pub(crate) fn is_valid_utf8(&self) -> bool {
+		let mut valid = true;
+		walk_raw_strings(self.inner.as_ptr(), &mut |s| valid = valid && str::from_utf8(s).is_ok());
+		valid
+	}
+
+	/// Deep copy of the stanza with the invalid UTF-8 sequences replaced by `U+FFFD REPLACEMENT CHARACTER`
+	pub(crate) fn to_lossy_utf8(&self) -> Result<Stanza> {
+		unsafe { lossy_copy(self.inner.as_ptr()) }
+	}
+
 	#[inline]
 	/// [xmpp_stanza_set_id](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#gaa19a4d40d3383881b3266631dd9f2a0d)
 	pub fn set_id(&mut self, id: impl AsRef<str>) -> Result<()> {
@@ -362,31 +683,34 @@ impl Stanza {
 	#[inline]
 	/// [xmpp_stanza_get_child_by_ns](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga09791fe5c7a5b3f4d90a95a46621eb1d)
 	pub fn get_child_by_ns(&self, ns: impl AsRef<str>) -> Option<StanzaRef> {
-		let ns = FFI(ns.as_ref()).send();
-		unsafe { sys::xmpp_stanza_get_child_by_ns(self.inner.as_ptr(), ns.as_ptr()).as_ref() }.map(|x| unsafe { Self::from_ref(x) })
+		FFI(ns.as_ref())
+			.with_ptr(|ns| unsafe { sys::xmpp_stanza_get_child_by_ns(self.inner.as_ptr(), ns).as_ref() })
+			.map(|x| unsafe { Self::from_ref(x) })
 	}
 
 	#[inline]
 	/// [xmpp_stanza_get_child_by_ns](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga09791fe5c7a5b3f4d90a95a46621eb1d)
 	pub fn get_child_by_ns_mut(&mut self, ns: impl AsRef<str>) -> Option<StanzaMutRef> {
-		let ns = FFI(ns.as_ref()).send();
-		unsafe { sys::xmpp_stanza_get_child_by_ns(self.inner.as_mut(), ns.as_ptr()).as_mut() }
+		let inner = self.inner.as_ptr();
+		FFI(ns.as_ref())
+			.with_ptr(|ns| unsafe { sys::xmpp_stanza_get_child_by_ns(inner, ns).as_mut() })
 			.map(|x| unsafe { Self::from_ref_mut(x) })
 	}
 
 	#[inline]
 	/// [xmpp_stanza_get_child_by_name](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga19933d39585d91285e02c0c5fff41082)
 	pub fn get_child_by_name(&self, name: impl AsRef<str>) -> Option<StanzaRef> {
-		let name = FFI(name.as_ref()).send();
-		unsafe { sys::xmpp_stanza_get_child_by_name(self.inner.as_ptr(), name.as_ptr()).as_ref() }
+		FFI(name.as_ref())
+			.with_ptr(|name| unsafe { sys::xmpp_stanza_get_child_by_name(self.inner.as_ptr(), name).as_ref() })
 			.map(|x| unsafe { Self::from_ref(x) })
 	}
 
 	#[inline]
 	/// [xmpp_stanza_get_child_by_name](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga19933d39585d91285e02c0c5fff41082)
 	pub fn get_child_by_name_mut(&mut self, name: impl AsRef<str>) -> Option<StanzaMutRef> {
-		let name = FFI(name.as_ref()).send();
-		unsafe { sys::xmpp_stanza_get_child_by_name(self.inner.as_mut(), name.as_ptr()).as_mut() }
+		let inner = self.inner.as_ptr();
+		FFI(name.as_ref())
+			.with_ptr(|name| unsafe { sys::xmpp_stanza_get_child_by_name(inner, name).as_mut() })
 			.map(|x| unsafe { Self::from_ref_mut(x) })
 	}
 
@@ -394,9 +718,11 @@ impl Stanza {
 	#[cfg(feature = "libstrophe-0_10_0")]
 	/// [xmpp_stanza_get_child_by_name_and_ns](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#gaf00933e114ada170c526f11589d3e072)
 	pub fn get_child_by_name_and_ns(&self, name: impl AsRef<str>, ns: impl AsRef<str>) -> Option<StanzaRef> {
-		let name = FFI(name.as_ref()).send();
-		let ns = FFI(ns.as_ref()).send();
-		unsafe { sys::xmpp_stanza_get_child_by_name_and_ns(self.inner.as_ptr(), name.as_ptr(), ns.as_ptr()).as_ref() }
+		FFI(name.as_ref())
+			.with_ptr(|name| {
+				FFI(ns.as_ref())
+					.with_ptr(|ns| unsafe { sys::xmpp_stanza_get_child_by_name_and_ns(self.inner.as_ptr(), name, ns).as_ref() })
+			})
 			.map(|x| unsafe { Self::from_ref(x) })
 	}
 
@@ -404,12 +730,29 @@ impl Stanza {
 	#[cfg(feature = "libstrophe-0_10_0")]
 	/// [xmpp_stanza_get_child_by_name_and_ns](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#gaf00933e114ada170c526f11589d3e072)
 	pub fn get_child_by_name_and_ns_mut(&mut self, name: impl AsRef<str>, ns: impl AsRef<str>) -> Option<StanzaMutRef> {
-		let name = FFI(name.as_ref()).send();
-		let ns = FFI(ns.as_ref()).send();
-		unsafe { sys::xmpp_stanza_get_child_by_name_and_ns(self.inner.as_mut(), name.as_ptr(), ns.as_ptr()).as_mut() }
+		let inner = self.inner.as_ptr();
+		FFI(name.as_ref())
+			.with_ptr(|name| {
+				FFI(ns.as_ref()).with_ptr(|ns| unsafe { sys::xmpp_stanza_get_child_by_name_and_ns(inner, name, ns).as_mut() })
+			})
 			.map(|x| unsafe { Self::from_ref_mut(x) })
 	}
 
+	/// [`get_child_by_name_and_ns()`](#method.get_child_by_name_and_ns) that falls back to iterating children with older
+	/// libstrophe versions
+	pub(crate) fn find_child_by_name_and_ns(&self, name: &str, ns: &str) -> Option<StanzaRef<'_>> {
+		#[cfg(feature = "libstrophe-0_10_0")]
+		{
+			self.get_child_by_name_and_ns(name, ns)
+		}
+		#[cfg(not(feature = "libstrophe-0_10_0"))]
+		{
+			self
+				.children()
+				.find(|child| child.name() == Some(name) && child.ns() == Some(ns))
+		}
+	}
+
 	#[cfg(feature = "libstrophe-0_12_0")]
 	/// [xmpp_stanza_get_child_by_path](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga12567a82abab6a54c396ea56cb895981)
 	///
@@ -445,6 +788,41 @@ impl Stanza {
 		}
 	}
 
+	/// Number of the direct child nodes (both elements and text) without creating the wrappers for them
+	pub fn child_count(&self) -> usize {
+		let mut out = 0;
+		let mut child = unsafe { sys::xmpp_stanza_get_children(self.inner.as_ptr()) };
+		while !child.is_null() {
+			out += 1;
+			child = unsafe { sys::xmpp_stanza_get_next(child) };
+		}
+		out
+	}
+
+	/// Approximate length in bytes of the serialized stanza, computed without serializing it
+	///
+	/// The result matches the length of [`to_text()`](#method.to_text) except for the characters that need escaping which
+	/// are counted as one byte, so it's suitable for the size limits and statistics, but not for allocating the exact
+	/// buffers.
+	pub fn byte_size_estimate(&self) -> usize {
+		unsafe { raw_size_estimate(self.inner.as_ptr()) }
+	}
+
+	/// Iterates over the child nodes in the document order distinguishing the elements from the text nodes
+	///
+	/// Unlike [`children()`](#method.children) it borrows the text directly, use it to reconstruct the mixed-content
+	/// payloads like XHTML-IM bodies.
+	pub fn nodes(&self) -> impl Iterator<Item = Node<'_>> {
+		self.children().map(|child| {
+			if child.is_text() {
+				// the text is owned by the parent stanza so it lives as long as the child reference
+				Node::Text(unsafe { FFI(sys::xmpp_stanza_get_text_ptr(child.as_ptr())).receive() }.unwrap_or_default())
+			} else {
+				Node::Element(child)
+			}
+		})
+	}
+
 	#[inline]
 	/// [xmpp_stanza_get_next](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga4eceb55b6a939767d473f7faacfcc6e2)
 	pub fn get_next(&self) -> Option<StanzaRef> {
@@ -467,7 +845,15 @@ impl Stanza {
 	#[inline]
 	/// [xmpp_stanza_reply](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga32c20758b86bf9c46688e58878a284b5)
 	pub fn reply(&self) -> Self {
-		unsafe { Self::from_owned(sys::xmpp_stanza_reply(self.inner.as_ptr())) }
+		self.try_reply().expect("Cannot allocate memory for Stanza")
+	}
+
+	#[inline]
+	/// [xmpp_stanza_reply](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#ga32c20758b86bf9c46688e58878a284b5)
+	///
+	/// Fallible version of [`reply()`](#method.reply), returns [Error::MemoryError] instead of panicking.
+	pub fn try_reply(&self) -> Result<Self> {
+		unsafe { Self::try_from_owned(sys::xmpp_stanza_reply(self.inner.as_ptr())) }
 	}
 
 	#[inline]
@@ -487,6 +873,45 @@ impl Stanza {
 		}
 	}
 
+	/// Parses the value of the attribute `name` into `T`
+	///
+	/// Returns `Ok(None)` if the attribute is not present and an error with the attribute name and its raw value if it's
+	/// present, but can't be parsed.
+	pub fn attr_parse<T: str::FromStr>(&self, name: impl AsRef<str>) -> Result<Option<T>, ParseAttrError<T::Err>> {
+		let name = name.as_ref();
+		self.get_attribute(name).map(|value| parse_value(name, value)).transpose()
+	}
+
+	/// Parses the text of the `<priority/>` child element of a presence stanza
+	///
+	/// See [RFC 6121](https://www.rfc-editor.org/rfc/rfc6121#section-4.7.2.3).
+	pub fn priority(&self) -> Result<Option<i8>, ParseAttrError<num::ParseIntError>> {
+		self
+			.get_child_by_name("priority")
+			.and_then(|priority| priority.with_text(|value| parse_value("priority", value.trim())))
+			.transpose()
+	}
+
+	/// Parses the `stamp` attribute of the `<delay xmlns="urn:xmpp:delay"/>` child element
+	///
+	/// See [XEP-0203](https://xmpp.org/extensions/xep-0203.html).
+	pub fn delay_stamp(&self) -> Result<Option<SystemTime>, ParseAttrError<ParseDateTimeError>> {
+		self
+			.find_child_by_name_and_ns("delay", "urn:xmpp:delay")
+			.and_then(|delay| {
+				delay
+					.get_attribute("stamp")
+					.map(|value| datetime::parse(value).map_err(|error| parse_attr_error("stamp", value, error)))
+			})
+			.transpose()
+	}
+
+	#[inline]
+	/// Parses the `seconds` attribute, e.g. of the [XEP-0012](https://xmpp.org/extensions/xep-0012.html) `<query/>` element
+	pub fn seconds(&self) -> Result<Option<u64>, ParseAttrError<num::ParseIntError>> {
+		self.attr_parse("seconds")
+	}
+
 	#[inline]
 	/// [xmpp_message_set_body](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#gace4a07d21a6700692d22ea13200d13f5)
 	pub fn set_body(&mut self, body: impl AsRef<str>) -> Result<()> {
@@ -499,8 +924,96 @@ impl Stanza {
 	pub fn body(&self) -> Option<String> {
 		unsafe { FFI(sys::xmpp_message_get_body(self.inner.as_ptr())).receive_with_free(|x| ALLOC_CONTEXT.free(x)) }
 	}
+
+	/// Sets the `<thread/>` child element of a message stanza, replacing the existing one
+	///
+	/// `parent` sets the `parent` attribute that links the thread to the thread it was forked from. See
+	/// [XEP-0201](https://xmpp.org/extensions/xep-0201.html).
+	pub fn set_thread(&mut self, thread: impl AsRef<str>, parent: Option<&str>) -> Result<()> {
+		let thread = thread.as_ref();
+		if let Some(mut existing) = self.get_child_by_name_mut("thread") {
+			if let Some(mut text) = existing.get_first_child_mut() {
+				text.set_text(thread)?;
+			} else {
+				let mut text = Stanza::new();
+				text.set_text(thread)?;
+				existing.add_child(text)?;
+			}
+			match parent {
+				Some(parent) => existing.set_attribute("parent", parent),
+				None => existing.del_attribute("parent"),
+			}
+		} else {
+			let mut element = Stanza::new();
+			element.set_name("thread")?;
+			if let Some(parent) = parent {
+				element.set_attribute("parent", parent)?;
+			}
+			let mut text = Stanza::new();
+			text.set_text(thread)?;
+			element.add_child(text)?;
+			self.add_child(element)
+		}
+	}
+
+	/// Starts a new thread with a random UUID as its identifier, see [`set_thread()`](#method.set_thread)
+	///
+	/// Returns the generated thread id.
+	pub fn set_new_thread(&mut self, parent: Option<&str>) -> Result<String> {
+		let thread = uuid_gen();
+		self.set_thread(&thread, parent)?;
+		Ok(thread)
+	}
+
+	/// Text of the `<thread/>` child element of a message stanza
+	pub fn thread(&self) -> Option<String> {
+		self.get_child_by_name("thread").and_then(|thread| thread.text())
+	}
+
+	/// `parent` attribute of the `<thread/>` child element of a message stanza
+	pub fn thread_parent(&self) -> Option<String> {
+		self
+			.get_child_by_name("thread")
+			.and_then(|thread| thread.get_attribute("parent").map(|parent| parent.to_owned()))
+	}
+
+	/// Marks the message as a correction of the message with the `id`
+	///
+	/// Adds the `<replace xmlns="urn:xmpp:message-correct:0"/>` child element, see
+	/// [XEP-0308](https://xmpp.org/extensions/xep-0308.html). Use [Connection::new_correction] to build the whole correction
+	/// message.
+	///
+	/// [Connection::new_correction]: crate::Connection::new_correction
+	pub fn set_replace(&mut self, id: impl AsRef<str>) -> Result<()> {
+		let mut replace = Stanza::new();
+		replace.set_name("replace")?;
+		replace.set_ns("urn:xmpp:message-correct:0")?;
+		replace.set_id(id)?;
+		self.add_child(replace)
+	}
+
+	/// Id of the message that this message corrects, see [`set_replace()`](#method.set_replace)
+	pub fn replace_id(&self) -> Option<String> {
+		self
+			.find_child_by_name_and_ns("replace", "urn:xmpp:message-correct:0")
+			.and_then(|replace| replace.id().map(|id| id.to_owned()))
+	}
+
+	#[inline]
+	/// Non-allocating version of [`body()`]
+	///
+	/// Calls `cb` with the text of the `body` child element, see [`with_text()`] for details.
+	///
+	/// [`body()`]: struct.Stanza.html#method.body
+	/// [`with_text()`]: struct.Stanza.html#method.with_text
+	pub fn with_body<T>(&self, cb: impl FnOnce(&str) -> T) -> Option<T> {
+		self.get_child_by_name("body").and_then(|body| body.with_text(cb))
+	}
 }
 
+/// Namespace permanently bound to the `xml` prefix
+pub const NS_XML: &str = "http://www.w3.org/XML/1998/namespace";
+
 #[inline]
 #[allow(non_snake_case)]
 /// Helper function for [Stanza::get_child_by_path]
@@ -515,7 +1028,7 @@ impl std::str::FromStr for Stanza {
 
 	#[inline]
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		Ok(Self::from_str(s))
+		Self::parse(s).map_err(|_| ())
 	}
 }
 
@@ -529,7 +1042,7 @@ impl Display for Stanza {
 impl Clone for Stanza {
 	#[inline]
 	fn clone(&self) -> Self {
-		unsafe { Stanza::from_owned(sys::xmpp_stanza_copy(self.inner.as_ptr())) }
+		self.try_clone().expect("Cannot allocate memory for Stanza")
 	}
 }
 
@@ -551,11 +1064,11 @@ impl Drop for Stanza {
 	#[inline]
 	/// [xmpp_stanza_release](https://strophe.im/libstrophe/doc/0.12.2/group___stanza.html#gaa231317e56af0b974d7a28793ebefb83)
 	fn drop(&mut self) {
-		if self.owned {
-			unsafe {
-				sys::xmpp_stanza_release(self.inner.as_mut());
-			}
-		}
+		let freed = self.owned && unsafe { FFI(sys::xmpp_stanza_release(self.inner.as_mut())).receive_bool() };
+		#[cfg(feature = "stanza-tracking")]
+		crate::stanza_tracking::untrack(self.track_id, freed);
+		#[cfg(not(feature = "stanza-tracking"))]
+		let _ = freed;
 	}
 }
 
@@ -568,6 +1081,143 @@ impl Default for Stanza {
 
 unsafe impl Send for Stanza {}
 
+/// Fluent builder of the [Stanza], e.g. `StanzaBuilder::iq("get", "id1").to(server).child(query).build()`
+///
+/// The errors of the individual steps are kept until [`build()`](StanzaBuilder::build) which also validates the top-level
+/// stanzas against [RFC 6120](https://www.rfc-editor.org/rfc/rfc6120#section-8).
+#[derive(Debug)]
+#[must_use]
+pub struct StanzaBuilder {
+	stanza: Result<Stanza>,
+}
+
+impl StanzaBuilder {
+	/// Starts building the element `name`
+	pub fn new(name: impl AsRef<str>) -> Self {
+		let mut stanza = Stanza::new();
+		Self {
+			stanza: stanza.set_name(name).map(|_| stanza),
+		}
+	}
+
+	/// Starts building the `<iq/>` of type `typ` (`get`, `set`, `result` or `error`) with the `id`
+	pub fn iq(typ: impl AsRef<str>, id: impl AsRef<str>) -> Self {
+		Self::new("iq").typ(typ).id(id)
+	}
+
+	#[inline]
+	pub fn message() -> Self {
+		Self::new("message")
+	}
+
+	#[inline]
+	pub fn presence() -> Self {
+		Self::new("presence")
+	}
+
+	fn map(self, f: impl FnOnce(&mut Stanza) -> Result<()>) -> Self {
+		Self {
+			stanza: self.stanza.and_then(|mut stanza| f(&mut stanza).map(|_| stanza)),
+		}
+	}
+
+	pub fn ns(self, ns: impl AsRef<str>) -> Self {
+		self.map(|stanza| stanza.set_ns(ns))
+	}
+
+	pub fn attr(self, name: impl AsRef<str>, value: impl AsRef<str>) -> Self {
+		self.map(|stanza| stanza.set_attribute(name, value))
+	}
+
+	pub fn id(self, id: impl AsRef<str>) -> Self {
+		self.map(|stanza| stanza.set_id(id))
+	}
+
+	pub fn typ(self, typ: impl AsRef<str>) -> Self {
+		self.map(|stanza| stanza.set_stanza_type(typ))
+	}
+
+	pub fn to(self, to: impl AsRef<str>) -> Self {
+		self.map(|stanza| stanza.set_to(to))
+	}
+
+	/// Sets the `from` attribute, it's not called `from()` to not shadow [From::from]
+	pub fn from_jid(self, from: impl AsRef<str>) -> Self {
+		self.map(|stanza| stanza.set_from(from))
+	}
+
+	/// Adds the text node child
+	pub fn text(self, text: impl AsRef<str>) -> Self {
+		self.map(|stanza| {
+			let mut child = Stanza::new();
+			child.set_text(text)?;
+			stanza.add_child(child)
+		})
+	}
+
+	/// Adds the `child` element, either a [Stanza] or another builder whose result is not validated
+	pub fn child(self, child: impl Into<StanzaBuilder>) -> Self {
+		let child = child.into().stanza;
+		self.map(|stanza| stanza.add_child(child?))
+	}
+
+	/// Returns the built stanza without the validation
+	pub fn build_unchecked(self) -> Result<Stanza> {
+		self.stanza
+	}
+
+	/// Returns the built stanza after checking that the `<iq/>`, `<message/>` and `<presence/>` are well-formed
+	///
+	/// The `<iq/>` must have the `id` and the `type`, the `get` and `set` ones must have exactly one payload element. The
+	/// `type` of `<message/>` and `<presence/>` must be one of the defined ones. The stanzas of type `error` must contain the
+	/// `<error/>` element. Other elements are not checked.
+	pub fn build(self) -> Result<Stanza, BuildStanzaError> {
+		let stanza = self.stanza?;
+		let allowed_types: &[&str] = match stanza.name() {
+			Some("iq") => {
+				stanza.id().ok_or(BuildStanzaError::MissingAttribute("id"))?;
+				stanza.stanza_type().ok_or(BuildStanzaError::MissingAttribute("type"))?;
+				&["get", "set", "result", "error"]
+			}
+			Some("message") => &["normal", "chat", "groupchat", "headline", "error"],
+			Some("presence") => &[
+				"unavailable",
+				"subscribe",
+				"subscribed",
+				"unsubscribe",
+				"unsubscribed",
+				"probe",
+				"error",
+			],
+			_ => return Ok(stanza),
+		};
+		match stanza.stanza_type() {
+			Some(typ) if !allowed_types.contains(&typ) => {
+				return Err(BuildStanzaError::InvalidType {
+					name: stanza.name().unwrap_or_default().to_owned(),
+					typ: typ.to_owned(),
+				})
+			}
+			Some("get" | "set") => {
+				let payloads = stanza.children().filter(|child| child.is_tag()).count();
+				if payloads != 1 {
+					return Err(BuildStanzaError::IqPayloads(payloads));
+				}
+			}
+			Some("error") if stanza.get_child_by_name("error").is_none() => return Err(BuildStanzaError::MissingError),
+			_ => {}
+		}
+		Ok(stanza)
+	}
+}
+
+impl From<Stanza> for StanzaBuilder {
+	#[inline]
+	fn from(stanza: Stanza) -> Self {
+		Self { stanza: Ok(stanza) }
+	}
+}
+
 impl<'st> From<Stanza> for StanzaRef<'st> {
 	#[inline]
 	fn from(s: Stanza) -> Self {
@@ -652,6 +1302,31 @@ impl Display for StanzaMutRef<'_> {
 	}
 }
 
+/// Child node of the stanza returned by [Stanza::nodes]
+#[derive(Debug)]
+pub enum Node<'st> {
+	Element(StanzaRef<'st>),
+	Text(&'st str),
+}
+
+impl<'st> Node<'st> {
+	#[inline]
+	pub fn as_element(&self) -> Option<&StanzaRef<'st>> {
+		match self {
+			Node::Element(element) => Some(element),
+			Node::Text(_) => None,
+		}
+	}
+
+	#[inline]
+	pub fn as_text(&self) -> Option<&'st str> {
+		match self {
+			Node::Element(_) => None,
+			Node::Text(text) => Some(text),
+		}
+	}
+}
+
 #[derive(Debug)]
 pub struct StanzaChildMutRef<'parent>(StanzaMutRef<'parent>);
 
@@ -693,6 +1368,113 @@ impl<'st> Iterator for ChildIteratorMut<'st> {
 	}
 }
 
+/// Size of the raw data chunk for [Stanza::set_text_from_reader], multiple of 3 so that base64-encoded chunks can be
+/// concatenated
+const TEXT_CHUNK_SIZE: usize = 3 * 16 * 1024;
+
+/// Reads from `r` until `buf` is full or EOF is reached, returns the number of bytes read
+fn read_full(r: &mut impl io::Read, buf: &mut [u8]) -> io::Result<usize> {
+	let mut len = 0;
+	while len < buf.len() {
+		match r.read(&mut buf[len..]) {
+			Ok(0) => break,
+			Ok(n) => len += n,
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+			Err(e) => return Err(e),
+		}
+	}
+	Ok(len)
+}
+
+unsafe fn raw_bytes<'s>(s: *const c_char) -> Option<&'s [u8]> {
+	s.as_ref().map(|s| CStr::from_ptr(s).to_bytes())
+}
+
+unsafe fn raw_attributes<'s>(stanza: *mut sys::xmpp_stanza_t) -> Vec<(&'s [u8], &'s [u8])> {
+	let count = sys::xmpp_stanza_get_attribute_count(stanza);
+	let mut arr = vec![ptr::null() as _; count as usize * 2];
+	sys::xmpp_stanza_get_attributes(stanza, arr.as_mut_ptr(), count * 2);
+	arr.chunks(2)
+		.filter_map(|pair| Some((raw_bytes(pair[0])?, raw_bytes(pair[1])?)))
+		.collect()
+}
+
+unsafe fn raw_size_estimate(stanza: *mut sys::xmpp_stanza_t) -> usize {
+	if let Some(text) = raw_bytes(sys::xmpp_stanza_get_text_ptr(stanza)) {
+		return text.len();
+	}
+	let name_len = raw_bytes(sys::xmpp_stanza_get_name(stanza)).map_or(0, <[u8]>::len);
+	// `<name` and either `/>` or `>` with `</name>`
+	let mut out = 1 + name_len;
+	for (key, val) in raw_attributes(stanza) {
+		// ` key="val"`
+		out += key.len() + val.len() + 4;
+	}
+	let mut child = sys::xmpp_stanza_get_children(stanza);
+	if child.is_null() {
+		return out + 2;
+	}
+	out += 1 + name_len + 3;
+	while !child.is_null() {
+		out += raw_size_estimate(child);
+		child = sys::xmpp_stanza_get_next(child);
+	}
+	out
+}
+
+/// Calls `cb` for the name, attribute keys and values and text of the `stanza` and all of its descendants
+fn walk_raw_strings(stanza: *mut sys::xmpp_stanza_t, cb: &mut impl FnMut(&[u8])) {
+	unsafe {
+		if let Some(text) = raw_bytes(sys::xmpp_stanza_get_text_ptr(stanza)) {
+			cb(text);
+		}
+		if let Some(name) = raw_bytes(sys::xmpp_stanza_get_name(stanza)) {
+			cb(name);
+		}
+		for (key, val) in raw_attributes(stanza) {
+			cb(key);
+			cb(val);
+		}
+		let mut child = sys::xmpp_stanza_get_children(stanza);
+		while !child.is_null() {
+			walk_raw_strings(child, cb);
+			child = sys::xmpp_stanza_get_next(child);
+		}
+	}
+}
+
+unsafe fn lossy_copy(stanza: *mut sys::xmpp_stanza_t) -> Result<Stanza> {
+	let mut out = Stanza::new();
+	if let Some(text) = raw_bytes(sys::xmpp_stanza_get_text_ptr(stanza)) {
+		out.set_text(String::from_utf8_lossy(text))?;
+		return Ok(out);
+	}
+	if let Some(name) = raw_bytes(sys::xmpp_stanza_get_name(stanza)) {
+		out.set_name(String::from_utf8_lossy(name))?;
+	}
+	for (key, val) in raw_attributes(stanza) {
+		out.set_attribute(String::from_utf8_lossy(key), String::from_utf8_lossy(val))?;
+	}
+	let mut child = sys::xmpp_stanza_get_children(stanza);
+	while !child.is_null() {
+		out.add_child(lossy_copy(child)?)?;
+		child = sys::xmpp_stanza_get_next(child);
+	}
+	Ok(out)
+}
+
+fn parse_value<T: str::FromStr>(name: &str, value: &str) -> Result<T, ParseAttrError<T::Err>> {
+	value.parse().map_err(|error| parse_attr_error(name, value, error))
+}
+
+fn parse_attr_error<E>(name: &str, value: &str, error: E) -> ParseAttrError<E> {
+	ParseAttrError {
+		name: name.to_owned(),
+		value: value.to_owned(),
+		error,
+	}
+}
+
 fn stanza_to_text<T, E>(stanza: *mut sys::xmpp_stanza_t, cb: impl FnOnce(&CStr) -> Result<T, E>) -> Result<T, E>
 where
 	E: From<Error>,
@@ -712,3 +1494,45 @@ where
 		cb(text)
 	})
 }
+
+fn stanza_to_text_canonical(stanza: &Stanza, out: &mut String) -> Result<()> {
+	fn escape(s: &str, attr: bool, out: &mut String) {
+		for c in s.chars() {
+			match c {
+				'&' => out.push_str("&amp;"),
+				'<' => out.push_str("&lt;"),
+				'>' if !attr => out.push_str("&gt;"),
+				'"' if attr => out.push_str("&quot;"),
+				'\t' if attr => out.push_str("&#x9;"),
+				'\n' if attr => out.push_str("&#xA;"),
+				'\r' => out.push_str("&#xD;"),
+				c => out.push(c),
+			}
+		}
+	}
+
+	if let Some(text) = stanza.text_ptr() {
+		escape(text, false, out);
+		return Ok(());
+	}
+	let name = stanza.name().ok_or(Error::InvalidOperation)?;
+	out.push('<');
+	out.push_str(name);
+	let mut attributes = stanza.attributes().into_iter().collect::<Vec<_>>();
+	attributes.sort_unstable_by_key(|&(name, _)| (name != "xmlns", !name.starts_with("xmlns:"), name));
+	for (name, value) in attributes {
+		out.push(' ');
+		out.push_str(name);
+		out.push_str("=\"");
+		escape(value, true, out);
+		out.push('"');
+	}
+	out.push('>');
+	for child in stanza.children() {
+		stanza_to_text_canonical(&child, out)?;
+	}
+	out.push_str("</");
+	out.push_str(name);
+	out.push('>');
+	Ok(())
+}
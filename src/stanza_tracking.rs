@@ -0,0 +1,107 @@
+//! Debugging aid tracking the [Stanza](crate::Stanza) wrappers to find ownership mistakes
+//!
+//! Enabled with the `stanza-tracking` feature (requires Rust 1.65). Every `Stanza`, `StanzaRef` and `StanzaMutRef` created
+//! from a raw pointer is recorded together with the backtrace of its creation. Owning wrappers that are still alive are
+//! reported as leak candidates, borrowing wrappers that were alive at the moment the underlying `xmpp_stanza_t` was freed
+//! are reported as use-after-free candidates. The report is logged through the context logger when an owned
+//! [Context](crate::Context) is dropped and is also available via [report()]. Tracking is slow, don't use it in
+//! production.
+
+#![allow(clippy::incompatible_msrv)]
+
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+
+/// Tracked stanza wrapper
+#[derive(Debug, Clone)]
+pub struct TrackedStanza {
+	/// Address of the underlying `xmpp_stanza_t`
+	pub ptr: usize,
+	/// Whether the wrapper owns a reference to the stanza
+	pub owned: bool,
+	/// Backtrace of the wrapper creation
+	pub backtrace: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+	/// Owning wrappers that are still alive
+	pub live: Vec<TrackedStanza>,
+	/// Borrowing wrappers that outlived the underlying stanza
+	pub dangling: Vec<TrackedStanza>,
+}
+
+impl Report {
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.live.is_empty() && self.dangling.is_empty()
+	}
+}
+
+#[derive(Default)]
+struct Registry {
+	live: HashMap<u64, TrackedStanza>,
+	dangling: Vec<TrackedStanza>,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static REGISTRY: Lazy<Mutex<Registry>> = Lazy::new(Default::default);
+
+/// Records a new wrapper, returns the id to pass to [untrack]
+pub(crate) fn track(ptr: *const sys::xmpp_stanza_t, owned: bool) -> u64 {
+	let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+	if let Ok(mut registry) = REGISTRY.lock() {
+		registry.live.insert(
+			id,
+			TrackedStanza {
+				ptr: ptr as usize,
+				owned,
+				backtrace: Backtrace::force_capture().to_string(),
+			},
+		);
+	}
+	id
+}
+
+/// Removes the dropped wrapper, `freed` tells whether dropping it has freed the underlying stanza
+pub(crate) fn untrack(id: u64, freed: bool) {
+	if let Ok(mut registry) = REGISTRY.lock() {
+		if let Some(stanza) = registry.live.remove(&id) {
+			if freed {
+				let dangling_ids = registry
+					.live
+					.iter()
+					.filter(|(_, other)| !other.owned && other.ptr == stanza.ptr)
+					.map(|(id, _)| *id)
+					.collect::<Vec<_>>();
+				for id in dangling_ids {
+					if let Some(dangling) = registry.live.remove(&id) {
+						registry.dangling.push(dangling);
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Returns the currently alive owning wrappers and all of the borrowing wrappers that have outlived their stanza so far
+pub fn report() -> Report {
+	REGISTRY
+		.lock()
+		.map(|registry| Report {
+			live: registry.live.values().filter(|stanza| stanza.owned).cloned().collect(),
+			dangling: registry.dangling.clone(),
+		})
+		.unwrap_or_default()
+}
+
+/// Clears the recorded use-after-free candidates, e.g. after they were reported
+pub fn clear_dangling() {
+	if let Ok(mut registry) = REGISTRY.lock() {
+		registry.dangling.clear();
+	}
+}
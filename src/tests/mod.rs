@@ -1,16 +1,20 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::process::Command;
+use std::rc::Rc;
 use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
-use std::{env, mem};
+use std::{env, io, mem};
 
 use matches::assert_matches;
 use names::Generator;
 
 use crate::*;
 
+mod server;
+
 #[test]
 fn examples() {
 	//	super::examples::bot_fn::main();
@@ -45,13 +49,649 @@ fn custom_logger() {
 			i.fetch_add(1, Ordering::Relaxed);
 		}));
 		let mut conn = Connection::new(ctx);
-		conn.set_jid("test-JID@127.50.60.70");
+		conn.set_jid("test-JID@127.50.60.70").unwrap();
 		let ctx = conn.connect_client(None, Some(1234), |_, _, _| {}).unwrap();
 		ctx.run_once(Duration::from_secs(1));
 	}
 	assert_eq!(i.load(Ordering::Relaxed), 5);
 }
 
+#[test]
+fn context_log() {
+	let lines = Mutex::new(vec![]);
+	{
+		let ctx = Context::new(Logger::new(|level, area, msg| {
+			lines.lock().unwrap().push(format!("{:?} {}: {}", level, area, msg));
+		}));
+		ctx.log_error("test", "error %s");
+		ctx.log_warn("test", "warn");
+		ctx.log_info("test", "info");
+		ctx.log_debug("test", "debug");
+		ctx.log(LogLevel::XMPP_LEVEL_INFO, "area", "message");
+	}
+	assert_eq!(
+		lines.into_inner().unwrap(),
+		[
+			"XMPP_LEVEL_ERROR test: error %s",
+			"XMPP_LEVEL_WARN test: warn",
+			"XMPP_LEVEL_INFO test: info",
+			"XMPP_LEVEL_DEBUG test: debug",
+			"XMPP_LEVEL_INFO area: message",
+		]
+	);
+}
+
+#[test]
+fn traffic_log_policy() {
+	let lines = Arc::new(Mutex::new(vec![]));
+	let ctx = Context::new(Logger::new({
+		let lines = Arc::clone(&lines);
+		move |_, _, msg| {
+			if msg.starts_with("SENT") {
+				lines.lock().unwrap().push(msg.to_owned());
+			}
+		}
+	}));
+	let mut conn = Connection::new(ctx);
+	conn.send_raw("<auth>secret</auth>");
+	conn.set_traffic_log_policy(TrafficLogPolicy::Metadata);
+	conn.send_raw("<auth>secret</auth>");
+	conn.set_traffic_log_policy(TrafficLogPolicy::Redacted(Box::new(|data| {
+		format!("{} redacted bytes", data.len())
+	})));
+	conn.send_raw("<auth>secret</auth>");
+	conn.set_traffic_log_policy(TrafficLogPolicy::Full);
+	conn.send_raw("<presence/>");
+	assert_eq!(
+		*lines.lock().unwrap(),
+		["SENT: 19 bytes", "SENT: 19 redacted bytes", "SENT: <presence/>"]
+	);
+}
+
+#[test]
+fn runtime_version() {
+	let version = crate::runtime_version();
+	assert_eq!(env!("CARGO_PKG_VERSION"), version.wrapper_version);
+	let (major, minor) = version
+		.linked_major_minor
+		.expect("Cannot detect the linked libstrophe version");
+	assert!(version_check(major, minor));
+	assert!(version_check(major, minor + 1));
+	assert!(minor == 0 || !version_check(major, minor - 1));
+	assert!(!version_check(major + 1, minor));
+	#[cfg(feature = "libstrophe-0_12_0")]
+	{
+		assert_eq!((0, 12), version.compiled_major_minor);
+		assert!(version.compiled_features.contains(&"libstrophe-0_12_0"));
+	}
+	assert!(version.is_compatible());
+	assert!(version
+		.to_string()
+		.starts_with(&format!("libstrophe-rs {}", version.wrapper_version)));
+}
+
+#[test]
+fn logger_filter() {
+	let received = Arc::new(Mutex::new(vec![]));
+	let logger = Logger::new_with_filter(|level| level != LogLevel::XMPP_LEVEL_DEBUG, {
+		let received = Arc::clone(&received);
+		move |_, _, msg| received.lock().unwrap().push(msg.to_owned())
+	});
+	assert!(!logger.enabled(LogLevel::XMPP_LEVEL_DEBUG));
+	assert!(logger.enabled(LogLevel::XMPP_LEVEL_INFO));
+	logger.log(LogLevel::XMPP_LEVEL_DEBUG, "test", "hidden");
+	logger.log(LogLevel::XMPP_LEVEL_INFO, "test", "shown");
+	let ctx = Context::new(logger);
+	ctx.log_debug("test", "hidden");
+	ctx.log_warn("test", "routed");
+	assert_eq!(vec!["shown".to_owned(), "routed".to_owned()], *received.lock().unwrap());
+	assert!(!Logger::new_null().enabled(LogLevel::XMPP_LEVEL_ERROR));
+}
+
+#[test]
+fn run_without_connections() {
+	use std::time::Instant;
+
+	let warnings = Arc::new(Mutex::new(vec![]));
+	let ctx = Context::new(Logger::new({
+		let warnings = Arc::clone(&warnings);
+		move |level, _, msg| {
+			if level == LogLevel::XMPP_LEVEL_WARN {
+				warnings.lock().unwrap().push(msg.to_owned())
+			}
+		}
+	}));
+	assert!(!ctx.has_connections());
+	assert!(unsafe { Context::from_ref(ctx.as_ptr()) }.has_connections());
+	ctx.schedule_at(Instant::now(), |ctx| ctx.stop());
+	ctx.run();
+	let warnings = warnings.lock().unwrap();
+	assert_eq!(1, warnings.len());
+	assert!(warnings[0].contains("without any connections"));
+}
+
+#[test]
+fn run_async() {
+	use std::future::Future;
+	use std::pin::Pin;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::task::{self, Wake, Waker};
+	use std::thread;
+	use std::time::Instant;
+
+	struct CountingWaker(AtomicUsize, thread::Thread);
+
+	impl Wake for CountingWaker {
+		fn wake(self: Arc<Self>) {
+			self.0.fetch_add(1, Ordering::Relaxed);
+			self.1.unpark();
+		}
+	}
+
+	let ctx = Context::new_with_null_logger();
+	ctx.run_once_nonblocking();
+	let shutdown = Arc::new(AtomicUsize::new(0));
+	ctx.on_shutdown({
+		let shutdown = Arc::clone(&shutdown);
+		move |_, _| {
+			shutdown.fetch_add(1, Ordering::Relaxed);
+		}
+	});
+	let start = Instant::now();
+	ctx.schedule_at(start + Duration::from_millis(50), |ctx| ctx.stop());
+	let counting = Arc::new(CountingWaker(AtomicUsize::new(0), thread::current()));
+	let waker = Waker::from(Arc::clone(&counting));
+	let mut cx = task::Context::from_waker(&waker);
+	let mut fut = ctx.run_async().poll_interval(Duration::from_millis(5));
+	while Pin::new(&mut fut).poll(&mut cx).is_pending() {
+		thread::park_timeout(Duration::from_secs(1));
+		assert!(start.elapsed() < Duration::from_secs(5));
+	}
+	assert!(start.elapsed() >= Duration::from_millis(50));
+	assert!(counting.0.load(Ordering::Relaxed) > 0);
+	assert_eq!(1, shutdown.load(Ordering::Relaxed));
+	assert!(Pin::new(&mut fut).poll(&mut cx).is_ready());
+}
+
+#[test]
+fn idle_callback() {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::time::Instant;
+
+	let mut ctx = Context::new_with_null_logger();
+	ctx.set_timeout(Duration::from_secs(10));
+	assert!(!ctx.clear_idle_callback());
+	let calls = Arc::new(AtomicUsize::new(0));
+	ctx.set_idle_callback(
+		{
+			let calls = Arc::clone(&calls);
+			move |_| {
+				calls.fetch_add(1, Ordering::Relaxed);
+			}
+		},
+		Duration::from_millis(10),
+	);
+	let start = Instant::now();
+	ctx.schedule_at(start + Duration::from_millis(100), |ctx| ctx.stop());
+	ctx.run();
+	assert!(start.elapsed() < Duration::from_secs(5));
+	assert!(calls.load(Ordering::Relaxed) >= 3);
+	assert!(ctx.clear_idle_callback());
+	assert!(!ctx.clear_idle_callback());
+}
+
+#[test]
+fn connection_logger() {
+	let collect = |lines: &Arc<Mutex<Vec<String>>>| {
+		let lines = Arc::clone(lines);
+		move |_: LogLevel, _: &str, msg: &str| {
+			if msg.starts_with("SENT") {
+				lines.lock().unwrap().push(msg.to_owned());
+			}
+		}
+	};
+	let ctx_lines = Arc::new(Mutex::new(vec![]));
+	let conn_lines = Arc::new(Mutex::new(vec![]));
+	let mut conn = Connection::new_with_logger(
+		Context::new(Logger::new(collect(&ctx_lines))),
+		Logger::new(collect(&conn_lines)),
+	);
+	assert!(conn.has_logger());
+	conn.set_traffic_log_policy(TrafficLogPolicy::Full);
+	conn.send_raw("<presence/>");
+	conn.clear_logger();
+	assert!(!conn.has_logger());
+	conn.send_raw("<iq/>");
+	assert_eq!(*conn_lines.lock().unwrap(), ["SENT: <presence/>"]);
+	assert_eq!(*ctx_lines.lock().unwrap(), ["SENT: <iq/>"]);
+}
+
+#[test]
+fn strict_send() {
+	let mut component = Connection::new(Context::new_with_null_logger());
+	component.set_jid("component.example.com").unwrap();
+	let mut msg = Stanza::new_message(Some("chat"), Some("m1"), Some("user@example.com"));
+	// not connected yet, the session type is unknown
+	assert_eq!(Ok(()), component.validate_outgoing(&msg));
+	let _ = component.try_connect_component("127.0.0.1", 1, |_, _, _| {});
+	assert_eq!(Err(SendValidationError::MissingFrom), component.validate_outgoing(&msg));
+	msg.set_from("bot@other.example.com").unwrap();
+	assert_eq!(
+		Err(SendValidationError::ForeignFrom {
+			from: "bot@other.example.com".to_owned(),
+			domain: "component.example.com".to_owned()
+		}),
+		component.validate_outgoing(&msg)
+	);
+	msg.set_from("bot@component.example.com").unwrap();
+	assert_eq!(Ok(()), component.validate_outgoing(&msg));
+	let presence = Stanza::new_presence();
+	assert_eq!(Err(SendValidationError::MissingTo), component.validate_outgoing(&presence));
+	component.set_strict_send(true);
+	assert_eq!(Err(SendValidationError::MissingTo), component.try_send(&presence));
+	let mut r = Stanza::new();
+	r.set_name("r").unwrap();
+	assert_eq!(Ok(()), component.try_send(&r));
+
+	let mut client = Connection::new(Context::new_with_null_logger());
+	client.set_jid("user@example.com/desktop").unwrap();
+	let _ = client.try_connect_client(Some("127.0.0.1"), 1, |_, _, _| {});
+	let mut msg = Stanza::new_message(Some("chat"), Some("m2"), Some("friend@example.com"));
+	assert_eq!(Ok(()), client.validate_outgoing(&msg));
+	msg.set_from("User@example.com").unwrap();
+	assert_eq!(Ok(()), client.validate_outgoing(&msg));
+	msg.set_from("user@example.com/phone").unwrap();
+	assert!(matches!(
+		client.validate_outgoing(&msg),
+		Err(SendValidationError::SpoofedFrom { .. })
+	));
+	msg.set_from("admin@example.com").unwrap();
+	assert!(matches!(
+		client.validate_outgoing(&msg),
+		Err(SendValidationError::SpoofedFrom { .. })
+	));
+	msg.set_from("not a jid@").unwrap();
+	assert_eq!(
+		Err(SendValidationError::InvalidFrom("not a jid@".to_owned())),
+		client.validate_outgoing(&msg)
+	);
+}
+
+#[test]
+fn tls_session_reused() {
+	let conn = Connection::new(Context::new_with_null_logger());
+	assert_eq!(None, conn.tls_session_reused());
+}
+
+#[test]
+fn byte_counters() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.send_raw("<presence/>");
+	assert_eq!(0, conn.bytes_sent());
+	conn.enable_byte_counters();
+	conn.send_raw("<presence/>");
+	assert_eq!(11, conn.bytes_sent());
+	let mut iq = Stanza::new_iq(Some("get"), Some("ping1"));
+	iq.set_attribute("note", "a & b").unwrap();
+	conn.send(&iq);
+	// the serialized length, not the estimate that counts the escaped characters as one byte
+	assert_ne!(iq.to_text().unwrap().len(), iq.byte_size_estimate());
+	assert_eq!(11 + iq.to_text().unwrap().len() as u64, conn.bytes_sent());
+	assert_eq!(0, conn.bytes_received());
+	assert!(conn.throughput_handler_add(|_, _, _| {}, Duration::from_secs(10)).is_some());
+	conn.disable_byte_counters();
+	assert_eq!(0, conn.bytes_sent());
+
+	let throughput = Throughput {
+		sent: 1000,
+		received: 500,
+		period: Duration::from_millis(500),
+	};
+	assert_eq!(2000., throughput.sent_per_sec());
+	assert_eq!(1000., throughput.received_per_sec());
+	assert_eq!(
+		0.,
+		Throughput {
+			period: Duration::ZERO,
+			..throughput
+		}
+		.sent_per_sec()
+	);
+}
+
+#[test]
+fn outbound_queue() {
+	let iq = Stanza::new_iq(Some("get"), Some("ping1"));
+	let presence = Stanza::new_presence();
+	let msg = Stanza::new_message(Some("chat"), Some("m1"), Some("user@example.com"));
+	assert_eq!(SendPriority::Control, SendPriority::of(&iq));
+	assert_eq!(SendPriority::Control, SendPriority::of(&presence));
+	assert_eq!(SendPriority::Chat, SendPriority::of(&msg));
+	assert!(SendPriority::Control < SendPriority::Bulk);
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.send_with_priority(&msg, SendPriority::Chat);
+	assert_eq!(0, conn.outbound_queue_len(SendPriority::Chat));
+	conn.enable_outbound_queue(1024, Duration::from_millis(10));
+	conn.send_with_priority(&msg, SendPriority::Bulk);
+	conn.send_with_priority(&msg, SendPriority::Bulk);
+	conn.send_with_priority(&iq, SendPriority::of(&iq));
+	conn.send_with_priority(&msg, SendPriority::of(&msg));
+	assert_eq!(1, conn.outbound_queue_len(SendPriority::Control));
+	assert_eq!(1, conn.outbound_queue_len(SendPriority::Chat));
+	assert_eq!(2, conn.outbound_queue_len(SendPriority::Bulk));
+	conn.enable_outbound_queue(2048, Duration::from_millis(10));
+	assert_eq!(2, conn.outbound_queue_len(SendPriority::Bulk));
+	conn.disable_outbound_queue();
+	assert_eq!(0, conn.outbound_queue_len(SendPriority::Control));
+	assert_eq!(0, conn.outbound_queue_len(SendPriority::Bulk));
+	// the queue is run by a single timer
+	conn.enable_outbound_queue(1024, Duration::from_millis(10));
+	conn.enable_outbound_queue(1024, Duration::from_millis(20));
+	assert_eq!(
+		1,
+		conn.handlers_remove_where(|info| matches!(info, HandlerInfo::Timed { .. }))
+	);
+}
+
+#[test]
+fn rx_tap() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	let received = Arc::new(Mutex::new(Vec::new()));
+	conn.set_rx_tap({
+		let received = Arc::clone(&received);
+		move |data| received.lock().unwrap().extend_from_slice(data)
+	});
+	conn.set_rx_tap(|_| {});
+	// the tap is not a stanza handler and is not affected by the handler management
+	assert_eq!(0, conn.handlers_remove_where(|_| true));
+	conn.clear_rx_tap();
+	conn.clear_rx_tap();
+	assert!(received.lock().unwrap().is_empty());
+}
+
+#[test]
+#[cfg(feature = "libstrophe-0_10_0")]
+fn xmpp_module() {
+	struct Ping;
+
+	impl XmppModule for Ping {
+		fn namespaces(&self) -> &[&str] {
+			&["urn:xmpp:ping"]
+		}
+
+		fn on_stanza(&mut self, _ctx: &Context, _conn: &mut Connection, _stanza: &Stanza) {}
+	}
+
+	let ping = Stanza::from_str("<iq type='get' id='1'><ping xmlns='urn:xmpp:ping'/></iq>");
+	assert!(module::matches_ns(&ping, Ping.namespaces()));
+	assert!(!module::matches_ns(&Stanza::new_presence(), Ping.namespaces()));
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	let first = conn.register_module(Box::new(Ping));
+	let second = conn.register_module(Box::new(Ping));
+	assert_ne!(first, second);
+	assert!(conn.unregister_module(first));
+	assert!(!conn.unregister_module(first));
+	// all modules share a single stanza handler
+	assert_eq!(1, conn.handlers_remove_where(|_| true));
+}
+
+#[test]
+#[cfg(feature = "libstrophe-0_10_0")]
+fn encryption_plugin() {
+	struct Reverse {
+		encrypted: Arc<Mutex<usize>>,
+	}
+
+	impl EncryptionPlugin for Reverse {
+		fn namespace(&self) -> &str {
+			"urn:example:reverse"
+		}
+
+		fn encrypt(&mut self, message: &Stanza) -> Result<Option<Stanza>, EncryptionError> {
+			let body = match message.body() {
+				Some(body) => body,
+				None => return Ok(None),
+			};
+			*self.encrypted.lock().unwrap() += 1;
+			let mut payload = Stanza::new();
+			payload.set_name("reversed")?;
+			payload.set_ns(self.namespace())?;
+			let mut text = Stanza::new();
+			text.set_text(body.chars().rev().collect::<String>())?;
+			payload.add_child(text)?;
+			Ok(Some(encryption::frame(
+				message,
+				payload,
+				self.namespace(),
+				None,
+				Some("encrypted"),
+			)?))
+		}
+
+		fn decrypt(&mut self, _message: &Stanza, payload: &Stanza) -> Result<Vec<Stanza>, EncryptionError> {
+			let text = payload
+				.text()
+				.ok_or_else(|| EncryptionError::Failed("empty payload".to_owned()))?;
+			let mut body = Stanza::new();
+			body.set_name("body")?;
+			let mut text_node = Stanza::new();
+			text_node.set_text(text.chars().rev().collect::<String>())?;
+			body.add_child(text_node)?;
+			Ok(vec![body])
+		}
+	}
+
+	let mut msg = Stanza::new_message(Some("chat"), Some("e1"), Some("juliet@example.com"));
+	msg.set_body("secret").unwrap();
+	let mut plugin = Reverse {
+		encrypted: Arc::new(Mutex::new(0)),
+	};
+	let encrypted = plugin.encrypt(&msg).unwrap().unwrap();
+	assert_eq!(Some("juliet@example.com"), encrypted.to());
+	assert_eq!(Some("encrypted".to_owned()), encrypted.body());
+	let payload = encryption::payload(&encrypted, "urn:example:reverse").unwrap();
+	assert_eq!(Some("terces".to_owned()), payload.text());
+	assert!(encrypted.get_child_by_name_and_ns("encryption", encryption::NS_EME).is_some());
+	let content = plugin.decrypt(&encrypted, &payload).unwrap();
+	let decrypted = encryption::unframe(&encrypted, "urn:example:reverse", content).unwrap();
+	assert_eq!(Some("secret".to_owned()), decrypted.body());
+	assert!(encryption::payload(&decrypted, "urn:example:reverse").is_none());
+	assert!(decrypted.get_child_by_name_and_ns("encryption", encryption::NS_EME).is_none());
+
+	#[cfg(feature = "libstrophe-0_12_0")]
+	{
+		let request = encryption::publish_request("urn:example:devices", Some("current"), Stanza::new_presence(), "pub1").unwrap();
+		assert_eq!(
+			Some("urn:example:devices"),
+			request
+				.get_child_by_path(&["pubsub", "publish"])
+				.unwrap()
+				.get_attribute("node")
+		);
+	}
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert_eq!(
+		Err(EncryptionError::Stanza(Error::InvalidOperation)),
+		conn.send_encrypted(&msg)
+	);
+	let counter = Arc::new(Mutex::new(0));
+	conn.set_encryption_plugin(Reverse {
+		encrypted: Arc::clone(&counter),
+	});
+	conn.send_encrypted(&msg).unwrap();
+	conn.send(&msg);
+	conn.send(&Stanza::new_presence());
+	assert_eq!(2, *counter.lock().unwrap());
+	assert_eq!(Ok(false), encryption::publish_device_list(&mut conn));
+	conn.clear_encryption_plugin();
+	conn.send(&msg);
+	assert_eq!(2, *counter.lock().unwrap());
+
+	struct NoSession;
+
+	impl EncryptionPlugin for NoSession {
+		fn namespace(&self) -> &str {
+			"urn:example:none"
+		}
+
+		fn encrypt(&mut self, _message: &Stanza) -> Result<Option<Stanza>, EncryptionError> {
+			Err(EncryptionError::Failed("no session".to_owned()))
+		}
+
+		fn decrypt(&mut self, _message: &Stanza, _payload: &Stanza) -> Result<Vec<Stanza>, EncryptionError> {
+			Ok(vec![])
+		}
+	}
+	conn.set_encryption_plugin(NoSession);
+	assert_eq!(
+		Err(SendValidationError::Encryption(EncryptionError::Failed(
+			"no session".to_owned()
+		))),
+		conn.try_send(&msg)
+	);
+	assert_eq!(Ok(()), conn.try_send(&Stanza::new_presence()));
+}
+
+#[test]
+#[cfg(feature = "libstrophe-0_11_0")]
+fn cert_expiry_warning() {
+	use std::time::UNIX_EPOCH;
+
+	let expected = UNIX_EPOCH + Duration::from_secs(1_741_003_200);
+	assert_eq!(Ok(expected), datetime::parse_cert_time("Mar  3 12:00:00 2025 GMT"));
+	assert_eq!(Ok(expected), datetime::parse_cert_time("Mar 03 12:00:00 2025 GMT"));
+	assert_eq!(Ok(expected), datetime::parse_cert_time("2025-03-03T12:00:00Z"));
+	assert_eq!(
+		Ok(expected + Duration::from_millis(500)),
+		datetime::parse_cert_time("Mar  3 12:00:00.5 2025 GMT")
+	);
+	assert!(datetime::parse_cert_time("Foo  3 12:00:00 2025 GMT").is_err());
+	assert!(datetime::parse_cert_time("Feb 30 12:00:00 2025 GMT").is_err());
+	assert!(datetime::parse_cert_time("Mar  3 12:00:00 2025 GMT extra").is_err());
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.set_cert_expiry_warning(Duration::from_secs(14 * 86400), |_, _| {});
+	conn.clear_cert_expiry_warning();
+}
+
+#[test]
+fn handlers_remove_where() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn
+		.handler_add(|_, _, _| HandlerResult::KeepHandler, Some("urn:xmpp:ping"), Some("iq"), None)
+		.unwrap();
+	conn
+		.handler_add(|_, _, _| HandlerResult::KeepHandler, Some("urn:xmpp:ping"), None, Some("get"))
+		.unwrap();
+	conn
+		.handler_add(
+			|_, _, _| HandlerResult::KeepHandler,
+			Some("jabber:iq:roster"),
+			Some("iq"),
+			None,
+		)
+		.unwrap();
+	conn.id_handler_add(|_, _, _| HandlerResult::KeepHandler, "ping1").unwrap();
+	conn
+		.timed_handler_add(|_, _| HandlerResult::KeepHandler, Duration::from_secs(30))
+		.unwrap();
+	conn
+		.timed_handler_add(|_, _| HandlerResult::KeepHandler, Duration::from_secs(60))
+		.unwrap();
+
+	let mut seen = vec![];
+	assert_eq!(
+		0,
+		conn.handlers_remove_where(|info| {
+			seen.push(format!("{:?}", info));
+			false
+		})
+	);
+	assert_eq!(6, seen.len());
+	assert_eq!(
+		2,
+		conn.handlers_remove_where(|info| matches!(
+			info,
+			HandlerInfo::Stanza {
+				ns: Some("urn:xmpp:ping"),
+				..
+			}
+		))
+	);
+	assert_eq!(
+		2,
+		conn.handlers_remove_where(|info| {
+			matches!(info, HandlerInfo::Id { .. })
+				|| *info
+					== HandlerInfo::Timed {
+						period: Duration::from_secs(30),
+					}
+		})
+	);
+	let mut left = vec![];
+	assert_eq!(
+		2,
+		conn.handlers_remove_where(|info| {
+			left.push(format!("{:?}", info));
+			true
+		})
+	);
+	assert_eq!(
+		vec![
+			r#"Stanza { ns: Some("jabber:iq:roster"), name: Some("iq"), typ: None }"#,
+			"Timed { period: 60s }",
+		],
+		left
+	);
+	assert_eq!(0, conn.handlers_remove_where(|_| true));
+}
+
+#[test]
+fn connection_token() {
+	let conn1 = Connection::new(Context::new_with_null_logger());
+	let conn2 = Connection::new(Context::new_with_null_logger());
+	assert_eq!(conn1.token(), conn1.token());
+	assert_ne!(conn1.token(), conn2.token());
+	let mut tokens = HashMap::new();
+	tokens.insert(conn1.token(), 1);
+	tokens.insert(conn2.token(), 2);
+	assert_eq!(Some(&2), tokens.get(&conn2.token()));
+	assert_eq!(None, Stanza::new_presence().connection_ptr_token());
+}
+
+#[test]
+#[cfg(feature = "presence")]
+fn presence_addressing() {
+	use xep::presence::{self, PresenceType};
+
+	let directed = presence::directed("room@muc.example.com/nick", PresenceType::Available).unwrap();
+	assert_eq!(Some("room@muc.example.com/nick"), directed.to());
+	assert_eq!(None, directed.stanza_type());
+	let leave = presence::directed("room@muc.example.com/nick", PresenceType::Unavailable).unwrap();
+	assert_eq!(Some("room@muc.example.com/nick"), leave.to());
+	assert_eq!(Some("unavailable"), leave.stanza_type());
+	let probe = presence::probe("user@example.com/phone").unwrap();
+	assert_eq!(Some("user@example.com"), probe.to());
+	assert_eq!(Some("probe"), probe.stanza_type());
+	let subscribe = presence::directed("user@example.com/phone", PresenceType::Subscribe).unwrap();
+	assert_eq!(Some("user@example.com"), subscribe.to());
+	assert!(presence::probe("@example.com").is_err());
+
+	let unavailable = presence::unavailable(Some("Gone fishing")).unwrap();
+	assert_eq!(None, unavailable.to());
+	assert_eq!(
+		Some("Gone fishing".to_owned()),
+		unavailable.get_child_by_name("status").and_then(|status| status.text())
+	);
+	assert!(presence::unavailable(None).unwrap().get_first_child().is_none());
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.disconnect_graceful(None).unwrap();
+}
+
 #[test]
 fn conn_client_wo_jid() {
 	let conn = Connection::new(Context::new_with_null_logger());
@@ -75,7 +715,7 @@ fn conn_client() {
 	// ref closure
 	{
 		let mut conn = Connection::new(Context::new_with_null_logger());
-		conn.set_jid("test-JID@127.50.60.70");
+		conn.set_jid("test-JID@127.50.60.70").unwrap();
 		let ctx = conn.connect_client(None, None, conn_handler).unwrap();
 		ctx.run();
 	}
@@ -83,12 +723,81 @@ fn conn_client() {
 	// own closure
 	{
 		let mut conn = Connection::new(Context::new_with_null_logger());
-		conn.set_jid("test-JID@127.50.60.70");
+		conn.set_jid("test-JID@127.50.60.70").unwrap();
 		let ctx = conn.connect_client(None, None, conn_handler).unwrap();
 		ctx.run();
 	}
 }
 
+#[test]
+fn conn_client_try_connect() {
+	fn connect(conn: &mut Connection) -> Result<()> {
+		conn.try_connect_client(None, None, |ctx, _, _| ctx.stop())?;
+		Ok(())
+	}
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert_eq!(connect(&mut conn), Err(Error::InvalidOperation));
+	// the same connection can be retried after fixing the configuration
+	conn.set_jid("test-JID@127.50.60.70").unwrap();
+	connect(&mut conn).unwrap();
+	let ctx = conn.into_context();
+	ctx.run();
+}
+
+#[test]
+fn conn_stream_error_handler() {
+	let fired = Arc::new(AtomicU16::new(0));
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.set_jid("test-JID@127.50.60.70").unwrap();
+	conn.set_stream_error_handler(Some({
+		let fired = Arc::clone(&fired);
+		move |_: &Context, _: &mut Connection, _: &StreamError| {
+			fired.fetch_add(1, Ordering::Relaxed);
+		}
+	}));
+	let ctx = conn
+		.connect_client(None, None, |ctx, _, event| {
+			// plain connection failure doesn't carry a stream error
+			assert_matches!(event, ConnectionEvent::Disconnect(_));
+			ctx.stop();
+		})
+		.unwrap();
+	ctx.run();
+	assert_eq!(0, fired.load(Ordering::Relaxed));
+}
+
+#[test]
+#[cfg(feature = "libstrophe-0_10_0")]
+fn stream_error_see_other_host() {
+	let see_other_host = |target: &str| {
+		let stanza = Stanza::from_str(format!(
+			"<stream:error xmlns:stream='http://etherx.jabber.org/streams'>\
+			<see-other-host xmlns='urn:ietf:params:xml:ns:xmpp-streams'>{}</see-other-host></stream:error>",
+			target
+		));
+		StreamError {
+			typ: ErrorType::XMPP_SE_SEE_OTHER_HOST,
+			text: None,
+			stanza: stanza.into(),
+		}
+		.see_other_host()
+	};
+	assert_eq!(Some(("example.net".to_string(), None)), see_other_host("example.net"));
+	assert_eq!(
+		Some(("example.net".to_string(), Some(5223))),
+		see_other_host(" example.net:5223 ")
+	);
+	assert_eq!(
+		Some(("2001:db8::1".to_string(), Some(5222))),
+		see_other_host("[2001:db8::1]:5222")
+	);
+	assert_eq!(Some(("2001:db8::1".to_string(), None)), see_other_host("[2001:db8::1]"));
+	assert_eq!(Some(("2001:db8::1".to_string(), None)), see_other_host("2001:db8::1"));
+	assert_eq!(None, see_other_host("example.net:port"));
+	assert_eq!(None, see_other_host(""));
+}
+
 #[test]
 fn conn_raw() {
 	let conn_handler = |ctx: &Context, _: &mut Connection, event: ConnectionEvent| {
@@ -99,7 +808,7 @@ fn conn_raw() {
 	// ref closure
 	{
 		let mut conn = Connection::new(Context::new_with_null_logger());
-		conn.set_jid("test-JID@127.50.60.70");
+		conn.set_jid("test-JID@127.50.60.70").unwrap();
 		let ctx = conn.connect_raw(None, Some(1234), conn_handler).unwrap();
 		ctx.run();
 	}
@@ -107,7 +816,7 @@ fn conn_raw() {
 	// own closure
 	{
 		let mut conn = Connection::new(Context::new_with_null_logger());
-		conn.set_jid("test-JID@127.50.60.70");
+		conn.set_jid("test-JID@127.50.60.70").unwrap();
 		let ctx = conn.connect_raw(None, Some(1234), conn_handler).unwrap();
 		ctx.run();
 	}
@@ -142,13 +851,1512 @@ fn stanza_handler() {
 }
 
 #[test]
-fn id_handler() {
-	let id_handler = |_: &Context, _: &mut Connection, _: &Stanza| HandlerResult::RemoveHandler;
-	let ctx = Context::new_with_null_logger();
-	let mut conn = Connection::new(ctx);
-	let h = conn.id_handler_add(&id_handler, "test").expect("Can't add id handler");
-	assert_matches!(conn.id_handler_add(&id_handler, "test"), None);
-	conn.id_handler_delete(h);
+fn id_handler() {
+	let id_handler = |_: &Context, _: &mut Connection, _: &Stanza| HandlerResult::RemoveHandler;
+	let ctx = Context::new_with_null_logger();
+	let mut conn = Connection::new(ctx);
+	let h = conn.id_handler_add(&id_handler, "test").expect("Can't add id handler");
+	assert_matches!(conn.id_handler_add(&id_handler, "test"), None);
+	conn.id_handler_delete(h);
+}
+
+#[test]
+fn connection_flags() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.set_flags(ConnectionFlags::TRUST_TLS).unwrap();
+	conn.add_flags(ConnectionFlags::MANDATORY_TLS).unwrap();
+	assert!(conn.tls_trusted());
+	assert!(conn.tls_mandatory());
+	assert!(!conn.tls_disabled());
+	assert!(!conn.legacy_ssl());
+	conn.remove_flags(ConnectionFlags::TRUST_TLS).unwrap();
+	assert_eq!(ConnectionFlags::MANDATORY_TLS.bits(), conn.flags().bits());
+	assert_eq!(ConnectionFlags::MANDATORY_TLS.bits(), conn.flags_raw());
+	#[cfg(feature = "libstrophe-0_12_0")]
+	{
+		conn.add_flags(ConnectionFlags::DISABLE_SM).unwrap();
+		assert!(conn.sm_disabled());
+		assert!(conn.tls_mandatory());
+	}
+}
+
+#[test]
+fn dispatch_ctx() {
+	let mut ctx = Context::new_with_null_logger();
+	assert_eq!(Duration::from_millis(1000), ctx.timeout());
+	ctx.set_timeout(Duration::from_secs(60));
+	assert_eq!(Duration::from_secs(60), ctx.timeout());
+	let mut conn = Connection::new(ctx);
+	let handler = conn
+		.dispatch_handler_add(
+			|ctx, _conn, _stanza| {
+				assert_eq!(Duration::from_secs(60), ctx.budget());
+				assert!(!ctx.yield_hint());
+				assert!(ctx.remaining_time() <= ctx.budget());
+				HandlerResult::RemoveHandler
+			},
+			None,
+			Some("message"),
+			None,
+		)
+		.expect("Cannot add handler");
+	conn.handler_delete(handler);
+}
+
+#[test]
+fn handler_error_hook() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.set_handler_error_hook(Some(|_: &Context, _: &mut Connection, kind, err: HandlerError| {
+		panic!("Unexpected {:?} handler error: {}", kind, err)
+	}));
+	let handler = conn
+		.try_handler_add(
+			|_, _, stanza| match stanza.id() {
+				Some(_) => Ok(HandlerResult::KeepHandler),
+				None => Err("missing id"),
+			},
+			None,
+			Some("message"),
+			None,
+		)
+		.expect("Cannot add handler");
+	conn.handler_delete(handler);
+	let handler = conn
+		.try_id_handler_add(|_, _, _| Err(Error::InvalidOperation), "id1")
+		.expect("Cannot add handler");
+	conn.id_handler_delete(handler);
+	let handler = conn
+		.try_timed_handler_add(
+			|_, _| "1".parse::<u8>().map(|_| HandlerResult::RemoveHandler),
+			Duration::from_secs(1),
+		)
+		.expect("Cannot add handler");
+	conn.timed_handler_delete(handler);
+	conn.set_handler_error_hook(None::<fn(&Context, &mut Connection, HandlerKind, HandlerError)>);
+}
+
+#[test]
+fn slow_handler_hook() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.set_handler_budget(Some(Duration::from_millis(50)));
+	conn.set_slow_handler_hook(Some(
+		|_: &Context, _: &mut Connection, info: &HandlerInfo, elapsed: Duration| panic!("{:?} took {:?}", info, elapsed),
+	));
+	assert!(format!("{:?}", conn).contains("handler_budget: Some(50ms)"));
+	conn.set_slow_handler_hook(None::<fn(&Context, &mut Connection, &HandlerInfo, Duration)>);
+	conn.set_handler_budget(None);
+}
+
+#[test]
+fn spawn_deferred() {
+	let ctx = Context::new_with_null_logger();
+	let order = Arc::new(Mutex::new(Vec::new()));
+	ctx.spawn_deferred({
+		let order = Arc::clone(&order);
+		move |ctx| {
+			order.lock().unwrap().push(1);
+			let order = Arc::clone(&order);
+			ctx.spawn_deferred(move |_| order.lock().unwrap().push(3));
+		}
+	});
+	ctx.spawn_deferred({
+		let order = Arc::clone(&order);
+		move |_| order.lock().unwrap().push(2)
+	});
+	assert!(order.lock().unwrap().is_empty());
+	ctx.run_once(Duration::from_millis(1));
+	assert_eq!(vec![1, 2], *order.lock().unwrap());
+	ctx.run_once(Duration::from_millis(1));
+	assert_eq!(vec![1, 2, 3], *order.lock().unwrap());
+
+	// the queue moves together with the context
+	let ctx = Context::new_with_null_logger();
+	let ran = Arc::new(Mutex::new(false));
+	ctx.spawn_deferred({
+		let ran = Arc::clone(&ran);
+		move |ctx| {
+			*ran.lock().unwrap() = true;
+			ctx.stop();
+		}
+	});
+	let handle = ctx.spawn_run();
+	assert_matches!(handle.join(), RunOutcome::Stopped);
+	assert!(*ran.lock().unwrap());
+}
+
+#[test]
+fn run_supervised() {
+	let ctx = Context::new_with_null_logger();
+	let shutdown = Arc::new(Mutex::new(false));
+	ctx.on_shutdown({
+		let shutdown = Arc::clone(&shutdown);
+		move |_, _| *shutdown.lock().unwrap() = true
+	});
+	ctx.spawn_deferred(|_| panic!("job failed"));
+	let outcome = ctx.run_supervised();
+	assert_matches!(outcome, RunOutcome::Panicked(_));
+	assert_eq!(Some("job failed"), outcome.panic_message());
+	assert!(*shutdown.lock().unwrap());
+
+	ctx.spawn_deferred(|ctx| ctx.stop());
+	assert_matches!(ctx.run_supervised(), RunOutcome::Stopped);
+
+	ctx.spawn_deferred(|_| panic!("resumed"));
+	let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| ctx.run()));
+	assert_eq!(Some(&"resumed"), res.unwrap_err().downcast_ref::<&str>());
+}
+
+#[test]
+fn spawn_run() {
+	let handle = Context::new_with_null_logger().spawn_run();
+	assert!(handle.is_running());
+	assert_matches!(handle.stop_and_join(), RunOutcome::Stopped);
+
+	let mut ctx = Context::new_with_null_logger();
+	ctx.set_timeout(Duration::from_millis(10));
+	let handle = ctx.spawn_run();
+	handle.stop();
+	while handle.is_running() {
+		std::thread::sleep(Duration::from_millis(10));
+	}
+	assert_matches!(handle.join(), RunOutcome::Stopped);
+}
+
+#[test]
+#[cfg(feature = "libstrophe-0_10_0")]
+fn sm_mirror() {
+	let mut mirror = SmMirror::default();
+	let message = Stanza::new_message(Some("chat"), None, Some("user@example.com"));
+	mirror.observe_incoming(&message);
+	mirror.observe_outgoing(&message);
+	assert_eq!(0, mirror.h_in);
+	assert_eq!(0, mirror.h_out);
+
+	mirror.observe_incoming(&Stanza::from_str(
+		"<enabled xmlns='urn:xmpp:sm:3' id='some-id' resume='true'/>",
+	));
+	assert!(mirror.enabled);
+	assert_eq!(Some("some-id"), mirror.resume_id.as_deref());
+	mirror.observe_incoming(&message);
+	mirror.observe_incoming(&Stanza::from_str("<r xmlns='urn:xmpp:sm:3'/>"));
+	for _ in 0..3 {
+		mirror.observe_outgoing(&message);
+	}
+	mirror.observe_incoming(&Stanza::from_str("<a xmlns='urn:xmpp:sm:3' h='2'/>"));
+	assert_eq!(1, mirror.h_in);
+	assert_eq!(3, mirror.h_out);
+	assert_eq!(1, mirror.unacked());
+
+	let saved = mirror.to_string();
+	assert_eq!("1 3 2 - some-id", saved);
+	let mut restored = saved.parse::<SmMirror>().unwrap();
+	assert!(!restored.enabled);
+	restored.enabled = true;
+	assert_eq!(mirror, restored);
+	assert!("1 3".parse::<SmMirror>().is_err());
+
+	mirror.observe_incoming(&Stanza::from_str(
+		"<failed xmlns='urn:xmpp:sm:3'><item-not-found xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/></failed>",
+	));
+	assert!(!mirror.enabled);
+	assert_eq!(None, mirror.resume_id);
+	assert_eq!(Some("item-not-found"), mirror.failed.as_deref());
+}
+
+#[test]
+#[cfg(feature = "libstrophe-0_11_0")]
+fn enum_names() {
+	assert_eq!("warn", LogLevel::XMPP_LEVEL_WARN.to_string());
+	assert_eq!(Ok(LogLevel::XMPP_LEVEL_DEBUG), "Debug".parse());
+	assert_eq!(Ok(LogLevel::XMPP_LEVEL_WARN), "warning".parse());
+	let err = "verbose".parse::<LogLevel>().unwrap_err();
+	assert_eq!("verbose", err.value);
+	assert_eq!("not-authorized", ErrorType::XMPP_SE_NOT_AUTHORIZED.to_string());
+	assert_eq!(Ok(ErrorType::XMPP_SE_XML_NOT_WELL_FORMED), "not-well-formed".parse());
+	assert_eq!(Ok(CertElement::XMPP_CERT_FINGERPRINT_SHA256), "fingerprint-sha256".parse());
+	for element in [CertElement::XMPP_CERT_NOTBEFORE, CertElement::XMPP_CERT_KEYALG] {
+		assert_eq!(Ok(element), element.to_string().parse());
+	}
+}
+
+#[test]
+#[cfg(all(feature = "rust-log", feature = "libstrophe-0_10_0"))]
+fn logger_max_level() {
+	use log::LevelFilter;
+
+	assert_eq!(None, Logger::default().max_level());
+	let logger = Logger::with_max_level(LevelFilter::Warn);
+	assert_eq!(Some(LevelFilter::Warn), logger.max_level());
+	logger.log(LogLevel::XMPP_LEVEL_DEBUG, "test", "filtered out");
+	let mut ctx = Context::new(Logger::with_max_level(LevelFilter::Trace));
+	ctx.log_debug("test", "verbose");
+	ctx.set_verbosity(0);
+}
+
+#[test]
+fn schedule() {
+	use std::time::Instant;
+
+	let ctx = Context::new_with_null_logger();
+	let ticks = Arc::new(Mutex::new(0));
+	let events = Arc::new(Mutex::new(Vec::new()));
+	ctx.schedule(Duration::from_millis(20), "tick", {
+		let ticks = Arc::clone(&ticks);
+		move |ctx| {
+			*ticks.lock().unwrap() += 1;
+			if *ticks.lock().unwrap() == 3 {
+				assert!(ctx.unschedule("tick"));
+			}
+		}
+	});
+	ctx.schedule(Duration::from_millis(5), "cancelled", |_| {
+		panic!("Cancelled job must not run")
+	});
+	assert!(ctx.unschedule("cancelled"));
+	assert!(!ctx.unschedule("cancelled"));
+	ctx.schedule_at(Instant::now() + Duration::from_millis(30), {
+		let events = Arc::clone(&events);
+		move |_| events.lock().unwrap().push("late")
+	});
+	ctx.schedule_at(Instant::now(), {
+		let events = Arc::clone(&events);
+		move |_| events.lock().unwrap().push("now")
+	});
+	let start = Instant::now();
+	while start.elapsed() < Duration::from_millis(150) {
+		ctx.run_once(Duration::from_millis(5));
+	}
+	assert_eq!(3, *ticks.lock().unwrap());
+	assert_eq!(vec!["now", "late"], *events.lock().unwrap());
+}
+
+#[test]
+fn connection_pool() {
+	use std::time::Instant;
+
+	let pool = ConnectionPool::new("component.example.com", "secret", "127.0.0.1", 1, 2);
+	pool.set_retry_delay(Duration::from_millis(10));
+	pool.set_max_retry_delay(Duration::from_millis(20));
+	let setups = Rc::new(RefCell::new(0));
+	let ctx = pool
+		.start(Context::new_with_null_logger(), {
+			let setups = Rc::clone(&setups);
+			move |_| *setups.borrow_mut() += 1
+		})
+		.unwrap();
+	assert_eq!(2, *setups.borrow());
+	let start = Instant::now();
+	while start.elapsed() < Duration::from_millis(150) {
+		ctx.run_once(Duration::from_millis(5));
+	}
+	let health = pool.health();
+	assert_eq!(2, health.size);
+	assert_eq!(0, health.connected);
+	assert!(health.reconnects >= 4, "{:?}", health);
+	assert!(!health.is_healthy());
+
+	let msg = Stanza::new_message(Some("chat"), Some("m1"), Some("user@example.com"));
+	assert_eq!(Err(Error::InvalidOperation), pool.send(&ctx, &msg));
+	assert_eq!(1, pool.health().failed_sends);
+	assert_eq!(0, pool.health().sent);
+	let other = Context::new_with_null_logger();
+	assert_eq!(Err(Error::InvalidOperation), pool.send(&other, &msg));
+	assert_eq!(1, pool.health().failed_sends);
+
+	pool.disconnect(&ctx);
+	let reconnects = pool.health().reconnects;
+	let start = Instant::now();
+	while start.elapsed() < Duration::from_millis(60) {
+		ctx.run_once(Duration::from_millis(5));
+	}
+	assert_eq!(reconnects, pool.health().reconnects);
+}
+
+#[test]
+fn connection_pool_stopped_while_connecting() {
+	use std::io::{Read, Write};
+	use std::net::TcpListener;
+	use std::thread;
+	use std::time::Instant;
+
+	// accepts any component handshake
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let port = listener.local_addr().unwrap().port();
+	thread::spawn(move || {
+		for mut stream in listener.incoming().flatten() {
+			thread::spawn(move || {
+				let mut buf = [0; 4096];
+				while let Ok(read @ 1..) = stream.read(&mut buf) {
+					let data = String::from_utf8_lossy(&buf[..read]);
+					if data.contains("<stream:stream") {
+						let _ = stream.write_all(
+							b"<?xml version='1.0'?><stream:stream xmlns='jabber:component:accept' \
+							xmlns:stream='http://etherx.jabber.org/streams' id='s1' from='component.example.com'>",
+						);
+					}
+					if data.contains("<handshake") {
+						let _ = stream.write_all(b"<handshake/>");
+					}
+				}
+			});
+		}
+	});
+	let run = |ctx: &Context| {
+		let start = Instant::now();
+		while start.elapsed() < Duration::from_millis(200) {
+			ctx.run_once(Duration::from_millis(5));
+		}
+	};
+
+	let pool = ConnectionPool::new("component.example.com", "secret", "127.0.0.1", port, 2);
+	let ctx = pool.start(Context::new_with_null_logger(), |_| {}).unwrap();
+	run(&ctx);
+	assert_eq!(2, pool.health().connected);
+	pool.disconnect(&ctx);
+
+	// stopped before the members got connected
+	let pool = ConnectionPool::new("component.example.com", "secret", "127.0.0.1", port, 2);
+	let ctx = pool.start(Context::new_with_null_logger(), |_| {}).unwrap();
+	pool.disconnect(&ctx);
+	run(&ctx);
+	assert_eq!(0, pool.health().connected);
+	assert_eq!(0, pool.health().reconnects);
+}
+
+#[test]
+fn on_shutdown() {
+	let events = Arc::new(Mutex::new(Vec::new()));
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.set_jid("component.example.com").unwrap();
+	let _ = conn.try_connect_component("127.0.0.1", 1, |_, _, _| {});
+	let ctx = conn.into_context();
+	ctx.on_shutdown({
+		let events = Arc::clone(&events);
+		move |_, conns| events.lock().unwrap().push(format!("first {}", conns.len()))
+	});
+	ctx.on_shutdown({
+		let events = Arc::clone(&events);
+		move |_, _| events.lock().unwrap().push("second".to_owned())
+	});
+	ctx.spawn_deferred(|ctx| ctx.stop());
+	ctx.run();
+	assert_eq!(vec!["second", "first 1"], *events.lock().unwrap());
+
+	ctx.on_shutdown({
+		let events = Arc::clone(&events);
+		move |_, _| events.lock().unwrap().push("drop".to_owned())
+	});
+	drop(ctx);
+	assert_eq!(vec!["second", "first 1", "drop"], *events.lock().unwrap());
+}
+
+#[test]
+fn dedup_cache() {
+	let mut msg = Stanza::new_message(Some("chat"), Some("m1"), Some("room@muc.example.com"));
+	msg.set_from("room@muc.example.com/nick").unwrap();
+	let mut other = msg.clone();
+	other.set_id("m2").unwrap();
+	let mut cache = DedupCache::new(1);
+	assert!(!cache.check(&msg));
+	assert!(cache.check(&msg));
+	assert!(!cache.check(&other));
+	// evicted
+	assert!(!cache.check(&msg));
+	assert_eq!(1, cache.len());
+
+	let mut origin_id = Stanza::new();
+	origin_id.set_name("origin-id").unwrap();
+	origin_id.set_ns("urn:xmpp:sid:0").unwrap();
+	origin_id.set_attribute("id", "origin").unwrap();
+	msg.add_child(origin_id).unwrap();
+	assert_eq!(
+		Some((Some("room@muc.example.com/nick".to_owned()), "origin".to_owned())),
+		DedupCache::key(&msg)
+	);
+	assert_eq!(None, DedupCache::key(&Stanza::new()));
+}
+
+#[test]
+fn event_history() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert!(conn.event_history().is_empty());
+	conn.set_event_history_capacity(0);
+	assert!(conn.event_history().is_empty());
+
+	let event = OwnedConnectionEvent::from(&ConnectionEvent::Disconnect(Some(ConnectionError::TimedOut)));
+	assert_matches!(event, OwnedConnectionEvent::Disconnect(Some(OwnedConnectionError::TimedOut)));
+	assert_eq!("Disconnect, error: Connection timed out", event.to_string());
+}
+
+#[test]
+#[cfg(feature = "libstrophe-0_12_0")]
+fn resend_policy() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.set_resend_policy(Some(ResendPolicy::Decide(Box::new(|stanza| {
+		stanza.name() == Some("message")
+	}))));
+	assert!(conn.unacked_stanzas().is_empty());
+	conn.set_resend_policy(Some(ResendPolicy::ResendAll));
+	conn.set_resend_policy(None);
+	assert!(conn.unacked_stanzas().is_empty());
+}
+
+#[test]
+#[cfg(feature = "xep-0077")]
+fn account_change_password() {
+	let jid = "user@example.com/res".parse::<Jid>().unwrap();
+	let request = xep::account::change_password_request(&jid, "new", "id1").unwrap();
+	assert_eq!(Some("example.com"), request.to());
+	let query = request.get_child_by_name("query").unwrap();
+	assert_eq!(Some(xep::account::NS_REGISTER), query.ns());
+	assert_eq!(
+		"<username>user</username><password>new</password>",
+		query.children().map(|x| x.to_string()).collect::<String>()
+	);
+	assert!(xep::account::change_password_request(&"example.com".parse().unwrap(), "new", "id1").is_err());
+
+	assert_eq!(
+		Ok(()),
+		xep::account::parse_response(&Stanza::new_iq(Some("result"), Some("id1")))
+	);
+	let error = Stanza::from_str(
+		"<iq type=\"error\" id=\"id1\"><error type=\"cancel\"><not-allowed xmlns=\"urn:ietf:params:xml:ns:xmpp-stanzas\"/></error></iq>",
+	);
+	assert_eq!(
+		Err(xep::account::RegistrationError::NotAllowed),
+		xep::account::parse_response(&error)
+	);
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert!(xep::account::change_password(&mut conn, "new", |_, _, _| {}).is_err());
+}
+
+#[test]
+#[cfg(feature = "xep-0077")]
+fn account_delete() {
+	let request = xep::account::remove_request("id2").unwrap();
+	assert_eq!(Some("set"), request.stanza_type());
+	let query = request.get_child_by_name("query").unwrap();
+	assert_eq!(Some(xep::account::NS_REGISTER), query.ns());
+	assert!(query.get_child_by_name("remove").is_some());
+	let error = Stanza::from_str(
+		"<iq type=\"error\" id=\"id2\"><error type=\"auth\"><forbidden xmlns=\"urn:ietf:params:xml:ns:xmpp-stanzas\"/></error></iq>",
+	);
+	assert_eq!(
+		Err(xep::account::RegistrationError::Other("forbidden".to_owned())),
+		xep::account::parse_response(&error)
+	);
+}
+
+#[test]
+#[cfg(feature = "xep-0004")]
+fn data_form() {
+	let stanza = Stanza::from_str(
+		"<x xmlns='jabber:x:data' type='form'><title>Search</title><field type='fixed'><value>Info</value></field><field var='first' type='text-single' label='Given Name'><required/></field><field var='gender' type='list-single'><option label='Male'><value>m</value></option><value>f</value></field></x>",
+	);
+	let mut form = xep::data_forms::DataForm::from_stanza(&stanza).unwrap();
+	assert_eq!(xep::data_forms::FormType::Form, form.typ);
+	assert_eq!(Some("Search"), form.title.as_deref());
+	assert_eq!(3, form.fields.len());
+	let first = form.field("first").unwrap();
+	assert!(first.required);
+	assert_eq!(Some("Given Name"), first.label.as_deref());
+	assert_eq!(
+		vec![(Some("Male".to_owned()), "m".to_owned())],
+		form.field("gender").unwrap().options
+	);
+	assert_eq!(Some("f"), form.field("gender").unwrap().value());
+	form.set_value("first", "Juliet");
+	let submit = form.submit();
+	assert_eq!(
+		"<x xmlns=\"jabber:x:data\" type=\"submit\"><field var=\"first\"><value>Juliet</value></field><field var=\"gender\"><value>f</value></field></x>",
+		submit.to_stanza().unwrap().to_string()
+	);
+	assert_eq!(
+		Some(submit.clone()),
+		xep::data_forms::DataForm::from_stanza(&submit.to_stanza().unwrap())
+	);
+}
+
+#[test]
+#[cfg(feature = "xep-0055")]
+fn search_helpers() {
+	let request = xep::search::form_request("search.example.com", "s1").unwrap();
+	assert_eq!(Some("search.example.com"), request.to());
+	assert_eq!(Some(xep::search::NS_SEARCH), request.get_child_by_name("query").unwrap().ns());
+
+	let legacy = Stanza::from_str(
+		"<iq type='result' id='s1'><query xmlns='jabber:iq:search'><instructions>Fill in</instructions><first/><last/></query></iq>",
+	);
+	assert_eq!(
+		Ok(xep::search::SearchForm::Fields {
+			instructions: Some("Fill in".to_owned()),
+			fields: vec!["first".to_owned(), "last".to_owned()],
+		}),
+		xep::search::parse_form(&legacy)
+	);
+	let query = xep::search::SearchQuery::Fields(vec![("last".to_owned(), "Capulet".to_owned())]);
+	let submit = xep::search::submit_request("search.example.com", "s2", &query).unwrap();
+	assert_eq!(
+		"<query xmlns=\"jabber:iq:search\"><last>Capulet</last></query>",
+		submit.get_child_by_name("query").unwrap().to_string()
+	);
+	let results = Stanza::from_str(
+		"<iq type='result' id='s2'><query xmlns='jabber:iq:search'><item jid='juliet@capulet.com'><first>Juliet</first><last>Capulet</last></item></query></iq>",
+	);
+	let items = xep::search::parse_results(&results).unwrap();
+	assert_eq!(1, items.len());
+	assert_eq!(Some("juliet@capulet.com"), items[0].jid.as_deref());
+	assert_eq!(Some("Capulet"), items[0].get("last"));
+
+	let extended = Stanza::from_str(
+		"<iq type='result' id='s3'><query xmlns='jabber:iq:search'><x xmlns='jabber:x:data' type='result'><reported><field var='jid'/><field var='nick'/></reported><item><field var='jid'><value>romeo@montague.net</value></field><field var='nick'><value>romeo</value></field></item></x></query></iq>",
+	);
+	let items = xep::search::parse_results(&extended).unwrap();
+	assert_eq!(Some("romeo@montague.net"), items[0].jid.as_deref());
+	assert_eq!(vec![("nick".to_owned(), "romeo".to_owned())], items[0].fields);
+
+	let error = Stanza::from_str(
+		"<iq type='error' id='s1'><error type='cancel'><service-unavailable xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/></error></iq>",
+	);
+	assert_eq!(
+		Err(xep::IqError::Stanza("service-unavailable".to_owned())),
+		xep::search::parse_form(&error)
+	);
+}
+
+#[test]
+#[cfg(feature = "xep-0045")]
+fn muc_admin() {
+	assert!(xep::muc::Room::new("room@conference.example.com/nick", "romeo").is_err());
+	let room = xep::muc::Room::new("room@conference.example.com", "romeo").unwrap();
+	assert_eq!("room@conference.example.com/romeo", room.occupant_jid());
+	let join = room.join_presence(Some("secret")).unwrap();
+	assert_eq!(Some("room@conference.example.com/romeo"), join.to());
+	assert_eq!(
+		"<x xmlns=\"http://jabber.org/protocol/muc\"><password>secret</password></x>",
+		join.get_child_by_name("x").unwrap().to_string()
+	);
+	assert_eq!(Some("unavailable"), room.leave_presence(None).unwrap().stanza_type());
+
+	let kick = room
+		.role_request("tybalt", xep::muc::Role::None, Some("Brawling"), "m1")
+		.unwrap();
+	assert_eq!(Some("room@conference.example.com"), kick.to());
+	assert_eq!(Some("set"), kick.stanza_type());
+	assert_eq!(
+		"<query xmlns=\"http://jabber.org/protocol/muc#admin\"><item nick=\"tybalt\" role=\"none\"><reason>Brawling</reason></item></query>",
+		kick.get_child_by_name("query").unwrap().to_string()
+	);
+	let ban = room
+		.affiliation_request("tybalt@capulet.com", xep::muc::Affiliation::Outcast, None, "m2")
+		.unwrap();
+	assert_eq!(
+		"<query xmlns=\"http://jabber.org/protocol/muc#admin\"><item jid=\"tybalt@capulet.com\" affiliation=\"outcast\"/></query>",
+		ban.get_child_by_name("query").unwrap().to_string()
+	);
+	assert_eq!(
+		Some(xep::muc::Affiliation::Outcast),
+		xep::muc::Affiliation::from_attr("outcast")
+	);
+	assert_eq!(Some(xep::muc::Role::Moderator), xep::muc::Role::from_attr("moderator"));
+
+	let config = room.config_request("m3").unwrap();
+	assert_eq!(Some("get"), config.stanza_type());
+	assert_eq!(
+		"<query xmlns=\"http://jabber.org/protocol/muc#owner\"/>",
+		config.get_child_by_name("query").unwrap().to_string()
+	);
+	let response = Stanza::from_str(
+		"<iq type='result' id='m3'><query xmlns='http://jabber.org/protocol/muc#owner'><x xmlns='jabber:x:data' type='form'><field var='muc#roomconfig_roomname' type='text-single'><value>Balcony</value></field></x></query></iq>",
+	);
+	let form = xep::muc::parse_config(&response).unwrap();
+	assert_eq!(xep::data_forms::FormType::Form, form.typ);
+	let submit = room.submit_config_request(&form, "m4").unwrap();
+	assert_eq!(
+		Some(xep::data_forms::FormType::Submit),
+		xep::data_forms::DataForm::from_stanza(&submit.get_child_by_name("query").unwrap()).map(|form| form.typ)
+	);
+
+	let error = Stanza::from_str(
+		"<iq type='error' id='m1'><error type='auth'><forbidden xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/></error></iq>",
+	);
+	assert_eq!(
+		Err(xep::IqError::Stanza("forbidden".to_owned())),
+		xep::muc::parse_response(&error)
+	);
+	assert_eq!(
+		Ok(()),
+		xep::muc::parse_response(&Stanza::from_str("<iq type='result' id='m2'/>"))
+	);
+}
+
+#[test]
+#[cfg(feature = "xep-0249")]
+fn muc_invites() {
+	let direct = xep::invite::direct_invite(
+		"darkcave@macbeth.shakespeare.lit",
+		"hecate@shakespeare.lit",
+		Some("Hey"),
+		None,
+	)
+	.unwrap();
+	assert_eq!(
+		"<x xmlns=\"jabber:x:conference\" jid=\"darkcave@macbeth.shakespeare.lit\" reason=\"Hey\"/>",
+		direct.get_child_by_name("x").unwrap().to_string()
+	);
+	let parsed = xep::invite::Invite::from_stanza(&direct).unwrap();
+	assert_eq!(xep::invite::InviteKind::Direct, parsed.kind);
+	assert_eq!("darkcave@macbeth.shakespeare.lit", parsed.room);
+	assert_eq!(Some("Hey"), parsed.reason.as_deref());
+
+	let mediated = xep::invite::mediated_invite("darkcave@macbeth.shakespeare.lit", "hecate@shakespeare.lit", None).unwrap();
+	assert_eq!(Some("darkcave@macbeth.shakespeare.lit"), mediated.to());
+	assert_eq!(
+		"<x xmlns=\"http://jabber.org/protocol/muc#user\"><invite to=\"hecate@shakespeare.lit\"/></x>",
+		mediated.get_child_by_name("x").unwrap().to_string()
+	);
+	assert!(xep::invite::mediated_invite("darkcave@macbeth.shakespeare.lit/nick", "hecate@shakespeare.lit", None).is_err());
+	let forwarded = Stanza::from_str(
+		"<message from='darkcave@macbeth.shakespeare.lit' to='hecate@shakespeare.lit'><x xmlns='http://jabber.org/protocol/muc#user'><invite from='crone1@shakespeare.lit/desktop'><reason>Join us</reason></invite><password>cauldronburn</password></x></message>",
+	);
+	let parsed = xep::invite::Invite::from_stanza(&forwarded).unwrap();
+	assert_eq!(xep::invite::InviteKind::Mediated, parsed.kind);
+	assert_eq!(Some("crone1@shakespeare.lit/desktop"), parsed.inviter.as_deref());
+	assert_eq!(Some("Join us"), parsed.reason.as_deref());
+	assert_eq!(Some("cauldronburn"), parsed.password());
+	assert_eq!(
+		"darkcave@macbeth.shakespeare.lit/hecate",
+		parsed.room("hecate").unwrap().occupant_jid()
+	);
+
+	assert!(xep::invite::Invite::from_stanza(&Stanza::from_str("<message><body>Hi</body></message>")).is_none());
+}
+
+#[test]
+#[cfg(feature = "xep-0224")]
+fn attention() {
+	use xep::attention::{Attention, Signal};
+
+	let msg = xep::attention::message("juliet@capulet.lit", Some("Wake up!")).unwrap();
+	assert_eq!(Some("headline"), msg.stanza_type());
+	assert_eq!(
+		"<attention xmlns=\"urn:xmpp:attention:0\"/>",
+		msg.get_child_by_name("attention").unwrap().to_string()
+	);
+	let parsed = Attention::from_stanza(&msg).unwrap();
+	assert_eq!(Signal::Attention, parsed.signal);
+	assert_eq!(Some("Wake up!"), parsed.body.as_deref());
+
+	let nudge = Signal::custom("nudge", "urn:example:nudge");
+	let mut msg = Stanza::from_str("<message from='romeo@montague.lit/orchard' type='chat'/>");
+	assert!(Attention::from_stanza(&msg).is_none());
+	xep::attention::attach_signal(&mut msg, &nudge).unwrap();
+	xep::attention::attach_signal(&mut msg, &nudge).unwrap();
+	assert_eq!(1, msg.children().count());
+	assert!(Attention::from_stanza(&msg).is_none());
+	let parsed = Attention::from_stanza_with(&msg, &[Signal::Attention, nudge.clone()]).unwrap();
+	assert_eq!(nudge, parsed.signal);
+	assert_eq!(Some("romeo@montague.lit/orchard"), parsed.from.as_deref());
+
+	let mut error = xep::attention::message("juliet@capulet.lit", None).unwrap();
+	error.set_stanza_type("error").unwrap();
+	assert!(Attention::from_stanza(&error).is_none());
+	assert!(xep::attention::attach(&mut Stanza::new_presence()).is_err());
+}
+
+#[test]
+#[cfg(feature = "xep-0388")]
+fn sasl2() {
+	use xep::sasl2::{Authenticate, Sasl2Features, ServerMessage, UserAgent};
+
+	let features = Stanza::from_str(
+		"<stream:features xmlns:stream='http://etherx.jabber.org/streams'><authentication xmlns='urn:xmpp:sasl:2'><mechanism>SCRAM-SHA-1</mechanism><mechanism>PLAIN</mechanism><inline><bind xmlns='urn:xmpp:bind:0'/><fast xmlns='urn:xmpp:fast:0'><mechanism>HT-SHA-256-NONE</mechanism></fast></inline></authentication></stream:features>",
+	);
+	let parsed = Sasl2Features::from_features(&features).unwrap();
+	assert_eq!(vec!["SCRAM-SHA-1", "PLAIN"], parsed.mechanisms);
+	assert!(parsed.supports_inline(xep::sasl2::NS_BIND2));
+	assert!(!parsed.supports_inline("urn:xmpp:sm:3"));
+	assert_eq!(Some(vec!["HT-SHA-256-NONE".to_owned()]), parsed.fast_mechanisms);
+	assert!(Sasl2Features::from_features(&Stanza::from_str("<stream:features xmlns:stream='http://etherx.jabber.org/streams'><mechanisms xmlns='urn:ietf:params:xml:ns:xmpp-sasl'/></stream:features>")).is_none());
+
+	let mut auth = Authenticate::new("PLAIN", Some(b"\0user\0pencil".to_vec()));
+	auth.user_agent = Some(UserAgent {
+		id: Some("d4565fa7-4d72-4749-b3d3-740edbf87770".to_owned()),
+		software: Some("AwesomeXMPP".to_owned()),
+		device: None,
+	});
+	let mut bind = Stanza::new();
+	bind.set_name("bind").unwrap();
+	bind.set_ns(xep::sasl2::NS_BIND2).unwrap();
+	let stanza = auth.to_stanza(vec![bind]).unwrap();
+	assert_eq!(
+		"<initial-response>AHVzZXIAcGVuY2ls</initial-response>",
+		stanza.get_child_by_name("initial-response").unwrap().to_string()
+	);
+	assert!(stanza.get_child_by_name_and_ns("bind", xep::sasl2::NS_BIND2).is_some());
+	assert_eq!(Some(auth), Authenticate::from_stanza(&stanza));
+
+	assert_eq!(
+		"<response xmlns=\"urn:xmpp:sasl:2\">=</response>",
+		xep::sasl2::response(b"").unwrap().to_string()
+	);
+	assert_eq!(
+		Some(ServerMessage::Challenge(b"r=abc".to_vec())),
+		ServerMessage::from_stanza(&Stanza::from_str("<challenge xmlns='urn:xmpp:sasl:2'>cj1hYmM=</challenge>"))
+	);
+	assert_eq!(
+		Some(ServerMessage::Success {
+			additional_data: Some(b"v=xyz".to_vec()),
+			authorization_identifier: Some("user@example.org/abc".to_owned()),
+		}),
+		ServerMessage::from_stanza(&Stanza::from_str("<success xmlns='urn:xmpp:sasl:2'><additional-data>dj14eXo=</additional-data><authorization-identifier>user@example.org/abc</authorization-identifier></success>"))
+	);
+	assert_eq!(
+		Some(ServerMessage::Failure {
+			condition: "aborted".to_owned(),
+			text: Some("This is a terrible example.".to_owned()),
+		}),
+		ServerMessage::from_stanza(&Stanza::from_str("<failure xmlns='urn:xmpp:sasl:2'><aborted xmlns='urn:ietf:params:xml:ns:xmpp-sasl'/><text>This is a terrible example.</text></failure>"))
+	);
+	assert_eq!(
+		Some(ServerMessage::Continue {
+			additional_data: None,
+			tasks: vec!["HOTP-EXAMPLE".to_owned()],
+			text: None,
+		}),
+		ServerMessage::from_stanza(&Stanza::from_str(
+			"<continue xmlns='urn:xmpp:sasl:2'><tasks><task>HOTP-EXAMPLE</task></tasks></continue>"
+		))
+	);
+	assert!(ServerMessage::from_stanza(&Stanza::from_str("<success xmlns='urn:ietf:params:xml:ns:xmpp-sasl'/>")).is_none());
+}
+
+#[test]
+#[cfg(feature = "xep-0016")]
+fn privacy_lists() {
+	let request = xep::privacy::set_active_request(None, "p1").unwrap();
+	assert_eq!(
+		"<query xmlns=\"jabber:iq:privacy\"><active/></query>",
+		request.get_child_by_name("query").unwrap().to_string()
+	);
+	let list = xep::privacy::PrivacyList {
+		name: "blocklist".to_owned(),
+		items: vec![xep::privacy::PrivacyItem::deny_jid("tybalt@example.com", 1)],
+	};
+	let request = xep::privacy::set_list_request(&list, "p2").unwrap();
+	assert_eq!(
+		"<query xmlns=\"jabber:iq:privacy\"><list name=\"blocklist\"><item type=\"jid\" value=\"tybalt@example.com\" action=\"deny\" order=\"1\"/></list></query>",
+		request.get_child_by_name("query").unwrap().to_string()
+	);
+
+	let lists = Stanza::from_str(
+		"<iq type='result' id='p3'><query xmlns='jabber:iq:privacy'><active name='private'/><default name='public'/><list name='public'/><list name='private'/></query></iq>",
+	);
+	assert_eq!(
+		Ok(xep::privacy::PrivacyLists {
+			active: Some("private".to_owned()),
+			default: Some("public".to_owned()),
+			names: vec!["public".to_owned(), "private".to_owned()],
+		}),
+		xep::privacy::parse_lists(&lists)
+	);
+	let response = Stanza::from_str(
+		"<iq type='result' id='p4'><query xmlns='jabber:iq:privacy'><list name='public'><item action='allow' order='2'/><item type='jid' value='tybalt@example.com' action='deny' order='1'><message/></item></list></query></iq>",
+	);
+	let list = xep::privacy::parse_list(&response).unwrap();
+	assert_eq!("public", list.name);
+	assert_eq!(vec![1, 2], list.items.iter().map(|item| item.order).collect::<Vec<_>>());
+	assert_eq!(vec!["message".to_owned()], list.items[0].stanzas);
+	assert_eq!(None, list.items[1].typ);
+	let error = Stanza::from_str(
+		"<iq type='error' id='p4'><error type='cancel'><item-not-found xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/></error></iq>",
+	);
+	assert_eq!(
+		Err(xep::IqError::Stanza("item-not-found".to_owned())),
+		xep::privacy::parse_list(&error)
+	);
+	assert_eq!(
+		Ok(()),
+		xep::privacy::parse_response(&Stanza::new_iq(Some("result"), Some("p2")))
+	);
+}
+
+#[test]
+#[cfg(feature = "xep-0016")]
+fn iq_responses() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	let sent = Arc::new(Mutex::new(vec![]));
+	conn.send_interceptor_add({
+		let sent = Arc::clone(&sent);
+		move |stanza| {
+			sent.lock().unwrap().push(stanza.id().unwrap().to_owned());
+			None
+		}
+	});
+	// both requests are sent from the same call site, so their response handlers are of the same type
+	let results = Arc::new(Mutex::new(vec![]));
+	for name in ["first", "second"] {
+		xep::privacy::remove_list(&mut conn, name, {
+			let results = Arc::clone(&results);
+			move |_, _, res| results.lock().unwrap().push((name, res))
+		})
+		.unwrap();
+	}
+	xep::privacy::get_lists(&mut conn, |_, _, _| {}).unwrap();
+	// the requests with the different handler types share the dispatcher
+	assert_eq!(
+		1,
+		conn.handlers_remove_where(|info| matches!(info, HandlerInfo::Stanza { name: Some("iq"), .. }))
+	);
+	let ids = sent.lock().unwrap().clone();
+	assert_eq!(3, ids.len());
+	let ctx = Context::new_with_null_logger();
+	let error = Stanza::from_str(format!(
+		"<iq type='error' id='{}'><error type='cancel'><item-not-found xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/></error></iq>",
+		ids[1]
+	));
+	conn.dispatch_iq_response(&ctx, &error);
+	conn.dispatch_iq_response(&ctx, &Stanza::new_iq(Some("result"), Some(&ids[0])));
+	// the handlers are called once
+	conn.dispatch_iq_response(&ctx, &Stanza::new_iq(Some("result"), Some(&ids[0])));
+	assert_eq!(
+		vec![
+			("second", Err(xep::IqError::Stanza("item-not-found".to_owned()))),
+			("first", Ok(())),
+		],
+		*results.lock().unwrap()
+	);
+}
+
+#[test]
+#[cfg(feature = "xep-0191")]
+fn blocking_facade() {
+	let request = xep::blocking::block_request(&["romeo@example.net"], "b1").unwrap();
+	assert_eq!(
+		"<block xmlns=\"urn:xmpp:blocking\"><item jid=\"romeo@example.net\"/></block>",
+		request.get_first_child().unwrap().to_string()
+	);
+	let response = Stanza::from_str(
+		"<iq type='result' id='b2'><blocklist xmlns='urn:xmpp:blocking'><item jid='romeo@example.net'/><item jid='iago@shakespeare.lit'/></blocklist></iq>",
+	);
+	assert_eq!(
+		Ok(vec!["romeo@example.net".to_owned(), "iago@shakespeare.lit".to_owned()]),
+		xep::blocking::parse_blocklist(&response)
+	);
+
+	// no JID to query, the server features resolve immediately and the privacy list is used
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	let detected = Arc::new(Mutex::new(None));
+	xep::blocking::Blocking::detect(&mut conn, {
+		let detected = Arc::clone(&detected);
+		move |_, _, blocking| *detected.lock().unwrap() = Some(blocking)
+	});
+	let blocking = detected.lock().unwrap().take().unwrap();
+	assert_eq!(xep::blocking::BlockingBackend::PrivacyList, blocking.backend());
+	assert_eq!(xep::blocking::Blocking::DEFAULT_LIST_NAME, blocking.list_name());
+}
+
+#[test]
+#[cfg(feature = "libstrophe-0_10_0")]
+fn server_supports() {
+	let request = xep::disco::info_request("example.com", "id1").unwrap();
+	assert_eq!(Some("example.com"), request.to());
+	let response = Stanza::from_str(
+		"<iq type='result' id='id1'><query xmlns='http://jabber.org/protocol/disco#info'><identity category='server' type='im'/><feature var='urn:xmpp:mam:2'/><feature var='jabber:iq:version'/></query></iq>",
+	);
+	assert_eq!(vec!["urn:xmpp:mam:2", "jabber:iq:version"], xep::disco::features(&response));
+	assert!(xep::disco::features(&Stanza::new_iq(Some("error"), Some("id1"))).is_empty());
+
+	// no JID to query, resolves immediately
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert_eq!(None, conn.cached_server_supports("urn:xmpp:mam:2"));
+	let supported = Arc::new(Mutex::new(None));
+	conn.server_supports("urn:xmpp:mam:2", {
+		let supported = Arc::clone(&supported);
+		move |_, _, res| *supported.lock().unwrap() = Some(res)
+	});
+	assert_eq!(Some(Some(false)), *supported.lock().unwrap());
+	assert_eq!(Some(false), conn.cached_server_supports("urn:xmpp:mam:2"));
+}
+
+#[test]
+fn conn_type() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert_eq!(ConnType::XMPP_UNKNOWN, conn.conn_type());
+	conn.set_jid("component.localhost").unwrap();
+	conn.set_pass("secret").unwrap();
+	// the type is recorded even if the connection attempt fails
+	let _ = conn.try_connect_component("127.0.0.1", 1, |_, _, _| {});
+	assert_eq!(ConnType::XMPP_COMPONENT, conn.conn_type());
+}
+
+#[test]
+fn utf8_policy() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert_eq!(Utf8Policy::Strict, conn.utf8_policy());
+	conn.set_utf8_policy(Utf8Policy::Lossy);
+	assert_eq!(Utf8Policy::Lossy, conn.utf8_policy());
+
+	let mut msg = Stanza::new_message(Some("chat"), Some("u1"), Some("user@example.com"));
+	msg.set_body("Привет").unwrap();
+	assert!(msg.is_valid_utf8());
+	let lossy = msg.to_lossy_utf8().unwrap();
+	assert_eq!(msg.id(), lossy.id());
+	assert_eq!(msg.to(), lossy.to());
+	assert_eq!(Some("Привет".to_owned()), lossy.body());
+}
+
+#[test]
+fn tls_info() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert!(conn.tls_info().is_none());
+	assert_eq!(None, conn.tls_hostname());
+	assert_eq!(Err(Error::InvalidOperation), conn.set_tls_hostname("example.com"));
+	conn.set_jid("user@example.com/res").unwrap();
+	assert_eq!(Some("example.com".to_owned()), conn.tls_hostname());
+
+	conn.set_jid("user@192.0.2.1/res").unwrap();
+	conn.set_tls_hostname("xmpp.example.com").unwrap();
+	conn.set_tls_hostname("example.com").unwrap();
+	assert_eq!(Some("user@example.com/res"), conn.jid());
+	assert_eq!(Some("example.com".to_owned()), conn.tls_hostname());
+	assert!(format!("{:?}", conn).contains("tls_route: Some(\"192.0.2.1\")"));
+	assert!(conn.set_tls_hostname("example.com/res").is_err());
+}
+
+#[test]
+#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+fn socket_ref() {
+	use std::ffi::c_void;
+	use std::net::{TcpListener, TcpStream};
+	use std::os::unix::io::AsRawFd;
+
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+	let mut fd = stream.as_raw_fd();
+	let sock = unsafe { SocketRef::from_raw(&mut fd as *mut _ as *mut c_void) };
+	assert_eq!(stream.as_raw_fd(), sock.fd());
+	sock.set_nodelay(true).unwrap();
+	assert!(stream.nodelay().unwrap());
+	sock.set_keepalive(true).unwrap();
+	sock.set_tos(0x10).unwrap();
+	#[cfg(any(target_os = "linux", target_os = "android"))]
+	sock
+		.set_keepalive_params(Duration::from_secs(60), Duration::from_secs(10), 3)
+		.unwrap();
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.set_socket_callback(|sock| sock.set_nodelay(true));
+}
+
+#[test]
+#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+fn local_binding() {
+	use std::ffi::c_void;
+	use std::net::{IpAddr, Ipv4Addr};
+
+	let mut fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0) };
+	assert!(fd >= 0);
+	let sock = unsafe { SocketRef::from_raw(&mut fd as *mut _ as *mut c_void) };
+	sock.bind(IpAddr::V4(Ipv4Addr::LOCALHOST)).unwrap();
+	assert!(sock.bind(IpAddr::V4(Ipv4Addr::LOCALHOST)).is_err());
+	unsafe { libc::close(fd) };
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.set_local_address(Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+	conn.set_local_address(None);
+}
+
+#[test]
+#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+fn local_address_with_keepalive() {
+	use std::ffi::c_void;
+	use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+	use std::os::unix::io::FromRawFd;
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.set_local_address(Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+	conn.set_keepalive_opts(KeepaliveOpts {
+		idle: Duration::from_secs(60),
+		interval: Duration::from_secs(10),
+		count: None,
+	});
+	conn.set_default_sockopt_callback();
+	let mut fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0) };
+	assert!(fd >= 0);
+	let res = unsafe { crate::socket::socket_options_sockopt_cb(conn.as_ptr(), &mut fd as *mut _ as *mut c_void) };
+	assert_eq!(0, res);
+	let sock = unsafe { TcpStream::from_raw_fd(fd) };
+	assert_matches!(sock.local_addr(), Ok(SocketAddr::V4(addr)) if *addr.ip() == Ipv4Addr::LOCALHOST);
+}
+
+#[test]
+#[cfg(all(unix, feature = "libstrophe-0_12_0"))]
+fn socket_fd() {
+	use std::ffi::c_void;
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert_eq!(None, conn.socket_fd());
+	let mut fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0) };
+	assert!(fd >= 0);
+	conn.set_default_sockopt_callback();
+	let res = unsafe { crate::socket::socket_options_sockopt_cb(conn.as_ptr(), &mut fd as *mut _ as *mut c_void) };
+	assert_eq!(0, res);
+	assert_eq!(Some(fd), conn.socket_fd());
+	drop(conn);
+	unsafe { libc::close(fd) };
+}
+
+#[test]
+#[cfg(feature = "libstrophe-0_12_0")]
+fn keepalive_opts() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.set_keepalive_opts(KeepaliveOpts {
+		idle: Duration::from_secs(60),
+		interval: Duration::from_secs(10),
+		count: Some(3),
+	});
+	#[cfg(unix)]
+	conn.set_local_address(None);
+}
+
+#[test]
+fn proxy_tunnel() {
+	use std::io::{BufRead, BufReader, Read, Write};
+	use std::net::{TcpListener, TcpStream};
+	use std::thread;
+
+	use proxy::{Proxy, ProxyTunnel};
+
+	fn fake_socks5() -> u16 {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				let mut stream = stream.unwrap();
+				let mut buf = [0; 4];
+				stream.read_exact(&mut buf).unwrap();
+				assert_eq!([5, 2, 0, 2], buf);
+				stream.write_all(&[5, 2]).unwrap();
+				let mut auth = [0; 11];
+				stream.read_exact(&mut auth).unwrap();
+				assert_eq!(b"\x01\x04user\x04pass", &auth);
+				stream.write_all(&[1, 0]).unwrap();
+				let mut request = [0; 18];
+				stream.read_exact(&mut request).unwrap();
+				assert_eq!(b"\x05\x01\x00\x03\x0bexample.com\x14\x66", &request);
+				stream.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0]).unwrap();
+				// echo
+				let mut data = [0; 4];
+				stream.read_exact(&mut data).unwrap();
+				stream.write_all(&data).unwrap();
+			}
+		});
+		port
+	}
+
+	let proxy = Proxy::Socks5 {
+		host: "127.0.0.1".to_owned(),
+		port: fake_socks5(),
+		auth: Some(("user".to_owned(), "pass".to_owned())),
+	};
+	let mut stream = proxy.connect("example.com", 5222).unwrap();
+	stream.write_all(b"ping").unwrap();
+	let mut data = [0; 4];
+	stream.read_exact(&mut data).unwrap();
+	assert_eq!(b"ping", &data);
+
+	let tunnel = ProxyTunnel::start(proxy, "example.com", 5222).unwrap();
+	let mut stream = TcpStream::connect(tunnel.local_addr()).unwrap();
+	stream.write_all(b"pong").unwrap();
+	stream.read_exact(&mut data).unwrap();
+	assert_eq!(b"pong", &data);
+	// only one connection is accepted
+	assert!(TcpStream::connect(tunnel.local_addr()).is_err());
+	assert!(tunnel.take_error().is_none());
+
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let http_proxy = Proxy::Http {
+		host: "127.0.0.1".to_owned(),
+		port: listener.local_addr().unwrap().port(),
+		auth: Some(("user".to_owned(), "pass".to_owned())),
+	};
+	thread::spawn(move || {
+		let (stream, _) = listener.accept().unwrap();
+		let mut reader = BufReader::new(stream.try_clone().unwrap());
+		let mut request = String::new();
+		while !request.ends_with("\r\n\r\n") {
+			reader.read_line(&mut request).unwrap();
+		}
+		assert_eq!(
+			"CONNECT example.com:5222 HTTP/1.1\r\nHost: example.com:5222\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n",
+			request
+		);
+		(&stream)
+			.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+			.unwrap();
+	});
+	assert!(http_proxy.connect("example.com", 5222).is_err());
+
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let not_socks5 = Proxy::Socks5 {
+		host: "127.0.0.1".to_owned(),
+		port: listener.local_addr().unwrap().port(),
+		auth: None,
+	};
+	thread::spawn(move || {
+		let (mut stream, _) = listener.accept().unwrap();
+		let mut buf = [0; 3];
+		stream.read_exact(&mut buf).unwrap();
+		// SOCKS4 style reply
+		stream.write_all(&[4, 0]).unwrap();
+	});
+	let err = not_socks5.connect("example.com", 5222).unwrap_err();
+	assert_eq!("Invalid SOCKS5 reply", err.to_string());
+
+	let closed_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+	let unreachable = Proxy::Socks5 {
+		host: "127.0.0.1".to_owned(),
+		port: closed_port,
+		auth: None,
+	};
+	let tunnel = ProxyTunnel::start(unreachable, "example.com", 5222).unwrap();
+	let mut stream = TcpStream::connect(tunnel.local_addr()).unwrap();
+	// the tunnel closes the connection once the proxy fails
+	assert_eq!(0, stream.read(&mut data).unwrap_or(0));
+	assert!(tunnel.take_error().is_some());
+	assert!(tunnel.take_error().is_none());
+}
+
+#[test]
+#[cfg(feature = "stanza-tracking")]
+fn stanza_tracking() {
+	let stanza = Stanza::new();
+	let ptr = stanza.as_ptr() as usize;
+	let report = stanza_tracking::report();
+	let tracked = report.live.iter().find(|tracked| tracked.ptr == ptr).unwrap();
+	assert!(tracked.owned);
+	assert!(!tracked.backtrace.is_empty());
+	drop(stanza);
+	assert!(stanza_tracking::report().live.iter().all(|tracked| tracked.ptr != ptr));
+}
+
+#[test]
+#[cfg(feature = "libstrophe-0_10_0")]
+fn stanza_parse() {
+	let stanza = Stanza::parse("<message id='1'><body>hi</body></message>").unwrap();
+	assert_eq!(stanza.name(), Some("message"));
+	assert_eq!(stanza.id(), Some("1"));
+	assert_eq!(Stanza::parse("<message><body>").unwrap_err(), Error::InvalidOperation);
+	assert_eq!(Stanza::parse("not xml").unwrap_err(), Error::InvalidOperation);
+	assert_eq!(Stanza::parse("<a>\0</a>").unwrap_err(), Error::InvalidOperation);
+	assert!("<a/>".parse::<Stanza>().is_ok());
+	assert!("<a".parse::<Stanza>().is_err());
+}
+
+#[test]
+fn stanza_builder() {
+	let iq = StanzaBuilder::iq("get", "id1")
+		.to("example.com")
+		.child(StanzaBuilder::new("query").ns("jabber:iq:version"))
+		.build()
+		.unwrap();
+	assert_eq!(Some("get"), iq.stanza_type());
+	assert_eq!(Some("id1"), iq.id());
+	assert_eq!(Some("example.com"), iq.to());
+	assert_eq!(
+		"<query xmlns=\"jabber:iq:version\"/>",
+		iq.get_child_by_name("query").unwrap().to_string()
+	);
+	let msg = StanzaBuilder::message()
+		.from_jid("user@example.com/res")
+		.typ("chat")
+		.attr("xml:lang", "en")
+		.child(StanzaBuilder::new("body").text("Hi & bye"))
+		.child(Stanza::new_presence())
+		.build()
+		.unwrap();
+	assert_eq!(Some("Hi & bye".to_owned()), msg.body());
+	assert_eq!(Some("en"), msg.get_attribute("xml:lang"));
+	assert_eq!(Some("user@example.com/res"), msg.from());
+	assert_eq!(2, msg.child_count());
+
+	assert_eq!(Err(BuildStanzaError::IqPayloads(0)), StanzaBuilder::iq("set", "id2").build());
+	assert_eq!(
+		Err(BuildStanzaError::MissingAttribute("id")),
+		StanzaBuilder::new("iq").typ("result").build()
+	);
+	assert_eq!(
+		Err(BuildStanzaError::InvalidType {
+			name: "message".to_owned(),
+			typ: "urgent".to_owned()
+		}),
+		StanzaBuilder::message().typ("urgent").build()
+	);
+	assert_eq!(
+		Err(BuildStanzaError::MissingError),
+		StanzaBuilder::presence().typ("error").build()
+	);
+	assert!(StanzaBuilder::iq("result", "id3").build().is_ok());
+	assert!(StanzaBuilder::new("query").build().is_ok());
+	let mut text = Stanza::new();
+	text.set_text("text").unwrap();
+	let builder: StanzaBuilder = text.into();
+	assert_eq!(
+		Err(BuildStanzaError::Strophe(Error::InvalidOperation)),
+		StanzaBuilder::message().child(builder.attr("id", "1")).build()
+	);
+}
+
+#[test]
+#[cfg(feature = "libstrophe-0_10_0")]
+fn stanza_size_estimate() {
+	let stanza =
+		Stanza::parse("<message id='1' to='a@example.com'><body>hi</body><active xmlns='urn:xmpp:chatstates'/></message>").unwrap();
+	assert_eq!(2, stanza.child_count());
+	assert_eq!(0, stanza.get_child_by_name("active").unwrap().child_count());
+	assert_eq!(stanza.to_text().unwrap().len(), stanza.byte_size_estimate());
+	let escaped = Stanza::parse("<body>a &amp; b</body>").unwrap();
+	assert_eq!(escaped.to_text().unwrap().len() - 4, escaped.byte_size_estimate());
+}
+
+#[test]
+#[cfg(feature = "libstrophe-0_10_0")]
+fn stanza_redacted() {
+	let msg = Stanza::parse(
+		"<message to='a@example.com'><body>secret</body><x xmlns='urn:example:auth' token='t0k3n'><key>k</key></x></message>",
+	)
+	.unwrap();
+	let redacted = msg.redacted(&RedactionPolicy::default());
+	assert!(redacted.contains("<body>[redacted]</body>"));
+	assert!(redacted.contains("t0k3n"));
+	assert!(!redacted.contains("secret"));
+	let policy = RedactionPolicy::new()
+		.element(Some("key"), Some("urn:example:auth"))
+		.attribute("token")
+		.placeholder("***");
+	let redacted = msg.redacted(&policy);
+	assert!(redacted.contains("secret"));
+	assert!(redacted.contains("<key>***</key>"));
+	assert!(redacted.contains("token=\"***\""));
+	assert!(redacted.contains("a@example.com"));
+
+	let auth = Stanza::parse("<auth xmlns='urn:ietf:params:xml:ns:xmpp-sasl' mechanism='PLAIN'>AGp1bGlldABwYXNz</auth>").unwrap();
+	let redacted = auth.redacted(&RedactionPolicy::default());
+	assert!(redacted.contains("mechanism=\"PLAIN\""));
+	assert!(!redacted.contains("AGp1bGlldABwYXNz"));
+	let success = Stanza::parse("<success xmlns='urn:ietf:params:xml:ns:xmpp-sasl'/>").unwrap();
+	assert_eq!(success.to_text().unwrap(), success.redacted(&RedactionPolicy::default()));
+}
+
+#[test]
+fn stanza_nodes() {
+	let mut body = Stanza::new();
+	body.set_name("body").unwrap();
+	for (name, text) in [(None, "Hello "), (Some("em"), "big"), (None, " world")] {
+		let mut text_node = Stanza::new();
+		text_node.set_text(text).unwrap();
+		if let Some(name) = name {
+			let mut element = Stanza::new();
+			element.set_name(name).unwrap();
+			element.add_child(text_node).unwrap();
+			body.add_child(element).unwrap();
+		} else {
+			body.add_child(text_node).unwrap();
+		}
+	}
+	let nodes = body.nodes().collect::<Vec<_>>();
+	assert_eq!(3, nodes.len());
+	assert_eq!(Some("Hello "), nodes[0].as_text());
+	let em = nodes[1].as_element().unwrap();
+	assert_eq!(Some("em"), em.name());
+	assert_eq!(Some("big".to_owned()), em.text());
+	assert!(nodes[1].as_text().is_none());
+	assert_eq!(Some(" world"), nodes[2].as_text());
+}
+
+#[test]
+#[cfg(all(feature = "libstrophe-0_10_0", feature = "xep-0071"))]
+fn xhtml_im() {
+	let mut msg = Stanza::new_message(Some("chat"), Some("x1"), Some("user@example.com"));
+	msg.set_body("Hello").unwrap();
+	xep::xhtml_im::attach(
+		&mut msg,
+		Stanza::from_str(
+			"<body><p style='color: red; background: url(evil.png)'>Hello <a href='javascript:alert(1)' onclick='x()'>there</a><script>alert(2)</script> <blink><em>you</em></blink></p></body>",
+		),
+	)
+	.unwrap();
+	assert!(xep::xhtml_im::attach(&mut msg, Stanza::new()).is_err());
+	let body = xep::xhtml_im::extract(&msg).unwrap();
+	assert_eq!(Some(xep::xhtml_im::NS_XHTML), body.ns());
+	let p = body.get_child_by_name("p").unwrap();
+	assert_eq!(Some("color: red"), p.get_attribute("style"));
+	let a = p.get_child_by_name("a").unwrap();
+	assert!(a.attributes().is_empty());
+	assert_eq!(Some("there".to_owned()), a.text());
+	assert!(p.get_child_by_name("script").is_none());
+	assert!(p.get_child_by_name("blink").is_none());
+	assert!(p.get_child_by_name("em").is_some());
+	assert_eq!(Some("Hello there you".to_owned()), p.text());
+
+	let link = Stanza::from_str("<a href='https://example.com'>x</a>");
+	assert_eq!(
+		Some("https://example.com"),
+		xep::xhtml_im::sanitize(&link).unwrap().get_attribute("href")
+	);
+	assert!(xep::xhtml_im::sanitize(&Stanza::from_str("<script/>")).is_err());
+}
+
+#[test]
+fn ffi_hostile_input() {
+	use std::ffi::CStr;
+	use std::os::raw::c_char;
+	use std::ptr;
+
+	let invalid = CStr::from_bytes_with_nul(b"bad \xff\xfe utf-8\0").unwrap();
+	let valid = CStr::from_bytes_with_nul(b"good\0").unwrap();
+	unsafe {
+		assert_eq!(None, FFI(ptr::null::<c_char>()).try_receive());
+		assert_eq!(None, FFI(invalid.as_ptr()).try_receive());
+		assert_eq!(Some("good"), FFI(valid.as_ptr()).try_receive());
+		assert_eq!(None, FFI(ptr::null::<c_char>()).receive_lossy());
+		assert_eq!(
+			Some("bad \u{FFFD}\u{FFFD} utf-8"),
+			FFI(invalid.as_ptr()).receive_lossy().as_deref()
+		);
+		assert!(void_ptr_as_opt::<u8>(ptr::null()).is_none());
+		let mut val = 5u8;
+		assert_eq!(Some(&mut 5), void_ptr_as_opt::<u8>(ptr::addr_of_mut!(val).cast()));
+	}
+}
+
+#[test]
+fn state_handlers() {
+	#[derive(Debug, Default, PartialEq)]
+	struct Counters {
+		messages: usize,
+		ticks: usize,
+	}
+
+	fn on_message(state: &mut Counters, _ctx: &Context, _conn: &mut Connection, _stanza: &Stanza) -> HandlerResult {
+		state.messages += 1;
+		HandlerResult::KeepHandler
+	}
+
+	fn on_presence(_state: &mut Counters, _ctx: &Context, _conn: &mut Connection, _stanza: &Stanza) -> HandlerResult {
+		HandlerResult::KeepHandler
+	}
+
+	fn on_tick(state: &mut Counters, _ctx: &Context, _conn: &mut Connection) -> HandlerResult {
+		state.ticks += 1;
+		HandlerResult::KeepHandler
+	}
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert!(!conn.has_extension::<Counters>());
+	assert_eq!(None, conn.with_extension(|state: &mut Counters, _| state.messages));
+	assert_eq!(None, conn.extension_insert(Counters::default()));
+	assert!(conn.has_extension::<Counters>());
+
+	assert!(conn.state_handler_add(on_message, None, Some("message"), None).is_some());
+	assert!(conn.state_handler_add(on_presence, None, Some("presence"), None).is_some());
+	assert!(conn.state_handler_add(on_message, None, Some("message"), None).is_none());
+	assert!(conn.state_id_handler_add(on_message, "id1").is_some());
+	assert!(conn.state_timed_handler_add(on_tick, Duration::from_secs(1)).is_some());
+
+	assert_eq!(
+		Some(1),
+		conn.with_extension(|state: &mut Counters, conn| {
+			state.messages += 1;
+			assert!(!conn.has_extension::<Counters>());
+			state.messages
+		})
+	);
+	assert_eq!(
+		Some(Counters { messages: 1, ticks: 0 }),
+		conn.extension_insert(Counters { messages: 0, ticks: 5 })
+	);
+	assert_eq!(Some(Counters { messages: 0, ticks: 5 }), conn.extension_remove());
+	assert!(conn.extension_remove::<Counters>().is_none());
+}
+
+#[test]
+fn sha1() {
+	use hash::{sha1, sha1_digest, Sha1};
+
+	assert_eq!("da39a3ee5e6b4b0d3255bfef95601890afd80709", sha1(""));
+	assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d", sha1("abc"));
+	let digest = sha1_digest(b"abc");
+	assert_eq!([0xa9, 0x99, 0x3e, 0x36], digest[..4]);
+
+	let mut hash = Sha1::new();
+	hash.update("a");
+	hash.update(b"bc");
+	assert_eq!(digest, hash.finalize());
+
+	let mut hash = Sha1::default();
+	// verification string from the XEP-0115 simple generation example
+	hash.update("client/pc//Exodus 0.9.1<http://jabber.org/protocol/caps<http://jabber.org/protocol/disco#info<http://jabber.org/protocol/disco#items<http://jabber.org/protocol/muc<");
+	assert_eq!("4206b23ca6b0a643d20d89b04ff58cf78b8096ed", hash.finalize_hex());
+}
+
+#[test]
+fn correlation_ids() {
+	let ctx1 = Context::new_with_null_logger();
+	let ctx2 = Context::new_with_null_logger();
+	assert_ne!(ctx1.id(), ctx2.id());
+	assert!(ctx1.id() < ctx2.id());
+	assert_eq!(format!("ctx#{}", ctx1.id().as_u64()), ctx1.id().to_string());
+	assert_eq!(ctx1.id(), unsafe { Context::from_ref(ctx1.as_ptr()) }.id());
+	assert!(format!("{:?}", ctx1).contains(&ctx1.id().to_string()));
+
+	let conn1 = Connection::new(ctx1);
+	let conn2 = Connection::new(ctx2);
+	assert_ne!(conn1.id(), conn2.id());
+	assert_eq!(format!("conn#{}", conn1.id().as_u64()), format!("{:?}", conn1.id()));
+	assert!(format!("{:?}", conn2).contains(&conn2.id().to_string()));
+}
+
+#[test]
+fn uuid_gen() {
+	let uuid = crate::uuid_gen();
+	assert_eq!(36, uuid.len());
+	assert_eq!(vec![8, 4, 4, 4, 12], uuid.split('-').map(str::len).collect::<Vec<_>>());
+	assert!(uuid.chars().all(|c| c == '-' || c.is_ascii_hexdigit()));
+	assert_ne!(uuid, crate::uuid_gen());
+}
+
+#[test]
+fn id_generator() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	let uuid = conn.generate_id();
+	assert_eq!(uuid.len(), 36);
+	assert_ne!(uuid, conn.generate_id());
+
+	let counter = AtomicU16::new(0);
+	conn.set_id_generator(move || format!("tenant-{}", counter.fetch_add(1, Ordering::Relaxed)));
+	assert_eq!("tenant-0", conn.generate_id());
+	assert_eq!("tenant-1", conn.generate_id());
+
+	conn.clear_id_generator();
+	assert_eq!(conn.generate_id().len(), 36);
+}
+
+#[test]
+fn auto_resource() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.set_jid("test-JID@127.50.60.70/fixed").unwrap();
+	assert_eq!(Some("fixed"), conn.resource());
+	conn.set_jid("test-JID@127.50.60.70").unwrap();
+	assert_eq!(None, conn.resource());
+	conn.set_auto_resource(true);
+	conn.try_connect_client(None, None, |ctx, _, _| ctx.stop()).unwrap();
+	let resource = conn.resource().expect("Resource must be generated").to_owned();
+	assert_eq!(36, resource.len());
+	assert_eq!(Some(format!("test-JID@127.50.60.70/{}", resource).as_str()), conn.jid());
+	conn.into_context().run();
 }
 
 #[test]
@@ -159,7 +2367,7 @@ fn stanza_handler_in_con() {
 	};
 	let ctx = Context::new_with_null_logger();
 	let mut conn = Connection::new(ctx);
-	conn.set_jid("test-JID@127.50.60.70");
+	conn.set_jid("test-JID@127.50.60.70").unwrap();
 	conn.connect_client(None, None, con_handler).unwrap();
 }
 
@@ -189,6 +2397,121 @@ fn jid_test() {
 	assert_eq!(None, jid::jid_resource(&jid_domain));
 }
 
+#[test]
+fn jid_type() {
+	let jid: Jid = "node@domain.com/res/with@chars".parse().unwrap();
+	assert_eq!(Some("node"), jid.node());
+	assert_eq!("domain.com", jid.domain());
+	assert_eq!(Some("res/with@chars"), jid.resource());
+	assert_eq!("node@domain.com", jid.bare());
+	assert_eq!("node@domain.com", jid.to_bare().as_str());
+	assert!(jid.to_bare().is_bare());
+	let jid: Jid = "domain.com".parse().unwrap();
+	assert_eq!((None, "domain.com", None), (jid.node(), jid.domain(), jid.resource()));
+	assert_eq!(Ok(jid), Jid::new(None, "domain.com", None));
+	assert_eq!(
+		"node@domain.com/test",
+		Jid::new(Some("node"), "domain.com", Some("test")).unwrap().to_string()
+	);
+
+	for invalid in [
+		"",
+		"@domain.com",
+		"node@",
+		"node@domain.com/",
+		"no de@domain.com",
+		"a:b@domain.com",
+		"/res",
+	] {
+		assert_eq!(Err(Error::InvalidOperation), invalid.parse::<Jid>(), "{}", invalid);
+	}
+	assert_eq!(Err(Error::InvalidOperation), Jid::new(Some("a@b"), "domain.com", None));
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert_eq!(Err(Error::InvalidOperation), conn.set_jid("invalid jid"));
+	assert_eq!(None, conn.jid());
+	conn.set_jid("node@domain.com").unwrap();
+	assert_eq!(Some("node@domain.com"), conn.jid());
+	conn.set_pass("pass").unwrap();
+}
+
+#[test]
+fn jid_escaping() {
+	use jid::{escape_node, unescape_node};
+
+	// examples from XEP-0106
+	assert_eq!("space\\20cadet", escape_node("space cadet"));
+	assert_eq!("call\\20me\\20\\22ishmael\\22", escape_node("call me \"ishmael\""));
+	assert_eq!("at\\26t\\20guy", escape_node("at&t guy"));
+	assert_eq!("d\\27artagnan", escape_node("d'artagnan"));
+	assert_eq!("\\2fd.fool", escape_node("/.fool"));
+	assert_eq!("\\3cconsole\\3e", escape_node("<console>"));
+	assert_eq!("user\\40host", escape_node("user@host"));
+	assert_eq!("c\\3a\\net", escape_node("c:\\net"));
+	assert_eq!("c\\3a\\5c5commas", escape_node("c:\\5commas"));
+	assert_eq!("space\\20cadet", escape_node("  space cadet "));
+	assert_eq!("c:\\net", unescape_node("c\\3a\\net"));
+	assert_eq!("\\2F", unescape_node("\\2F"));
+	let jid = Jid::new(Some(&escape_node("user@host")), "transport.example", None).unwrap();
+	assert_eq!("user\\40host@transport.example", jid.as_str());
+	assert_eq!("user@host", unescape_node(jid.node().unwrap()));
+
+	// every string over the alphabet of the special characters and the escape sequence parts round-trips
+	let alphabet = ['a', '\\', '2', '0', '5', 'c', '4', ' ', '@', '/', '"', 'f'];
+	let mut queue = vec![String::new()];
+	while let Some(node) = queue.pop() {
+		let escaped = escape_node(&node);
+		assert!(
+			!escaped.contains(|c: char| " \"&'/:<>@".contains(c)),
+			"{:?} escaped as {:?}",
+			node,
+			escaped
+		);
+		assert_eq!(
+			node.trim_matches(' '),
+			unescape_node(&escaped),
+			"{:?} escaped as {:?}",
+			node,
+			escaped
+		);
+		if node.chars().count() < 4 {
+			queue.extend(alphabet.iter().map(|c| format!("{}{}", node, c)));
+		}
+	}
+}
+
+#[test]
+fn jid_matcher() {
+	let full = JidMatcher::Full("Node@Domain.com/Res".parse().unwrap());
+	assert!(full.matches("node@domain.com/Res"));
+	assert!(full.matches("node@domain.com./Res"));
+	assert!(!full.matches("node@domain.com/res"));
+	assert!(!full.matches("node@domain.com"));
+
+	let bare = JidMatcher::Bare("node@domain.com/res".parse().unwrap());
+	assert!(bare.matches("NODE@domain.com"));
+	assert!(bare.matches("node@DOMAIN.com/other"));
+	assert!(!bare.matches("other@domain.com/res"));
+	assert!(!bare.matches("domain.com"));
+	assert!(!bare.matches("malformed@"));
+
+	let domain = JidMatcher::Domain("domain.com".to_owned());
+	assert!(domain.matches("domain.com"));
+	assert!(domain.matches("node@Domain.Com/res"));
+	assert!(!domain.matches("node@muc.domain.com"));
+
+	let wildcard = JidMatcher::DomainWildcard("domain.com".to_owned());
+	assert!(wildcard.matches("node@domain.com"));
+	assert!(wildcard.matches("room@muc.domain.com/nick"));
+	assert!(!wildcard.matches("node@otherdomain.com"));
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	let handler_id = conn
+		.handler_add_from(|_, _, _| HandlerResult::KeepHandler, bare, None, Some("message"), None)
+		.unwrap();
+	conn.handler_delete(handler_id);
+}
+
 #[test]
 fn stanza_err() {
 	let mut stanza = Stanza::new();
@@ -197,6 +2520,237 @@ fn stanza_err() {
 	assert_matches!(stanza.set_body("body"), Err(Error::InvalidOperation));
 }
 
+#[test]
+#[cfg(feature = "libstrophe-0_10_0")]
+fn stanza_attr_parse() {
+	use std::time::{Duration, UNIX_EPOCH};
+
+	let stanza = Stanza::from_str("<iq seconds='903' height='tall'><query xmlns='jabber:iq:last' seconds='-1'/></iq>");
+	assert_eq!(Ok(Some(903)), stanza.seconds());
+	assert_eq!(Ok(None), stanza.attr_parse::<u32>("width"));
+	let err = stanza.attr_parse::<u32>("height").unwrap_err();
+	assert_eq!("height", err.name);
+	assert_eq!("tall", err.value);
+	assert!(stanza.get_first_child().unwrap().seconds().is_err());
+
+	let stanza = Stanza::from_str(
+		"<presence><priority> -5 </priority><delay xmlns='urn:xmpp:delay' stamp='2002-09-10T23:41:07.25+02:00'/></presence>",
+	);
+	assert_eq!(Ok(Some(-5)), stanza.priority());
+	assert_eq!(
+		Ok(Some(UNIX_EPOCH + Duration::from_millis(1_031_694_067_250))),
+		stanza.delay_stamp()
+	);
+	let stanza =
+		Stanza::from_str("<presence><priority>200</priority><delay xmlns='urn:xmpp:delay' stamp='yesterday'/></presence>");
+	assert!(stanza.priority().is_err());
+	assert_eq!("stamp", stanza.delay_stamp().unwrap_err().name);
+	assert_eq!(Ok(None), Stanza::new_presence().delay_stamp());
+}
+
+#[test]
+fn message_thread() {
+	let mut msg = Stanza::new_message(Some("chat"), Some("id"), Some("to"));
+	assert_eq!(None, msg.thread());
+	let thread = msg.set_new_thread(None).unwrap();
+	assert_eq!(Some(thread), msg.thread());
+	msg.set_thread("thread-2", Some("thread-1")).unwrap();
+	assert_eq!(Some("thread-2".to_string()), msg.thread());
+	assert_eq!(Some("thread-1".to_string()), msg.thread_parent());
+	assert_eq!(1, msg.children().filter(|child| child.name() == Some("thread")).count());
+	msg.set_thread("thread-3", None).unwrap();
+	assert_eq!(None, msg.thread_parent());
+
+	let mut threads = MessageThreads::new();
+	assert_eq!(None, threads.track(&Stanza::new_message(None, None, None)));
+	let mut msg = Stanza::new_message(None, None, None);
+	msg.set_thread("root", None).unwrap();
+	assert_eq!(Some("root".to_string()), threads.track(&msg));
+	assert_eq!(Some("root".to_string()), threads.track(&msg));
+	msg.set_thread("fork", Some("root")).unwrap();
+	threads.track(&msg);
+	assert_eq!(2, threads.len());
+	assert_eq!(2, threads.get("root").unwrap().messages().len());
+	assert_eq!(Some("root"), threads.get("fork").unwrap().parent());
+	assert_eq!(vec!["fork"], threads.children("root").collect::<Vec<_>>());
+	assert!(threads.remove("root").is_some());
+	assert_eq!(1, threads.len());
+}
+
+#[test]
+fn message_correction() {
+	let conn = Connection::new(Context::new_with_null_logger());
+	let mut msg = Stanza::new_message(Some("chat"), None, Some("juliet@capulet.net/balcony"));
+	msg.set_body("But soft, what light through yonder airlock breaks?").unwrap();
+	msg.set_thread("thread", None).unwrap();
+	assert_eq!(Err(Error::InvalidOperation), conn.new_correction(&msg, "body"));
+	msg.set_id("bad1").unwrap();
+	assert_eq!(None, msg.replace_id());
+
+	let correction = conn
+		.new_correction(&msg, "But soft, what light through yonder window breaks?")
+		.unwrap();
+	assert_eq!(Some("bad1".to_string()), correction.replace_id());
+	assert_eq!(Some("chat"), correction.stanza_type());
+	assert_eq!(Some("juliet@capulet.net/balcony"), correction.to());
+	assert_eq!(Some("thread".to_string()), correction.thread());
+	assert_eq!(
+		Some("But soft, what light through yonder window breaks?".to_string()),
+		correction.body()
+	);
+	assert_ne!(Some("bad1"), correction.id());
+	assert!(correction.id().is_some());
+}
+
+#[test]
+#[cfg(feature = "xep-0166")]
+fn jingle_session() {
+	use xep::jingle::{Action, Candidate, Content, Creator, Jingle, Reason, Transport};
+
+	let initiator: Jid = "romeo@montague.example/dr4hcr0st3lup4c".parse().unwrap();
+	let mut content = Content::new(Creator::Initiator, "ex");
+	content.description = Some(Stanza::from_str(
+		"<description xmlns='urn:xmpp:jingle:apps:file-transfer:5'/>",
+	));
+	content.transport = Some(Transport::Socks5 {
+		sid: "vj3hs98y".to_string(),
+		dstaddr: None,
+		candidates: vec![Candidate {
+			cid: "hft54dqy".to_string(),
+			host: "192.168.4.1".to_string(),
+			jid: initiator.to_string(),
+			port: Some(5086),
+			priority: 8257636,
+			typ: Some("direct".to_string()),
+		}],
+	});
+	let mut ibb = Content::new(Creator::Initiator, "ibb");
+	ibb.transport = Some(Transport::Ibb {
+		sid: "ch3d9s71".to_string(),
+		block_size: 4096,
+	});
+	let iq = Jingle::session_initiate("a73sjjvkla37jfea", &initiator, vec![content, ibb])
+		.to_iq("xs51r0k4", "juliet@capulet.example/yn0cl4bnw0yr3vym")
+		.unwrap();
+	assert_eq!(Some("set"), iq.stanza_type());
+
+	let parsed = Jingle::from_stanza(&Stanza::from_str(iq.to_text().unwrap())).unwrap();
+	assert_eq!(Action::SessionInitiate, parsed.action);
+	assert_eq!("a73sjjvkla37jfea", parsed.sid);
+	assert_eq!(Some(initiator.to_string()), parsed.initiator);
+	assert_eq!(2, parsed.contents.len());
+	assert_eq!(Some("description"), parsed.contents[0].description.as_ref().unwrap().name());
+	assert_matches!(&parsed.contents[0].transport, Some(Transport::Socks5 { candidates, .. }) if candidates[0].port == Some(5086));
+	assert_eq!(
+		Some(Transport::Ibb {
+			sid: "ch3d9s71".to_string(),
+			block_size: 4096
+		}),
+		parsed.contents[1].transport
+	);
+
+	let mut terminate = Jingle::session_terminate("a73sjjvkla37jfea", Reason::Decline);
+	terminate.reason_text = Some("Not now".to_string());
+	let parsed = Jingle::from_stanza(&terminate.to_stanza().unwrap()).unwrap();
+	assert_eq!(Some(Reason::Decline), parsed.reason);
+	assert_eq!(Some("Not now".to_string()), parsed.reason_text);
+	assert!(Jingle::from_stanza(&Stanza::new_iq(Some("set"), Some("id"))).is_none());
+}
+
+#[test]
+#[cfg(feature = "xep-0297")]
+fn stanza_forwarding() {
+	use std::time::{Duration, UNIX_EPOCH};
+
+	use xep::delay::Delay;
+
+	let mut msg = Stanza::new_message(Some("chat"), Some("0202197"), Some("juliet@capulet.lit"));
+	msg.set_body("Yet I should kill thee with much cherishing.").unwrap();
+	let mut delay = Delay::new(UNIX_EPOCH + Duration::from_millis(1_262_443_200_500));
+	delay.from = Some("capulet.lit".to_string());
+	let forwarded = xep::forwarded::wrap(&msg, Some(&delay)).unwrap();
+	assert_eq!(None, msg.ns());
+	let text = forwarded.to_text().unwrap();
+	assert!(text.contains("stamp=\"2010-01-02T14:40:00.500Z\""), "{}", text);
+
+	let mut result = Stanza::from_str("<result xmlns='urn:xmpp:mam:2' id='28482-98726-73623'/>");
+	result.add_child(forwarded).unwrap();
+	let (inner, parsed_delay) = xep::forwarded::unwrap(&result).unwrap();
+	drop(result);
+	assert_eq!(Some(delay), parsed_delay);
+	assert_eq!(Some("jabber:client"), inner.ns());
+	assert_eq!(Some("0202197"), inner.id());
+	assert_eq!(Some("Yet I should kill thee with much cherishing.".to_string()), inner.body());
+
+	let (_, no_delay) = xep::forwarded::unwrap(&xep::forwarded::wrap(&msg, None).unwrap()).unwrap();
+	assert_eq!(None, no_delay);
+	assert!(xep::forwarded::unwrap(&msg).is_none());
+}
+
+#[test]
+#[cfg(feature = "xep-0203")]
+fn offline_delay() {
+	use std::time::{Duration, UNIX_EPOCH};
+
+	use xep::delay::Delay;
+
+	let stamp = UNIX_EPOCH + Duration::from_secs(1_031_696_467);
+	let mut interceptor = xep::delay::offline_delay_interceptor("capulet.com", |stanza| {
+		if stanza.id() == Some("replayed") {
+			Some(stamp)
+		} else {
+			None
+		}
+	});
+	let live = Stanza::new_message(Some("chat"), Some("live"), Some("juliet@capulet.com"));
+	assert!(interceptor(&live).is_none());
+	let replayed = Stanza::new_message(Some("chat"), Some("replayed"), Some("juliet@capulet.com"));
+	let tagged = interceptor(&replayed).unwrap();
+	let delay = Delay::from_parent(&tagged).unwrap();
+	assert_eq!(stamp, delay.stamp);
+	assert_eq!(Some("capulet.com".to_string()), delay.from);
+	assert_eq!(Some(xep::delay::OFFLINE_STORAGE_REASON.to_string()), delay.reason);
+	assert!(Delay::from_parent(&replayed).is_none());
+	assert!(interceptor(&tagged).is_none());
+	assert!(interceptor(&Stanza::new_iq(Some("get"), Some("replayed"))).is_none());
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert!(conn.intercept_outgoing(&replayed).is_none());
+	conn.send_interceptor_add(interceptor);
+	conn.send_interceptor_add(|stanza: &Stanza| {
+		let mut out = stanza.clone();
+		out.set_attribute("xml:lang", "it").unwrap();
+		Some(out)
+	});
+	let out = conn.intercept_outgoing(&replayed).unwrap();
+	assert!(Delay::from_parent(&out).is_some());
+	assert_eq!(Some("it"), out.get_attribute("xml:lang"));
+	conn.send_interceptors_clear();
+	assert!(conn.intercept_outgoing(&replayed).is_none());
+}
+
+#[test]
+fn error_conversion() {
+	use std::io;
+
+	assert_eq!(Error::InvalidOperation.code(), sys::XMPP_EINVOP);
+	assert_eq!(Error::from(Error::MemoryError.code()), Error::MemoryError);
+	let err = io::Error::from(Error::InvalidOperation);
+	assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+	assert_eq!(err.to_string(), "Invalid operation");
+	let err = io::Error::from(OwnedConnectionError::TimedOut);
+	assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+	let err = Connection::new(Context::new_with_null_logger())
+		.connect_client(None, None, |_, _, _| {})
+		.unwrap_err();
+	assert_eq!(err.to_string(), "Cannot connect: Invalid operation");
+	assert_eq!(
+		std::error::Error::source(&err).map(|e| e.to_string()).as_deref(),
+		Some("Invalid operation")
+	);
+}
+
 #[test]
 fn stanza_display() {
 	let mut stanza = Stanza::new();
@@ -338,6 +2892,70 @@ fn stanza() {
 	assert_ne!(stanza.id(), stanza3.id());
 }
 
+#[test]
+fn stanza_with_text() {
+	let mut text = Stanza::new();
+	text.set_text("text").unwrap();
+	assert_eq!(Some("text"), text.text_ptr());
+	assert_eq!(Some(4), text.with_text(|t| t.len()));
+
+	let mut msg = Stanza::new_message(Some("chat"), Some("id"), Some("to"));
+	assert_eq!(None, msg.with_body(|b| b.to_owned()));
+	msg.set_body("Test body").unwrap();
+	assert_eq!(None, msg.text_ptr());
+	assert_eq!(msg.body(), msg.with_body(|b| b.to_owned()));
+
+	let mut tag = Stanza::new();
+	tag.set_name("tag").unwrap();
+	assert_eq!(None, tag.with_text(|t| t.to_owned()));
+	tag.add_child(text.clone()).unwrap();
+	tag.add_child(text).unwrap();
+	assert_eq!(Some("texttext".to_string()), tag.with_text(|t| t.to_owned()));
+}
+
+#[test]
+fn stanza_try_clone() {
+	let mut msg = Stanza::new_message(Some("chat"), Some("m1"), Some("user@example.com"));
+	msg.set_body("Hello").unwrap();
+	let copy = msg.try_clone().unwrap();
+	assert_eq!(msg, copy);
+	let reply = msg.try_reply().unwrap();
+	assert_eq!(Some("user@example.com"), reply.from());
+	assert_eq!(msg.reply().to_text().unwrap(), reply.to_text().unwrap());
+}
+
+#[test]
+fn stanza_write_to() {
+	let mut msg = Stanza::new_message(Some("chat"), Some("m1"), Some("user@example.com"));
+	msg.set_body("Hello & <bye>").unwrap();
+	let mut out = vec![];
+	let written = msg.write_to(&mut out).unwrap();
+	assert_eq!(out.len(), written);
+	assert_eq!(msg.to_text().unwrap().as_bytes(), out.as_slice());
+	assert_eq!(written, msg.write_to(&mut io::sink()).unwrap());
+}
+
+#[test]
+fn stanza_to_text_canonical() {
+	let mut msg = Stanza::new_message(Some("chat"), Some("id"), Some("to"));
+	msg.set_ns("jabber:client").unwrap();
+	msg.set_attribute("xmlns:x", "urn:x").unwrap();
+	msg.set_attribute("a", "\"quoted\"\t").unwrap();
+	msg.set_body("1 < 2 & 3 > 2\r\n").unwrap();
+	let mut empty = Stanza::new();
+	empty.set_name("empty").unwrap();
+	msg.add_child(empty).unwrap();
+	let expected =
+		"<message xmlns=\"jabber:client\" xmlns:x=\"urn:x\" a=\"&quot;quoted&quot;&#x9;\" id=\"id\" to=\"to\" type=\"chat\">\
+		<body>1 &lt; 2 &amp; 3 &gt; 2&#xD;\n</body><empty></empty></message>";
+	assert_eq!(expected, msg.to_text_canonical().unwrap());
+	assert_eq!(expected, msg.clone().to_text_canonical().unwrap());
+	assert_matches!(
+		Stanza::new().to_text_canonical(),
+		Err(ToTextError::StropheError(Error::InvalidOperation))
+	);
+}
+
 #[test]
 fn stanza_clone() {
 	let stanza = {
@@ -382,6 +3000,81 @@ fn stanza_attributes() {
 	assert_eq!(stanza.attributes(), compare);
 }
 
+#[test]
+fn stanza_attributes_ns() {
+	let mut stanza = Stanza::new();
+	stanza.set_name("item").unwrap();
+	stanza.set_attribute("attr", "plain").unwrap();
+	stanza.set_attribute("xmlns:a", "urn:a").unwrap();
+	stanza.set_attribute("a:attr", "prefixed").unwrap();
+
+	assert_eq!(Some("plain"), stanza.get_attribute("attr"));
+	assert_eq!(Some("prefixed"), stanza.get_attribute_ns("attr", "urn:a"));
+	assert_eq!(None, stanza.get_attribute_ns("attr", "urn:b"));
+
+	stanza.set_attribute_ns("attr", "urn:a", "changed").unwrap();
+	assert_eq!(Some("changed"), stanza.get_attribute("a:attr"));
+
+	stanza.set_attribute_ns("attr", "urn:b", "other").unwrap();
+	assert_eq!(Some("urn:b"), stanza.get_attribute("xmlns:ns0"));
+	assert_eq!(Some("other"), stanza.get_attribute("ns0:attr"));
+	assert_eq!(Some("other"), stanza.get_attribute_ns("attr", "urn:b"));
+
+	stanza.set_attribute_ns("lang", NS_XML, "en").unwrap();
+	assert_eq!(Some("en"), stanza.get_attribute("xml:lang"));
+
+	stanza.del_attribute_ns("attr", "urn:b").unwrap();
+	assert_eq!(None, stanza.get_attribute_ns("attr", "urn:b"));
+	assert_eq!(Some("plain"), stanza.get_attribute("attr"));
+	assert_eq!(Some("changed"), stanza.get_attribute_ns("attr", "urn:a"));
+}
+
+#[test]
+fn stanza_set_text_from_reader() {
+	let data = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+	let mut stanza = Stanza::new();
+	stanza.set_name("data").unwrap();
+	assert_eq!(data.len() as u64, stanza.set_text_from_reader(data.as_slice(), true).unwrap());
+	assert!(stanza.children().count() > 1);
+	let expected = unsafe {
+		FFI(sys::xmpp_base64_encode(ALLOC_CONTEXT.as_ptr(), data.as_ptr(), data.len())).receive_with_free(|x| ALLOC_CONTEXT.free(x))
+	};
+	assert_eq!(expected, stanza.text());
+	assert_matches!(stanza.set_text_from_reader(data.as_slice(), true), Err(_));
+
+	let text = "ab€".repeat(50_000);
+	let mut stanza = Stanza::new();
+	stanza.set_name("text").unwrap();
+	assert_eq!(
+		text.len() as u64,
+		stanza.set_text_from_reader(text.as_bytes(), false).unwrap()
+	);
+	assert_eq!(Some(text), stanza.text());
+
+	let mut stanza = Stanza::new();
+	stanza.set_name("text").unwrap();
+	assert_matches!(stanza.set_text_from_reader(&b"invalid \xff"[..], false), Err(_));
+}
+
+#[test]
+fn stanza_prefixed_child() {
+	let mut stanza = Stanza::new();
+	stanza.set_name("root").unwrap();
+	stanza.declare_ns("p", "urn:p").unwrap();
+	{
+		let mut child = stanza.add_prefixed_child("p", "item").unwrap();
+		child.set_attribute("id", "1").unwrap();
+		child.add_prefixed_child("p", "sub").unwrap();
+	}
+	assert_eq!(
+		"<root xmlns:p=\"urn:p\"><p:item id=\"1\"><p:sub/></p:item></root>",
+		stanza.to_text().unwrap()
+	);
+	assert_eq!(Some("urn:p"), stanza.get_attribute("xmlns:p"));
+	stanza.set_attribute_ns("attr", "urn:p", "val").unwrap();
+	assert_eq!(Some("val"), stanza.get_attribute("p:attr"));
+}
+
 #[test]
 #[cfg(feature = "libstrophe-0_10_0")]
 fn stanza_from_str() {
@@ -423,7 +3116,7 @@ fn zero_sized_handlers() {
 
 			let conn = creds.make_conn();
 			let ctx = conn
-				.connect_client(None, None, {
+				.connect_client(creds.alt_host(), creds.alt_port(), {
 					let i_incrementer = i_incrementer.clone();
 					move |ctx, conn, evt| match evt {
 						ConnectionEvent::Connect => {
@@ -457,7 +3150,7 @@ fn zero_sized_handlers() {
 
 			let conn = creds.make_conn();
 			let ctx = conn
-				.connect_client(None, None, {
+				.connect_client(creds.alt_host(), creds.alt_port(), {
 					let i_incrementer = i_incrementer.clone();
 					move |ctx, conn, evt| match evt {
 						ConnectionEvent::Connect => {
@@ -497,7 +3190,7 @@ fn zero_sized_handlers() {
 
 			let conn = creds.make_conn();
 			let ctx = conn
-				.connect_client(None, None, {
+				.connect_client(creds.alt_host(), creds.alt_port(), {
 					let i_incrementer = i_incrementer.clone();
 					move |ctx, conn, evt| match evt {
 						ConnectionEvent::Connect => {
@@ -557,7 +3250,7 @@ fn connection_handler() {
 	{
 		let conn = creds.make_conn();
 		let ctx = conn
-			.connect_client(None, None, {
+			.connect_client(creds.alt_host(), creds.alt_port(), {
 				let flags = Arc::clone(&flags);
 				move |ctx, conn, evt| match evt {
 					ConnectionEvent::Connect => {
@@ -585,6 +3278,317 @@ fn connection_handler() {
 	);
 }
 
+#[test]
+fn xml_lang() {
+	use std::io::Read;
+	use std::net::TcpListener;
+	use std::sync::mpsc;
+	use std::thread;
+	use std::time::Instant;
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert_eq!(None, conn.xml_lang());
+	conn.set_xml_lang(Some("de"));
+	assert_eq!(Some("de".to_owned()), conn.xml_lang());
+	conn.set_stream_version(Some("0.9"));
+
+	// the header sent by open_stream_default() on a raw connection
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let port = listener.local_addr().unwrap().port();
+	let (tx, rx) = mpsc::channel();
+	thread::spawn(move || {
+		if let Ok((mut stream, _)) = listener.accept() {
+			let mut header = String::new();
+			let mut buf = [0; 1024];
+			while !header.contains("<stream:stream") || !header.ends_with('>') {
+				match stream.read(&mut buf) {
+					Ok(read @ 1..) => header.push_str(&String::from_utf8_lossy(&buf[..read])),
+					_ => break,
+				}
+			}
+			let _ = tx.send(header);
+		}
+	});
+	conn.set_jid("user@localhost").unwrap();
+	let ctx = conn
+		.connect_raw(Some("127.0.0.1"), port, |_, conn, event| {
+			if let ConnectionEvent::RawConnect = event {
+				conn.open_stream_default().unwrap();
+			}
+		})
+		.unwrap();
+	let start = Instant::now();
+	let header = loop {
+		ctx.run_once(Duration::from_millis(5));
+		if let Ok(header) = rx.try_recv() {
+			break header;
+		}
+		assert!(start.elapsed() < Duration::from_secs(5), "No stream header received");
+	};
+	assert!(header.contains("xml:lang=\"de\""), "{}", header);
+	assert!(header.contains("version=\"0.9\""), "{}", header);
+	assert!(header.contains("to=\"localhost\""), "{}", header);
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.set_xml_lang(Some("de"));
+	conn.set_xml_lang(None);
+	assert_eq!(None, conn.xml_lang());
+}
+
+#[test]
+#[cfg(all(feature = "roster", feature = "libstrophe-0_10_0"))]
+fn roster_versioning() {
+	use xep::roster::{self, Roster, RosterEvent, RosterItem, RosterStore, Subscription};
+
+	let mut roster = Roster::new();
+	let request = roster.request(true, "r0").unwrap();
+	assert_eq!(Some("r0"), request.id());
+	assert_eq!(
+		"<query xmlns=\"jabber:iq:roster\" ver=\"\"/>",
+		request.get_child_by_name("query").unwrap().to_string()
+	);
+	assert!(roster
+		.request(false, "r0")
+		.unwrap()
+		.get_child_by_name("query")
+		.unwrap()
+		.get_attribute("ver")
+		.is_none());
+
+	let result = Stanza::from_str(
+		"<iq type='result' id='r1'><query xmlns='jabber:iq:roster' ver='ver7'><item jid='nurse@example.com' subscription='both'><group>Servants</group></item><item jid='romeo@example.net/orchard' name='Romeo' ask='subscribe'/></query></iq>",
+	);
+	assert!(roster.apply_result(&result));
+	assert_eq!(Some("ver7"), roster.ver.as_deref());
+	assert_eq!(2, roster.items.len());
+	assert_eq!(vec!["Servants".to_owned()], roster.get("nurse@example.com").unwrap().groups);
+	let romeo = roster.get("romeo@example.net").unwrap();
+	assert_eq!(Subscription::None, romeo.subscription);
+	assert!(romeo.ask);
+	assert_eq!(roster, Roster::from_query(&roster.to_query().unwrap()));
+
+	// the cached copy is current
+	assert!(roster.apply_result(&Stanza::from_str("<iq type='result' id='r2'/>")));
+	assert_eq!(2, roster.items.len());
+	assert!(!roster.apply_result(&Stanza::from_str("<iq type='error' id='r3'/>")));
+
+	let push = Stanza::from_str(
+		"<iq type='set' id='p1'><query xmlns='jabber:iq:roster' ver='ver8'><item jid='nurse@example.com' subscription='remove'/></query></iq>",
+	);
+	let item = roster.apply_push(&push).unwrap();
+	assert_eq!(Subscription::Remove, item.subscription);
+	assert_eq!(Some("ver8"), roster.ver.as_deref());
+	assert!(roster.get("nurse@example.com").is_none());
+	let push = Stanza::from_str(
+		"<iq type='set' id='p2'><query xmlns='jabber:iq:roster'><item jid='a@example.com'/><item jid='b@example.com'/></query></iq>",
+	);
+	assert!(roster.apply_push(&push).is_none());
+
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	conn.set_jid("juliet@example.com/balcony").unwrap();
+	assert!(roster::is_valid_push(&conn, &Stanza::new_iq(Some("set"), Some("p3"))));
+	let mut spoofed = Stanza::new_iq(Some("set"), Some("p4"));
+	spoofed.set_from("tybalt@example.com").unwrap();
+	assert!(!roster::is_valid_push(&conn, &spoofed));
+	spoofed.set_from("juliet@example.com").unwrap();
+	assert!(roster::is_valid_push(&conn, &spoofed));
+
+	struct Cached(Option<Roster>);
+
+	impl RosterStore for Cached {
+		fn load(&mut self) -> Option<Roster> {
+			self.0.take()
+		}
+
+		fn save(&mut self, roster: &Roster) {
+			self.0 = Some(roster.clone());
+		}
+	}
+
+	let mut cached = Roster::new();
+	cached.ver = Some("ver8".to_owned());
+	cached.items.insert(
+		"romeo@example.net".to_owned(),
+		RosterItem {
+			jid: "romeo@example.net".to_owned(),
+			name: None,
+			subscription: Subscription::Both,
+			ask: false,
+			groups: vec![],
+		},
+	);
+	let shared = roster::enable_sync(&mut conn, Cached(Some(cached.clone())), true, |_, _, _: &RosterEvent| {});
+	assert_eq!(cached, *shared.lock().unwrap());
+}
+
+#[test]
+fn cancel_token() {
+	let token = CancelToken::new();
+	let clone = token.clone();
+	assert!(!token.is_cancelled());
+	clone.cancel();
+	assert!(token.is_cancelled());
+
+	let token = CancelToken::new();
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert!(!conn.on_cancel(|_, _| {}));
+	let registered = conn.with_cancel_token(&token, |conn| {
+		assert!(conn
+			.id_handler_add(|_, _, _| HandlerResult::RemoveHandler, "cancel_1")
+			.is_some());
+		conn.on_cancel(|_, _| {})
+	});
+	assert!(registered);
+	assert!(!conn.on_cancel(|_, _| {}));
+	assert_eq!(42, conn.with_cancel_token(&CancelToken::new(), |_| 42));
+}
+
+#[test]
+fn connect_twice() {
+	let mut conn = Connection::new(Context::new_with_null_logger());
+	assert_eq!(Err(Error::InvalidOperation), conn.reconnect());
+	conn.set_jid("user@127.0.0.1/desktop").unwrap();
+	if conn.try_connect_client(Some("127.0.0.1"), 1, |_, _, _| {}).is_err() {
+		return;
+	}
+	assert_eq!(
+		Err(Error::InvalidOperation),
+		conn.try_connect_client(Some("127.0.0.1"), 1, |_, _, _| {})
+	);
+	assert_eq!(
+		Err(Error::InvalidOperation),
+		conn.try_connect_component("127.0.0.1", 1, |_, _, _| {})
+	);
+	assert_eq!(ConnType::XMPP_CLIENT, conn.conn_type());
+	let ctx = conn.into_context();
+	assert_eq!(Err(Error::InvalidOperation), ctx.reconnect("other@127.0.0.1"));
+	assert_eq!(Err(Error::InvalidOperation), ctx.reconnect("user@127.0.0.1"));
+}
+
+#[test]
+fn connection_event_callbacks() {
+	let creds = if let Some(creds) = Creds::acquire() {
+		creds
+	} else {
+		eprintln!("Can't acquire creds, skipping test");
+		return;
+	};
+
+	let order = Arc::new(Mutex::new(vec![]));
+	{
+		let mut conn = creds.make_conn();
+		conn.on_connect({
+			let order = Arc::clone(&order);
+			move |_, _| order.lock().unwrap().push("connect 1")
+		});
+		conn.on_connect({
+			let order = Arc::clone(&order);
+			move |_, conn| {
+				order.lock().unwrap().push("connect 2");
+				conn.disconnect();
+			}
+		});
+		conn.on_disconnect({
+			let order = Arc::clone(&order);
+			move |ctx, _, _| {
+				order.lock().unwrap().push("disconnect");
+				ctx.stop();
+			}
+		});
+		let ctx = conn
+			.connect_client(creds.alt_host(), creds.alt_port(), {
+				let order = Arc::clone(&order);
+				move |_, _, evt| {
+					if let ConnectionEvent::Connect = evt {
+						order.lock().unwrap().push("handler");
+					}
+				}
+			})
+			.unwrap();
+		ctx.run();
+	}
+	assert_eq!(
+		vec!["connect 1", "connect 2", "handler", "disconnect"],
+		*order.lock().unwrap()
+	);
+}
+
+#[test]
+fn handler_order() {
+	let creds = if let Some(creds) = Creds::acquire() {
+		creds
+	} else {
+		eprintln!("Can't acquire creds, skipping test");
+		return;
+	};
+
+	let order = Arc::new(Mutex::new(Vec::new()));
+	let recorder = |label: &'static str| {
+		let order = Arc::clone(&order);
+		move |_: &Context, _: &mut Connection, stanza: &Stanza| {
+			if stanza.id() == Some("ping-1") {
+				order.lock().unwrap().push(label);
+			}
+			HandlerResult::KeepHandler
+		}
+	};
+	{
+		let mut conn = creds.make_conn();
+		let by_name = recorder("name");
+		let by_type = recorder("type");
+		let by_id = recorder("id");
+		let chain = HandlerChain::new()
+			.handler(None, Some("iq"), None, recorder("chain-name"))
+			.id_handler("ping-1", recorder("chain-id"));
+		conn.handler_add(by_name, None, Some("iq"), None).expect("Can't add handler");
+		assert!(conn.handler_chain_add(chain));
+		assert!(!conn.handler_chain_add(HandlerChain::new()));
+		conn.handler_add(
+			move |ctx: &Context, conn: &mut Connection, stanza: &Stanza| by_type(ctx, conn, stanza),
+			None,
+			None,
+			Some("result"),
+		);
+		// every handler needs a distinct closure type to be registered
+		conn
+			.id_handler_add(
+				move |ctx: &Context, conn: &mut Connection, stanza: &Stanza| by_id(ctx, conn, stanza),
+				"ping-1",
+			)
+			.expect("Can't add id handler");
+		let ctx = conn
+			.connect_client(creds.alt_host(), creds.alt_port(), {
+				let order = Arc::clone(&order);
+				move |ctx, conn, evt| match evt {
+					ConnectionEvent::Connect => {
+						order.lock().unwrap().push("connect");
+						let mut ping = Stanza::new_iq(Some("get"), Some("ping-1"));
+						let mut ping_el = Stanza::new();
+						ping_el.set_name("ping").unwrap();
+						ping_el.set_ns("urn:xmpp:ping").unwrap();
+						ping.add_child(ping_el).unwrap();
+						conn.send(&ping);
+						conn.timed_handler_add(
+							|_, conn| {
+								conn.disconnect();
+								HandlerResult::RemoveHandler
+							},
+							Duration::from_secs(1),
+						);
+					}
+					ConnectionEvent::Disconnect(_) => ctx.stop(),
+					_ => {}
+				}
+			})
+			.unwrap();
+		ctx.run();
+	}
+	assert_eq!(
+		vec!["connect", "id", "name", "chain-name", "chain-id", "type"],
+		*order.lock().unwrap()
+	);
+}
+
 #[test]
 fn timed_handler_creds() {
 	let creds = if let Some(creds) = Creds::acquire() {
@@ -621,7 +3625,7 @@ fn timed_handler_creds() {
 		{
 			let conn = creds.make_conn();
 			let ctx = conn
-				.connect_client(None, None, {
+				.connect_client(creds.alt_host(), creds.alt_port(), {
 					let i_incrementer = i_incrementer.clone();
 					move |ctx, conn, evt| match evt {
 						ConnectionEvent::Connect => {
@@ -648,7 +3652,7 @@ fn timed_handler_creds() {
 				.timed_handler_add(i_incrementer.clone(), Duration::from_millis(1))
 				.expect("Can't add timed handler");
 			let ctx = conn
-				.connect_client(None, None, {
+				.connect_client(creds.alt_host(), creds.alt_port(), {
 					move |ctx, conn, evt| match evt {
 						ConnectionEvent::Connect => {
 							do_common_stuff(conn);
@@ -670,7 +3674,7 @@ fn timed_handler_creds() {
 		{
 			let conn = creds.make_conn();
 			let ctx = conn
-				.connect_client(None, None, {
+				.connect_client(creds.alt_host(), creds.alt_port(), {
 					let i_incrementer = i_incrementer.clone();
 					move |ctx, conn, evt| match evt {
 						ConnectionEvent::Connect => {
@@ -696,7 +3700,7 @@ fn timed_handler_creds() {
 		{
 			let conn = creds.make_conn();
 			let ctx = conn
-				.connect_client(None, None, {
+				.connect_client(creds.alt_host(), creds.alt_port(), {
 					let i_incrementer = i_incrementer.clone();
 					move |ctx, conn, evt| match evt {
 						ConnectionEvent::Connect => {
@@ -736,7 +3740,7 @@ fn ctx_con_lifetime_issues() {
 	let default_con_handler = |conn: &mut Connection, evt: ConnectionEvent, _: i32, _: Option<&StreamError>| {};
 
 	let mut conn = creds.make_conn();
-	conn.connect_client(None, None, &default_con_handler).unwrap();
+	conn.connect_client(creds.alt_host(), creds.alt_port(), &default_con_handler).unwrap();
 }*/
 
 #[test]
@@ -782,7 +3786,7 @@ fn id_handler_creds() {
 		{
 			let conn = creds.make_conn();
 			let ctx = conn
-				.connect_client(None, None, {
+				.connect_client(creds.alt_host(), creds.alt_port(), {
 					let i_incrementer = i_incrementer.clone();
 					move |ctx, conn, evt| match evt {
 						ConnectionEvent::Connect => {
@@ -818,7 +3822,7 @@ fn id_handler_creds() {
 				.id_handler_add(i_incrementer.clone(), "get_roster")
 				.expect("Can't add id handler");
 			let ctx = conn
-				.connect_client(None, None, {
+				.connect_client(creds.alt_host(), creds.alt_port(), {
 					move |ctx, conn, evt| match evt {
 						ConnectionEvent::Connect => {
 							let mut iq = Stanza::new_iq(Some("get"), Some("get_roster1"));
@@ -846,7 +3850,7 @@ fn id_handler_creds() {
 		{
 			let conn = creds.make_conn();
 			let ctx = conn
-				.connect_client(None, None, {
+				.connect_client(creds.alt_host(), creds.alt_port(), {
 					let i_incrementer = i_incrementer.clone();
 					move |ctx, conn, evt| match evt {
 						ConnectionEvent::Connect => {
@@ -873,7 +3877,7 @@ fn id_handler_creds() {
 		{
 			let conn = creds.make_conn();
 			let ctx = conn
-				.connect_client(None, None, {
+				.connect_client(creds.alt_host(), creds.alt_port(), {
 					let i_incrementer = i_incrementer.clone();
 					move |ctx, conn, evt| match evt {
 						ConnectionEvent::Connect => {
@@ -938,7 +3942,9 @@ fn handler() {
 		conn
 			.handler_add(i_incrementer.clone(), None, Some("iq"), None)
 			.expect("Can't add handler");
-		let ctx = conn.connect_client(None, None, default_con_handler).unwrap();
+		let ctx = conn
+			.connect_client(creds.alt_host(), creds.alt_port(), default_con_handler)
+			.unwrap();
 		ctx.run();
 		assert_eq!(*i.read().unwrap(), 1);
 	}
@@ -950,7 +3956,9 @@ fn handler() {
 		conn
 			.handler_add(i_incrementer.clone(), None, Some("non-existent"), None)
 			.expect("Can't add handler");
-		let ctx = conn.connect_client(None, None, default_con_handler).unwrap();
+		let ctx = conn
+			.connect_client(creds.alt_host(), creds.alt_port(), default_con_handler)
+			.unwrap();
 		ctx.run();
 		assert_eq!(*i.read().unwrap(), 0);
 	}
@@ -963,7 +3971,9 @@ fn handler() {
 			.handler_add(i_incrementer.clone(), None, None, None)
 			.expect("Can't add handler");
 		conn.handler_delete(handler);
-		let ctx = conn.connect_client(None, None, default_con_handler).unwrap();
+		let ctx = conn
+			.connect_client(creds.alt_host(), creds.alt_port(), default_con_handler)
+			.unwrap();
 		ctx.run();
 		assert_eq!(*i.read().unwrap(), 0);
 	}
@@ -976,7 +3986,9 @@ fn handler() {
 			.handler_add(i_incrementer.clone(), None, None, None)
 			.expect("Can't add handler");
 		conn.handlers_clear();
-		let ctx = conn.connect_client(None, None, default_con_handler).unwrap();
+		let ctx = conn
+			.connect_client(creds.alt_host(), creds.alt_port(), default_con_handler)
+			.unwrap();
 		ctx.run();
 		assert_eq!(*i.read().unwrap(), 0);
 	}
@@ -987,7 +3999,9 @@ fn handler() {
 		let mut conn = creds.make_conn();
 		assert!(conn.handler_add(&i_incrementer, None, Some("iq"), None,).is_some());
 		assert!(conn.handler_add(&i_incrementer, None, Some("iq"), None).is_none());
-		let ctx = conn.connect_client(None, None, default_con_handler).unwrap();
+		let ctx = conn
+			.connect_client(creds.alt_host(), creds.alt_port(), default_con_handler)
+			.unwrap();
 		ctx.run();
 		assert_eq!(*i.read().unwrap(), 1);
 	}
@@ -998,7 +4012,9 @@ fn handler() {
 		let mut conn = creds.make_conn();
 		assert!(conn.handler_add(i_incrementer.clone(), None, Some("iq"), None,).is_some());
 		assert!(conn.handler_add(i_incrementer.clone(), None, Some("iq"), None).is_none());
-		let ctx = conn.connect_client(None, None, default_con_handler).unwrap();
+		let ctx = conn
+			.connect_client(creds.alt_host(), creds.alt_port(), default_con_handler)
+			.unwrap();
 		ctx.run();
 		assert_eq!(*i.read().unwrap(), 1);
 	}
@@ -1031,7 +4047,7 @@ fn stanza_global_context() {
 			)
 			.expect("Can't add handler");
 		let ctx = conn
-			.connect_client(None, None, |ctx, conn, evt| match evt {
+			.connect_client(creds.alt_host(), creds.alt_port(), |ctx, conn, evt| match evt {
 				ConnectionEvent::Connect => conn.disconnect(),
 				ConnectionEvent::Disconnect(_) => ctx.stop(),
 				_ => (),
@@ -1047,7 +4063,7 @@ fn stanza_global_context() {
 #[test]
 #[cfg(feature = "libstrophe-0_11_0")]
 fn connection_handler_tls() {
-	let creds = if let Some(creds) = Creds::acquire() {
+	let creds = if let Some(creds) = Creds::acquire_external() {
 		creds
 	} else {
 		eprintln!("Can't acquire creds, skipping test");
@@ -1075,7 +4091,7 @@ fn connection_handler_tls() {
 				}
 			});
 			let ctx = conn
-				.connect_client(None, None, {
+				.connect_client(creds.alt_host(), creds.alt_port(), {
 					let flags = Arc::clone(&flags);
 					move |ctx, conn, evt| match evt {
 						ConnectionEvent::Connect => {
@@ -1116,7 +4132,7 @@ fn connection_handler_tls() {
 				}
 			});
 			let ctx = conn
-				.connect_client(None, None, {
+				.connect_client(creds.alt_host(), creds.alt_port(), {
 					let flags = Arc::clone(&flags);
 					move |ctx, conn, evt| match evt {
 						ConnectionEvent::Connect => {
@@ -1158,26 +4174,59 @@ struct Creds {
 	jid: String,
 	name: String,
 	pass: String,
+	/// Port of the embedded [server], `None` for the external one
+	embedded_port: Option<u16>,
 }
 
 impl Creds {
+	/// Registers an account on the external server, falls back to the embedded [server] if it's not available
 	pub fn acquire() -> Option<Self> {
+		Self::acquire_external().or_else(|| {
+			let port = server::ensure_started()?;
+			let mut generator = Generator::default();
+			let name = generator.next()?;
+			Some(Self {
+				jid: format!("{name}@localhost"),
+				name,
+				pass: generator.next()?,
+				embedded_port: Some(port),
+			})
+		})
+	}
+
+	/// Registers an account on the external server using `creds-acquire.sh`, required for the TLS tests
+	pub fn acquire_external() -> Option<Self> {
 		let mut generator = Generator::default();
 		let name = generator.next()?;
 		let jid = format!("{name}@localhost");
 		let pass = generator.next()?;
 		let creds_acquire_script = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR")?).join("creds-acquire.sh");
-		let result = Command::new(creds_acquire_script).arg(&name).arg(&pass).output().unwrap();
+		let result = Command::new(creds_acquire_script).arg(&name).arg(&pass).output().ok()?;
 		if !result.status.success() {
 			return None;
 		}
-		Some(Self { jid, name, pass })
+		Some(Self {
+			jid,
+			name,
+			pass,
+			embedded_port: None,
+		})
+	}
+
+	/// Host to pass to `connect_client()`, the external server is found by the JID domain
+	pub fn alt_host(&self) -> Option<&'static str> {
+		self.embedded_port.map(|_| server::HOST)
+	}
+
+	/// Port to pass to `connect_client()`
+	pub fn alt_port(&self) -> Option<u16> {
+		self.embedded_port
 	}
 
 	pub fn make_conn(&self) -> Connection<'_, 'static> {
 		let mut conn = Connection::new(Context::new_with_default_logger());
-		conn.set_jid(&self.jid);
-		conn.set_pass(&self.pass);
+		conn.set_jid(&self.jid).unwrap();
+		conn.set_pass(&self.pass).unwrap();
 		conn
 			.set_flags(ConnectionFlags::TRUST_TLS)
 			.expect("Cannot set connection flags");
@@ -1187,8 +4236,8 @@ impl Creds {
 	#[cfg(feature = "libstrophe-0_11_0")]
 	pub fn make_tls_conn(&self) -> Connection<'_, 'static> {
 		let mut conn = Connection::new(Context::new_with_default_logger());
-		conn.set_jid(&self.jid);
-		conn.set_pass(&self.pass);
+		conn.set_jid(&self.jid).unwrap();
+		conn.set_pass(&self.pass).unwrap();
 		conn
 			.set_flags(ConnectionFlags::MANDATORY_TLS)
 			.expect("Cannot set connection flags");
@@ -1198,6 +4247,9 @@ impl Creds {
 
 impl Drop for Creds {
 	fn drop(&mut self) {
+		if self.embedded_port.is_some() {
+			return;
+		}
 		if let Some(cargo_manifest_dir) = env::var_os("CARGO_MANIFEST_DIR") {
 			let creds_release_script = PathBuf::from(cargo_manifest_dir).join("creds-release.sh");
 			if let Err(e) = Command::new(creds_release_script).arg(&self.name).output() {
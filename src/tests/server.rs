@@ -0,0 +1,344 @@
+//! Minimal in-process XMPP server for the tests that need a live connection
+//!
+//! It implements just enough of the client protocol for libstrophe to establish a session: SASL PLAIN (any credentials
+//! are accepted), resource binding and the legacy session establishment. After that the stanzas are handled like this:
+//!
+//!   * IQ requests to the server (no `to` or `to` equal to the domain) get an empty result for pings and a
+//!     `service-unavailable` error otherwise, the responses are ignored
+//!   * presences without `to` are broadcast back to the session
+//!   * everything addressed to a JID is echoed back to the session with `from` set to the session JID
+//!
+//! TLS is not supported, so the TLS tests still require an external server.
+
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use once_cell::sync::Lazy;
+
+use crate::base64;
+
+const NS_SASL: &str = "urn:ietf:params:xml:ns:xmpp-sasl";
+const NS_BIND: &str = "urn:ietf:params:xml:ns:xmpp-bind";
+const NS_SESSION: &str = "urn:ietf:params:xml:ns:xmpp-session";
+const NS_PING: &str = "urn:xmpp:ping";
+
+/// Host the server listens on, pass it together with the port to `connect_client()`
+pub const HOST: &str = "127.0.0.1";
+
+static PORT: Lazy<Option<u16>> = Lazy::new(|| {
+	let listener = TcpListener::bind((HOST, 0)).ok()?;
+	let port = listener.local_addr().ok()?.port();
+	thread::spawn(move || {
+		for stream in listener.incoming().flatten() {
+			thread::spawn(move || Session::new(stream).run());
+		}
+	});
+	Some(port)
+});
+
+/// Starts the server on the first call, returns the port it listens on or `None` if it can't be started
+pub fn ensure_started() -> Option<u16> {
+	*PORT
+}
+
+enum Event {
+	Skip,
+	StreamOpen(String),
+	StreamClose,
+	Element(String),
+}
+
+struct Session {
+	stream: TcpStream,
+	domain: String,
+	user: Option<String>,
+	jid: Option<String>,
+}
+
+impl Session {
+	fn new(stream: TcpStream) -> Self {
+		Self {
+			stream,
+			domain: "localhost".to_owned(),
+			user: None,
+			jid: None,
+		}
+	}
+
+	fn run(mut self) {
+		let mut buf = String::new();
+		let mut chunk = [0; 4096];
+		loop {
+			let read = match self.stream.read(&mut chunk) {
+				Ok(0) | Err(_) => return,
+				Ok(read) => read,
+			};
+			buf.push_str(&String::from_utf8_lossy(&chunk[..read]));
+			while let Some((event, consumed)) = next_event(&buf) {
+				buf.drain(..consumed);
+				let keep_going = match event {
+					Event::Skip => Ok(true),
+					Event::StreamOpen(tag) => self.stream_open(&tag).map(|_| true),
+					Event::StreamClose => {
+						let _ = self.send("</stream:stream>");
+						let _ = self.stream.shutdown(Shutdown::Both);
+						Ok(false)
+					}
+					Event::Element(element) => self.element(&element).map(|_| true),
+				};
+				if !matches!(keep_going, Ok(true)) {
+					return;
+				}
+			}
+			if !buf.contains('<') {
+				buf.clear();
+			}
+		}
+	}
+
+	fn send(&mut self, data: &str) -> std::io::Result<()> {
+		self.stream.write_all(data.as_bytes())
+	}
+
+	fn stream_open(&mut self, tag: &str) -> std::io::Result<()> {
+		static STREAM_ID: AtomicUsize = AtomicUsize::new(1);
+		let (_, attrs) = parse_start(tag);
+		if let Some(domain) = attr(&attrs, "to") {
+			self.domain = domain.to_owned();
+		}
+		let features = if self.user.is_none() {
+			format!("<mechanisms xmlns='{NS_SASL}'><mechanism>PLAIN</mechanism></mechanisms>")
+		} else {
+			format!("<bind xmlns='{NS_BIND}'/><session xmlns='{NS_SESSION}'><optional/></session>")
+		};
+		let header = format!(
+			"<?xml version='1.0'?><stream:stream xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams' \
+			id='{}' from='{}' version='1.0' xml:lang='en'><stream:features>{}</stream:features>",
+			STREAM_ID.fetch_add(1, Ordering::Relaxed),
+			self.domain,
+			features
+		);
+		self.send(&header)
+	}
+
+	fn element(&mut self, element: &str) -> std::io::Result<()> {
+		let (name, attrs) = parse_start(element);
+		match name.as_str() {
+			"auth" => {
+				let user = base64::decode(inner_text(element).trim())
+					.and_then(|decoded| {
+						// authzid \0 authcid \0 password
+						let decoded = String::from_utf8(decoded).ok()?;
+						decoded.split('\0').nth(1).map(str::to_owned)
+					})
+					.filter(|user| !user.is_empty());
+				match user {
+					Some(user) => {
+						self.user = Some(user);
+						self.send(&format!("<success xmlns='{NS_SASL}'/>"))
+					}
+					None => self.send(&format!("<failure xmlns='{NS_SASL}'><malformed-request/></failure>")),
+				}
+			}
+			"iq" if element.contains(NS_BIND) => {
+				let resource = child_text(element, "resource").unwrap_or("libstrophe-test");
+				let jid = format!("{}@{}/{}", self.user.as_deref().unwrap_or_default(), self.domain, resource);
+				let reply = format!(
+					"<iq type='result' id='{}'><bind xmlns='{NS_BIND}'><jid>{}</jid></bind></iq>",
+					attr(&attrs, "id").unwrap_or_default(),
+					jid
+				);
+				self.jid = Some(jid);
+				self.send(&reply)
+			}
+			"iq" if element.contains(NS_SESSION) => {
+				let reply = format!("<iq type='result' id='{}'/>", attr(&attrs, "id").unwrap_or_default());
+				self.send(&reply)
+			}
+			"iq" | "message" | "presence" if self.jid.is_some() => self.route(element, &name, &attrs),
+			_ => Ok(()),
+		}
+	}
+
+	fn route(&mut self, element: &str, name: &str, attrs: &[(String, String)]) -> std::io::Result<()> {
+		let jid = self.jid.clone().unwrap_or_default();
+		let to = attr(attrs, "to").filter(|&to| to != self.domain);
+		let typ = attr(attrs, "type");
+		match (name, to, typ) {
+			("iq", None, Some("get" | "set")) => {
+				let id = attr(attrs, "id").unwrap_or_default();
+				let reply = if element.contains(NS_PING) {
+					format!("<iq type='result' id='{}' from='{}' to='{}'/>", id, self.domain, jid)
+				} else {
+					format!(
+						"<iq type='error' id='{}' from='{}' to='{}'><error type='cancel'>\
+						<service-unavailable xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/></error></iq>",
+						id, self.domain, jid
+					)
+				};
+				self.send(&reply)
+			}
+			("presence", None, _) => {
+				let echo = with_attrs(element, name, attrs, &jid, &jid);
+				self.send(&echo)
+			}
+			(_, Some(to), _) => {
+				let echo = with_attrs(element, name, attrs, &jid, to);
+				self.send(&echo)
+			}
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Extracts the next complete event from the stream buffer, returns it together with the number of consumed bytes
+fn next_event(buf: &str) -> Option<(Event, usize)> {
+	let start = buf.find('<')?;
+	let rest = &buf[start..];
+	if rest.starts_with("<?") {
+		return rest.find("?>").map(|end| (Event::Skip, start + end + 2));
+	}
+	if rest.starts_with("<stream:stream") {
+		let end = tag_end(rest, 0)?;
+		return Some((Event::StreamOpen(rest[..end].to_owned()), start + end));
+	}
+	if rest.starts_with("</stream:stream") {
+		let end = tag_end(rest, 0)?;
+		return Some((Event::StreamClose, start + end));
+	}
+	let mut depth = 0usize;
+	let mut pos = 0;
+	loop {
+		let lt = pos + rest[pos..].find('<')?;
+		let end = tag_end(rest, lt)?;
+		let tag = &rest[lt..end];
+		if tag.starts_with("</") {
+			depth = depth.saturating_sub(1);
+		} else if !tag.ends_with("/>") {
+			depth += 1;
+		}
+		pos = end;
+		if depth == 0 {
+			return Some((Event::Element(rest[..end].to_owned()), start + end));
+		}
+	}
+}
+
+/// Position right after the `>` closing the tag starting at `from`, the quoted attribute values are skipped
+fn tag_end(s: &str, from: usize) -> Option<usize> {
+	let mut quote = None;
+	for (i, c) in s[from..].char_indices() {
+		match (quote, c) {
+			(None, '"' | '\'') => quote = Some(c),
+			(Some(q), c) if q == c => quote = None,
+			(None, '>') => return Some(from + i + 1),
+			_ => {}
+		}
+	}
+	None
+}
+
+/// Name and the raw (still escaped) attributes of the first tag of `element`
+fn parse_start(element: &str) -> (String, Vec<(String, String)>) {
+	let end = tag_end(element, 0).unwrap_or(element.len());
+	let tag = element[1..end].trim_end_matches('>').trim_end_matches('/');
+	let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+	let name = tag[..name_end].to_owned();
+	let mut attrs = vec![];
+	let mut rest = &tag[name_end..];
+	while let Some(eq) = rest.find('=') {
+		let attr_name = rest[..eq].trim().to_owned();
+		let value_part = rest[eq + 1..].trim_start();
+		let quote = match value_part.chars().next() {
+			Some(quote @ ('"' | '\'')) => quote,
+			_ => break,
+		};
+		let value_end = match value_part[1..].find(quote) {
+			Some(value_end) => value_end + 1,
+			None => break,
+		};
+		attrs.push((attr_name, value_part[1..value_end].to_owned()));
+		rest = &value_part[value_end + 1..];
+	}
+	(name, attrs)
+}
+
+fn attr<'a>(attrs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+	attrs.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+}
+
+/// Rebuilds the first tag of `element` with the replaced `from` and `to` attributes
+fn with_attrs(element: &str, name: &str, attrs: &[(String, String)], from: &str, to: &str) -> String {
+	let end = tag_end(element, 0).unwrap_or(element.len());
+	let self_closing = element[..end].ends_with("/>");
+	let mut out = format!("<{} from='{}' to='{}'", name, from, to);
+	for (attr_name, value) in attrs {
+		if attr_name != "from" && attr_name != "to" {
+			let quote = if value.contains('\'') {
+				'"'
+			} else {
+				'\''
+			};
+			out.push_str(&format!(" {attr_name}={quote}{value}{quote}"));
+		}
+	}
+	out.push_str(if self_closing {
+		"/>"
+	} else {
+		">"
+	});
+	out.push_str(&element[end..]);
+	out
+}
+
+/// Text between the first tag of `element` and its closing tag
+fn inner_text(element: &str) -> &str {
+	let start = tag_end(element, 0).unwrap_or(element.len());
+	let end = element.rfind("</").filter(|&end| end >= start).unwrap_or(start);
+	&element[start..end]
+}
+
+/// Text of the first descendant element called `name`
+fn child_text<'e>(element: &'e str, name: &str) -> Option<&'e str> {
+	let open = element.find(&format!("<{}>", name))?;
+	let text_start = open + name.len() + 2;
+	let text_end = text_start + element[text_start..].find(&format!("</{}>", name))?;
+	Some(&element[text_start..text_end])
+}
+
+#[test]
+fn parsing() {
+	let buf = "<?xml version='1.0'?><stream:stream to='localhost' version='1.0'> <iq id='a' type=\"get\"><ping xmlns='urn:xmpp:ping'/></iq><message";
+	let (event, consumed) = next_event(buf).unwrap();
+	assert!(matches!(event, Event::Skip));
+	let buf = &buf[consumed..];
+	let (event, consumed) = next_event(buf).unwrap();
+	assert!(matches!(event, Event::StreamOpen(tag) if tag.contains("to='localhost'")));
+	let buf = &buf[consumed..];
+	let (event, consumed) = next_event(buf).unwrap();
+	let element = match event {
+		Event::Element(element) => element,
+		_ => panic!("Element expected"),
+	};
+	assert_eq!("<iq id='a' type=\"get\"><ping xmlns='urn:xmpp:ping'/></iq>", element);
+	let (name, attrs) = parse_start(&element);
+	assert_eq!("iq", name);
+	assert_eq!(Some("get"), attr(&attrs, "type"));
+	assert_eq!(
+		"<iq from='u@localhost/r' to='v@localhost' id='a' type='get'><ping xmlns='urn:xmpp:ping'/></iq>",
+		with_attrs(&element, &name, &attrs, "u@localhost/r", "v@localhost")
+	);
+	assert!(next_event(&buf[consumed..]).is_none());
+
+	assert_eq!(Some(b"\0user\0pass".to_vec()), base64::decode("AHVzZXIAcGFzcw=="));
+	assert_eq!(
+		"AHVzZXIAcGFzcw==",
+		inner_text("<auth mechanism='PLAIN'>AHVzZXIAcGFzcw==</auth>")
+	);
+	assert_eq!(
+		Some("res"),
+		child_text("<iq><bind xmlns='x'><resource>res</resource></bind></iq>", "resource")
+	);
+}
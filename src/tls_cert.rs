@@ -1,7 +1,8 @@
 use std::fmt;
 use std::ptr::NonNull;
+use std::time::SystemTime;
 
-use crate::{CertElement, Context, FFI};
+use crate::{datetime, CertElement, Context, ParseDateTimeError, FFI};
 
 pub struct TlsCert {
 	inner: NonNull<sys::xmpp_tlscert_t>,
@@ -55,6 +56,24 @@ impl TlsCert {
 		unsafe { FFI(sys::xmpp_tlscert_get_string(self.as_ptr(), element)).receive() }
 	}
 
+	/// Start of the certificate validity period, parsed from the [CertElement::XMPP_CERT_NOTBEFORE] string
+	///
+	/// Returns `None` if the TLS backend doesn't provide the value.
+	pub fn not_before(&self) -> Option<Result<SystemTime, ParseDateTimeError>> {
+		self
+			.get_string(CertElement::XMPP_CERT_NOTBEFORE)
+			.map(datetime::parse_cert_time)
+	}
+
+	/// End of the certificate validity period, parsed from the [CertElement::XMPP_CERT_NOTAFTER] string
+	///
+	/// Returns `None` if the TLS backend doesn't provide the value.
+	pub fn not_after(&self) -> Option<Result<SystemTime, ParseDateTimeError>> {
+		self
+			.get_string(CertElement::XMPP_CERT_NOTAFTER)
+			.map(datetime::parse_cert_time)
+	}
+
 	#[inline]
 	/// [xmpp_tlscert_get_description](https://strophe.im/libstrophe/doc/0.12.2/group___t_l_s.html#ga3373a412085f6c3a3db9adb2c49d9d07)
 	pub fn get_element_description(element: CertElement) -> Option<&'static str> {
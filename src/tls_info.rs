@@ -0,0 +1,18 @@
+#[cfg(feature = "libstrophe-0_11_0")]
+use crate::TlsCert;
+
+/// Parameters of the established TLS session, see [Connection::tls_info](crate::Connection::tls_info)
+///
+/// libstrophe doesn't expose the negotiated protocol version and cipher suite through its public API, so only the
+/// information that is available is provided. More fields are going to be added once upstream exposes them.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct TlsInfo {
+	/// TLS was established directly on connect (legacy SSL, XEP-0368) instead of using STARTTLS
+	pub direct_tls: bool,
+	/// The certificate verification was disabled with `TRUST_TLS` connection flag
+	pub trusted: bool,
+	#[cfg(feature = "libstrophe-0_11_0")]
+	/// Certificate presented by the server
+	pub peer_cert: Option<TlsCert>,
+}
@@ -0,0 +1,96 @@
+use std::fmt;
+
+use crate::version_check;
+
+/// Upper bounds for probing the linked library version with [version_check]
+const MAX_PROBED_MAJOR: i32 = 9;
+const MAX_PROBED_MINOR: i32 = 99;
+
+/// Versions of the crate and the C library it runs with, see [runtime_version](crate::runtime_version)
+///
+/// The `Display` implementation formats everything on one line suitable for the bug reports and the diagnostic output.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RuntimeVersion {
+	/// Version of this crate
+	pub wrapper_version: &'static str,
+	/// Newest libstrophe API version the crate was compiled for, selected with the `libstrophe-0_*` features
+	pub compiled_major_minor: (i32, i32),
+	/// Major and minor version of the linked libstrophe, `None` if it's outside of the probed range
+	pub linked_major_minor: Option<(i32, i32)>,
+	/// Enabled crate features, except for the XEP helper ones
+	pub compiled_features: Vec<&'static str>,
+}
+
+impl RuntimeVersion {
+	pub(crate) fn detect() -> Self {
+		let mut compiled_features = vec![];
+		let mut add = |enabled: bool, feature: &'static str| {
+			if enabled {
+				compiled_features.push(feature);
+			}
+		};
+		add(cfg!(feature = "libstrophe-0_9_3"), "libstrophe-0_9_3");
+		add(cfg!(feature = "libstrophe-0_10_0"), "libstrophe-0_10_0");
+		add(cfg!(feature = "libstrophe-0_11_0"), "libstrophe-0_11_0");
+		add(cfg!(feature = "libstrophe-0_12_0"), "libstrophe-0_12_0");
+		add(cfg!(feature = "rust-log"), "rust-log");
+		add(cfg!(feature = "unsafe-internals"), "unsafe-internals");
+		add(cfg!(feature = "stanza-tracking"), "stanza-tracking");
+		add(cfg!(feature = "buildtime_bindgen"), "buildtime_bindgen");
+		add(cfg!(feature = "core_ffi"), "core_ffi");
+		add(cfg!(feature = "serde"), "serde");
+		Self {
+			wrapper_version: env!("CARGO_PKG_VERSION"),
+			compiled_major_minor: compiled_major_minor(),
+			linked_major_minor: linked_major_minor(),
+			compiled_features,
+		}
+	}
+
+	/// Checks that the linked library provides at least the API the crate was compiled for
+	///
+	/// Returns `false` if the linked version couldn't be detected.
+	pub fn is_compatible(&self) -> bool {
+		self.linked_major_minor.map_or(false, |(major, minor)| {
+			let (compiled_major, compiled_minor) = self.compiled_major_minor;
+			major == compiled_major && minor >= compiled_minor
+		})
+	}
+}
+
+impl fmt::Display for RuntimeVersion {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let (compiled_major, compiled_minor) = self.compiled_major_minor;
+		write!(
+			f,
+			"libstrophe-rs {}, compiled for libstrophe {}.{}, linked ",
+			self.wrapper_version, compiled_major, compiled_minor
+		)?;
+		match self.linked_major_minor {
+			Some((major, minor)) => write!(f, "{}.{}", major, minor)?,
+			None => write!(f, "unknown")?,
+		}
+		write!(f, ", features: [{}]", self.compiled_features.join(", "))
+	}
+}
+
+fn compiled_major_minor() -> (i32, i32) {
+	if cfg!(feature = "libstrophe-0_12_0") {
+		(0, 12)
+	} else if cfg!(feature = "libstrophe-0_11_0") {
+		(0, 11)
+	} else if cfg!(feature = "libstrophe-0_10_0") {
+		(0, 10)
+	} else {
+		(0, 9)
+	}
+}
+
+/// `xmpp_version_check()` succeeds for the matching major version and any minor version starting with the linked one
+/// (`major == LIBXMPP_VERSION_MAJOR && minor >= LIBXMPP_VERSION_MINOR` in `ctx.c`), so the linked version is the first
+/// minor version that passes the check
+fn linked_major_minor() -> Option<(i32, i32)> {
+	let major = (0..=MAX_PROBED_MAJOR).find(|&major| version_check(major, MAX_PROBED_MINOR))?;
+	let minor = (0..=MAX_PROBED_MINOR).find(|&minor| version_check(major, minor))?;
+	Some((major, minor))
+}
@@ -0,0 +1,65 @@
+//! Protocol helpers for the XMPP extensions
+//!
+//! Every helper module lives behind its own `xep-NNNN` feature, the [RFC 6121](https://www.rfc-editor.org/rfc/rfc6121)
+//! [presence] and [roster] helpers behind the `presence` and `roster` features, the `xep-helpers` feature enables all of
+//! them. The core wrapper doesn't depend on them with the exception of [disco] which is used for the server features
+//! discovery and is always available. New helpers go here as `xep::<name>` modules with the matching feature.
+
+#[cfg(feature = "xep-0077")]
+pub mod account;
+#[cfg(feature = "xep-0224")]
+pub mod attention;
+#[cfg(feature = "xep-0191")]
+pub mod blocking;
+#[cfg(feature = "xep-0004")]
+pub mod data_forms;
+#[cfg(feature = "xep-0203")]
+pub mod delay;
+pub mod disco;
+#[cfg(feature = "xep-0297")]
+pub mod forwarded;
+#[cfg(feature = "xep-0249")]
+pub mod invite;
+#[cfg(feature = "xep-0166")]
+pub mod jingle;
+#[cfg(feature = "xep-0045")]
+pub mod muc;
+#[cfg(feature = "presence")]
+pub mod presence;
+#[cfg(feature = "xep-0016")]
+pub mod privacy;
+#[cfg(feature = "roster")]
+pub mod roster;
+#[cfg(feature = "xep-0388")]
+pub mod sasl2;
+#[cfg(feature = "xep-0055")]
+pub mod search;
+#[cfg(feature = "xep-0071")]
+pub mod xhtml_im;
+
+#[cfg(feature = "xep-0071")]
+pub(crate) use xhtml_im::NS_XHTML_IM;
+/// Used by the core to recognize the XHTML-IM bodies when the [XEP-0071](https://xmpp.org/extensions/xep-0071.html)
+/// helpers are disabled
+#[cfg(not(feature = "xep-0071"))]
+pub(crate) const NS_XHTML_IM: &str = "http://jabber.org/protocol/xhtml-im";
+
+#[cfg(any(
+	feature = "xep-0004",
+	feature = "xep-0016",
+	feature = "xep-0045",
+	feature = "xep-0055",
+	feature = "xep-0077",
+	feature = "xep-0388"
+))]
+mod iq;
+#[cfg(any(feature = "xep-0016", feature = "xep-0045", feature = "xep-0055"))]
+pub(crate) use iq::send_iq;
+#[cfg(feature = "xep-0077")]
+pub(crate) use iq::stanza_error_condition;
+#[cfg(any(feature = "xep-0004", feature = "xep-0077", feature = "xep-0388"))]
+pub(crate) use iq::text_element;
+#[cfg(any(feature = "xep-0016", feature = "xep-0045", feature = "xep-0055"))]
+pub use iq::IqError;
+#[cfg(any(feature = "xep-0016", feature = "xep-0045", feature = "xep-0055", feature = "xep-0077"))]
+pub use iq::NS_STANZA_ERRORS;
@@ -0,0 +1,143 @@
+//! [XEP-0077](https://xmpp.org/extensions/xep-0077.html) In-Band Registration account management
+
+use std::error::Error as StdError;
+use std::{fmt, result};
+
+use crate::xep::{stanza_error_condition, text_element};
+use crate::{Connection, Context, Error, HandlerResult, Jid, Result, Stanza};
+
+pub const NS_REGISTER: &str = "jabber:iq:register";
+
+/// Reason for the server rejecting the registration request
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RegistrationError {
+	/// `<bad-request/>`: the request is malformed, e.g. the username doesn't match the account
+	BadRequest,
+	/// `<not-authorized/>`: the server requires additional information (e.g. an old password form)
+	NotAuthorized,
+	/// `<not-allowed/>`: the server forbids the operation in-band
+	NotAllowed,
+	/// Any other stanza error condition
+	Other(String),
+	/// The response is neither a result nor an error with a known condition
+	Unexpected,
+}
+
+impl fmt::Display for RegistrationError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RegistrationError::BadRequest => write!(f, "Bad request"),
+			RegistrationError::NotAuthorized => write!(f, "Not authorized"),
+			RegistrationError::NotAllowed => write!(f, "Not allowed"),
+			RegistrationError::Other(condition) => write!(f, "Stanza error: {}", condition),
+			RegistrationError::Unexpected => write!(f, "Unexpected response"),
+		}
+	}
+}
+
+impl StdError for RegistrationError {}
+
+/// Builds the change password IQ for the account of `jid`
+pub fn change_password_request(jid: &Jid, new_password: &str, id: &str) -> Result<Stanza> {
+	let mut out = Stanza::new_iq(Some("set"), Some(id));
+	out.set_to(jid.domain())?;
+	let mut query = Stanza::new();
+	query.set_name("query")?;
+	query.set_ns(NS_REGISTER)?;
+	query.add_child(text_element("username", jid.node().ok_or(Error::InvalidOperation)?)?)?;
+	query.add_child(text_element("password", new_password)?)?;
+	out.add_child(query)?;
+	Ok(out)
+}
+
+/// Interprets the response to the [change_password_request] or [remove_request]
+pub fn parse_response(stanza: &Stanza) -> result::Result<(), RegistrationError> {
+	match stanza.stanza_type() {
+		Some("result") => Ok(()),
+		Some("error") => Err(match stanza_error_condition(stanza).as_deref() {
+			Some("bad-request") => RegistrationError::BadRequest,
+			Some("not-authorized") => RegistrationError::NotAuthorized,
+			Some("not-allowed") => RegistrationError::NotAllowed,
+			Some(condition) => RegistrationError::Other(condition.to_owned()),
+			None => RegistrationError::Unexpected,
+		}),
+		_ => Err(RegistrationError::Unexpected),
+	}
+}
+
+/// Changes the password of the account the connection is logged in to
+///
+/// Sends the change password request and calls `handler` with the outcome. On success the new password is stored in the
+/// connection so that it's used for the subsequent reconnects. libstrophe only accepts a new password for a disconnected
+/// connection, so it's actually applied when the current connection is closed. Returns [Error::InvalidOperation] if the
+/// connection JID is not set or has no node part.
+pub fn change_password<'cb, 'cx, CB>(conn: &mut Connection<'cb, 'cx>, new_password: impl Into<String>, handler: CB) -> Result<()>
+where
+	CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), RegistrationError>) + Send + 'cb,
+{
+	let new_password = new_password.into();
+	let jid = conn.jid().ok_or(Error::InvalidOperation)?.parse::<Jid>()?;
+	let id = conn.generate_id();
+	let request = change_password_request(&jid, &new_password, &id)?;
+	let mut handler = Some(handler);
+	conn.id_handler_add(
+		move |ctx, conn, stanza| {
+			let res = parse_response(stanza);
+			if res.is_ok() {
+				conn.set_pending_pass(new_password.clone());
+			}
+			if let Some(handler) = handler.take() {
+				handler(ctx, conn, res);
+			}
+			HandlerResult::RemoveHandler
+		},
+		id,
+	);
+	conn.send(&request);
+	Ok(())
+}
+
+/// Builds the account removal IQ
+pub fn remove_request(id: &str) -> Result<Stanza> {
+	let mut out = Stanza::new_iq(Some("set"), Some(id));
+	let mut query = Stanza::new();
+	query.set_name("query")?;
+	query.set_ns(NS_REGISTER)?;
+	let mut remove = Stanza::new();
+	remove.set_name("remove")?;
+	query.add_child(remove)?;
+	out.add_child(query)?;
+	Ok(out)
+}
+
+/// Deletes the account the connection is logged in to
+///
+/// Sends the account removal request and calls `handler` with the outcome. On success the connection is disconnected
+/// because the server is going to close the stream anyway, the connection handler then receives the corresponding
+/// disconnect event. Note that some servers close the stream without responding to the request, in that case `handler`
+/// is not called and only the disconnect event is delivered.
+pub fn delete<'cb, 'cx, CB>(conn: &mut Connection<'cb, 'cx>, handler: CB) -> Result<()>
+where
+	CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), RegistrationError>) + Send + 'cb,
+{
+	let id = conn.generate_id();
+	let request = remove_request(&id)?;
+	let mut handler = Some(handler);
+	conn.id_handler_add(
+		move |ctx, conn, stanza| {
+			let res = parse_response(stanza);
+			let removed = res.is_ok();
+			if let Some(handler) = handler.take() {
+				handler(ctx, conn, res);
+			}
+			if removed {
+				conn.disconnect();
+			}
+			HandlerResult::RemoveHandler
+		},
+		id,
+	);
+	conn.send(&request);
+	Ok(())
+}
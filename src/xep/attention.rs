@@ -0,0 +1,147 @@
+//! [XEP-0224](https://xmpp.org/extensions/xep-0224.html) Attention
+//!
+//! [attach] marks the message as requesting the attention of the recipient, [Attention::from_stanza] detects it in the
+//! incoming messages and [handler_add] delivers them to the handler as [Attention] events. Some clients use their own
+//! "nudge" or "buzz" elements instead, those are supported through [Signal::Custom].
+
+use crate::{Connection, Context, Error, HandlerId, HandlerResult, Result, Stanza};
+
+pub const NS_ATTENTION: &str = "urn:xmpp:attention:0";
+
+/// Element signalling the attention request
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Signal {
+	/// `<attention xmlns="urn:xmpp:attention:0"/>`
+	Attention,
+	/// Client specific element, e.g. a nudge
+	Custom { name: String, ns: String },
+}
+
+impl Signal {
+	#[inline]
+	pub fn custom(name: impl Into<String>, ns: impl Into<String>) -> Self {
+		Signal::Custom {
+			name: name.into(),
+			ns: ns.into(),
+		}
+	}
+
+	pub fn name(&self) -> &str {
+		match self {
+			Signal::Attention => "attention",
+			Signal::Custom { name, .. } => name,
+		}
+	}
+
+	pub fn ns(&self) -> &str {
+		match self {
+			Signal::Attention => NS_ATTENTION,
+			Signal::Custom { ns, .. } => ns,
+		}
+	}
+
+	/// Builds the empty signal element
+	pub fn to_stanza(&self) -> Result<Stanza> {
+		let mut out = Stanza::new();
+		out.set_name(self.name())?;
+		out.set_ns(self.ns())?;
+		Ok(out)
+	}
+
+	fn is_in(&self, message: &Stanza) -> bool {
+		message.get_child_by_name_and_ns(self.name(), self.ns()).is_some()
+	}
+}
+
+/// Attention request received in a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attention {
+	pub signal: Signal,
+	pub from: Option<String>,
+	/// Text sent along with the request
+	pub body: Option<String>,
+}
+
+impl Attention {
+	/// Detects the XEP-0224 attention request in the `message`, returns `None` for the other stanzas
+	pub fn from_stanza(message: &Stanza) -> Option<Self> {
+		Self::from_stanza_with(message, &[Signal::Attention])
+	}
+
+	/// Detects any of the `signals` in the `message`, the first matching one in the order of `signals` is reported
+	///
+	/// Error messages are ignored, they bounce the requests sent by this client.
+	pub fn from_stanza_with(message: &Stanza, signals: &[Signal]) -> Option<Self> {
+		if message.name() != Some("message") || message.stanza_type() == Some("error") {
+			return None;
+		}
+		let signal = signals.iter().find(|signal| signal.is_in(message))?;
+		Some(Attention {
+			signal: signal.clone(),
+			from: message.from().map(str::to_owned),
+			body: message.body(),
+		})
+	}
+}
+
+/// Adds the XEP-0224 attention request to the `message`
+pub fn attach(message: &mut Stanza) -> Result<()> {
+	attach_signal(message, &Signal::Attention)
+}
+
+/// Adds the `signal` to the `message`, does nothing if it's already there
+///
+/// Returns [Error::InvalidOperation] if `message` is not a `<message/>`.
+pub fn attach_signal(message: &mut Stanza, signal: &Signal) -> Result<()> {
+	if message.name() != Some("message") {
+		return Err(Error::InvalidOperation);
+	}
+	if signal.is_in(message) {
+		return Ok(());
+	}
+	message.add_child(signal.to_stanza()?)
+}
+
+/// Builds the `headline` message to `to` requesting the attention, as recommended by the XEP, with an optional `body`
+pub fn message(to: &str, body: Option<&str>) -> Result<Stanza> {
+	let mut out = Stanza::new_message(Some("headline"), None, Some(to));
+	if let Some(body) = body {
+		out.set_body(body)?;
+	}
+	attach(&mut out)?;
+	Ok(out)
+}
+
+/// Adds the handler receiving the XEP-0224 attention requests, see [Connection::handler_add]
+pub fn handler_add<'cb, 'cx, CB>(
+	conn: &mut Connection<'cb, 'cx>,
+	handler: CB,
+) -> Option<HandlerId<'cb, 'cx, impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb>>
+where
+	CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Attention) -> HandlerResult + Send + 'cb,
+{
+	handler_add_with(conn, vec![Signal::Attention], handler)
+}
+
+/// Adds the handler receiving the attention requests signalled by any of the `signals`, see
+/// [Attention::from_stanza_with]
+pub fn handler_add_with<'cb, 'cx, CB>(
+	conn: &mut Connection<'cb, 'cx>,
+	signals: Vec<Signal>,
+	mut handler: CB,
+) -> Option<HandlerId<'cb, 'cx, impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb>>
+where
+	CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Attention) -> HandlerResult + Send + 'cb,
+{
+	conn.handler_add(
+		move |ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>, stanza: &Stanza| match Attention::from_stanza_with(
+			stanza, &signals,
+		) {
+			Some(attention) => handler(ctx, conn, &attention),
+			None => HandlerResult::KeepHandler,
+		},
+		None,
+		Some("message"),
+		None,
+	)
+}
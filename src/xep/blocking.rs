@@ -0,0 +1,294 @@
+//! [XEP-0191](https://xmpp.org/extensions/xep-0191.html) Blocking Command with the privacy lists fallback
+//!
+//! The [Blocking] facade uses the blocking command when the server supports it and falls back to a dedicated
+//! [privacy list](crate::xep::privacy) otherwise, so that the application can block and unblock contacts with a single
+//! API regardless of the server age.
+
+use std::collections::VecDeque;
+use std::result;
+
+use crate::xep::privacy::{self, PrivacyAction, PrivacyItem, PrivacyItemType, PrivacyList};
+use crate::xep::{send_iq, IqError};
+use crate::{Connection, Context, Result, Stanza};
+
+pub const NS_BLOCKING: &str = "urn:xmpp:blocking";
+
+/// Protocol used by the [Blocking] facade
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockingBackend {
+	/// XEP-0191 blocking command
+	BlockingCommand,
+	/// XEP-0016 privacy list
+	PrivacyList,
+}
+
+/// Builds the IQ requesting the blocklist
+pub fn blocklist_request(id: &str) -> Result<Stanza> {
+	let mut out = Stanza::new_iq(Some("get"), Some(id));
+	out.add_child(element("blocklist", &[])?)?;
+	Ok(out)
+}
+
+/// Builds the IQ blocking the `jids`
+pub fn block_request(jids: &[&str], id: &str) -> Result<Stanza> {
+	let mut out = Stanza::new_iq(Some("set"), Some(id));
+	out.add_child(element("block", jids)?)?;
+	Ok(out)
+}
+
+/// Builds the IQ unblocking the `jids`, empty `jids` unblocks all contacts
+pub fn unblock_request(jids: &[&str], id: &str) -> Result<Stanza> {
+	let mut out = Stanza::new_iq(Some("set"), Some(id));
+	out.add_child(element("unblock", jids)?)?;
+	Ok(out)
+}
+
+/// Extracts the blocked JIDs from the response to the [blocklist_request]
+pub fn parse_blocklist(stanza: &Stanza) -> result::Result<Vec<String>, IqError> {
+	match stanza.stanza_type() {
+		Some("result") => stanza
+			.find_child_by_name_and_ns("blocklist", NS_BLOCKING)
+			.map(|blocklist| {
+				blocklist
+					.children()
+					.filter(|child| child.name() == Some("item"))
+					.filter_map(|item| item.get_attribute("jid").map(str::to_owned))
+					.collect()
+			})
+			.ok_or(IqError::Unexpected),
+		_ => Err(IqError::from_response(stanza)),
+	}
+}
+
+/// Interprets the response to the [block_request] or [unblock_request]
+pub fn parse_response(stanza: &Stanza) -> result::Result<(), IqError> {
+	match stanza.stanza_type() {
+		Some("result") => Ok(()),
+		_ => Err(IqError::from_response(stanza)),
+	}
+}
+
+/// Single API for blocking the contacts on both the new and the old servers
+///
+/// With the [BlockingBackend::PrivacyList] backend the facade keeps the blocked JIDs in the privacy list `list_name`
+/// which it makes both the active and the default list of the account, any other default list is replaced. Privacy
+/// lists are only active for the session that activated them, so [Blocking::block] should be called at least once per
+/// session (e.g. with an empty list of JIDs) for the list to be applied to the current session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blocking {
+	backend: BlockingBackend,
+	list_name: String,
+}
+
+impl Blocking {
+	/// Name of the privacy list used by [Blocking::detect]
+	pub const DEFAULT_LIST_NAME: &'static str = "blocklist";
+
+	/// Creates the facade with an explicitly selected `backend`
+	pub fn new(backend: BlockingBackend, list_name: impl Into<String>) -> Self {
+		Self {
+			backend,
+			list_name: list_name.into(),
+		}
+	}
+
+	/// Checks whether the server supports the blocking command and calls `handler` with the facade using the best
+	/// available backend, see [Connection::server_supports]. The privacy list backend is used if the connection is lost
+	/// before the server answers
+	pub fn detect<'cb, 'cx, CB>(conn: &mut Connection<'cb, 'cx>, handler: CB)
+	where
+		CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, Blocking) + Send + 'cb,
+	{
+		conn.server_supports(NS_BLOCKING, move |ctx, conn, supported| {
+			let backend = if supported == Some(true) {
+				BlockingBackend::BlockingCommand
+			} else {
+				BlockingBackend::PrivacyList
+			};
+			handler(ctx, conn, Blocking::new(backend, Self::DEFAULT_LIST_NAME));
+		});
+	}
+
+	#[inline]
+	pub fn backend(&self) -> BlockingBackend {
+		self.backend
+	}
+
+	#[inline]
+	pub fn list_name(&self) -> &str {
+		&self.list_name
+	}
+
+	/// Requests the blocked JIDs and calls `handler` with the outcome
+	pub fn blocked<'cb, 'cx, CB>(&self, conn: &mut Connection<'cb, 'cx>, handler: CB) -> Result<()>
+	where
+		CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<Vec<String>, IqError>) + Send + 'cb,
+	{
+		match self.backend {
+			BlockingBackend::BlockingCommand => {
+				let request = blocklist_request(&conn.generate_id())?;
+				send_iq(conn, &request, parse_blocklist, handler);
+				Ok(())
+			}
+			BlockingBackend::PrivacyList => self.with_list(conn, move |ctx, conn, list| {
+				handler(ctx, conn, list.map(|list| blocked_jids(&list).map(str::to_owned).collect()))
+			}),
+		}
+	}
+
+	/// Blocks all communication with the `jids` and calls `handler` with the outcome
+	pub fn block<'cb, 'cx, CB>(&self, conn: &mut Connection<'cb, 'cx>, jids: &[&str], handler: CB) -> Result<()>
+	where
+		CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), IqError>) + Send + 'cb,
+	{
+		match self.backend {
+			BlockingBackend::BlockingCommand => {
+				let request = block_request(jids, &conn.generate_id())?;
+				send_iq(conn, &request, parse_response, handler);
+				Ok(())
+			}
+			BlockingBackend::PrivacyList => {
+				let jids = jids.iter().map(|&jid| jid.to_owned()).collect::<Vec<_>>();
+				self.with_list(conn, move |ctx, conn, list| {
+					let mut list = match list {
+						Ok(list) => list,
+						Err(e) => return handler(ctx, conn, Err(e)),
+					};
+					for jid in jids {
+						if !blocked_jids(&list).any(|blocked| blocked == jid) {
+							let order = list.items.iter().map(|item| item.order + 1).max().unwrap_or(0);
+							list.items.push(PrivacyItem::deny_jid(jid, order));
+						}
+					}
+					let requests = if list.items.is_empty() {
+						Ok(VecDeque::new())
+					} else {
+						activate_requests(conn, &list)
+					};
+					finish(ctx, conn, requests, handler);
+				})
+			}
+		}
+	}
+
+	/// Unblocks the `jids` and calls `handler` with the outcome, empty `jids` unblocks all contacts
+	pub fn unblock<'cb, 'cx, CB>(&self, conn: &mut Connection<'cb, 'cx>, jids: &[&str], handler: CB) -> Result<()>
+	where
+		CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), IqError>) + Send + 'cb,
+	{
+		match self.backend {
+			BlockingBackend::BlockingCommand => {
+				let request = unblock_request(jids, &conn.generate_id())?;
+				send_iq(conn, &request, parse_response, handler);
+				Ok(())
+			}
+			BlockingBackend::PrivacyList => {
+				let jids = jids.iter().map(|&jid| jid.to_owned()).collect::<Vec<_>>();
+				self.with_list(conn, move |ctx, conn, list| {
+					let mut list = match list {
+						Ok(list) => list,
+						Err(e) => return handler(ctx, conn, Err(e)),
+					};
+					let len = list.items.len();
+					list.items.retain(|item| {
+						!is_blocking_item(item) || !(jids.is_empty() || jids.iter().any(|jid| item.value.as_ref() == Some(jid)))
+					});
+					let requests = if list.items.len() == len {
+						Ok(VecDeque::new())
+					} else if list.items.is_empty() {
+						remove_requests(conn, &list.name)
+					} else {
+						activate_requests(conn, &list)
+					};
+					finish(ctx, conn, requests, handler);
+				})
+			}
+		}
+	}
+
+	/// Fetches the facade privacy list, the missing list is reported as empty
+	fn with_list<'cb, 'cx, CB>(&self, conn: &mut Connection<'cb, 'cx>, handler: CB) -> Result<()>
+	where
+		CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<PrivacyList, IqError>) + Send + 'cb,
+	{
+		let name = self.list_name.clone();
+		privacy::get_list(conn, &self.list_name, move |ctx, conn, list| {
+			let list = match list {
+				Err(IqError::Stanza(condition)) if condition == "item-not-found" => Ok(PrivacyList { name, items: vec![] }),
+				list => list,
+			};
+			handler(ctx, conn, list)
+		})
+	}
+}
+
+fn is_blocking_item(item: &PrivacyItem) -> bool {
+	item.typ == Some(PrivacyItemType::Jid) && item.action == PrivacyAction::Deny && item.stanzas.is_empty()
+}
+
+fn blocked_jids(list: &PrivacyList) -> impl Iterator<Item = &str> {
+	list
+		.items
+		.iter()
+		.filter(|item| is_blocking_item(item))
+		.filter_map(|item| item.value.as_deref())
+}
+
+fn activate_requests(conn: &Connection, list: &PrivacyList) -> Result<VecDeque<Stanza>> {
+	Ok(VecDeque::from(vec![
+		privacy::set_list_request(list, &conn.generate_id())?,
+		privacy::set_active_request(Some(&list.name), &conn.generate_id())?,
+		privacy::set_default_request(Some(&list.name), &conn.generate_id())?,
+	]))
+}
+
+/// The list without items can't be stored, it must be deactivated and removed instead
+fn remove_requests(conn: &Connection, name: &str) -> Result<VecDeque<Stanza>> {
+	Ok(VecDeque::from(vec![
+		privacy::set_active_request(None, &conn.generate_id())?,
+		privacy::set_default_request(None, &conn.generate_id())?,
+		privacy::remove_list_request(name, &conn.generate_id())?,
+	]))
+}
+
+fn finish<'cb, 'cx, CB>(ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>, requests: Result<VecDeque<Stanza>>, handler: CB)
+where
+	CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), IqError>) + Send + 'cb,
+{
+	match requests {
+		Ok(requests) if requests.is_empty() => handler(ctx, conn, Ok(())),
+		Ok(requests) => send_chain(conn, requests, handler),
+		Err(_) => handler(ctx, conn, Err(IqError::Unexpected)),
+	}
+}
+
+/// Sends the modification `requests` one after another, stops at the first failure
+///
+/// `handler` is not called if `requests` is empty.
+fn send_chain<'cb, 'cx, CB>(conn: &mut Connection<'cb, 'cx>, mut requests: VecDeque<Stanza>, handler: CB)
+where
+	CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), IqError>) + Send + 'cb,
+{
+	if let Some(request) = requests.pop_front() {
+		let id = request.id().expect("IQ request must have an id").to_owned();
+		conn.iq_response_handler_add(id, move |ctx, conn, stanza| match privacy::parse_response(stanza) {
+			Ok(()) if requests.is_empty() => handler(ctx, conn, Ok(())),
+			Ok(()) => send_chain(conn, requests, handler),
+			Err(e) => handler(ctx, conn, Err(e)),
+		});
+		conn.send(&request);
+	}
+}
+
+fn element(name: &str, jids: &[&str]) -> Result<Stanza> {
+	let mut out = Stanza::new();
+	out.set_name(name)?;
+	out.set_ns(NS_BLOCKING)?;
+	for jid in jids {
+		let mut item = Stanza::new();
+		item.set_name("item")?;
+		item.set_attribute("jid", jid)?;
+		out.add_child(item)?;
+	}
+	Ok(out)
+}
@@ -0,0 +1,260 @@
+//! [XEP-0004](https://xmpp.org/extensions/xep-0004.html) Data Forms model
+
+use crate::xep::text_element;
+use crate::{Result, Stanza};
+
+pub const NS_DATA: &str = "jabber:x:data";
+
+/// Value of the `type` attribute of the `<x/>` form element
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FormType {
+	Form,
+	Submit,
+	Cancel,
+	Result,
+}
+
+impl FormType {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			FormType::Form => "form",
+			FormType::Submit => "submit",
+			FormType::Cancel => "cancel",
+			FormType::Result => "result",
+		}
+	}
+
+	fn from_attr(typ: &str) -> Option<Self> {
+		match typ {
+			"form" => Some(FormType::Form),
+			"submit" => Some(FormType::Submit),
+			"cancel" => Some(FormType::Cancel),
+			"result" => Some(FormType::Result),
+			_ => None,
+		}
+	}
+}
+
+/// Single `<field/>` of the form
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FormField {
+	pub var: Option<String>,
+	/// Field type, e.g. `text-single` or `boolean`, `None` means `text-single`
+	pub typ: Option<String>,
+	pub label: Option<String>,
+	pub required: bool,
+	pub values: Vec<String>,
+	/// `(label, value)` pairs of the `<option/>` elements of the list fields
+	pub options: Vec<(Option<String>, String)>,
+}
+
+impl FormField {
+	/// Creates the field `var` with a single `value`
+	pub fn new(var: impl Into<String>, value: impl Into<String>) -> Self {
+		Self {
+			var: Some(var.into()),
+			values: vec![value.into()],
+			..Self::default()
+		}
+	}
+
+	/// First value of the field
+	pub fn value(&self) -> Option<&str> {
+		self.values.first().map(String::as_str)
+	}
+
+	fn from_stanza(field: &Stanza) -> Self {
+		let mut out = FormField {
+			var: field.get_attribute("var").map(str::to_owned),
+			typ: field.get_attribute("type").map(str::to_owned),
+			label: field.get_attribute("label").map(str::to_owned),
+			..Self::default()
+		};
+		for child in field.children() {
+			match child.name() {
+				Some("required") => out.required = true,
+				Some("value") => out.values.push(child.text().unwrap_or_default()),
+				Some("option") => {
+					if let Some(value) = child.get_child_by_name("value") {
+						out.options.push((
+							child.get_attribute("label").map(str::to_owned),
+							value.text().unwrap_or_default(),
+						));
+					}
+				}
+				_ => {}
+			}
+		}
+		out
+	}
+
+	fn to_stanza(&self, with_meta: bool) -> Result<Stanza> {
+		let mut out = Stanza::new();
+		out.set_name("field")?;
+		if let Some(var) = &self.var {
+			out.set_attribute("var", var)?;
+		}
+		if with_meta {
+			if let Some(typ) = &self.typ {
+				out.set_attribute("type", typ)?;
+			}
+			if let Some(label) = &self.label {
+				out.set_attribute("label", label)?;
+			}
+			if self.required {
+				let mut required = Stanza::new();
+				required.set_name("required")?;
+				out.add_child(required)?;
+			}
+		}
+		for value in &self.values {
+			out.add_child(text_element("value", value)?)?;
+		}
+		if with_meta {
+			for (label, value) in &self.options {
+				let mut option = Stanza::new();
+				option.set_name("option")?;
+				if let Some(label) = label {
+					option.set_attribute("label", label)?;
+				}
+				option.add_child(text_element("value", value)?)?;
+				out.add_child(option)?;
+			}
+		}
+		Ok(out)
+	}
+}
+
+/// Parsed `jabber:x:data` form
+///
+/// The forms of type [FormType::Result] can carry a table of results: the column definitions in `reported` and the rows
+/// in `items`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataForm {
+	pub typ: FormType,
+	pub title: Option<String>,
+	pub instructions: Vec<String>,
+	pub fields: Vec<FormField>,
+	pub reported: Vec<FormField>,
+	pub items: Vec<Vec<FormField>>,
+}
+
+impl DataForm {
+	/// Creates an empty form of type `typ`
+	pub fn new(typ: FormType) -> Self {
+		Self {
+			typ,
+			title: None,
+			instructions: vec![],
+			fields: vec![],
+			reported: vec![],
+			items: vec![],
+		}
+	}
+
+	/// Parses the `<x xmlns="jabber:x:data"/>` element, `stanza` can be either the element itself or its parent
+	pub fn from_stanza(stanza: &Stanza) -> Option<Self> {
+		if stanza.name() == Some("x") && stanza.ns() == Some(NS_DATA) {
+			Self::from_element(stanza)
+		} else {
+			stanza
+				.find_child_by_name_and_ns("x", NS_DATA)
+				.and_then(|x| Self::from_element(&x))
+		}
+	}
+
+	fn from_element(x: &Stanza) -> Option<Self> {
+		let mut out = Self::new(FormType::from_attr(x.get_attribute("type")?)?);
+		for child in x.children() {
+			match child.name() {
+				Some("title") => out.title = child.text(),
+				Some("instructions") => out.instructions.push(child.text().unwrap_or_default()),
+				Some("field") => out.fields.push(FormField::from_stanza(&child)),
+				Some("reported") => {
+					out.reported = child
+						.children()
+						.filter(|field| field.name() == Some("field"))
+						.map(|field| FormField::from_stanza(&field))
+						.collect();
+				}
+				Some("item") => out.items.push(
+					child
+						.children()
+						.filter(|field| field.name() == Some("field"))
+						.map(|field| FormField::from_stanza(&field))
+						.collect(),
+				),
+				_ => {}
+			}
+		}
+		Some(out)
+	}
+
+	/// Builds the `<x/>` element of the form
+	pub fn to_stanza(&self) -> Result<Stanza> {
+		let mut out = Stanza::new();
+		out.set_name("x")?;
+		out.set_ns(NS_DATA)?;
+		out.set_attribute("type", self.typ.as_str())?;
+		// the submitted forms only carry the values
+		let with_meta = self.typ != FormType::Submit;
+		if with_meta {
+			if let Some(title) = &self.title {
+				out.add_child(text_element("title", title)?)?;
+			}
+			for instructions in &self.instructions {
+				out.add_child(text_element("instructions", instructions)?)?;
+			}
+		}
+		for field in &self.fields {
+			out.add_child(field.to_stanza(with_meta)?)?;
+		}
+		if !self.reported.is_empty() {
+			let mut reported = Stanza::new();
+			reported.set_name("reported")?;
+			for field in &self.reported {
+				reported.add_child(field.to_stanza(true)?)?;
+			}
+			out.add_child(reported)?;
+		}
+		for item in &self.items {
+			let mut item_el = Stanza::new();
+			item_el.set_name("item")?;
+			for field in item {
+				item_el.add_child(field.to_stanza(false)?)?;
+			}
+			out.add_child(item_el)?;
+		}
+		Ok(out)
+	}
+
+	/// Returns the field with the `var` name
+	pub fn field(&self, var: &str) -> Option<&FormField> {
+		self.fields.iter().find(|field| field.var.as_deref() == Some(var))
+	}
+
+	/// Replaces the values of the field `var` with a single `value`, adds the field if it's missing
+	pub fn set_value(&mut self, var: &str, value: impl Into<String>) {
+		match self.fields.iter_mut().find(|field| field.var.as_deref() == Some(var)) {
+			Some(field) => field.values = vec![value.into()],
+			None => self.fields.push(FormField::new(var, value)),
+		}
+	}
+
+	/// Creates the form of type [FormType::Submit] with the values of all named fields of this form except the `fixed`
+	/// ones
+	pub fn submit(&self) -> Self {
+		let mut out = Self::new(FormType::Submit);
+		out.fields = self
+			.fields
+			.iter()
+			.filter(|field| field.var.is_some() && field.typ.as_deref() != Some("fixed"))
+			.map(|field| FormField {
+				var: field.var.clone(),
+				values: field.values.clone(),
+				..FormField::default()
+			})
+			.collect();
+		out
+	}
+}
@@ -0,0 +1,101 @@
+//! [XEP-0203](https://xmpp.org/extensions/xep-0203.html) Delayed Delivery
+
+use std::time::SystemTime;
+
+use crate::{datetime, Result, Stanza};
+
+/// Reason of the delay added by [offline_delay_interceptor], the one used in the XEP examples
+pub const OFFLINE_STORAGE_REASON: &str = "Offline Storage";
+
+pub const NS_DELAY: &str = "urn:xmpp:delay";
+
+/// Contents of the `<delay xmlns="urn:xmpp:delay"/>` element
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delay {
+	/// Time when the stanza was originally sent
+	pub stamp: SystemTime,
+	/// JID of the entity that delayed the delivery
+	pub from: Option<String>,
+	/// Human-readable reason of the delay
+	pub reason: Option<String>,
+}
+
+impl Delay {
+	#[inline]
+	pub fn new(stamp: SystemTime) -> Self {
+		Delay {
+			stamp,
+			from: None,
+			reason: None,
+		}
+	}
+
+	/// Builds the `<delay/>` element
+	pub fn to_stanza(&self) -> Result<Stanza> {
+		let mut out = Stanza::new();
+		out.set_name("delay")?;
+		out.set_ns(NS_DELAY)?;
+		out.set_attribute("stamp", datetime::format(self.stamp))?;
+		if let Some(from) = &self.from {
+			out.set_from(from)?;
+		}
+		if let Some(reason) = &self.reason {
+			let mut text = Stanza::new();
+			text.set_text(reason)?;
+			out.add_child(text)?;
+		}
+		Ok(out)
+	}
+
+	/// Adds the `<delay/>` element to the `stanza`
+	pub fn attach(&self, stanza: &mut Stanza) -> Result<()> {
+		stanza.add_child(self.to_stanza()?)
+	}
+
+	/// Parses the `<delay/>` element, returns `None` if it's not a delay element or the `stamp` is invalid
+	pub fn from_stanza(stanza: &Stanza) -> Option<Self> {
+		if stanza.name() != Some("delay") || stanza.ns() != Some(NS_DELAY) {
+			return None;
+		}
+		Some(Delay {
+			stamp: datetime::parse(stanza.get_attribute("stamp")?).ok()?,
+			from: stanza.from().map(|x| x.to_owned()),
+			reason: stanza.text().filter(|reason| !reason.is_empty()),
+		})
+	}
+
+	/// Parses the `<delay/>` child element of the `stanza`
+	pub fn from_parent(stanza: &Stanza) -> Option<Self> {
+		stanza
+			.find_child_by_name_and_ns("delay", NS_DELAY)
+			.as_deref()
+			.and_then(Self::from_stanza)
+	}
+}
+
+/// Returns the send interceptor tagging the stanzas replayed from the offline storage with `<delay/>`, see
+/// [Connection::send_interceptor_add](crate::Connection::send_interceptor_add)
+///
+/// Meant for the components that store the stanzas for the offline users and send them once the user reconnects.
+/// `stamp_of` returns the time when the replayed stanza was originally received and `None` for the live traffic. The delay
+/// is attributed to `from` (usually the domain of the component) with the [OFFLINE_STORAGE_REASON]. Only `<message/>` and
+/// `<presence/>` are tagged, the stanzas that already have a `<delay/>` are sent as is.
+pub fn offline_delay_interceptor<F>(from: impl Into<String>, mut stamp_of: F) -> impl FnMut(&Stanza) -> Option<Stanza> + Send
+where
+	F: FnMut(&Stanza) -> Option<SystemTime> + Send,
+{
+	let from = from.into();
+	move |stanza| {
+		if !matches!(stanza.name(), Some("message" | "presence")) || stanza.get_child_by_name_and_ns("delay", NS_DELAY).is_some() {
+			return None;
+		}
+		let delay = Delay {
+			stamp: stamp_of(stanza)?,
+			from: Some(from.clone()),
+			reason: Some(OFFLINE_STORAGE_REASON.to_owned()),
+		};
+		let mut out = stanza.try_clone().ok()?;
+		delay.attach(&mut out).ok()?;
+		Some(out)
+	}
+}
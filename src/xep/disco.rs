@@ -0,0 +1,35 @@
+//! [XEP-0030](https://xmpp.org/extensions/xep-0030.html) Service Discovery information queries
+
+use crate::{Result, Stanza};
+
+pub const NS_DISCO_INFO: &str = "http://jabber.org/protocol/disco#info";
+
+/// Builds the `disco#info` query IQ addressed to `to`
+pub fn info_request(to: &str, id: &str) -> Result<Stanza> {
+	let mut out = Stanza::new_iq(Some("get"), Some(id));
+	out.set_to(to)?;
+	let mut query = Stanza::new();
+	query.set_name("query")?;
+	query.set_ns(NS_DISCO_INFO)?;
+	out.add_child(query)?;
+	Ok(out)
+}
+
+/// Extracts the `var`s of the `<feature/>` elements from the `disco#info` result
+///
+/// Returns an empty list for the error responses.
+pub fn features(stanza: &Stanza) -> Vec<String> {
+	if stanza.stanza_type() != Some("result") {
+		return vec![];
+	}
+	stanza
+		.find_child_by_name_and_ns("query", NS_DISCO_INFO)
+		.map(|query| {
+			query
+				.children()
+				.filter(|child| child.name() == Some("feature"))
+				.filter_map(|feature| feature.get_attribute("var").map(str::to_owned))
+				.collect()
+		})
+		.unwrap_or_default()
+}
@@ -0,0 +1,58 @@
+//! [XEP-0297](https://xmpp.org/extensions/xep-0297.html) Stanza Forwarding
+//!
+//! Shared by the Message Carbons and Message Archive Management wrappers.
+
+use crate::xep::delay::Delay;
+use crate::{Result, Stanza};
+
+pub const NS_FORWARD: &str = "urn:xmpp:forward:0";
+
+/// Namespace that the forwarded stanza must be qualified by
+const NS_CLIENT: &str = "jabber:client";
+
+/// Wraps a copy of the `stanza` into the `<forwarded/>` element, optionally adding the `delay` information
+///
+/// The copy gets the `jabber:client` namespace if it has none, as required by the XEP. The original `stanza` is not
+/// modified.
+pub fn wrap(stanza: &Stanza, delay: Option<&Delay>) -> Result<Stanza> {
+	let mut out = Stanza::new();
+	out.set_name("forwarded")?;
+	out.set_ns(NS_FORWARD)?;
+	if let Some(delay) = delay {
+		out.add_child(delay.to_stanza()?)?;
+	}
+	let mut inner = stanza.try_clone()?;
+	if inner.ns().is_none() {
+		inner.set_ns(NS_CLIENT)?;
+	}
+	out.add_child(inner)?;
+	Ok(out)
+}
+
+/// Extracts the forwarded stanza and its delay information
+///
+/// Accepts either the `<forwarded/>` element itself or a stanza that contains it as a direct child (e.g. MAM
+/// `<result/>`). The returned stanza is an independent copy that can outlive `stanza`.
+pub fn unwrap(stanza: &Stanza) -> Option<(Stanza, Option<Delay>)> {
+	if stanza.name() == Some("forwarded") && stanza.ns() == Some(NS_FORWARD) {
+		unwrap_forwarded(stanza)
+	} else {
+		stanza
+			.find_child_by_name_and_ns("forwarded", NS_FORWARD)
+			.as_deref()
+			.and_then(unwrap_forwarded)
+	}
+}
+
+fn unwrap_forwarded(forwarded: &Stanza) -> Option<(Stanza, Option<Delay>)> {
+	let mut delay = None;
+	let mut inner = None;
+	for child in forwarded.children() {
+		if let Some(parsed) = Delay::from_stanza(&child) {
+			delay = Some(parsed);
+		} else if inner.is_none() && child.is_tag() {
+			inner = child.try_clone().ok();
+		}
+	}
+	inner.map(|inner| (inner, delay))
+}
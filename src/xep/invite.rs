@@ -0,0 +1,159 @@
+//! [XEP-0249](https://xmpp.org/extensions/xep-0249.html) Direct MUC Invitations and the
+//! [XEP-0045](https://xmpp.org/extensions/xep-0045.html#invite) mediated invitations
+//!
+//! The direct invitations are sent straight to the invitee, the mediated ones go through the room which forwards them
+//! and may add the room password. Both kinds are parsed into [Invite], [handler_add] delivers them to the handler as they
+//! arrive so that the client can prompt the user to join with [Invite::room]. There is no bookmarks helper in the crate,
+//! so persisting the room for autojoin is up to the client: [Invite::room] and [Invite::password] carry everything the
+//! bookmark needs.
+
+use crate::xep::muc::{Room, NS_MUC_USER};
+use crate::{Connection, Context, Error, HandlerId, HandlerResult, Jid, Result, Stanza};
+
+pub const NS_CONFERENCE: &str = "jabber:x:conference";
+
+/// How the invitation was delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InviteKind {
+	/// Sent directly by the inviter, [XEP-0249](https://xmpp.org/extensions/xep-0249.html)
+	Direct,
+	/// Forwarded by the room, [XEP-0045](https://xmpp.org/extensions/xep-0045.html#invite-mediated)
+	Mediated,
+}
+
+/// Invitation to join the room
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invite {
+	pub kind: InviteKind,
+	/// Bare JID of the room
+	pub room: String,
+	/// JID of the user who sent the invitation, for the mediated invitations it's only known if the room discloses it
+	pub inviter: Option<String>,
+	pub reason: Option<String>,
+	pub password: Option<String>,
+	/// Thread of the one-to-one conversation continued in the room
+	pub thread: Option<String>,
+}
+
+impl Invite {
+	/// Parses the invitation of either kind from the `message`, returns `None` for the other stanzas
+	pub fn from_stanza(message: &Stanza) -> Option<Self> {
+		if message.name() != Some("message") || message.stanza_type() == Some("error") {
+			return None;
+		}
+		if let Some(x) = message.get_child_by_name_and_ns("x", NS_MUC_USER) {
+			let invite = x.get_child_by_name("invite")?;
+			let room = message.from()?.parse::<Jid>().ok()?;
+			return Some(Self {
+				kind: InviteKind::Mediated,
+				room: room.bare().to_owned(),
+				inviter: invite.get_attribute("from").map(str::to_owned),
+				reason: child_text(&invite, "reason"),
+				password: child_text(&x, "password"),
+				thread: invite
+					.get_child_by_name("continue")
+					.and_then(|cont| cont.get_attribute("thread").map(str::to_owned)),
+			});
+		}
+		let x = message.get_child_by_name_and_ns("x", NS_CONFERENCE)?;
+		let room = x.get_attribute("jid")?.parse::<Jid>().ok()?;
+		Some(Self {
+			kind: InviteKind::Direct,
+			room: room.bare().to_owned(),
+			inviter: message.from().map(str::to_owned),
+			reason: x.get_attribute("reason").map(str::to_owned),
+			password: x.get_attribute("password").map(str::to_owned),
+			thread: x
+				.get_attribute("continue")
+				.filter(|cont| *cont == "true")
+				.and_then(|_| x.get_attribute("thread").map(str::to_owned)),
+		})
+	}
+
+	/// Creates the [Room] to join with the `nick`, use [Invite::password] with [Room::join]
+	#[inline]
+	pub fn room(&self, nick: impl Into<String>) -> Result<Room> {
+		Room::new(&self.room, nick)
+	}
+
+	#[inline]
+	pub fn password(&self) -> Option<&str> {
+		self.password.as_deref()
+	}
+}
+
+/// Builds the message inviting `to` directly
+pub fn direct_invite(room: &str, to: &str, reason: Option<&str>, password: Option<&str>) -> Result<Stanza> {
+	let mut out = Stanza::new_message(None, None, Some(to));
+	let mut x = Stanza::new();
+	x.set_name("x")?;
+	x.set_ns(NS_CONFERENCE)?;
+	x.set_attribute("jid", room)?;
+	if let Some(reason) = reason {
+		x.set_attribute("reason", reason)?;
+	}
+	if let Some(password) = password {
+		x.set_attribute("password", password)?;
+	}
+	out.add_child(x)?;
+	Ok(out)
+}
+
+/// Builds the message asking the `room` to invite `to`
+pub fn mediated_invite(room: &str, to: &str, reason: Option<&str>) -> Result<Stanza> {
+	user_message(room, "invite", to, reason)
+}
+
+/// Builds the message declining the mediated invitation, the `room` forwards it to the `inviter`
+pub fn decline(room: &str, inviter: &str, reason: Option<&str>) -> Result<Stanza> {
+	user_message(room, "decline", inviter, reason)
+}
+
+/// Adds the handler called with every incoming invitation of either kind, see [Connection::handler_add]
+///
+/// The handler is only called for the messages that carry an invitation, the other messages keep it registered.
+pub fn handler_add<'cb, 'cx, CB>(
+	conn: &mut Connection<'cb, 'cx>,
+	mut handler: CB,
+) -> Option<HandlerId<'cb, 'cx, impl FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Stanza) -> HandlerResult + Send + 'cb>>
+where
+	CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &Invite) -> HandlerResult + Send + 'cb,
+{
+	conn.handler_add(
+		move |ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>, stanza: &Stanza| match Invite::from_stanza(stanza) {
+			Some(invite) => handler(ctx, conn, &invite),
+			None => HandlerResult::KeepHandler,
+		},
+		None,
+		Some("message"),
+		None,
+	)
+}
+
+fn user_message(room: &str, element: &str, jid: &str, reason: Option<&str>) -> Result<Stanza> {
+	if !room.parse::<Jid>()?.is_bare() {
+		return Err(Error::InvalidOperation);
+	}
+	let mut out = Stanza::new_message(None, None, Some(room));
+	let mut x = Stanza::new();
+	x.set_name("x")?;
+	x.set_ns(NS_MUC_USER)?;
+	let mut item = Stanza::new();
+	item.set_name(element)?;
+	item.set_attribute("to", jid)?;
+	if let Some(reason) = reason {
+		let mut reason_el = Stanza::new();
+		reason_el.set_name("reason")?;
+		let mut text = Stanza::new();
+		text.set_text(reason)?;
+		reason_el.add_child(text)?;
+		item.add_child(reason_el)?;
+	}
+	x.add_child(item)?;
+	out.add_child(x)?;
+	Ok(out)
+}
+
+fn child_text(stanza: &Stanza, name: &str) -> Option<String> {
+	stanza.get_child_by_name(name).and_then(|child| child.text())
+}
@@ -0,0 +1,98 @@
+//! Request/response plumbing and the element builders shared by the IQ based helpers
+
+#[cfg(any(feature = "xep-0016", feature = "xep-0045", feature = "xep-0055"))]
+use std::error::Error as StdError;
+#[cfg(any(feature = "xep-0016", feature = "xep-0045", feature = "xep-0055"))]
+use std::fmt;
+
+#[cfg(any(
+	feature = "xep-0004",
+	feature = "xep-0016",
+	feature = "xep-0045",
+	feature = "xep-0055",
+	feature = "xep-0077",
+	feature = "xep-0388"
+))]
+use crate::Result;
+use crate::Stanza;
+#[cfg(any(feature = "xep-0016", feature = "xep-0045", feature = "xep-0055"))]
+use crate::{Connection, Context};
+
+#[cfg(any(feature = "xep-0016", feature = "xep-0045", feature = "xep-0055", feature = "xep-0077"))]
+pub const NS_STANZA_ERRORS: &str = "urn:ietf:params:xml:ns:xmpp-stanzas";
+
+/// Reason for the failure of the IQ request sent by the XEP helpers
+#[cfg(any(feature = "xep-0016", feature = "xep-0045", feature = "xep-0055"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IqError {
+	/// Stanza error with the condition, e.g. `item-not-found`, `forbidden` or `service-unavailable`
+	Stanza(String),
+	/// The response is neither a result nor an error with a condition
+	Unexpected,
+}
+
+#[cfg(any(feature = "xep-0016", feature = "xep-0045", feature = "xep-0055"))]
+impl IqError {
+	/// Interprets the error response, [IqError::Unexpected] is returned for the other stanzas
+	pub(crate) fn from_response(stanza: &Stanza) -> Self {
+		match stanza.stanza_type() {
+			Some("error") => stanza_error_condition(stanza).map_or(IqError::Unexpected, IqError::Stanza),
+			_ => IqError::Unexpected,
+		}
+	}
+}
+
+#[cfg(any(feature = "xep-0016", feature = "xep-0045", feature = "xep-0055"))]
+impl fmt::Display for IqError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			IqError::Stanza(condition) => write!(f, "Stanza error: {}", condition),
+			IqError::Unexpected => write!(f, "Unexpected response"),
+		}
+	}
+}
+
+#[cfg(any(feature = "xep-0016", feature = "xep-0045", feature = "xep-0055"))]
+impl StdError for IqError {}
+
+/// Returns the defined condition of the `<error/>` child of the error stanza, e.g. `item-not-found`
+#[cfg(any(feature = "xep-0016", feature = "xep-0045", feature = "xep-0055", feature = "xep-0077"))]
+pub(crate) fn stanza_error_condition(stanza: &Stanza) -> Option<String> {
+	stanza.get_child_by_name("error").and_then(|error| {
+		error
+			.children()
+			.find(|child| child.ns() == Some(NS_STANZA_ERRORS))
+			.and_then(|condition| condition.name().map(str::to_owned))
+	})
+}
+
+/// Builds the `<name>text</name>` element
+#[cfg(any(feature = "xep-0004", feature = "xep-0077", feature = "xep-0388"))]
+pub(crate) fn text_element(name: &str, text: &str) -> Result<Stanza> {
+	let mut out = Stanza::new();
+	out.set_name(name)?;
+	let mut text_node = Stanza::new();
+	text_node.set_text(text)?;
+	out.add_child(text_node)?;
+	Ok(out)
+}
+
+/// Sends the IQ `request` and calls `handler` with the response interpreted by `parse`
+///
+/// `request` must have its `id` set.
+#[cfg(any(feature = "xep-0016", feature = "xep-0045", feature = "xep-0055"))]
+pub(crate) fn send_iq<'cb, 'cx, T, E, CB>(
+	conn: &mut Connection<'cb, 'cx>,
+	request: &Stanza,
+	parse: fn(&Stanza) -> Result<T, E>,
+	handler: CB,
+) where
+	T: 'cb,
+	E: 'cb,
+	CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, Result<T, E>) + Send + 'cb,
+{
+	let id = request.id().expect("IQ request must have an id").to_owned();
+	conn.iq_response_handler_add(id, move |ctx, conn, stanza| handler(ctx, conn, parse(stanza)));
+	conn.send(request);
+}
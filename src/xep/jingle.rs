@@ -0,0 +1,379 @@
+//! [XEP-0166](https://xmpp.org/extensions/xep-0166.html) Jingle session negotiation scaffolding
+//!
+//! Typed representation of the `<jingle/>` element for the session-initiate/accept/terminate choreography along with the
+//! [XEP-0261](https://xmpp.org/extensions/xep-0261.html) In-Band Bytestreams and
+//! [XEP-0260](https://xmpp.org/extensions/xep-0260.html) SOCKS5 Bytestreams transports. Application descriptions (e.g. the
+//! file transfer offer) are kept as raw stanzas, the media handling is left to the application.
+
+use std::str::FromStr;
+
+use crate::{Error, Jid, Result, Stanza};
+
+pub const NS_JINGLE: &str = "urn:xmpp:jingle:1";
+pub const NS_JINGLE_IBB: &str = "urn:xmpp:jingle:transports:ibb:1";
+pub const NS_JINGLE_S5B: &str = "urn:xmpp:jingle:transports:s5b:1";
+
+macro_rules! string_enum {
+	($(#[$attr: meta])* $name: ident { $($variant: ident => $value: literal,)+ }) => {
+		$(#[$attr])*
+		#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+		pub enum $name {
+			$($variant,)+
+		}
+
+		impl $name {
+			pub fn as_str(&self) -> &'static str {
+				match self {
+					$($name::$variant => $value,)+
+				}
+			}
+		}
+
+		impl FromStr for $name {
+			type Err = Error;
+
+			fn from_str(s: &str) -> Result<Self> {
+				match s {
+					$($value => Ok($name::$variant),)+
+					_ => Err(Error::InvalidOperation),
+				}
+			}
+		}
+	};
+}
+
+string_enum!(
+	/// Value of the `action` attribute
+	Action {
+		ContentAccept => "content-accept",
+		ContentAdd => "content-add",
+		ContentModify => "content-modify",
+		ContentReject => "content-reject",
+		ContentRemove => "content-remove",
+		DescriptionInfo => "description-info",
+		SecurityInfo => "security-info",
+		SessionAccept => "session-accept",
+		SessionInfo => "session-info",
+		SessionInitiate => "session-initiate",
+		SessionTerminate => "session-terminate",
+		TransportAccept => "transport-accept",
+		TransportInfo => "transport-info",
+		TransportReject => "transport-reject",
+		TransportReplace => "transport-replace",
+	}
+);
+
+string_enum!(
+	/// Value of the `creator` attribute of the `<content/>` element
+	Creator {
+		Initiator => "initiator",
+		Responder => "responder",
+	}
+);
+
+string_enum!(
+	/// Value of the `senders` attribute of the `<content/>` element
+	Senders {
+		Both => "both",
+		Initiator => "initiator",
+		None => "none",
+		Responder => "responder",
+	}
+);
+
+string_enum!(
+	/// Condition of the `<reason/>` element
+	Reason {
+		AlternativeSession => "alternative-session",
+		Busy => "busy",
+		Cancel => "cancel",
+		ConnectivityError => "connectivity-error",
+		Decline => "decline",
+		Expired => "expired",
+		FailedApplication => "failed-application",
+		FailedTransport => "failed-transport",
+		GeneralError => "general-error",
+		Gone => "gone",
+		IncompatibleParameters => "incompatible-parameters",
+		MediaError => "media-error",
+		SecurityError => "security-error",
+		Success => "success",
+		Timeout => "timeout",
+		UnsupportedApplications => "unsupported-applications",
+		UnsupportedTransports => "unsupported-transports",
+	}
+);
+
+/// SOCKS5 streamhost candidate, see [XEP-0260](https://xmpp.org/extensions/xep-0260.html#protocol-negotiation)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+	pub cid: String,
+	pub host: String,
+	pub jid: String,
+	pub port: Option<u16>,
+	pub priority: u32,
+	/// `direct`, `assisted`, `tunnel` or `proxy`
+	pub typ: Option<String>,
+}
+
+/// Transport of the `<content/>` element
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Transport {
+	/// [XEP-0261](https://xmpp.org/extensions/xep-0261.html) In-Band Bytestreams
+	Ibb { sid: String, block_size: u16 },
+	/// [XEP-0260](https://xmpp.org/extensions/xep-0260.html) SOCKS5 Bytestreams
+	Socks5 {
+		sid: String,
+		dstaddr: Option<String>,
+		candidates: Vec<Candidate>,
+	},
+}
+
+impl Transport {
+	fn to_stanza(&self) -> Result<Stanza> {
+		match self {
+			Transport::Ibb { sid, block_size } => {
+				let mut out = element("transport", Some(NS_JINGLE_IBB))?;
+				out.set_attribute("sid", sid)?;
+				out.set_attribute("block-size", block_size.to_string())?;
+				Ok(out)
+			}
+			Transport::Socks5 {
+				sid,
+				dstaddr,
+				candidates,
+			} => {
+				let mut out = element("transport", Some(NS_JINGLE_S5B))?;
+				out.set_attribute("sid", sid)?;
+				if let Some(dstaddr) = dstaddr {
+					out.set_attribute("dstaddr", dstaddr)?;
+				}
+				for candidate in candidates {
+					let mut child = element("candidate", None)?;
+					child.set_attribute("cid", &candidate.cid)?;
+					child.set_attribute("host", &candidate.host)?;
+					child.set_attribute("jid", &candidate.jid)?;
+					if let Some(port) = candidate.port {
+						child.set_attribute("port", port.to_string())?;
+					}
+					child.set_attribute("priority", candidate.priority.to_string())?;
+					if let Some(typ) = &candidate.typ {
+						child.set_attribute("type", typ)?;
+					}
+					out.add_child(child)?;
+				}
+				Ok(out)
+			}
+		}
+	}
+
+	fn from_stanza(stanza: &Stanza) -> Option<Self> {
+		let sid = stanza.get_attribute("sid")?.to_owned();
+		match stanza.ns()? {
+			NS_JINGLE_IBB => Some(Transport::Ibb {
+				sid,
+				block_size: stanza.attr_parse("block-size").ok()??,
+			}),
+			NS_JINGLE_S5B => Some(Transport::Socks5 {
+				sid,
+				dstaddr: stanza.get_attribute("dstaddr").map(|x| x.to_owned()),
+				candidates: stanza
+					.children()
+					.filter(|child| child.name() == Some("candidate"))
+					.map(|child| {
+						Some(Candidate {
+							cid: child.get_attribute("cid")?.to_owned(),
+							host: child.get_attribute("host")?.to_owned(),
+							jid: child.get_attribute("jid")?.to_owned(),
+							port: child.attr_parse("port").ok()?,
+							priority: child.attr_parse("priority").ok()??,
+							typ: child.get_attribute("type").map(|x| x.to_owned()),
+						})
+					})
+					.collect::<Option<_>>()?,
+			}),
+			_ => None,
+		}
+	}
+}
+
+/// `<content/>` element of the Jingle session
+#[derive(Clone, Debug)]
+pub struct Content {
+	pub creator: Creator,
+	pub name: String,
+	pub senders: Option<Senders>,
+	/// Application format description, e.g. [XEP-0234](https://xmpp.org/extensions/xep-0234.html) file offer
+	pub description: Option<Stanza>,
+	pub transport: Option<Transport>,
+}
+
+impl Content {
+	pub fn new(creator: Creator, name: impl Into<String>) -> Self {
+		Content {
+			creator,
+			name: name.into(),
+			senders: None,
+			description: None,
+			transport: None,
+		}
+	}
+
+	fn to_stanza(&self) -> Result<Stanza> {
+		let mut out = element("content", None)?;
+		out.set_attribute("creator", self.creator.as_str())?;
+		out.set_attribute("name", &self.name)?;
+		if let Some(senders) = self.senders {
+			out.set_attribute("senders", senders.as_str())?;
+		}
+		if let Some(description) = &self.description {
+			out.add_child(description.clone())?;
+		}
+		if let Some(transport) = &self.transport {
+			out.add_child(transport.to_stanza()?)?;
+		}
+		Ok(out)
+	}
+
+	fn from_stanza(stanza: &Stanza) -> Option<Self> {
+		Some(Content {
+			creator: stanza.attr_parse("creator").ok()??,
+			name: stanza.get_attribute("name")?.to_owned(),
+			senders: stanza.attr_parse("senders").ok()?,
+			description: stanza.get_child_by_name("description").map(|x| x.clone()),
+			transport: match stanza.get_child_by_name("transport") {
+				Some(transport) => Some(Transport::from_stanza(&transport)?),
+				None => None,
+			},
+		})
+	}
+}
+
+/// Typed `<jingle/>` element
+#[derive(Clone, Debug)]
+pub struct Jingle {
+	pub action: Action,
+	pub sid: String,
+	pub initiator: Option<String>,
+	pub responder: Option<String>,
+	pub contents: Vec<Content>,
+	pub reason: Option<Reason>,
+	/// Human-readable text of the `<reason/>` element
+	pub reason_text: Option<String>,
+}
+
+impl Jingle {
+	pub fn new(action: Action, sid: impl Into<String>) -> Self {
+		Jingle {
+			action,
+			sid: sid.into(),
+			initiator: None,
+			responder: None,
+			contents: vec![],
+			reason: None,
+			reason_text: None,
+		}
+	}
+
+	/// `session-initiate` sent by the `initiator` (full JID) with the offered `contents`
+	pub fn session_initiate(sid: impl Into<String>, initiator: &Jid, contents: Vec<Content>) -> Self {
+		Jingle {
+			initiator: Some(initiator.to_string()),
+			contents,
+			..Self::new(Action::SessionInitiate, sid)
+		}
+	}
+
+	/// `session-accept` sent by the `responder` (full JID) with the accepted `contents`
+	pub fn session_accept(sid: impl Into<String>, responder: &Jid, contents: Vec<Content>) -> Self {
+		Jingle {
+			responder: Some(responder.to_string()),
+			contents,
+			..Self::new(Action::SessionAccept, sid)
+		}
+	}
+
+	/// `session-terminate` with the specified `reason`
+	pub fn session_terminate(sid: impl Into<String>, reason: Reason) -> Self {
+		Jingle {
+			reason: Some(reason),
+			..Self::new(Action::SessionTerminate, sid)
+		}
+	}
+
+	/// Builds the `<jingle/>` element
+	pub fn to_stanza(&self) -> Result<Stanza> {
+		let mut out = element("jingle", Some(NS_JINGLE))?;
+		out.set_attribute("action", self.action.as_str())?;
+		out.set_attribute("sid", &self.sid)?;
+		if let Some(initiator) = &self.initiator {
+			out.set_attribute("initiator", initiator)?;
+		}
+		if let Some(responder) = &self.responder {
+			out.set_attribute("responder", responder)?;
+		}
+		for content in &self.contents {
+			out.add_child(content.to_stanza()?)?;
+		}
+		if let Some(reason) = self.reason {
+			let mut reason_element = element("reason", None)?;
+			reason_element.add_child(element(reason.as_str(), None)?)?;
+			if let Some(text) = &self.reason_text {
+				let mut text_element = element("text", None)?;
+				let mut text_node = Stanza::new();
+				text_node.set_text(text)?;
+				text_element.add_child(text_node)?;
+				reason_element.add_child(text_element)?;
+			}
+			out.add_child(reason_element)?;
+		}
+		Ok(out)
+	}
+
+	/// Builds the `<iq type="set"/>` stanza carrying the `<jingle/>` element
+	pub fn to_iq(&self, id: &str, to: &str) -> Result<Stanza> {
+		let mut out = Stanza::new_iq(Some("set"), Some(id));
+		out.set_to(to)?;
+		out.add_child(self.to_stanza()?)?;
+		Ok(out)
+	}
+
+	/// Parses either `<jingle/>` element or the IQ stanza containing it
+	///
+	/// Returns `None` if there is no `<jingle/>` element or it's malformed.
+	pub fn from_stanza(stanza: &Stanza) -> Option<Self> {
+		if stanza.name() == Some("jingle") && stanza.ns() == Some(NS_JINGLE) {
+			Self::parse(stanza)
+		} else {
+			Self::parse(&*stanza.find_child_by_name_and_ns("jingle", NS_JINGLE)?)
+		}
+	}
+
+	fn parse(stanza: &Stanza) -> Option<Self> {
+		let reason = stanza.get_child_by_name("reason");
+		Some(Jingle {
+			action: stanza.attr_parse("action").ok()??,
+			sid: stanza.get_attribute("sid")?.to_owned(),
+			initiator: stanza.get_attribute("initiator").map(|x| x.to_owned()),
+			responder: stanza.get_attribute("responder").map(|x| x.to_owned()),
+			contents: stanza
+				.children()
+				.filter(|child| child.name() == Some("content"))
+				.map(|child| Content::from_stanza(&child))
+				.collect::<Option<_>>()?,
+			reason: reason
+				.as_ref()
+				.and_then(|reason| reason.children().find_map(|child| child.name()?.parse().ok())),
+			reason_text: reason.and_then(|reason| reason.get_child_by_name("text").and_then(|text| text.text())),
+		})
+	}
+}
+
+fn element(name: &str, ns: Option<&str>) -> Result<Stanza> {
+	let mut out = Stanza::new();
+	out.set_name(name)?;
+	if let Some(ns) = ns {
+		out.set_ns(ns)?;
+	}
+	Ok(out)
+}
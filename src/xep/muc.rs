@@ -0,0 +1,320 @@
+//! [XEP-0045](https://xmpp.org/extensions/xep-0045.html) Multi-User Chat helpers
+//!
+//! [Room] builds the join and leave presences and wraps the moderation and the administration requests: changing the
+//! roles (kicking the occupants) and the affiliations (banning the users) and retrieving and submitting the room
+//! configuration [data form](crate::xep::data_forms). The requests need the corresponding privileges in the room, the
+//! server answers with a `forbidden` or `not-allowed` [IqError::Stanza] otherwise.
+
+use std::result;
+
+use crate::xep::data_forms::{DataForm, FormType};
+use crate::xep::{send_iq, text_element, IqError};
+use crate::{Connection, Context, Jid, Result, Stanza, StanzaRef};
+
+pub const NS_MUC: &str = "http://jabber.org/protocol/muc";
+pub const NS_MUC_ADMIN: &str = "http://jabber.org/protocol/muc#admin";
+pub const NS_MUC_OWNER: &str = "http://jabber.org/protocol/muc#owner";
+pub const NS_MUC_USER: &str = "http://jabber.org/protocol/muc#user";
+
+/// Role of the occupant, valid only for the duration of the visit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+	Moderator,
+	Participant,
+	Visitor,
+	/// Removes the occupant from the room
+	None,
+}
+
+impl Role {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Role::Moderator => "moderator",
+			Role::Participant => "participant",
+			Role::Visitor => "visitor",
+			Role::None => "none",
+		}
+	}
+
+	/// Parses the value of the `role` attribute
+	pub fn from_attr(role: &str) -> Option<Self> {
+		match role {
+			"moderator" => Some(Role::Moderator),
+			"participant" => Some(Role::Participant),
+			"visitor" => Some(Role::Visitor),
+			"none" => Some(Role::None),
+			_ => None,
+		}
+	}
+}
+
+/// Long-lived affiliation of the user with the room
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Affiliation {
+	Owner,
+	Admin,
+	Member,
+	/// Banned user
+	Outcast,
+	/// Removes the affiliation
+	None,
+}
+
+impl Affiliation {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Affiliation::Owner => "owner",
+			Affiliation::Admin => "admin",
+			Affiliation::Member => "member",
+			Affiliation::Outcast => "outcast",
+			Affiliation::None => "none",
+		}
+	}
+
+	/// Parses the value of the `affiliation` attribute
+	pub fn from_attr(affiliation: &str) -> Option<Self> {
+		match affiliation {
+			"owner" => Some(Affiliation::Owner),
+			"admin" => Some(Affiliation::Admin),
+			"member" => Some(Affiliation::Member),
+			"outcast" => Some(Affiliation::Outcast),
+			"none" => Some(Affiliation::None),
+			_ => None,
+		}
+	}
+}
+
+/// Multi-user chat room joined (or to be joined) with the `nick`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Room {
+	jid: Jid,
+	nick: String,
+}
+
+impl Room {
+	/// Creates the room with the bare `jid`, [Error::InvalidOperation](crate::Error::InvalidOperation) is returned if `jid`
+	/// is not a valid bare JID or the `nick` is not a valid resource
+	pub fn new(jid: &str, nick: impl Into<String>) -> Result<Self> {
+		let jid = jid.parse::<Jid>()?;
+		let nick = nick.into();
+		if !jid.is_bare() {
+			return Err(crate::Error::InvalidOperation);
+		}
+		Jid::new(jid.node(), jid.domain(), Some(&nick))?;
+		Ok(Self { jid, nick })
+	}
+
+	#[inline]
+	/// Bare JID of the room
+	pub fn jid(&self) -> &str {
+		self.jid.as_str()
+	}
+
+	#[inline]
+	pub fn nick(&self) -> &str {
+		&self.nick
+	}
+
+	/// JID of the own occupant in the form of `room@service/nick`
+	pub fn occupant_jid(&self) -> String {
+		self.occupant(&self.nick)
+	}
+
+	/// Builds the presence joining the room, `password` is needed for the password-protected rooms
+	pub fn join_presence(&self, password: Option<&str>) -> Result<Stanza> {
+		let mut out = Stanza::new_presence();
+		out.set_to(self.occupant_jid())?;
+		let mut x = Stanza::new();
+		x.set_name("x")?;
+		x.set_ns(NS_MUC)?;
+		if let Some(password) = password {
+			x.add_child(text_element("password", password)?)?;
+		}
+		out.add_child(x)?;
+		Ok(out)
+	}
+
+	/// Builds the presence leaving the room with an optional `status`
+	pub fn leave_presence(&self, status: Option<&str>) -> Result<Stanza> {
+		let mut out = Stanza::new_presence();
+		out.set_stanza_type("unavailable")?;
+		out.set_to(self.occupant_jid())?;
+		if let Some(status) = status {
+			out.add_child(text_element("status", status)?)?;
+		}
+		Ok(out)
+	}
+
+	/// Sends the [join_presence](Room::join_presence)
+	pub fn join(&self, conn: &mut Connection, password: Option<&str>) -> Result<()> {
+		conn.send(&self.join_presence(password)?);
+		Ok(())
+	}
+
+	/// Sends the [leave_presence](Room::leave_presence)
+	pub fn leave(&self, conn: &mut Connection, status: Option<&str>) -> Result<()> {
+		conn.send(&self.leave_presence(status)?);
+		Ok(())
+	}
+
+	/// Builds the IQ changing the role of the occupant `nick`, the optional `reason` is passed to the occupant
+	pub fn role_request(&self, nick: &str, role: Role, reason: Option<&str>, id: &str) -> Result<Stanza> {
+		self.admin_request(id, "nick", nick, "role", role.as_str(), reason)
+	}
+
+	/// Builds the IQ changing the affiliation of the user with the bare `jid`
+	pub fn affiliation_request(&self, jid: &str, affiliation: Affiliation, reason: Option<&str>, id: &str) -> Result<Stanza> {
+		self.admin_request(id, "jid", jid, "affiliation", affiliation.as_str(), reason)
+	}
+
+	/// Builds the IQ requesting the configuration form of the room
+	pub fn config_request(&self, id: &str) -> Result<Stanza> {
+		self.owner_request("get", id, |_| Ok(()))
+	}
+
+	/// Builds the IQ submitting the room configuration `form`, the form of any type other than
+	/// [FormType::Submit] is converted with [DataForm::submit]
+	pub fn submit_config_request(&self, form: &DataForm, id: &str) -> Result<Stanza> {
+		self.owner_request("set", id, |query| {
+			let form = if form.typ == FormType::Submit {
+				form.to_stanza()?
+			} else {
+				form.submit().to_stanza()?
+			};
+			query.add_child(form)
+		})
+	}
+
+	/// Removes the occupant `nick` from the room and calls `handler` with the outcome
+	pub fn kick<'cb, 'cx, CB>(&self, conn: &mut Connection<'cb, 'cx>, nick: &str, reason: Option<&str>, handler: CB) -> Result<()>
+	where
+		CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), IqError>) + Send + 'cb,
+	{
+		self.set_role(conn, nick, Role::None, reason, handler)
+	}
+
+	/// Bans the user with the bare `jid` from the room and calls `handler` with the outcome
+	pub fn ban<'cb, 'cx, CB>(&self, conn: &mut Connection<'cb, 'cx>, jid: &str, reason: Option<&str>, handler: CB) -> Result<()>
+	where
+		CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), IqError>) + Send + 'cb,
+	{
+		self.set_affiliation(conn, jid, Affiliation::Outcast, reason, handler)
+	}
+
+	/// Changes the role of the occupant `nick` and calls `handler` with the outcome
+	pub fn set_role<'cb, 'cx, CB>(
+		&self,
+		conn: &mut Connection<'cb, 'cx>,
+		nick: &str,
+		role: Role,
+		reason: Option<&str>,
+		handler: CB,
+	) -> Result<()>
+	where
+		CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), IqError>) + Send + 'cb,
+	{
+		let request = self.role_request(nick, role, reason, &conn.generate_id())?;
+		send_iq(conn, &request, parse_response, handler);
+		Ok(())
+	}
+
+	/// Changes the affiliation of the user with the bare `jid` and calls `handler` with the outcome
+	pub fn set_affiliation<'cb, 'cx, CB>(
+		&self,
+		conn: &mut Connection<'cb, 'cx>,
+		jid: &str,
+		affiliation: Affiliation,
+		reason: Option<&str>,
+		handler: CB,
+	) -> Result<()>
+	where
+		CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), IqError>) + Send + 'cb,
+	{
+		let request = self.affiliation_request(jid, affiliation, reason, &conn.generate_id())?;
+		send_iq(conn, &request, parse_response, handler);
+		Ok(())
+	}
+
+	/// Requests the configuration form of the room and calls `handler` with the outcome
+	pub fn get_config<'cb, 'cx, CB>(&self, conn: &mut Connection<'cb, 'cx>, handler: CB) -> Result<()>
+	where
+		CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<DataForm, IqError>) + Send + 'cb,
+	{
+		let request = self.config_request(&conn.generate_id())?;
+		send_iq(conn, &request, parse_config, handler);
+		Ok(())
+	}
+
+	/// Submits the room configuration `form` and calls `handler` with the outcome
+	pub fn submit_config<'cb, 'cx, CB>(&self, conn: &mut Connection<'cb, 'cx>, form: &DataForm, handler: CB) -> Result<()>
+	where
+		CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), IqError>) + Send + 'cb,
+	{
+		let request = self.submit_config_request(form, &conn.generate_id())?;
+		send_iq(conn, &request, parse_response, handler);
+		Ok(())
+	}
+
+	fn occupant(&self, nick: &str) -> String {
+		format!("{}/{}", self.jid, nick)
+	}
+
+	fn admin_request(
+		&self,
+		id: &str,
+		key: &str,
+		value: &str,
+		change: &str,
+		change_value: &str,
+		reason: Option<&str>,
+	) -> Result<Stanza> {
+		let mut out = Stanza::new_iq(Some("set"), Some(id));
+		out.set_to(self.jid())?;
+		let mut query = Stanza::new();
+		query.set_name("query")?;
+		query.set_ns(NS_MUC_ADMIN)?;
+		let mut item = Stanza::new();
+		item.set_name("item")?;
+		item.set_attribute(key, value)?;
+		item.set_attribute(change, change_value)?;
+		if let Some(reason) = reason {
+			item.add_child(text_element("reason", reason)?)?;
+		}
+		query.add_child(item)?;
+		out.add_child(query)?;
+		Ok(out)
+	}
+
+	fn owner_request(&self, typ: &str, id: &str, fill: impl FnOnce(&mut Stanza) -> Result<()>) -> Result<Stanza> {
+		let mut out = Stanza::new_iq(Some(typ), Some(id));
+		out.set_to(self.jid())?;
+		let mut query = Stanza::new();
+		query.set_name("query")?;
+		query.set_ns(NS_MUC_OWNER)?;
+		fill(&mut query)?;
+		out.add_child(query)?;
+		Ok(out)
+	}
+}
+
+/// Interprets the response to the role, affiliation and configuration change requests
+pub fn parse_response(stanza: &Stanza) -> result::Result<(), IqError> {
+	match stanza.stanza_type() {
+		Some("result") => Ok(()),
+		_ => Err(IqError::from_response(stanza)),
+	}
+}
+
+/// Extracts the configuration form from the response to the [Room::config_request]
+pub fn parse_config(stanza: &Stanza) -> result::Result<DataForm, IqError> {
+	match stanza.stanza_type() {
+		Some("result") => owner_query(stanza)
+			.and_then(|query| DataForm::from_stanza(&query))
+			.ok_or(IqError::Unexpected),
+		_ => Err(IqError::from_response(stanza)),
+	}
+}
+
+fn owner_query(stanza: &Stanza) -> Option<StanzaRef<'_>> {
+	stanza.find_child_by_name_and_ns("query", NS_MUC_OWNER)
+}
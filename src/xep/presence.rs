@@ -0,0 +1,79 @@
+//! [RFC 6121](https://www.rfc-editor.org/rfc/rfc6121#section-4) presence helpers
+//!
+//! The builders apply the addressing rules that are easy to get wrong: presence probes and subscription management
+//! stanzas are always addressed to the bare JID of the contact, while the directed presence can be sent to a bare or to a
+//! full JID (e.g. a MUC occupant).
+
+use crate::{Jid, Result, Stanza};
+
+/// Type of the presence stanza, see [RFC 6121](https://www.rfc-editor.org/rfc/rfc6121#section-4.7.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PresenceType {
+	/// No `type` attribute
+	Available,
+	Unavailable,
+	Probe,
+	Subscribe,
+	Subscribed,
+	Unsubscribe,
+	Unsubscribed,
+}
+
+impl PresenceType {
+	/// Value of the `type` attribute, `None` for [PresenceType::Available]
+	pub fn as_str(&self) -> Option<&'static str> {
+		match self {
+			PresenceType::Available => None,
+			PresenceType::Unavailable => Some("unavailable"),
+			PresenceType::Probe => Some("probe"),
+			PresenceType::Subscribe => Some("subscribe"),
+			PresenceType::Subscribed => Some("subscribed"),
+			PresenceType::Unsubscribe => Some("unsubscribe"),
+			PresenceType::Unsubscribed => Some("unsubscribed"),
+		}
+	}
+
+	/// Returns `true` if the presence of this type must be addressed to the bare JID
+	pub fn requires_bare_jid(&self) -> bool {
+		!matches!(self, PresenceType::Available | PresenceType::Unavailable)
+	}
+}
+
+/// Builds the presence of `typ` addressed to `to`
+///
+/// The resource of `to` is stripped for the types that require the bare JID (see [PresenceType::requires_bare_jid]),
+/// [Error::InvalidOperation](crate::Error::InvalidOperation) is returned if `to` is not a valid JID.
+pub fn directed(to: &str, typ: PresenceType) -> Result<Stanza> {
+	let to = to.parse::<Jid>()?;
+	let mut out = Stanza::new_presence();
+	if typ.requires_bare_jid() {
+		out.set_to(to.bare())?;
+	} else {
+		out.set_to(to.as_str())?;
+	}
+	if let Some(typ) = typ.as_str() {
+		out.set_stanza_type(typ)?;
+	}
+	Ok(out)
+}
+
+#[inline]
+/// Builds the presence probe for the contact `to`, always addressed to the bare JID
+pub fn probe(to: &str) -> Result<Stanza> {
+	directed(to, PresenceType::Probe)
+}
+
+/// Builds the broadcast unavailable presence with an optional `<status/>` text
+pub fn unavailable(status: Option<&str>) -> Result<Stanza> {
+	let mut out = Stanza::new_presence();
+	out.set_stanza_type("unavailable")?;
+	if let Some(status) = status {
+		let mut status_el = Stanza::new();
+		status_el.set_name("status")?;
+		let mut text = Stanza::new();
+		text.set_text(status)?;
+		status_el.add_child(text)?;
+		out.add_child(status_el)?;
+	}
+	Ok(out)
+}
@@ -0,0 +1,290 @@
+//! [XEP-0016](https://xmpp.org/extensions/xep-0016.html) Privacy Lists management
+//!
+//! Privacy lists are superseded by the [blocking command](crate::xep::blocking), but they're the only way to block
+//! contacts on the older servers. Use the [Blocking](crate::xep::blocking::Blocking) facade to pick the supported
+//! protocol automatically.
+
+use std::result;
+
+use crate::xep::{send_iq, IqError};
+use crate::{Connection, Context, Result, Stanza, StanzaRef};
+
+pub const NS_PRIVACY: &str = "jabber:iq:privacy";
+
+/// Action of the privacy list item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PrivacyAction {
+	Allow,
+	Deny,
+}
+
+/// Type of the privacy list item, the item without a type matches everything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PrivacyItemType {
+	Jid,
+	Group,
+	Subscription,
+}
+
+impl PrivacyItemType {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			PrivacyItemType::Jid => "jid",
+			PrivacyItemType::Group => "group",
+			PrivacyItemType::Subscription => "subscription",
+		}
+	}
+}
+
+/// Single rule of the privacy list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrivacyItem {
+	pub typ: Option<PrivacyItemType>,
+	pub value: Option<String>,
+	pub action: PrivacyAction,
+	/// Items are processed in the ascending order
+	pub order: u32,
+	/// Stanza kinds the item applies to: `message`, `iq`, `presence-in` or `presence-out`, empty means all of them
+	pub stanzas: Vec<String>,
+}
+
+impl PrivacyItem {
+	/// Creates the item blocking all communication with `jid`
+	pub fn deny_jid(jid: impl Into<String>, order: u32) -> Self {
+		Self {
+			typ: Some(PrivacyItemType::Jid),
+			value: Some(jid.into()),
+			action: PrivacyAction::Deny,
+			order,
+			stanzas: vec![],
+		}
+	}
+
+	fn from_stanza(item: &Stanza) -> Option<Self> {
+		Some(Self {
+			typ: match item.get_attribute("type") {
+				Some("jid") => Some(PrivacyItemType::Jid),
+				Some("group") => Some(PrivacyItemType::Group),
+				Some("subscription") => Some(PrivacyItemType::Subscription),
+				Some(_) => return None,
+				None => None,
+			},
+			value: item.get_attribute("value").map(str::to_owned),
+			action: match item.get_attribute("action")? {
+				"allow" => PrivacyAction::Allow,
+				"deny" => PrivacyAction::Deny,
+				_ => return None,
+			},
+			order: item.get_attribute("order")?.parse().ok()?,
+			stanzas: item.children().filter_map(|child| child.name().map(str::to_owned)).collect(),
+		})
+	}
+
+	fn to_stanza(&self) -> Result<Stanza> {
+		let mut out = Stanza::new();
+		out.set_name("item")?;
+		if let Some(typ) = self.typ {
+			out.set_attribute("type", typ.as_str())?;
+		}
+		if let Some(value) = &self.value {
+			out.set_attribute("value", value)?;
+		}
+		out.set_attribute(
+			"action",
+			match self.action {
+				PrivacyAction::Allow => "allow",
+				PrivacyAction::Deny => "deny",
+			},
+		)?;
+		out.set_attribute("order", self.order.to_string())?;
+		for kind in &self.stanzas {
+			let mut kind_el = Stanza::new();
+			kind_el.set_name(kind)?;
+			out.add_child(kind_el)?;
+		}
+		Ok(out)
+	}
+}
+
+/// Named privacy list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrivacyList {
+	pub name: String,
+	pub items: Vec<PrivacyItem>,
+}
+
+/// Names of the privacy lists of the account
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PrivacyLists {
+	/// Active list of the current session
+	pub active: Option<String>,
+	/// Default list of the account
+	pub default: Option<String>,
+	pub names: Vec<String>,
+}
+
+/// Builds the IQ requesting the names of the privacy lists
+pub fn lists_request(id: &str) -> Result<Stanza> {
+	request("get", id, |_| Ok(()))
+}
+
+/// Builds the IQ requesting the items of the list `name`
+pub fn list_request(name: &str, id: &str) -> Result<Stanza> {
+	request("get", id, |query| query.add_child(named("list", Some(name))?))
+}
+
+/// Builds the IQ creating or replacing the `list`
+///
+/// Note that the list without items is removed by the server, see [remove_list_request].
+pub fn set_list_request(list: &PrivacyList, id: &str) -> Result<Stanza> {
+	request("set", id, |query| {
+		let mut list_el = named("list", Some(&list.name))?;
+		for item in &list.items {
+			list_el.add_child(item.to_stanza()?)?;
+		}
+		query.add_child(list_el)
+	})
+}
+
+/// Builds the IQ removing the list `name`
+pub fn remove_list_request(name: &str, id: &str) -> Result<Stanza> {
+	request("set", id, |query| query.add_child(named("list", Some(name))?))
+}
+
+/// Builds the IQ setting the active list of the current session, `None` declines the use of any active list
+pub fn set_active_request(name: Option<&str>, id: &str) -> Result<Stanza> {
+	request("set", id, |query| query.add_child(named("active", name)?))
+}
+
+/// Builds the IQ setting the default list of the account, `None` declines the use of any default list
+pub fn set_default_request(name: Option<&str>, id: &str) -> Result<Stanza> {
+	request("set", id, |query| query.add_child(named("default", name)?))
+}
+
+/// Interprets the response to the [lists_request]
+pub fn parse_lists(stanza: &Stanza) -> result::Result<PrivacyLists, IqError> {
+	let query = result_query(stanza)?;
+	let mut out = PrivacyLists::default();
+	for child in query.children() {
+		let name = child.get_attribute("name").map(str::to_owned);
+		match child.name() {
+			Some("active") => out.active = name,
+			Some("default") => out.default = name,
+			Some("list") => out.names.extend(name),
+			_ => {}
+		}
+	}
+	Ok(out)
+}
+
+/// Interprets the response to the [list_request], the items are sorted by their order
+pub fn parse_list(stanza: &Stanza) -> result::Result<PrivacyList, IqError> {
+	let query = result_query(stanza)?;
+	let list = query.get_child_by_name("list").ok_or(IqError::Unexpected)?;
+	let mut items = list
+		.children()
+		.filter(|child| child.name() == Some("item"))
+		.filter_map(|item| PrivacyItem::from_stanza(&item))
+		.collect::<Vec<_>>();
+	items.sort_by_key(|item| item.order);
+	Ok(PrivacyList {
+		name: list.get_attribute("name").ok_or(IqError::Unexpected)?.to_owned(),
+		items,
+	})
+}
+
+/// Interprets the response to the modification requests
+pub fn parse_response(stanza: &Stanza) -> result::Result<(), IqError> {
+	match stanza.stanza_type() {
+		Some("result") => Ok(()),
+		_ => result_query(stanza).map(|_| ()),
+	}
+}
+
+/// Requests the names of the privacy lists and calls `handler` with the outcome
+pub fn get_lists<'cb, 'cx, CB>(conn: &mut Connection<'cb, 'cx>, handler: CB) -> Result<()>
+where
+	CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<PrivacyLists, IqError>) + Send + 'cb,
+{
+	let request = lists_request(&conn.generate_id())?;
+	send_iq(conn, &request, parse_lists, handler);
+	Ok(())
+}
+
+/// Requests the items of the list `name` and calls `handler` with the outcome
+pub fn get_list<'cb, 'cx, CB>(conn: &mut Connection<'cb, 'cx>, name: &str, handler: CB) -> Result<()>
+where
+	CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<PrivacyList, IqError>) + Send + 'cb,
+{
+	let request = list_request(name, &conn.generate_id())?;
+	send_iq(conn, &request, parse_list, handler);
+	Ok(())
+}
+
+/// Creates or replaces the `list` and calls `handler` with the outcome
+pub fn set_list<'cb, 'cx, CB>(conn: &mut Connection<'cb, 'cx>, list: &PrivacyList, handler: CB) -> Result<()>
+where
+	CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), IqError>) + Send + 'cb,
+{
+	let request = set_list_request(list, &conn.generate_id())?;
+	send_iq(conn, &request, parse_response, handler);
+	Ok(())
+}
+
+/// Removes the list `name` and calls `handler` with the outcome
+pub fn remove_list<'cb, 'cx, CB>(conn: &mut Connection<'cb, 'cx>, name: &str, handler: CB) -> Result<()>
+where
+	CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), IqError>) + Send + 'cb,
+{
+	let request = remove_list_request(name, &conn.generate_id())?;
+	send_iq(conn, &request, parse_response, handler);
+	Ok(())
+}
+
+/// Sets the active list of the current session and calls `handler` with the outcome
+pub fn set_active<'cb, 'cx, CB>(conn: &mut Connection<'cb, 'cx>, name: Option<&str>, handler: CB) -> Result<()>
+where
+	CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), IqError>) + Send + 'cb,
+{
+	let request = set_active_request(name, &conn.generate_id())?;
+	send_iq(conn, &request, parse_response, handler);
+	Ok(())
+}
+
+/// Sets the default list of the account and calls `handler` with the outcome
+pub fn set_default<'cb, 'cx, CB>(conn: &mut Connection<'cb, 'cx>, name: Option<&str>, handler: CB) -> Result<()>
+where
+	CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<(), IqError>) + Send + 'cb,
+{
+	let request = set_default_request(name, &conn.generate_id())?;
+	send_iq(conn, &request, parse_response, handler);
+	Ok(())
+}
+
+fn request(typ: &str, id: &str, fill: impl FnOnce(&mut Stanza) -> Result<()>) -> Result<Stanza> {
+	let mut out = Stanza::new_iq(Some(typ), Some(id));
+	let mut query = Stanza::new();
+	query.set_name("query")?;
+	query.set_ns(NS_PRIVACY)?;
+	fill(&mut query)?;
+	out.add_child(query)?;
+	Ok(out)
+}
+
+fn named(element: &str, name: Option<&str>) -> Result<Stanza> {
+	let mut out = Stanza::new();
+	out.set_name(element)?;
+	if let Some(name) = name {
+		out.set_attribute("name", name)?;
+	}
+	Ok(out)
+}
+
+fn result_query(stanza: &Stanza) -> result::Result<StanzaRef<'_>, IqError> {
+	match stanza.stanza_type() {
+		Some("result") => stanza
+			.find_child_by_name_and_ns("query", NS_PRIVACY)
+			.ok_or(IqError::Unexpected),
+		_ => Err(IqError::from_response(stanza)),
+	}
+}
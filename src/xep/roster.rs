@@ -0,0 +1,337 @@
+//! [RFC 6121](https://www.rfc-editor.org/rfc/rfc6121#section-2) roster with the roster versioning
+//!
+//! [Roster] is the client side copy of the roster, it's updated with the results of the roster requests and with the
+//! roster pushes. With the [versioning](https://www.rfc-editor.org/rfc/rfc6121#section-2.6) the request carries the
+//! version of the cached copy and the server answers with either an empty result, if the copy is current, or with the
+//! pushes of the changed items, so the large rosters are not downloaded again on every reconnect. [enable_sync] wires it
+//! all up on a connection and persists the roster through the [RosterStore].
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::{Connection, Context, HandlerInfo, HandlerResult, Jid, Result, Stanza};
+
+pub const NS_ROSTER: &str = "jabber:iq:roster";
+
+/// Subscription state of the roster item, see [RFC 6121](https://www.rfc-editor.org/rfc/rfc6121#section-2.1.2.5)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Subscription {
+	None,
+	To,
+	From,
+	Both,
+	/// Only used in the pushes, the item was removed from the roster
+	Remove,
+}
+
+impl Subscription {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Subscription::None => "none",
+			Subscription::To => "to",
+			Subscription::From => "from",
+			Subscription::Both => "both",
+			Subscription::Remove => "remove",
+		}
+	}
+
+	/// Parses the `subscription` attribute, the missing or unknown value is treated as `none`
+	pub fn from_attr(value: Option<&str>) -> Self {
+		match value {
+			Some("to") => Subscription::To,
+			Some("from") => Subscription::From,
+			Some("both") => Subscription::Both,
+			Some("remove") => Subscription::Remove,
+			_ => Subscription::None,
+		}
+	}
+}
+
+/// Contents of the `<item/>` element of the roster
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RosterItem {
+	/// Bare JID of the contact
+	pub jid: String,
+	pub name: Option<String>,
+	pub subscription: Subscription,
+	/// Whether the outgoing subscription request is pending (`ask="subscribe"`)
+	pub ask: bool,
+	pub groups: Vec<String>,
+}
+
+impl RosterItem {
+	/// Parses the `<item/>` element, returns `None` if it's not an item or has no valid `jid`
+	pub fn from_stanza(item: &Stanza) -> Option<Self> {
+		if item.name() != Some("item") {
+			return None;
+		}
+		let jid = item.get_attribute("jid")?.parse::<Jid>().ok()?;
+		Some(RosterItem {
+			jid: jid.bare().to_owned(),
+			name: item.get_attribute("name").map(str::to_owned),
+			subscription: Subscription::from_attr(item.get_attribute("subscription")),
+			ask: item.get_attribute("ask") == Some("subscribe"),
+			groups: item
+				.children()
+				.filter(|child| child.name() == Some("group"))
+				.filter_map(|group| group.text())
+				.filter(|group| !group.is_empty())
+				.collect(),
+		})
+	}
+
+	/// Builds the `<item/>` element
+	pub fn to_stanza(&self) -> Result<Stanza> {
+		let mut out = Stanza::new();
+		out.set_name("item")?;
+		out.set_attribute("jid", &self.jid)?;
+		if let Some(name) = &self.name {
+			out.set_attribute("name", name)?;
+		}
+		out.set_attribute("subscription", self.subscription.as_str())?;
+		if self.ask {
+			out.set_attribute("ask", "subscribe")?;
+		}
+		for group in &self.groups {
+			let mut group_el = Stanza::new();
+			group_el.set_name("group")?;
+			let mut text = Stanza::new();
+			text.set_text(group)?;
+			group_el.add_child(text)?;
+			out.add_child(group_el)?;
+		}
+		Ok(out)
+	}
+}
+
+/// Cached copy of the roster
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Roster {
+	/// Version of the roster as reported by the server, `None` if the server doesn't support the versioning
+	pub ver: Option<String>,
+	/// Items keyed by the bare JID
+	pub items: BTreeMap<String, RosterItem>,
+}
+
+impl Roster {
+	#[inline]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	#[inline]
+	pub fn get(&self, jid: &str) -> Option<&RosterItem> {
+		self.items.get(jid)
+	}
+
+	/// Builds the roster request with the `id`, with `versioned` it carries the version of this copy
+	///
+	/// Only request the versioned roster from the servers that advertise the `urn:xmpp:features:rosterver` stream feature.
+	/// The empty version is sent when there is no cached version yet, so that the server starts the versioning.
+	pub fn request(&self, versioned: bool, id: &str) -> Result<Stanza> {
+		let mut out = Stanza::new_iq(Some("get"), Some(id));
+		let mut query = Stanza::new();
+		query.set_name("query")?;
+		query.set_ns(NS_ROSTER)?;
+		if versioned {
+			query.set_attribute("ver", self.ver.as_deref().unwrap_or(""))?;
+		}
+		out.add_child(query)?;
+		Ok(out)
+	}
+
+	/// Applies the result of the [request](Roster::request), returns `false` if the `response` is not a roster result
+	///
+	/// The result with the `<query/>` replaces the whole roster. The empty result means that the cached copy is current,
+	/// the changes since its version (if any) follow as the pushes.
+	pub fn apply_result(&mut self, response: &Stanza) -> bool {
+		if response.name() != Some("iq") || response.stanza_type() != Some("result") {
+			return false;
+		}
+		if let Some(query) = response.find_child_by_name_and_ns("query", NS_ROSTER) {
+			*self = Self::from_query(&query);
+		}
+		true
+	}
+
+	/// Applies the roster push, returns the updated item or `None` if `push` is not a roster push
+	///
+	/// The item with the [Subscription::Remove] is removed from the roster and returned as is. The caller must check that
+	/// the push comes from the own account, see [is_valid_push].
+	pub fn apply_push(&mut self, push: &Stanza) -> Option<RosterItem> {
+		if push.name() != Some("iq") || push.stanza_type() != Some("set") {
+			return None;
+		}
+		let query = push.find_child_by_name_and_ns("query", NS_ROSTER)?;
+		// the push must contain exactly one item
+		let mut items = query.children().filter(|child| child.name() == Some("item"));
+		let item = items.next().and_then(|item| RosterItem::from_stanza(&item))?;
+		if items.next().is_some() {
+			return None;
+		}
+		if let Some(ver) = query.get_attribute("ver") {
+			self.ver = Some(ver.to_owned());
+		}
+		if item.subscription == Subscription::Remove {
+			self.items.remove(&item.jid);
+		} else {
+			self.items.insert(item.jid.clone(), item.clone());
+		}
+		Some(item)
+	}
+
+	/// Parses the roster from the `<query xmlns="jabber:iq:roster"/>` element, e.g. the one persisted with
+	/// [Roster::to_query]
+	pub fn from_query(query: &Stanza) -> Self {
+		Roster {
+			ver: query.get_attribute("ver").map(str::to_owned),
+			items: query
+				.children()
+				.filter_map(|item| RosterItem::from_stanza(&item))
+				.filter(|item| item.subscription != Subscription::Remove)
+				.map(|item| (item.jid.clone(), item))
+				.collect(),
+		}
+	}
+
+	/// Builds the `<query xmlns="jabber:iq:roster"/>` element with the whole roster, e.g. to persist it as XML
+	pub fn to_query(&self) -> Result<Stanza> {
+		let mut out = Stanza::new();
+		out.set_name("query")?;
+		out.set_ns(NS_ROSTER)?;
+		if let Some(ver) = &self.ver {
+			out.set_attribute("ver", ver)?;
+		}
+		for item in self.items.values() {
+			out.add_child(item.to_stanza()?)?;
+		}
+		Ok(out)
+	}
+}
+
+/// Persistent storage of the [Roster] used by [enable_sync]
+pub trait RosterStore: Send {
+	/// Returns the roster saved earlier, called once when the sync is enabled
+	fn load(&mut self) -> Option<Roster>;
+
+	/// Saves the `roster` after every change
+	fn save(&mut self, roster: &Roster);
+}
+
+/// Store that keeps nothing, the roster is downloaded in full on every start of the application
+impl RosterStore for () {
+	fn load(&mut self) -> Option<Roster> {
+		None
+	}
+
+	fn save(&mut self, _roster: &Roster) {}
+}
+
+/// Change of the roster reported by [enable_sync]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RosterEvent {
+	/// The result of the roster request was applied, `full` is `false` if the server confirmed the cached copy
+	Loaded { full: bool },
+	/// The item was added, changed or removed (with [Subscription::Remove]) by the roster push
+	Pushed(RosterItem),
+}
+
+/// Checks that the roster `push` comes from the own account of the `conn` as required by
+/// [RFC 6121](https://www.rfc-editor.org/rfc/rfc6121#section-2.1.6)
+pub fn is_valid_push(conn: &Connection, push: &Stanza) -> bool {
+	let from = match push.from() {
+		Some(from) => from,
+		None => return true,
+	};
+	let own = conn
+		.bound_jid()
+		.or_else(|| conn.jid())
+		.and_then(|jid| jid.parse::<Jid>().ok());
+	match (from.parse::<Jid>(), own) {
+		(Ok(from), Some(own)) => from.is_bare() && from.bare().eq_ignore_ascii_case(own.bare()),
+		_ => false,
+	}
+}
+
+/// Keeps the roster of the `conn` synchronized and persisted in the `store`
+///
+/// The roster is requested on every [connect](Connection::on_connect), with `versioned` the request carries the version
+/// of the cached copy (see [Roster::request] for when to enable it). The result and the roster pushes are applied to the
+/// returned shared copy, saved to the `store` and reported to `on_event`. The valid pushes are acknowledged, the ones that
+/// don't come from the own account are ignored.
+pub fn enable_sync<'cb, 'cx, S, CB>(
+	conn: &mut Connection<'cb, 'cx>,
+	mut store: S,
+	versioned: bool,
+	on_event: CB,
+) -> Arc<Mutex<Roster>>
+where
+	S: RosterStore + 'cb,
+	CB: FnMut(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, &RosterEvent) + Send + 'cb,
+{
+	let roster = Arc::new(Mutex::new(store.load().unwrap_or_default()));
+	let store = Arc::new(Mutex::new(store));
+	let on_event = Arc::new(Mutex::new(on_event));
+	let notify = {
+		let roster = Arc::clone(&roster);
+		move |ctx: &Context<'cx, 'cb>, conn: &mut Connection<'cb, 'cx>, event: RosterEvent| {
+			if let (Ok(roster), Ok(mut store)) = (roster.lock(), store.lock()) {
+				store.save(&roster);
+			}
+			if let Ok(mut on_event) = on_event.lock() {
+				on_event(ctx, conn, &event);
+			}
+		}
+	};
+	conn.handler_add(
+		{
+			let roster = Arc::clone(&roster);
+			let notify = notify.clone();
+			move |ctx, conn, stanza| {
+				if !is_valid_push(conn, stanza) {
+					return HandlerResult::KeepHandler;
+				}
+				let item = roster.lock().ok().and_then(|mut roster| roster.apply_push(stanza));
+				if let Some(item) = item {
+					conn.send(&stanza.reply());
+					notify(ctx, conn, RosterEvent::Pushed(item));
+				}
+				HandlerResult::KeepHandler
+			}
+		},
+		Some(NS_ROSTER),
+		Some("iq"),
+		Some("set"),
+	);
+	conn.on_connect({
+		let roster = Arc::clone(&roster);
+		// id of the last request, its handler is still there if the connection was lost before the response
+		let mut pending = None::<String>;
+		move |_, conn| {
+			if let Some(stale) = pending.take() {
+				conn.handlers_remove_where(|info| matches!(info, HandlerInfo::Id { id } if *id == stale));
+			}
+			let id = conn.generate_id();
+			let request = match roster.lock().ok().and_then(|roster| roster.request(versioned, &id).ok()) {
+				Some(request) => request,
+				None => return,
+			};
+			pending = Some(id.clone());
+			let roster = Arc::clone(&roster);
+			let notify = notify.clone();
+			conn.id_handler_add(
+				move |ctx, conn, response| {
+					let full = response.find_child_by_name_and_ns("query", NS_ROSTER).is_some();
+					let applied = roster.lock().map_or(false, |mut roster| roster.apply_result(response));
+					if applied {
+						notify(ctx, conn, RosterEvent::Loaded { full });
+					}
+					HandlerResult::RemoveHandler
+				},
+				id,
+			);
+			conn.send(&request);
+		}
+	});
+	roster
+}
@@ -0,0 +1,254 @@
+//! [XEP-0388](https://xmpp.org/extensions/xep-0388.html) Extensible SASL Profile (SASL2) elements
+//!
+//! libstrophe negotiates the legacy RFC 6120 SASL for the client connections itself, so these are the building blocks for
+//! trying SASL2 and [XEP-0484](https://xmpp.org/extensions/xep-0484.html) FAST over the raw connections (see
+//! [Connection::connect_raw](crate::Connection::connect_raw)): [Sasl2Features] detects the support in
+//! `<stream:features/>`, [Authenticate] starts the exchange, [response] and [abort] continue it and [ServerMessage]
+//! parses the server side of it. Implementing the SASL mechanisms is up to the application.
+
+use crate::xep::text_element;
+use crate::{base64, Result, Stanza};
+
+pub const NS_SASL2: &str = "urn:xmpp:sasl:2";
+pub const NS_BIND2: &str = "urn:xmpp:bind:0";
+pub const NS_FAST: &str = "urn:xmpp:fast:0";
+
+/// SASL2 support advertised in `<stream:features/>`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sasl2Features {
+	pub mechanisms: Vec<String>,
+	/// Namespaces of the features that can be negotiated inline with the authentication, e.g. [NS_BIND2]
+	pub inline: Vec<String>,
+	/// Mechanisms of the FAST tokens, `None` if FAST is not offered
+	pub fast_mechanisms: Option<Vec<String>>,
+}
+
+impl Sasl2Features {
+	/// Parses the `<authentication xmlns="urn:xmpp:sasl:2"/>` child of the `<stream:features/>`, returns `None` if the server
+	/// doesn't offer SASL2
+	pub fn from_features(features: &Stanza) -> Option<Self> {
+		let authentication = features.get_child_by_name_and_ns("authentication", NS_SASL2)?;
+		let mut out = Sasl2Features {
+			mechanisms: texts_of(&authentication, "mechanism"),
+			..Sasl2Features::default()
+		};
+		if let Some(inline) = authentication.get_child_by_name("inline") {
+			for feature in inline.children().filter(|child| child.is_tag()) {
+				if let Some(ns) = feature.ns() {
+					out.inline.push(ns.to_owned());
+					if ns == NS_FAST && feature.name() == Some("fast") {
+						out.fast_mechanisms = Some(texts_of(&feature, "mechanism"));
+					}
+				}
+			}
+		}
+		Some(out)
+	}
+
+	/// Checks whether the feature with the namespace `ns` can be negotiated inline
+	pub fn supports_inline(&self, ns: &str) -> bool {
+		self.inline.iter().any(|inline| inline == ns)
+	}
+}
+
+/// Identification of the client sent with [Authenticate]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserAgent {
+	/// Stable UUID of the client installation
+	pub id: Option<String>,
+	pub software: Option<String>,
+	pub device: Option<String>,
+}
+
+/// The `<authenticate/>` element starting the SASL2 exchange
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Authenticate {
+	pub mechanism: String,
+	pub initial_response: Option<Vec<u8>>,
+	pub user_agent: Option<UserAgent>,
+}
+
+impl Authenticate {
+	#[inline]
+	pub fn new(mechanism: impl Into<String>, initial_response: Option<Vec<u8>>) -> Self {
+		Self {
+			mechanism: mechanism.into(),
+			initial_response,
+			user_agent: None,
+		}
+	}
+
+	/// Builds the `<authenticate/>` element, the `inline` payloads (e.g. the Bind 2 `<bind/>` request) are appended to it
+	pub fn to_stanza(&self, inline: Vec<Stanza>) -> Result<Stanza> {
+		let mut out = element("authenticate")?;
+		out.set_attribute("mechanism", &self.mechanism)?;
+		if let Some(initial_response) = &self.initial_response {
+			out.add_child(text_element("initial-response", &encode(initial_response)?)?)?;
+		}
+		if let Some(user_agent) = &self.user_agent {
+			let mut user_agent_el = Stanza::new();
+			user_agent_el.set_name("user-agent")?;
+			if let Some(id) = &user_agent.id {
+				user_agent_el.set_attribute("id", id)?;
+			}
+			if let Some(software) = &user_agent.software {
+				user_agent_el.add_child(text_element("software", software)?)?;
+			}
+			if let Some(device) = &user_agent.device {
+				user_agent_el.add_child(text_element("device", device)?)?;
+			}
+			out.add_child(user_agent_el)?;
+		}
+		for payload in inline {
+			out.add_child(payload)?;
+		}
+		Ok(out)
+	}
+
+	/// Parses the `<authenticate/>` element, returns `None` if it's not one or the initial response is not valid base64
+	pub fn from_stanza(stanza: &Stanza) -> Option<Self> {
+		if stanza.name() != Some("authenticate") || stanza.ns() != Some(NS_SASL2) {
+			return None;
+		}
+		let initial_response = match stanza.get_child_by_name("initial-response") {
+			Some(initial_response) => Some(decode(&initial_response.text().unwrap_or_default())?),
+			None => None,
+		};
+		Some(Authenticate {
+			mechanism: stanza.get_attribute("mechanism")?.to_owned(),
+			initial_response,
+			user_agent: stanza.get_child_by_name("user-agent").map(|user_agent| UserAgent {
+				id: user_agent.get_attribute("id").map(str::to_owned),
+				software: child_text(&user_agent, "software"),
+				device: child_text(&user_agent, "device"),
+			}),
+		})
+	}
+}
+
+/// Message sent by the server during the SASL2 exchange
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerMessage {
+	Challenge(Vec<u8>),
+	/// The authentication has succeeded, `authorization_identifier` is the full JID of the session if Bind 2 was used
+	Success {
+		additional_data: Option<Vec<u8>>,
+		authorization_identifier: Option<String>,
+	},
+	/// The authentication has failed with the defined `condition`, e.g. `not-authorized`
+	Failure {
+		condition: String,
+		text: Option<String>,
+	},
+	/// The authentication must continue with one of the `tasks`, e.g. a second factor
+	Continue {
+		additional_data: Option<Vec<u8>>,
+		tasks: Vec<String>,
+		text: Option<String>,
+	},
+}
+
+impl ServerMessage {
+	/// Parses the SASL2 server message, returns `None` for the other elements and for invalid base64 data
+	pub fn from_stanza(stanza: &Stanza) -> Option<Self> {
+		if stanza.ns() != Some(NS_SASL2) {
+			return None;
+		}
+		let additional_data = || match stanza.get_child_by_name("additional-data") {
+			Some(data) => decode(&data.text().unwrap_or_default()).map(Some),
+			None => Some(None),
+		};
+		Some(match stanza.name()? {
+			"challenge" => ServerMessage::Challenge(decode(&stanza.text().unwrap_or_default())?),
+			"success" => ServerMessage::Success {
+				additional_data: additional_data()?,
+				authorization_identifier: child_text(stanza, "authorization-identifier"),
+			},
+			"failure" => ServerMessage::Failure {
+				condition: stanza
+					.children()
+					.find(|child| child.is_tag() && child.name() != Some("text"))
+					.and_then(|condition| condition.name().map(str::to_owned))
+					.unwrap_or_default(),
+				text: child_text(stanza, "text"),
+			},
+			"continue" => ServerMessage::Continue {
+				additional_data: additional_data()?,
+				tasks: stanza
+					.get_child_by_name("tasks")
+					.map_or_else(Vec::new, |tasks| texts_of(&tasks, "task")),
+				text: child_text(stanza, "text"),
+			},
+			_ => return None,
+		})
+	}
+}
+
+/// Builds the `<response/>` to the challenge
+pub fn response(data: &[u8]) -> Result<Stanza> {
+	let mut out = element("response")?;
+	let mut text = Stanza::new();
+	text.set_text(encode(data)?)?;
+	out.add_child(text)?;
+	Ok(out)
+}
+
+/// Builds the `<abort/>` cancelling the exchange with an optional human-readable `text`
+pub fn abort(text: Option<&str>) -> Result<Stanza> {
+	let mut out = element("abort")?;
+	if let Some(text) = text {
+		out.add_child(text_element("text", text)?)?;
+	}
+	Ok(out)
+}
+
+/// Builds the `<next/>` selecting the `task` offered by [ServerMessage::Continue]
+pub fn next(task: &str, data: Option<&[u8]>) -> Result<Stanza> {
+	let mut out = element("next")?;
+	out.set_attribute("task", task)?;
+	if let Some(data) = data {
+		let mut text = Stanza::new();
+		text.set_text(encode(data)?)?;
+		out.add_child(text)?;
+	}
+	Ok(out)
+}
+
+fn element(name: &str) -> Result<Stanza> {
+	let mut out = Stanza::new();
+	out.set_name(name)?;
+	out.set_ns(NS_SASL2)?;
+	Ok(out)
+}
+
+fn child_text(stanza: &Stanza, name: &str) -> Option<String> {
+	stanza.get_child_by_name(name).and_then(|child| child.text())
+}
+
+fn texts_of(stanza: &Stanza, name: &str) -> Vec<String> {
+	stanza
+		.children()
+		.filter(|child| child.name() == Some(name))
+		.filter_map(|child| child.text())
+		.collect()
+}
+
+/// Encodes the SASL data, the empty data is sent as `=`
+fn encode(data: &[u8]) -> Result<String> {
+	if data.is_empty() {
+		return Ok("=".to_owned());
+	}
+	base64::encode(data)
+}
+
+/// Decodes the SASL data, `=` stands for the empty data
+fn decode(text: &str) -> Option<Vec<u8>> {
+	let text = text.trim();
+	if text == "=" {
+		return Some(vec![]);
+	}
+	if text.is_empty() {
+		return None;
+	}
+	base64::decode(text)
+}
@@ -0,0 +1,182 @@
+//! [XEP-0055](https://xmpp.org/extensions/xep-0055.html) Jabber Search client helpers
+//!
+//! The search service first returns the search form, either as the legacy fixed fields (`<first/>`, `<last/>`, etc.) or
+//! as an extended [data form](crate::xep::data_forms). The filled form is then submitted and the matching items are
+//! returned in the same format.
+
+use std::result;
+
+use crate::xep::data_forms::{DataForm, FormType};
+use crate::xep::{send_iq, IqError};
+use crate::{Connection, Context, Result, Stanza, StanzaRef};
+
+pub const NS_SEARCH: &str = "jabber:iq:search";
+
+/// Search form returned by the service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchForm {
+	/// Legacy form: the names of the supported fields, e.g. `first`, `last`, `nick` or `email`
+	Fields {
+		instructions: Option<String>,
+		fields: Vec<String>,
+	},
+	/// Extended search with the data form of type [FormType::Form]
+	Data(DataForm),
+}
+
+/// Filled search query to submit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchQuery {
+	/// Values for the legacy fields as `(name, value)` pairs
+	Fields(Vec<(String, String)>),
+	/// The filled data form, submitted as [DataForm::submit]
+	Data(DataForm),
+}
+
+/// Single row of the search results
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SearchItem {
+	/// JID of the found entity, from the `jid` attribute or the `jid` field of the data form item
+	pub jid: Option<String>,
+	/// `(name, value)` pairs of the rest of the columns, multi-valued data form fields are joined with a newline
+	pub fields: Vec<(String, String)>,
+}
+
+impl SearchItem {
+	/// Returns the value of the column `name`
+	pub fn get(&self, name: &str) -> Option<&str> {
+		self
+			.fields
+			.iter()
+			.find(|(field, _)| field == name)
+			.map(|(_, value)| value.as_str())
+	}
+}
+
+/// Builds the IQ requesting the search form from the service `to`
+pub fn form_request(to: &str, id: &str) -> Result<Stanza> {
+	let mut out = Stanza::new_iq(Some("get"), Some(id));
+	out.set_to(to)?;
+	out.add_child(query_element()?)?;
+	Ok(out)
+}
+
+/// Extracts the search form from the response to the [form_request]
+///
+/// The data form takes precedence over the legacy fields if the service provides both.
+pub fn parse_form(stanza: &Stanza) -> result::Result<SearchForm, IqError> {
+	let query = result_query(stanza)?;
+	if let Some(form) = DataForm::from_stanza(&query) {
+		return Ok(SearchForm::Data(form));
+	}
+	let mut instructions = None;
+	let mut fields = vec![];
+	for child in query.children() {
+		match child.name() {
+			Some("instructions") => instructions = child.text(),
+			Some(name) => fields.push(name.to_owned()),
+			None => {}
+		}
+	}
+	Ok(SearchForm::Fields { instructions, fields })
+}
+
+/// Builds the IQ submitting the search `query` to the service `to`
+pub fn submit_request(to: &str, id: &str, query: &SearchQuery) -> Result<Stanza> {
+	let mut out = Stanza::new_iq(Some("set"), Some(id));
+	out.set_to(to)?;
+	let mut query_el = query_element()?;
+	match query {
+		SearchQuery::Fields(fields) => {
+			for (name, value) in fields {
+				let mut field = Stanza::new();
+				field.set_name(name)?;
+				let mut text = Stanza::new();
+				text.set_text(value)?;
+				field.add_child(text)?;
+				query_el.add_child(field)?;
+			}
+		}
+		SearchQuery::Data(form) => {
+			let form = if form.typ == FormType::Submit {
+				form.to_stanza()?
+			} else {
+				form.submit().to_stanza()?
+			};
+			query_el.add_child(form)?;
+		}
+	}
+	out.add_child(query_el)?;
+	Ok(out)
+}
+
+/// Extracts the found items from the response to the [submit_request]
+pub fn parse_results(stanza: &Stanza) -> result::Result<Vec<SearchItem>, IqError> {
+	let query = result_query(stanza)?;
+	if let Some(form) = DataForm::from_stanza(&query) {
+		return Ok(form
+			.items
+			.into_iter()
+			.map(|row| {
+				let mut item = SearchItem::default();
+				for field in row {
+					match field.var {
+						Some(var) if var == "jid" => item.jid = field.values.into_iter().next(),
+						Some(var) => item.fields.push((var, field.values.join("\n"))),
+						None => {}
+					}
+				}
+				item
+			})
+			.collect());
+	}
+	Ok(query
+		.children()
+		.filter(|child| child.name() == Some("item"))
+		.map(|item| SearchItem {
+			jid: item.get_attribute("jid").map(str::to_owned),
+			fields: item
+				.children()
+				.filter_map(|field| Some((field.name()?.to_owned(), field.text().unwrap_or_default())))
+				.collect(),
+		})
+		.collect())
+}
+
+/// Requests the search form from the service `to` and calls `handler` with the outcome
+pub fn request_form<'cb, 'cx, CB>(conn: &mut Connection<'cb, 'cx>, to: &str, handler: CB) -> Result<()>
+where
+	CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<SearchForm, IqError>) + Send + 'cb,
+{
+	let id = conn.generate_id();
+	let request = form_request(to, &id)?;
+	send_iq(conn, &request, parse_form, handler);
+	Ok(())
+}
+
+/// Submits the search `query` to the service `to` and calls `handler` with the found items
+pub fn search<'cb, 'cx, CB>(conn: &mut Connection<'cb, 'cx>, to: &str, query: &SearchQuery, handler: CB) -> Result<()>
+where
+	CB: FnOnce(&Context<'cx, 'cb>, &mut Connection<'cb, 'cx>, result::Result<Vec<SearchItem>, IqError>) + Send + 'cb,
+{
+	let id = conn.generate_id();
+	let request = submit_request(to, &id, query)?;
+	send_iq(conn, &request, parse_results, handler);
+	Ok(())
+}
+
+fn query_element() -> Result<Stanza> {
+	let mut out = Stanza::new();
+	out.set_name("query")?;
+	out.set_ns(NS_SEARCH)?;
+	Ok(out)
+}
+
+fn result_query(stanza: &Stanza) -> result::Result<StanzaRef<'_>, IqError> {
+	match stanza.stanza_type() {
+		Some("result") => stanza
+			.find_child_by_name_and_ns("query", NS_SEARCH)
+			.ok_or(IqError::Unexpected),
+		_ => Err(IqError::from_response(stanza)),
+	}
+}
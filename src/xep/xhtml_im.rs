@@ -0,0 +1,144 @@
+//! [XEP-0071](https://xmpp.org/extensions/xep-0071.html) XHTML-IM message bodies
+//!
+//! Incoming XHTML is untrusted markup, [extract] passes it through [sanitize] which only keeps the elements, attributes
+//! and style properties of the XEP-0071 recommended profile.
+
+use crate::{Error, Node, Result, Stanza};
+
+pub const NS_XHTML_IM: &str = "http://jabber.org/protocol/xhtml-im";
+pub const NS_XHTML: &str = "http://www.w3.org/1999/xhtml";
+
+/// Elements whose content is dropped together with the element
+const DROPPED_ELEMENTS: &[&str] = &["script", "style", "head", "title", "object", "iframe"];
+
+/// CSS properties allowed in the `style` attribute
+const ALLOWED_STYLE_PROPERTIES: &[&str] = &[
+	"background-color",
+	"color",
+	"font-family",
+	"font-size",
+	"font-style",
+	"font-weight",
+	"margin-left",
+	"margin-right",
+	"text-align",
+	"text-decoration",
+];
+
+/// Attributes allowed for the element `name` besides `style`, `None` if the element itself is not allowed
+fn allowed_attributes(name: &str) -> Option<&'static [&'static str]> {
+	Some(match name {
+		"a" => &["href", "type"],
+		"img" => &["alt", "height", "src", "width"],
+		"blockquote" | "body" | "br" | "cite" | "em" | "li" | "ol" | "p" | "span" | "strong" | "ul" => &[],
+		_ => return None,
+	})
+}
+
+/// Attaches the XHTML `body` to the `message` alongside its plain `<body/>`
+///
+/// `body` must be a `<body/>` element, it gets the XHTML namespace if it has none. An existing XHTML-IM wrapper is
+/// reused so that several bodies in different languages can be attached.
+pub fn attach(message: &mut Stanza, mut body: Stanza) -> Result<()> {
+	if body.name() != Some("body") {
+		return Err(Error::InvalidOperation);
+	}
+	if body.ns().is_none() {
+		body.set_ns(NS_XHTML)?;
+	}
+	if let Some(mut html) = message
+		.children_mut()
+		.find(|child| child.name() == Some("html") && child.ns() == Some(NS_XHTML_IM))
+	{
+		return html.add_child(body);
+	}
+	let mut html = Stanza::new();
+	html.set_name("html")?;
+	html.set_ns(NS_XHTML_IM)?;
+	html.add_child(body)?;
+	message.add_child(html)
+}
+
+/// Extracts the sanitized XHTML `<body/>` from the `message`, see [sanitize]
+pub fn extract(message: &Stanza) -> Option<Stanza> {
+	let html = message.find_child_by_name_and_ns("html", NS_XHTML_IM)?;
+	let body = html.find_child_by_name_and_ns("body", NS_XHTML)?;
+	sanitize(&body).ok()
+}
+
+/// Returns a copy of the XHTML `element` containing only the allowed markup
+///
+/// The disallowed elements are replaced by their sanitized content, while the content of `<script/>`, `<style/>` and
+/// other non-presentational elements is dropped entirely. Only the attributes of the recommended profile are kept, links
+/// and images must use the `http`, `https`, `xmpp` or `mailto` schemes and the `style` attribute is reduced to the
+/// allowed CSS properties. Returns [Error::InvalidOperation] if `element` itself is not an allowed element.
+pub fn sanitize(element: &Stanza) -> Result<Stanza> {
+	let name = element.name().ok_or(Error::InvalidOperation)?;
+	let attributes = allowed_attributes(name).ok_or(Error::InvalidOperation)?;
+	let mut out = Stanza::new();
+	out.set_name(name)?;
+	if let Some(ns) = element.ns() {
+		out.set_ns(ns)?;
+	}
+	for (attr, value) in element.attributes() {
+		if attr == "style" {
+			let style = sanitize_style(value);
+			if !style.is_empty() {
+				out.set_attribute("style", style)?;
+			}
+		} else if attributes.contains(&attr) && (!matches!(attr, "href" | "src") || is_safe_uri(value)) {
+			out.set_attribute(attr, value)?;
+		}
+	}
+	sanitize_children(element, &mut out)?;
+	Ok(out)
+}
+
+fn sanitize_children(element: &Stanza, out: &mut Stanza) -> Result<()> {
+	for node in element.nodes() {
+		match node {
+			Node::Text(text) => {
+				let mut text_node = Stanza::new();
+				text_node.set_text(text)?;
+				out.add_child(text_node)?;
+			}
+			Node::Element(child) => match child.name() {
+				Some(name) if DROPPED_ELEMENTS.contains(&name) => {}
+				Some(name) if allowed_attributes(name).is_some() => out.add_child(sanitize(&child)?)?,
+				_ => sanitize_children(&child, out)?,
+			},
+		}
+	}
+	Ok(())
+}
+
+fn is_safe_uri(uri: &str) -> bool {
+	let scheme = match uri.split_once(':') {
+		Some((scheme, _)) => scheme.trim().to_ascii_lowercase(),
+		// relative reference
+		None => return true,
+	};
+	matches!(scheme.as_str(), "http" | "https" | "xmpp" | "mailto")
+}
+
+fn sanitize_style(style: &str) -> String {
+	style
+		.split(';')
+		.filter_map(|declaration| {
+			let (property, value) = declaration.split_once(':')?;
+			let property = property.trim().to_ascii_lowercase();
+			let value = value.trim();
+			let lower_value = value.to_ascii_lowercase();
+			if ALLOWED_STYLE_PROPERTIES.contains(&property.as_str())
+				&& !value.is_empty()
+				&& !lower_value.contains("url(")
+				&& !lower_value.contains("expression(")
+			{
+				Some(format!("{}: {}", property, value))
+			} else {
+				None
+			}
+		})
+		.collect::<Vec<_>>()
+		.join("; ")
+}